@@ -0,0 +1,281 @@
+// Detection and flattening of animated GIF, PNG (APNG) and WebP files.
+//
+// Everything here works on the container structure only; no pixel data is
+// decoded, so flattening keeps the first frame bit-for-bit.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+/// Whether the file is an animation with more than one frame. Unknown or
+/// unreadable-as-image content is reported as not animated.
+pub fn is_animated(path: &Path) -> Result<bool, String> {
+    let mut head = [0u8; 12];
+    let n = File::open(path)
+        .and_then(|f| f.take(head.len() as u64).read(&mut head))
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    let head = &head[..n];
+    // Only read the whole file for container formats that can animate.
+    if !(head.starts_with(b"GIF8") || head.starts_with(PNG_SIGNATURE) || is_webp(head)) {
+        return Ok(false);
+    }
+    let data = fs::read(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    Ok(animated_bytes(&data))
+}
+
+fn animated_bytes(data: &[u8]) -> bool {
+    if data.starts_with(b"GIF8") {
+        gif_frames(data).map(|f| f.len() > 1).unwrap_or(false)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        png_chunks(data).iter().any(|c| &c.kind == b"acTL")
+    } else if is_webp(data) {
+        riff_chunks(data).iter().any(|c| &c.kind == b"ANIM" || &c.kind == b"ANMF")
+    } else {
+        false
+    }
+}
+
+/// A still image holding only the first frame of `src`.
+pub fn read_first_frame(src: &Path) -> Result<Vec<u8>, String> {
+    let data = fs::read(src).map_err(|e| format!("cannot read {}: {e}", src.display()))?;
+    first_frame(&data).ok_or_else(|| format!("cannot extract first frame of {}", src.display()))
+}
+
+fn first_frame(data: &[u8]) -> Option<Vec<u8>> {
+    if data.starts_with(b"GIF8") {
+        let frames = gif_frames(data)?;
+        let first = frames.first()?;
+        // Header + screen descriptor + colour table, then the first frame
+        // (with its graphic control extension) and the trailer.
+        let mut out = data[..gif_body_start(data)?].to_vec();
+        out.extend_from_slice(&data[first.clone()]);
+        out.push(0x3B);
+        Some(out)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        let mut out = PNG_SIGNATURE.to_vec();
+        for c in png_chunks(data) {
+            if matches!(&c.kind, b"acTL" | b"fcTL" | b"fdAT") {
+                continue;
+            }
+            out.extend_from_slice(&data[c.start..c.end]);
+        }
+        Some(out)
+    } else if is_webp(data) {
+        webp_first_frame(data)
+    } else {
+        None
+    }
+}
+
+// --- GIF -------------------------------------------------------------------
+
+fn gif_body_start(data: &[u8]) -> Option<usize> {
+    let flags = *data.get(10)?;
+    let mut at = 13;
+    if flags & 0x80 != 0 {
+        at += 3 * (1usize << ((flags & 0x07) + 1));
+    }
+    (at <= data.len()).then_some(at)
+}
+
+/// Skips a chain of GIF sub-blocks, returning the offset after the terminator.
+fn skip_sub_blocks(data: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(at)? as usize;
+        at += 1;
+        if len == 0 {
+            return Some(at);
+        }
+        at += len;
+    }
+}
+
+/// Byte ranges of each frame, each starting at its graphic control extension
+/// (when present) and ending after its image data.
+fn gif_frames(data: &[u8]) -> Option<Vec<std::ops::Range<usize>>> {
+    let mut at = gif_body_start(data)?;
+    let mut frames = Vec::new();
+    let mut frame_start = None;
+    loop {
+        match *data.get(at)? {
+            0x21 => {
+                let label = *data.get(at + 1)?;
+                if label == 0xF9 && frame_start.is_none() {
+                    frame_start = Some(at);
+                }
+                at = skip_sub_blocks(data, at + 2)?;
+            }
+            0x2C => {
+                let start = frame_start.take().unwrap_or(at);
+                let flags = *data.get(at + 9)?;
+                at += 10;
+                if flags & 0x80 != 0 {
+                    at += 3 * (1usize << ((flags & 0x07) + 1));
+                }
+                // LZW minimum code size, then the data sub-blocks.
+                at = skip_sub_blocks(data, at + 1)?;
+                frames.push(start..at);
+            }
+            0x3B => return Some(frames),
+            _ => return None,
+        }
+    }
+}
+
+// --- PNG -------------------------------------------------------------------
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+struct Chunk {
+    kind: [u8; 4],
+    /// Whole chunk including its length/type header and any trailer.
+    start: usize,
+    end: usize,
+}
+
+fn png_chunks(data: &[u8]) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    let mut at = PNG_SIGNATURE.len();
+    while let Some(head) = data.get(at..at + 8) {
+        let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as usize;
+        let kind = [head[4], head[5], head[6], head[7]];
+        let end = at + 12 + len;
+        if end > data.len() {
+            break;
+        }
+        out.push(Chunk { kind, start: at, end });
+        at = end;
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    out
+}
+
+// --- WebP ------------------------------------------------------------------
+
+fn is_webp(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP"
+}
+
+fn riff_chunks_in(data: &[u8], mut at: usize, limit: usize) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    while let Some(head) = data.get(at..at + 8) {
+        let kind = [head[0], head[1], head[2], head[3]];
+        let len = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as usize;
+        // Chunks are padded to an even size.
+        let end = at + 8 + len + (len & 1);
+        if at + 8 + len > limit.min(data.len()) {
+            break;
+        }
+        out.push(Chunk {
+            kind,
+            start: at,
+            end: end.min(limit),
+        });
+        at = end;
+    }
+    out
+}
+
+fn riff_chunks(data: &[u8]) -> Vec<Chunk> {
+    riff_chunks_in(data, 12, data.len())
+}
+
+fn webp_first_frame(data: &[u8]) -> Option<Vec<u8>> {
+    let chunks = riff_chunks(data);
+    let anmf = chunks.iter().find(|c| &c.kind == b"ANMF")?;
+    // ANMF payload: 16 bytes of frame geometry/timing, then the frame's own
+    // (ALPH +) VP8 or VP8L chunks.
+    let geometry = data.get(anmf.start + 8..anmf.start + 24)?;
+    let width_minus_one = u32::from_le_bytes([geometry[6], geometry[7], geometry[8], 0]);
+    let height_minus_one = u32::from_le_bytes([geometry[9], geometry[10], geometry[11], 0]);
+    let inner = riff_chunks_in(data, anmf.start + 24, anmf.end);
+    let has_alpha = inner.iter().any(|c| &c.kind == b"ALPH");
+
+    let mut body = b"WEBP".to_vec();
+    if has_alpha {
+        body.extend_from_slice(b"VP8X");
+        body.extend_from_slice(&10u32.to_le_bytes());
+        body.extend_from_slice(&[0x10, 0, 0, 0]);
+        body.extend_from_slice(&width_minus_one.to_le_bytes()[..3]);
+        body.extend_from_slice(&height_minus_one.to_le_bytes()[..3]);
+    }
+    let mut image = false;
+    for c in &inner {
+        if matches!(&c.kind, b"ALPH" | b"VP8 " | b"VP8L") {
+            image |= &c.kind != b"ALPH";
+            body.extend_from_slice(&data[c.start..c.end]);
+        }
+    }
+    if !image {
+        return None;
+    }
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 GIF with `frames` frames, each with a graphic control extension.
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut g = b"GIF89a".to_vec();
+        g.extend_from_slice(&[1, 0, 1, 0, 0x80, 0, 0]); // 2-colour global table
+        g.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        // NETSCAPE looping extension.
+        g.extend_from_slice(&[0x21, 0xFF, 11]);
+        g.extend_from_slice(b"NETSCAPE2.0");
+        g.extend_from_slice(&[3, 1, 0, 0, 0]);
+        for k in 0..frames {
+            g.extend_from_slice(&[0x21, 0xF9, 4, 0, 10, 0, 0, 0]);
+            g.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            g.extend_from_slice(&[2, 2, 0x44, k as u8, 0]);
+        }
+        g.push(0x3B);
+        g
+    }
+
+    #[test]
+    fn detects_animated_gif() {
+        assert!(animated_bytes(&gif(3)));
+        assert!(!animated_bytes(&gif(1)));
+        assert!(!animated_bytes(b"\xFF\xD8\xFF\xE0"));
+    }
+
+    #[test]
+    fn gif_first_frame_is_a_still() {
+        let still = first_frame(&gif(3)).unwrap();
+        assert!(!animated_bytes(&still));
+        assert_eq!(gif_frames(&still).unwrap().len(), 1);
+        assert!(!still.windows(8).any(|w| w == b"NETSCAPE"));
+    }
+
+    #[test]
+    fn apng_first_frame_drops_animation_chunks() {
+        let chunk = |kind: &[u8; 4], body: &[u8]| {
+            let mut c = (body.len() as u32).to_be_bytes().to_vec();
+            c.extend_from_slice(kind);
+            c.extend_from_slice(body);
+            c.extend_from_slice(&[0, 0, 0, 0]); // CRC is not checked here
+            c
+        };
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &[0; 13]));
+        png.extend(chunk(b"acTL", &[0; 8]));
+        png.extend(chunk(b"fcTL", &[0; 26]));
+        png.extend(chunk(b"IDAT", &[1, 2, 3]));
+        png.extend(chunk(b"fcTL", &[0; 26]));
+        png.extend(chunk(b"fdAT", &[0; 7]));
+        png.extend(chunk(b"IEND", &[]));
+        assert!(animated_bytes(&png));
+
+        let still = first_frame(&png).unwrap();
+        assert!(!animated_bytes(&still));
+        let kinds: Vec<[u8; 4]> = png_chunks(&still).iter().map(|c| c.kind).collect();
+        assert_eq!(kinds, vec![*b"IHDR", *b"IDAT", *b"IEND"]);
+    }
+}
@@ -0,0 +1,134 @@
+// Destination throughput measurement (`bench`).
+//
+// Writes synthetic files into a scratch folder on the destination, reads them
+// back and deletes them. Writes are synced to the device so the numbers are
+// the device's, not the page cache's; reads right after writing may still be
+// served from the cache, which is why estimates only use the write figures.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Size of each file in the small-file test.
+pub const SMALL_FILE_BYTES: usize = 64 * 1024;
+/// Number of files in the small-file test.
+pub const SMALL_FILES: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    /// Bytes per second writing (and syncing) one large file.
+    pub sequential_write: f64,
+    pub sequential_read: f64,
+    /// Files per second writing (and syncing) `SMALL_FILES` small ones.
+    pub small_writes: f64,
+    pub small_reads: f64,
+}
+
+impl Throughput {
+    /// Seconds spent per file beyond moving its bytes: creating, naming and
+    /// syncing it.
+    fn per_file_overhead(&self) -> f64 {
+        (1.0 / self.small_writes - SMALL_FILE_BYTES as f64 / self.sequential_write).max(0.0)
+    }
+
+    /// How long copying `files` files of `bytes` bytes in total should take.
+    pub fn estimate(&self, files: usize, bytes: u64) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / self.sequential_write + files as f64 * self.per_file_overhead())
+    }
+}
+
+/// Runs both tests in a scratch folder inside `dir` (removed afterwards); the
+/// sequential test writes `size` bytes.
+pub fn measure(dir: &Path, size: u64) -> Result<Throughput, String> {
+    let scratch = dir.join(format!(".image-rando-bench-{}", std::process::id()));
+    fs::create_dir_all(&scratch).map_err(|e| format!("cannot create {}: {e}", scratch.display()))?;
+    let result = run_tests(&scratch, size);
+    if let Err(e) = fs::remove_dir_all(&scratch) {
+        eprintln!("warning: cannot remove {}: {e}", scratch.display());
+    }
+    result
+}
+
+fn run_tests(scratch: &Path, size: u64) -> Result<Throughput, String> {
+    let chunk = noise(1024 * 1024);
+
+    let big = scratch.join("sequential.bin");
+    let start = Instant::now();
+    let mut left = size;
+    let mut file = File::create(&big).map_err(|e| format!("cannot create {}: {e}", big.display()))?;
+    while left > 0 {
+        let n = left.min(chunk.len() as u64) as usize;
+        file.write_all(&chunk[..n]).map_err(|e| format!("cannot write {}: {e}", big.display()))?;
+        left -= n as u64;
+    }
+    file.sync_all().map_err(|e| format!("cannot sync {}: {e}", big.display()))?;
+    drop(file);
+    let sequential_write = size as f64 / seconds(start);
+
+    let start = Instant::now();
+    read_all(&big)?;
+    let sequential_read = size as f64 / seconds(start);
+
+    let start = Instant::now();
+    for k in 0..SMALL_FILES {
+        let path = scratch.join(format!("small-{k}.bin"));
+        let mut file = File::create(&path).map_err(|e| format!("cannot create {}: {e}", path.display()))?;
+        file.write_all(&chunk[..SMALL_FILE_BYTES])
+            .and_then(|()| file.sync_all())
+            .map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+    }
+    let small_writes = SMALL_FILES as f64 / seconds(start);
+
+    let start = Instant::now();
+    for k in 0..SMALL_FILES {
+        read_all(&scratch.join(format!("small-{k}.bin")))?;
+    }
+    let small_reads = SMALL_FILES as f64 / seconds(start);
+
+    Ok(Throughput { sequential_write, sequential_read, small_writes, small_reads })
+}
+
+fn read_all(path: &Path) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+    let mut buf = vec![0u8; 1024 * 1024];
+    while file.read(&mut buf).map_err(|e| format!("cannot read {}: {e}", path.display()))? > 0 {}
+    Ok(())
+}
+
+/// Elapsed time, never zero so rates stay finite.
+fn seconds(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64().max(1e-6)
+}
+
+/// Incompressible bytes, so compressing or deduplicating filesystems can't
+/// flatter the result.
+fn noise(len: usize) -> Vec<u8> {
+    let mut x: u64 = 0x9E37_79B9_7F4A_7C15;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            (x >> 24) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_adds_per_file_overhead_to_transfer_time() {
+        // 10 MB/s and 100 files/s: 64 KiB moves in ~6.5 ms, leaving ~3.5 ms
+        // of overhead per file.
+        let t = Throughput { sequential_write: 10e6, sequential_read: 0.0, small_writes: 100.0, small_reads: 0.0 };
+        let secs = t.estimate(1000, 100_000_000).as_secs_f64();
+        let expected = 10.0 + 1000.0 * (0.01 - SMALL_FILE_BYTES as f64 / 10e6);
+        assert!((secs - expected).abs() < 1e-6, "{secs}");
+        // Small files faster than the sequential rate implies: no overhead.
+        let fast = Throughput { small_writes: 1e6, ..t };
+        assert!((fast.estimate(1000, 10_000_000).as_secs_f64() - 1.0).abs() < 1e-6);
+    }
+}
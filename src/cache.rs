@@ -0,0 +1,217 @@
+// The EXIF cache: the fields `load_exif` extracts, kept between runs in one
+// JSON file per user so weekly runs over a large library don't parse every
+// JPEG header again.
+//
+// Entries are keyed by absolute path and only used while the file's size and
+// modification time are unchanged. Entries for files that are gone are
+// dropped when the cache is saved.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::exif::{self, DateTime, Exif};
+use crate::json::Value;
+
+/// File name inside the per-user cache folder.
+pub const FILE_NAME: &str = "exif-cache.json";
+
+/// Caches written by older versions lack fields and are rebuilt.
+const VERSION: u64 = 2;
+
+struct Cached {
+    size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    modified: u64,
+    exif: Exif,
+    /// Looked up by this run, so known to still exist.
+    seen: bool,
+}
+
+pub struct Cache {
+    path: PathBuf,
+    entries: HashMap<String, Cached>,
+    changed: bool,
+}
+
+impl Cache {
+    /// The cache at `path`; empty when there is none yet, or (with a warning)
+    /// when it can't be read.
+    pub fn open(path: &Path) -> Cache {
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => Value::parse(&text).and_then(|v| entries_from_json(&v)).unwrap_or_else(|e| {
+                eprintln!("warning: ignoring EXIF cache {}: {e}", path.display());
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Cache { path: path.to_path_buf(), entries, changed: false }
+    }
+
+    /// `exif::read_exif`, answered from the cache when the file hasn't
+    /// changed since it was stored.
+    pub fn read_exif(&mut self, path: &Path) -> Result<Exif, String> {
+        let meta = fs::metadata(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as u64);
+        let key = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().into_owned();
+        if let Some(c) = self.entries.get_mut(&key) {
+            if c.size == meta.len() && c.modified == modified {
+                c.seen = true;
+                return Ok(c.exif.clone());
+            }
+        }
+        let exif = exif::read_exif(path)?;
+        self.entries.insert(key, Cached { size: meta.len(), modified, exif: exif.clone(), seen: true });
+        self.changed = true;
+        Ok(exif)
+    }
+
+    /// Writes the cache back if this run added to it.
+    pub fn save(mut self) -> Result<(), String> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.entries.retain(|path, c| c.seen || Path::new(path).exists());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+        }
+        // Written aside and renamed, so an interrupted run can't leave half a
+        // cache behind.
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, self.to_json().to_pretty())
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .map_err(|e| format!("cannot write EXIF cache {}: {e}", self.path.display()))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut paths: Vec<&String> = self.entries.keys().collect();
+        paths.sort();
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let c = &self.entries[path];
+                let mut fields = vec![
+                    ("path".to_string(), Value::from(path.as_str())),
+                    ("size".to_string(), Value::from(c.size)),
+                    ("modified".to_string(), Value::from(c.modified)),
+                ];
+                push_exif(&mut fields, &c.exif);
+                Value::Object(fields)
+            })
+            .collect();
+        Value::Object(vec![
+            ("version".to_string(), Value::from(VERSION)),
+            ("files".to_string(), Value::Array(files)),
+        ])
+    }
+}
+
+fn entries_from_json(v: &Value) -> Result<HashMap<String, Cached>, String> {
+    let mut entries = HashMap::new();
+    if v.get("version").and_then(Value::as_u64) != Some(VERSION) {
+        return Ok(entries);
+    }
+    for item in v.get("files").and_then(Value::as_array).ok_or("files is not a list")? {
+        let number = |key| item.get(key).and_then(Value::as_u64);
+        let text = |key| item.get(key).and_then(Value::as_str).map(str::to_string);
+        let (Some(path), Some(size), Some(modified)) = (text("path"), number("size"), number("modified")) else {
+            return Err("entry without path, size or modified".to_string());
+        };
+        entries.insert(path, Cached { size, modified, exif: exif_from_json(item), seen: false });
+    }
+    Ok(entries)
+}
+
+/// Adds the fields of `e` that are set to a JSON record (also used by the
+/// `index` catalog).
+pub fn push_exif(fields: &mut Vec<(String, Value)>, e: &Exif) {
+    if let Some(text) = &e.captured_text {
+        fields.push(("captured".to_string(), Value::from(text.as_str())));
+    }
+    if let Some(offset) = e.offset {
+        fields.push(("offset".to_string(), Value::from(i64::from(offset))));
+    }
+    if let Some((w, h)) = e.dimensions {
+        fields.push(("width".to_string(), Value::from(u64::from(w))));
+        fields.push(("height".to_string(), Value::from(u64::from(h))));
+    }
+    if let Some(o) = e.orientation {
+        fields.push(("orientation".to_string(), Value::from(u64::from(o))));
+    }
+    if let Some((lat, lon)) = e.gps {
+        fields.push(("lat".to_string(), Value::from(lat)));
+        fields.push(("lon".to_string(), Value::from(lon)));
+    }
+    if let Some(camera) = &e.camera {
+        fields.push(("camera".to_string(), Value::from(camera.as_str())));
+    }
+    if let Some(rating) = e.rating {
+        fields.push(("rating".to_string(), Value::from(u64::from(rating))));
+    }
+}
+
+/// The inverse of `push_exif`; missing fields stay unset.
+pub fn exif_from_json(item: &Value) -> Exif {
+    let number = |key| item.get(key).and_then(Value::as_u64);
+    let captured_text = item.get("captured").and_then(Value::as_str).map(str::to_string);
+    Exif {
+        gps: item.get("lat").and_then(Value::as_f64).zip(item.get("lon").and_then(Value::as_f64)),
+        captured: captured_text.as_deref().and_then(DateTime::parse),
+        captured_text,
+        offset: item.get("offset").and_then(Value::as_i64).map(|o| o as i16),
+        orientation: number("orientation").map(|o| o as u16),
+        dimensions: number("width").zip(number("height")).map(|(w, h)| (w as u32, h as u32)),
+        camera: item.get("camera").and_then(Value::as_str).map(str::to_string),
+        rating: number("rating").map(|r| r as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn reuses_entries_until_the_file_changes() {
+        let root = env::temp_dir().join(format!("image-rando-exif-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let photo = root.join("a.jpg");
+        fs::write(&photo, b"not really a jpeg").unwrap();
+        let cache_file = root.join("cache").join(FILE_NAME);
+
+        let mut cache = Cache::open(&cache_file);
+        assert_eq!(cache.read_exif(&photo).unwrap(), Exif::default());
+        // Pretend the header held more, to see that it comes back unparsed.
+        let key = std::path::absolute(&photo).unwrap().to_string_lossy().into_owned();
+        let stored = Exif {
+            gps: Some((47.6062, -122.3321)),
+            captured: DateTime::parse("2019:07:04 09:05:30"),
+            captured_text: Some("2019:07:04 09:05:30".to_string()),
+            offset: Some(-420),
+            orientation: Some(6),
+            dimensions: Some((4032, 3024)),
+            camera: Some("Apple iPhone 12".to_string()),
+            rating: Some(4),
+        };
+        cache.entries.get_mut(&key).unwrap().exif = stored.clone();
+        fs::write(root.join("gone.jpg"), b"x").unwrap();
+        cache.read_exif(&root.join("gone.jpg")).unwrap();
+        cache.save().unwrap();
+
+        fs::remove_file(root.join("gone.jpg")).unwrap();
+        let mut cache = Cache::open(&cache_file);
+        assert_eq!(cache.read_exif(&photo).unwrap(), stored);
+        assert!(!cache.changed);
+        fs::write(&photo, b"a different, longer file").unwrap();
+        assert_eq!(cache.read_exif(&photo).unwrap(), Exif::default());
+        cache.save().unwrap();
+        assert_eq!(Cache::open(&cache_file).entries.len(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
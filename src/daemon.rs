@@ -0,0 +1,344 @@
+// `daemon`: runs the copy on a schedule and takes commands on a local Unix
+// socket, so scripts can control it without an HTTP port.
+//
+// A client connects, writes one command line and reads the reply, a line
+// starting with `ok` or `error`:
+//
+//   run-now   start a run now, or right after the one in progress
+//   status    idle, running or paused; the last run and the next one
+//   pause     skip scheduled runs until `resume` (`run-now` still works)
+//   resume
+//   reload    read the options file again
+//   events    keep the connection open and get a line per event
+//
+// Events are `run started`, `run finished in 12s`, `run failed: ...`,
+// `paused`, `resumed` and `reloaded`.
+
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::exif::DateTime;
+use crate::format_duration;
+
+pub struct Config {
+    pub socket: PathBuf,
+    /// Time between the start of one scheduled run and the next.
+    pub every: Duration,
+    /// Run options from the command line, program name first.
+    pub args: Vec<String>,
+    /// More run options, read at start and on `reload`.
+    pub options_file: Option<PathBuf>,
+}
+
+struct State {
+    /// The run's whole command line.
+    options: Vec<String>,
+    paused: bool,
+    running: bool,
+    run_requested: bool,
+    next_run: Instant,
+    /// How the last run went and when it ended.
+    last: Option<String>,
+}
+
+/// How long an event may wait for a subscriber that stopped reading before
+/// that subscriber is dropped.
+const EVENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    /// `events` connections. Kept apart from `state`, so an event is written
+    /// without holding up the scheduler or other commands.
+    subscribers: Mutex<Vec<UnixStream>>,
+}
+
+impl Shared {
+    fn new(options: Vec<String>) -> Shared {
+        Shared {
+            state: Mutex::new(State {
+                options,
+                paused: false,
+                running: false,
+                run_requested: false,
+                next_run: Instant::now(),
+                last: None,
+            }),
+            wake: Condvar::new(),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends `event` to every subscriber, dropping the ones that hung up or
+    /// stopped reading, and logs it. Call it with `state` unlocked.
+    fn emit(&self, event: &str) {
+        println!("daemon: {event}");
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain_mut(|s| writeln!(s, "{event}").is_ok());
+    }
+}
+
+/// Listens on `config.socket` and runs `run` with the options on schedule
+/// and on request; returns only if the socket fails. `check` vets the
+/// options at start and on `reload`.
+pub fn serve(
+    config: Config,
+    check: impl Fn(&[String]) -> Result<(), String>,
+    run: impl Fn(&[String]) -> Result<(), String> + Sync,
+) -> Result<(), String> {
+    let options = load_options(&config)?;
+    check(&options)?;
+    let listener = bind(&config.socket)?;
+    println!(
+        "daemon: listening on {}, running every {}",
+        config.socket.display(),
+        format_duration(config.every)
+    );
+    let shared = Shared::new(options);
+    thread::scope(|scope| {
+        let (shared, config, check, run) = (&shared, &config, &check, &run);
+        scope.spawn(move || schedule(shared, config.every, run));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // Commands are short; handling them here keeps `check` off
+                    // other threads.
+                    if let Err(e) = handle(stream, shared, config, check) {
+                        eprintln!("warning: daemon client: {e}");
+                    }
+                }
+                Err(e) => return Err(format!("cannot accept on {}: {e}", config.socket.display())),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Binds the socket, replacing one left behind by a daemon that is gone, and
+/// makes it private to this user.
+fn bind(path: &Path) -> Result<UnixListener, String> {
+    if fs::symlink_metadata(path).is_ok() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("another daemon is already listening on {}", path.display()));
+        }
+        fs::remove_file(path).map_err(|e| format!("cannot remove stale socket {}: {e}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("cannot listen on {}: {e}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("cannot restrict {}: {e}", path.display()))?;
+    Ok(listener)
+}
+
+fn schedule(shared: &Shared, every: Duration, run: &(impl Fn(&[String]) -> Result<(), String> + Sync)) {
+    loop {
+        let options = {
+            let mut state = shared.lock();
+            loop {
+                let now = Instant::now();
+                if state.run_requested || (!state.paused && now >= state.next_run) {
+                    break;
+                }
+                state = if state.paused {
+                    shared.wake.wait(state).unwrap_or_else(|e| e.into_inner())
+                } else {
+                    let wait = state.next_run - now;
+                    shared.wake.wait_timeout(state, wait).unwrap_or_else(|e| e.into_inner()).0
+                };
+            }
+            state.run_requested = false;
+            state.running = true;
+            state.options.clone()
+        };
+        shared.emit("run started");
+        let start = Instant::now();
+        let result = run(&options);
+        let mut state = shared.lock();
+        state.running = false;
+        state.next_run = start + every;
+        let event = match result {
+            Ok(()) => format!("run finished in {}", format_duration(start.elapsed())),
+            Err(e) => format!("run failed: {}", e.lines().next().unwrap_or_default()),
+        };
+        state.last = Some(format!("{event} (at {})", utc_now()));
+        drop(state);
+        shared.emit(&event);
+    }
+}
+
+fn handle(
+    stream: UnixStream,
+    shared: &Shared,
+    config: &Config,
+    check: &impl Fn(&[String]) -> Result<(), String>,
+) -> Result<(), String> {
+    // A client that connects and says nothing mustn't block the others.
+    stream.set_read_timeout(Some(Duration::from_secs(5))).map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut state = shared.lock();
+    let mut event = None;
+    let reply = match line.trim() {
+        "run-now" => {
+            state.run_requested = true;
+            shared.wake.notify_all();
+            if state.running { "ok will run again after the current run".to_string() } else { "ok".to_string() }
+        }
+        "status" => status(&state),
+        "pause" => {
+            state.paused = true;
+            event = Some("paused");
+            "ok".to_string()
+        }
+        "resume" => {
+            state.paused = false;
+            shared.wake.notify_all();
+            event = Some("resumed");
+            "ok".to_string()
+        }
+        "reload" => match load_options(config).and_then(|options| check(&options).map(|()| options)) {
+            Ok(options) => {
+                state.options = options;
+                event = Some("reloaded");
+                "ok".to_string()
+            }
+            Err(e) => format!("error {}", e.replace('\n', " ")),
+        },
+        "events" => {
+            let mut stream = stream;
+            if writeln!(stream, "ok").is_ok() {
+                stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+                stream.set_write_timeout(Some(EVENT_WRITE_TIMEOUT)).map_err(|e| e.to_string())?;
+                shared.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(stream);
+            }
+            return Ok(());
+        }
+        other => format!("error unknown command: {other} (expected run-now, status, pause, resume, reload or events)"),
+    };
+    drop(state);
+    if let Some(event) = event {
+        shared.emit(event);
+    }
+    let mut stream = stream;
+    match writeln!(stream, "{reply}") {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+fn status(state: &State) -> String {
+    let now = if state.running {
+        "running"
+    } else if state.paused {
+        "paused"
+    } else {
+        "idle"
+    };
+    let next = if state.paused {
+        "no run scheduled".to_string()
+    } else {
+        format!("next run in {}", format_duration(state.next_run.saturating_duration_since(Instant::now())))
+    };
+    format!(
+        "ok {now}; last: {}; {next}",
+        state.last.as_deref().unwrap_or("none yet")
+    )
+}
+
+/// The command-line options followed by those in the options file: one per
+/// line, with its value (if any) after the first space. Blank lines and lines
+/// starting with `#` are skipped.
+fn load_options(config: &Config) -> Result<Vec<String>, String> {
+    let mut options = config.args.clone();
+    if let Some(path) = &config.options_file {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        options.extend(parse_options(&text));
+    }
+    Ok(options)
+}
+
+fn parse_options(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        match line.split_once(char::is_whitespace) {
+            Some((flag, value)) => {
+                out.push(flag.to_string());
+                out.push(value.trim().to_string());
+            }
+            None => out.push(line.to_string()),
+        }
+    }
+    out
+}
+
+fn utc_now() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let t = DateTime::from_unix(now);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", t.year, t.month, t.day, t.hour, t.minute, t.second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_reach_subscribers_and_a_stalled_one_is_dropped() {
+        let socket = std::env::temp_dir().join(format!("image-rando-daemon-{}.sock", std::process::id()));
+        let listener = bind(&socket).unwrap();
+        let shared = Shared::new(Vec::new());
+        let config = Config { socket: socket.clone(), every: Duration::from_secs(3600), args: Vec::new(), options_file: None };
+        let check = |_: &[String]| Ok(());
+        thread::scope(|scope| {
+            let (listener, shared, config) = (&listener, &shared, &config);
+            scope.spawn(move || {
+                // The two subscribers and `pause`.
+                for stream in listener.incoming().take(3) {
+                    handle(stream.unwrap(), shared, config, &check).unwrap();
+                }
+            });
+            let command = |line: &str| {
+                let mut client = UnixStream::connect(&socket).unwrap();
+                writeln!(client, "{line}").unwrap();
+                let mut reader = BufReader::new(client);
+                let mut reply = String::new();
+                reader.read_line(&mut reply).unwrap();
+                assert_eq!(reply, "ok\n");
+                reader
+            };
+            let mut listening = command("events");
+            let stalled = command("events");
+            command("pause");
+            let mut event = String::new();
+            listening.read_line(&mut event).unwrap();
+            assert_eq!(event, "paused\n");
+            assert!(shared.lock().paused);
+
+            // Nobody reads `stalled`: once its buffer is full, events give
+            // up on it instead of blocking.
+            let started = Instant::now();
+            let big = "x".repeat(64 * 1024);
+            while shared.subscribers.lock().unwrap().len() == 2 {
+                assert!(started.elapsed() < Duration::from_secs(30), "stalled subscriber never dropped");
+                shared.emit(&big);
+                listening.read_line(&mut event).unwrap();
+            }
+            drop(stalled);
+        });
+        fs::remove_file(&socket).unwrap();
+    }
+
+    #[test]
+    fn options_file_gives_each_line_one_option_and_value() {
+        let text = "# weekly load\n--src /home/me/My Photos\n-r\n\n  --max-files 500  \n";
+        assert_eq!(parse_options(text), vec!["--src", "/home/me/My Photos", "-r", "--max-files", "500"]);
+    }
+}
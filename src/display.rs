@@ -0,0 +1,110 @@
+// `display`: a Linux framebuffer (`/dev/fb0`) to show photos on.
+//
+// The geometry comes from sysfs rather than the FBIOGET_*SCREENINFO ioctls,
+// which would need libc bindings. On a Raspberry Pi the KMS driver keeps a
+// framebuffer device for consoles, so this also works without X or Wayland.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub struct Framebuffer {
+    file: File,
+    pub width: u32,
+    pub height: u32,
+    /// Bytes from the start of one line to the next.
+    stride: usize,
+    bits_per_pixel: u32,
+}
+
+impl Framebuffer {
+    pub fn open(device: &Path) -> Result<Framebuffer, String> {
+        let name = device.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let sys = Path::new("/sys/class/graphics").join(&name);
+        let read = |attr: &str| {
+            fs::read_to_string(sys.join(attr))
+                .map(|v| v.trim().to_string())
+                .map_err(|e| format!("cannot read {}: {e}", sys.join(attr).display()))
+        };
+        let size = read("virtual_size")?;
+        let bad = || format!("unexpected framebuffer geometry for {}", device.display());
+        let (width, height) = parse_size(&size).ok_or_else(bad)?;
+        if width == 0 || height == 0 {
+            return Err(format!("{} reports a {width}x{height} screen", device.display()));
+        }
+        let bits_per_pixel = read("bits_per_pixel")?.parse().map_err(|_| bad())?;
+        if !matches!(bits_per_pixel, 16 | 24 | 32) {
+            return Err(format!("{} uses {bits_per_pixel} bits per pixel; only 16, 24 and 32 are supported", device.display()));
+        }
+        let stride = match read("stride") {
+            Ok(v) => v.parse().map_err(|_| bad())?,
+            Err(_) => width as usize * bits_per_pixel as usize / 8,
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .open(device)
+            .map_err(|e| format!("cannot open {}: {e}", device.display()))?;
+        Ok(Framebuffer { file, width, height, stride, bits_per_pixel })
+    }
+
+    /// Puts `rgb` (`width` x `height` RGB pixels) on the screen.
+    pub fn show(&mut self, rgb: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0u8; self.stride * self.height as usize];
+        let row = self.width as usize * 3;
+        for (line, pixels) in frame.chunks_mut(self.stride).zip(rgb.chunks(row)) {
+            pack(pixels, self.bits_per_pixel, line);
+        }
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.write_all(&frame))
+            .map_err(|e| format!("cannot write to the framebuffer: {e}"))
+    }
+}
+
+/// The `width,height` pair sysfs gives as `virtual_size`.
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (w, h) = size.split_once(',')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// RGB pixels in the framebuffer's little-endian layout: XRGB8888, RGB888
+/// (stored B, G, R) or RGB565.
+fn pack(rgb: &[u8], bits_per_pixel: u32, out: &mut [u8]) {
+    let bytes = bits_per_pixel as usize / 8;
+    for (px, dst) in rgb.chunks_exact(3).zip(out.chunks_exact_mut(bytes)) {
+        let [r, g, b] = [px[0], px[1], px[2]];
+        match bits_per_pixel {
+            16 => {
+                let v = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+                dst.copy_from_slice(&v.to_le_bytes());
+            }
+            _ => {
+                dst[..3].copy_from_slice(&[b, g, r]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_virtual_size() {
+        assert_eq!(parse_size("1920,1080"), Some((1920, 1080)));
+        assert_eq!(parse_size("0,0"), Some((0, 0)));
+        assert_eq!(parse_size("1920x1080"), None);
+        assert_eq!(parse_size("1920,"), None);
+    }
+
+    #[test]
+    fn packs_the_common_pixel_formats() {
+        let rgb = [0xFF, 0x80, 0x00, 0x00, 0x00, 0xFF];
+        let mut out = [0u8; 8];
+        pack(&rgb, 32, &mut out);
+        assert_eq!(out, [0x00, 0x80, 0xFF, 0, 0xFF, 0x00, 0x00, 0]);
+        let mut out = [0u8; 4];
+        pack(&rgb, 16, &mut out);
+        assert_eq!(out, [0x00, 0xFC, 0x1F, 0x00]);
+    }
+}
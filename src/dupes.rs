@@ -0,0 +1,107 @@
+// Duplicate detection (`analyze --dupes`).
+//
+// Identical files are found by size first and a content hash second, so only
+// files that share a size are ever read. Near-duplicates are clusters of
+// perceptual hashes (see `imaging::dhash`) that differ in only a few bits;
+// this module only does the clustering and doesn't decode anything.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// 64-bit FNV-1a of the whole file. Collisions between files of the same size
+/// are astronomically unlikely, so equal hashes are treated as equal content.
+pub fn content_hash(path: &Path) -> Result<u64, String> {
+    let mut file = File::open(path).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+    let mut hash = FNV_OFFSET;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+}
+
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+pub fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Indices of `hashes` grouped into clusters whose members are each within
+/// `max_distance` differing bits of some other member. Singletons are left
+/// out. Compares every pair, which is fine for tens of thousands of photos.
+pub fn similar_clusters(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    for a in 0..hashes.len() {
+        for b in a + 1..hashes.len() {
+            if (hashes[a] ^ hashes[b]).count_ones() <= max_distance {
+                union(&mut parent, a, b);
+            }
+        }
+    }
+    clusters(&mut parent)
+}
+
+/// Groups `0..n` by the key each index maps to; singletons are left out.
+pub fn equal_clusters<K: Eq + std::hash::Hash>(keys: &[K]) -> Vec<Vec<usize>> {
+    let mut first = std::collections::HashMap::new();
+    let mut parent: Vec<usize> = (0..keys.len()).collect();
+    for (idx, key) in keys.iter().enumerate() {
+        let root = *first.entry(key).or_insert(idx);
+        union(&mut parent, root, idx);
+    }
+    clusters(&mut parent)
+}
+
+fn find(parent: &mut [usize], mut x: usize) -> usize {
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (ra, rb) = (find(parent, a), find(parent, b));
+    if ra != rb {
+        // Keep the smallest index as the root so clusters list in input order.
+        parent[ra.max(rb)] = ra.min(rb);
+    }
+}
+
+fn clusters(parent: &mut [usize]) -> Vec<Vec<usize>> {
+    let mut by_root: Vec<Vec<usize>> = vec![Vec::new(); parent.len()];
+    for idx in 0..parent.len() {
+        let root = find(parent, idx);
+        by_root[root].push(idx);
+    }
+    by_root.into_iter().filter(|c| c.len() > 1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(fnv1a(FNV_OFFSET, b"foo"), b"bar"), fnv1a(FNV_OFFSET, b"foobar"));
+    }
+
+    #[test]
+    fn clusters_are_transitive_and_skip_singletons() {
+        // 0 and 1 differ by 2 bits, 1 and 2 by 2 bits, 0 and 2 by 4.
+        let hashes = [0b0000, 0b0011, 0b1111, u64::MAX];
+        assert_eq!(similar_clusters(&hashes, 2), vec![vec![0, 1, 2]]);
+        assert_eq!(similar_clusters(&hashes, 1), Vec::<Vec<usize>>::new());
+        assert_eq!(equal_clusters(&["a", "b", "a", "c", "b"]), vec![vec![0, 2], vec![1, 4]]);
+    }
+}
@@ -0,0 +1,815 @@
+// Minimal, dependency-free EXIF reader.
+//
+// Only the handful of tags the planner cares about are decoded; everything
+// else in the TIFF structure is skipped. The one thing ever written back is
+// the EXIF block minus its embedded thumbnail (`--strip-thumbnails`).
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATETIME: u16 = 0x0132;
+const TAG_RATING: u16 = 0x4746;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATETIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME: u16 = 0x9010;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_GPS_LAT_REF: u16 = 0x0001;
+const TAG_GPS_LAT: u16 = 0x0002;
+const TAG_GPS_LON_REF: u16 = 0x0003;
+const TAG_GPS_LON: u16 = 0x0004;
+const TAG_INTEROP_IFD: u16 = 0xA005;
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exif {
+    /// Decimal degrees (latitude, longitude).
+    pub gps: Option<(f64, f64)>,
+    /// DateTimeOriginal, falling back to the IFD0 DateTime.
+    pub captured: Option<DateTime>,
+    /// The text `captured` was parsed from, kept even when it doesn't parse.
+    pub captured_text: Option<String>,
+    /// Minutes east of UTC of `captured`, from OffsetTimeOriginal (or
+    /// OffsetTime, with the IFD0 DateTime). `captured` is local time either
+    /// way.
+    pub offset: Option<i16>,
+    /// EXIF orientation (1..=8); 5..=8 mean the image is stored rotated 90°.
+    pub orientation: Option<u16>,
+    /// Stored (width, height) from the JPEG frame header.
+    pub dimensions: Option<(u32, u32)>,
+    /// Make and model, e.g. `Apple iPhone 12`.
+    pub camera: Option<String>,
+    /// Star rating (0..=5) as written by Windows and some cameras.
+    pub rating: Option<u8>,
+}
+
+impl Exif {
+    /// (width, height) as displayed, i.e. after applying the orientation.
+    pub fn display_dimensions(&self) -> Option<(u32, u32)> {
+        let (w, h) = self.dimensions?;
+        match self.orientation {
+            Some(5..=8) => Some((h, w)),
+            _ => Some((w, h)),
+        }
+    }
+}
+
+/// An EXIF timestamp as written by the camera (no timezone).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Parses the EXIF `YYYY:MM:DD HH:MM:SS` format.
+    pub fn parse(s: &str) -> Option<DateTime> {
+        let b = s.as_bytes();
+        if b.len() < 19 || b[4] != b':' || b[7] != b':' || b[10] != b' ' || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        let num = |from: usize, to: usize| s.get(from..to)?.parse::<u16>().ok();
+        let dt = DateTime {
+            year: num(0, 4)?,
+            month: num(5, 7)? as u8,
+            day: num(8, 10)? as u8,
+            hour: num(11, 13)? as u8,
+            minute: num(14, 16)? as u8,
+            second: num(17, 19)? as u8,
+        };
+        // "0000:00:00 00:00:00" is a common placeholder for "unset".
+        if dt.year == 0 || !(1..=12).contains(&dt.month) || !(1..=31).contains(&dt.day) {
+            return None;
+        }
+        Some(dt)
+    }
+
+    /// Seconds since the Unix epoch, reading the time as UTC.
+    pub fn to_unix(self) -> i64 {
+        // Howard Hinnant's days-from-civil algorithm.
+        let (month, day) = (i64::from(self.month), i64::from(self.day));
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400 + i64::from(self.hour) * 3_600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+
+    /// UTC calendar time for seconds since the Unix epoch.
+    pub fn from_unix(secs: u64) -> DateTime {
+        let days = (secs / 86_400) as i64;
+        let rem = secs % 86_400;
+        // Howard Hinnant's days-to-civil algorithm.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3_600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+}
+
+/// Parses a UTC offset as EXIF writes it (`+09:00`, `-05:30`), also taking
+/// `+0900`, `-5`, `Z` and `UTC`. Returns minutes east of UTC.
+pub fn parse_utc_offset(s: &str) -> Option<i16> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("z") || s.eq_ignore_ascii_case("utc") {
+        return Some(0);
+    }
+    let (sign, rest) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        // `+0900`; `get` keeps a multi-byte character from being split.
+        None if rest.len() == 4 => (rest.get(..2)?, rest.get(2..)?),
+        None => (rest, "0"),
+    };
+    if hours.is_empty() || !hours.bytes().chain(minutes.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = (hours.parse::<i16>().ok()?, minutes.parse::<i16>().ok()?);
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Reads the EXIF block of a JPEG. Files without (or with unparseable) EXIF
+/// yield `Exif::default()`; only I/O errors are reported.
+pub fn read_exif(path: &Path) -> Result<Exif, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+    let mut r = BufReader::new(file);
+    let header = scan_header(&mut r).map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    let mut exif = header
+        .tiff
+        .and_then(|tiff| parse_tiff(&tiff))
+        .unwrap_or_default();
+    exif.dimensions = header.dimensions;
+    Ok(exif)
+}
+
+/// Returns the XMP packet embedded in a JPEG's APP1 segment, if any.
+pub fn read_xmp_packet(path: &Path) -> Result<Option<String>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+    let header = scan_header(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    Ok(header
+        .xmp
+        .map(|raw| String::from_utf8_lossy(&raw).into_owned()))
+}
+
+/// How many bytes `strip_thumbnail` would save on the JPEG at `path`; 0 when
+/// it has no thumbnail or it can't be removed.
+pub fn thumbnail_bytes(path: &Path) -> Result<u64, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {e}", path.display()))?;
+    let header = scan_header(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    Ok(header
+        .tiff
+        .and_then(|tiff| thumbnail_cut(&tiff).map(|(cut, _)| (tiff.len() - cut) as u64))
+        .unwrap_or(0))
+}
+
+/// The JPEG in `data` without the thumbnail in its EXIF block, or `None` when
+/// there is nothing (safely) removable. Only the tail of the TIFF data holding
+/// IFD1 and the thumbnail goes; every other byte stays where it was, so no
+/// offsets need rewriting.
+pub fn strip_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        let marker = *data.get(pos + 1)?;
+        if data[pos] != 0xFF || marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let body = data.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && body.starts_with(b"Exif\0\0") {
+            let tiff = &body[6..];
+            let (cut, next_at) = thumbnail_cut(tiff)?;
+            let mut out = Vec::with_capacity(data.len() - (tiff.len() - cut));
+            out.extend_from_slice(&data[..pos + 2]);
+            out.extend_from_slice(&((cut + 8) as u16).to_be_bytes());
+            out.extend_from_slice(b"Exif\0\0");
+            let start = out.len();
+            out.extend_from_slice(&tiff[..cut]);
+            // IFD0 no longer links to IFD1.
+            out[start + next_at..start + next_at + 4].fill(0);
+            out.extend_from_slice(&data[pos + 2 + len..]);
+            return Some(out);
+        }
+        pos += 2 + len;
+    }
+}
+
+pub const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+#[derive(Debug, Default)]
+struct Header {
+    /// TIFF payload of the first `Exif\0\0` APP1 segment.
+    tiff: Option<Vec<u8>>,
+    /// Packet of the first XMP APP1 segment.
+    xmp: Option<Vec<u8>>,
+    /// (width, height) from the start-of-frame segment.
+    dimensions: Option<(u32, u32)>,
+}
+
+/// Walks the JPEG marker segments up to the start of scan.
+fn scan_header(r: &mut impl Read) -> std::io::Result<Header> {
+    let mut header = Header::default();
+    let mut soi = [0u8; 2];
+    if r.read_exact(&mut soi).is_err() || soi != [0xFF, 0xD8] {
+        return Ok(header);
+    }
+    loop {
+        let mut marker = [0u8; 2];
+        if r.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            return Ok(header);
+        }
+        // Standalone markers carry no length.
+        if marker[1] == 0x01 || (0xD0..=0xD7).contains(&marker[1]) {
+            continue;
+        }
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            return Ok(header);
+        }
+        let mut len = [0u8; 2];
+        if !read_unless_cut_short(r, &mut len)? {
+            return Ok(header);
+        }
+        let len = u16::from_be_bytes(len) as usize;
+        if len < 2 {
+            return Ok(header);
+        }
+        let mut body = vec![0u8; len - 2];
+        if !read_unless_cut_short(r, &mut body)? {
+            return Ok(header);
+        }
+        if marker[1] == 0xE1 && header.tiff.is_none() && body.starts_with(b"Exif\0\0") {
+            header.tiff = Some(body.split_off(6));
+        } else if marker[1] == 0xE1 && header.xmp.is_none() && body.starts_with(XMP_SIGNATURE) {
+            header.xmp = Some(body.split_off(XMP_SIGNATURE.len()));
+        } else if is_sof(marker[1]) && body.len() >= 5 {
+            let h = u16::from_be_bytes([body[1], body[2]]) as u32;
+            let w = u16::from_be_bytes([body[3], body[4]]) as u32;
+            header.dimensions = Some((w, h));
+        }
+    }
+}
+
+/// `read_exact`, with `false` when the file ends first, as one that is still
+/// being synced does; only other I/O errors are errors.
+fn read_unless_cut_short(r: &mut impl Read, buf: &mut [u8]) -> std::io::Result<bool> {
+    match r.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_sof(marker: u8) -> bool {
+    matches!(marker, 0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF)
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+struct Entry {
+    tag: u16,
+    typ: u16,
+    count: u32,
+    /// Offset of the value (inline or out-of-line) within the TIFF data.
+    value_at: usize,
+    /// Bytes the value takes up.
+    len: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        let le = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Tiff { data, le };
+        (tiff.u16_at(2)? == 42).then_some(tiff)
+    }
+
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let b = self.data.get(at..at + 2)?;
+        Some(if self.le {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<u32> {
+        let b = self.data.get(at..at + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if self.le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn entries(&self, ifd: usize) -> Option<Vec<Entry>> {
+        let n = self.u16_at(ifd)? as usize;
+        let mut out = Vec::with_capacity(n);
+        for k in 0..n {
+            let at = ifd + 2 + k * 12;
+            let tag = self.u16_at(at)?;
+            let typ = self.u16_at(at + 2)?;
+            let count = self.u32_at(at + 4)?;
+            let unit = match typ {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            };
+            let total = unit * count as usize;
+            let value_at = if total <= 4 {
+                at + 8
+            } else {
+                self.u32_at(at + 8)? as usize
+            };
+            out.push(Entry {
+                tag,
+                typ,
+                count,
+                value_at,
+                len: total,
+            });
+        }
+        Some(out)
+    }
+
+    fn ascii(&self, e: &Entry) -> Option<String> {
+        if e.typ != 2 {
+            return None;
+        }
+        let raw = self.data.get(e.value_at..e.value_at + e.count as usize)?;
+        let raw = raw.split(|&b| b == 0).next().unwrap_or_default();
+        std::str::from_utf8(raw).ok().map(|s| s.trim().to_string())
+    }
+
+    fn long(&self, e: &Entry) -> Option<u32> {
+        match e.typ {
+            3 => self.u16_at(e.value_at).map(u32::from),
+            4 => self.u32_at(e.value_at),
+            _ => None,
+        }
+    }
+
+    fn rationals(&self, e: &Entry) -> Option<Vec<f64>> {
+        if e.typ != 5 {
+            return None;
+        }
+        (0..e.count as usize)
+            .map(|k| {
+                let num = self.u32_at(e.value_at + k * 8)? as f64;
+                let den = self.u32_at(e.value_at + k * 8 + 4)? as f64;
+                Some(if den == 0.0 { 0.0 } else { num / den })
+            })
+            .collect()
+    }
+}
+
+fn parse_tiff(data: &[u8]) -> Option<Exif> {
+    let tiff = Tiff::new(data)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+    let mut exif = Exif::default();
+    let mut modified = None;
+    let mut modified_text = None;
+    let mut modified_offset = None;
+    let (mut make, mut model) = (None, None);
+
+    for e in tiff.entries(ifd0)? {
+        match e.tag {
+            TAG_GPS_IFD => {
+                if let Some(off) = tiff.long(&e) {
+                    exif.gps = parse_gps(&tiff, off as usize);
+                }
+            }
+            TAG_EXIF_IFD => {
+                if let Some(off) = tiff.long(&e) {
+                    modified_offset = parse_exif_ifd(&tiff, off as usize, &mut exif);
+                }
+            }
+            TAG_ORIENTATION => exif.orientation = tiff.long(&e).map(|o| o as u16),
+            TAG_DATETIME => {
+                modified_text = tiff.ascii(&e);
+                modified = modified_text.as_deref().and_then(DateTime::parse);
+            }
+            TAG_MAKE => make = tiff.ascii(&e).filter(|s| !s.is_empty()),
+            TAG_MODEL => model = tiff.ascii(&e).filter(|s| !s.is_empty()),
+            TAG_RATING => exif.rating = tiff.long(&e).map(|r| r.min(5) as u8),
+            _ => {}
+        }
+    }
+    if exif.captured.is_none() {
+        // Report a garbled DateTimeOriginal over a missing DateTime.
+        if modified.is_some() || exif.captured_text.is_none() {
+            exif.captured_text = modified_text;
+        }
+        exif.captured = modified;
+        exif.offset = modified_offset;
+    }
+    exif.camera = camera_name(make, model);
+    Some(exif)
+}
+
+/// Most models already start with the make ("Canon EOS R5"); don't repeat it.
+fn camera_name(make: Option<String>, model: Option<String>) -> Option<String> {
+    match (make, model) {
+        (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{make} {model}")),
+        (make, model) => make.or(model),
+    }
+}
+
+/// Returns OffsetTime, which belongs to the IFD0 DateTime.
+fn parse_exif_ifd(tiff: &Tiff, ifd: usize, exif: &mut Exif) -> Option<i16> {
+    let mut modified_offset = None;
+    for e in tiff.entries(ifd).unwrap_or_default() {
+        match e.tag {
+            TAG_DATETIME_ORIGINAL => {
+                exif.captured_text = tiff.ascii(&e);
+                exif.captured = exif.captured_text.as_deref().and_then(DateTime::parse);
+            }
+            TAG_OFFSET_TIME_ORIGINAL => exif.offset = tiff.ascii(&e).as_deref().and_then(parse_utc_offset),
+            TAG_OFFSET_TIME => modified_offset = tiff.ascii(&e).as_deref().and_then(parse_utc_offset),
+            _ => {}
+        }
+    }
+    modified_offset
+}
+
+fn parse_gps(tiff: &Tiff, ifd: usize) -> Option<(f64, f64)> {
+    let (mut lat, mut lat_ref, mut lon, mut lon_ref) = (None, None, None, None);
+    for e in tiff.entries(ifd)? {
+        match e.tag {
+            TAG_GPS_LAT_REF => lat_ref = tiff.ascii(&e),
+            TAG_GPS_LAT => lat = tiff.rationals(&e),
+            TAG_GPS_LON_REF => lon_ref = tiff.ascii(&e),
+            TAG_GPS_LON => lon = tiff.rationals(&e),
+            _ => {}
+        }
+    }
+    let lat = dms_to_degrees(&lat?)? * if lat_ref.as_deref() == Some("S") { -1.0 } else { 1.0 };
+    let lon = dms_to_degrees(&lon?)? * if lon_ref.as_deref() == Some("W") { -1.0 } else { 1.0 };
+    // Cameras without a fix often write all-zero coordinates.
+    if lat == 0.0 && lon == 0.0 {
+        return None;
+    }
+    Some((lat, lon))
+}
+
+/// Where the TIFF data can be cut to drop IFD1 and its thumbnail, and the
+/// offset of IFD0's link to IFD1. `None` when there is no IFD1 or anything
+/// the other IFDs point at lies beyond the cut.
+fn thumbnail_cut(data: &[u8]) -> Option<(usize, usize)> {
+    let tiff = Tiff::new(data)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+    let next_at = ifd0 + 2 + tiff.u16_at(ifd0)? as usize * 12;
+    let ifd1 = tiff.u32_at(next_at)? as usize;
+    if ifd1 == 0 {
+        return None;
+    }
+    let (mut offset, mut length) = (None, None);
+    for e in tiff.entries(ifd1)? {
+        match e.tag {
+            TAG_THUMBNAIL_OFFSET => offset = tiff.long(&e),
+            TAG_THUMBNAIL_LENGTH => length = tiff.long(&e),
+            _ => {}
+        }
+    }
+    let mut cut = ifd1;
+    if let (Some(offset), Some(length)) = (offset, length) {
+        if offset as usize + length as usize <= data.len() {
+            cut = cut.min(offset as usize);
+        }
+    }
+
+    let mut ifds = vec![ifd0];
+    let mut k = 0;
+    while let Some(&ifd) = ifds.get(k) {
+        k += 1;
+        // The raw count: `entries` leaves out entries of unknown types.
+        if ifd + 2 + tiff.u16_at(ifd)? as usize * 12 + 4 > cut {
+            return None;
+        }
+        let entries = tiff.entries(ifd)?;
+        for e in entries {
+            if e.value_at + e.len > cut {
+                return None;
+            }
+            if matches!(e.tag, TAG_EXIF_IFD | TAG_GPS_IFD | TAG_INTEROP_IFD) {
+                let at = tiff.long(&e)? as usize;
+                if !ifds.contains(&at) {
+                    ifds.push(at);
+                }
+            }
+        }
+    }
+    Some((cut, next_at))
+}
+
+fn dms_to_degrees(dms: &[f64]) -> Option<f64> {
+    match dms {
+        [d, m, s] => Some(d + m / 60.0 + s / 3600.0),
+        _ => None,
+    }
+}
+
+/// Great-circle distance in kilometres between two (lat, lon) points.
+pub fn haversine_km(a: (f64, f64), b: (f64, f64)) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let (lat1, lat2) = (a.0.to_radians(), b.0.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b.1 - a.1).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little-endian TIFF block with a GPS IFD.
+    fn gps_tiff(lat: (u32, u32, u32), north: bool, lon: (u32, u32, u32), east: bool) -> Vec<u8> {
+        let mut t: Vec<u8> = Vec::new();
+        t.extend_from_slice(b"II");
+        t.extend_from_slice(&42u16.to_le_bytes());
+        t.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 at 8: one entry pointing at the GPS IFD at 26.
+        t.extend_from_slice(&1u16.to_le_bytes());
+        t.extend_from_slice(&TAG_GPS_IFD.to_le_bytes());
+        t.extend_from_slice(&4u16.to_le_bytes());
+        t.extend_from_slice(&1u32.to_le_bytes());
+        t.extend_from_slice(&26u32.to_le_bytes());
+        t.extend_from_slice(&0u32.to_le_bytes());
+        // GPS IFD at 26: four entries, rationals stored from 80 on.
+        t.extend_from_slice(&4u16.to_le_bytes());
+        let refs = [
+            (TAG_GPS_LAT_REF, if north { b'N' } else { b'S' }),
+            (TAG_GPS_LON_REF, if east { b'E' } else { b'W' }),
+        ];
+        for (tag, c) in refs {
+            t.extend_from_slice(&tag.to_le_bytes());
+            t.extend_from_slice(&2u16.to_le_bytes());
+            t.extend_from_slice(&2u32.to_le_bytes());
+            t.extend_from_slice(&[c, 0, 0, 0]);
+        }
+        for (tag, off) in [(TAG_GPS_LAT, 80u32), (TAG_GPS_LON, 104u32)] {
+            t.extend_from_slice(&tag.to_le_bytes());
+            t.extend_from_slice(&5u16.to_le_bytes());
+            t.extend_from_slice(&3u32.to_le_bytes());
+            t.extend_from_slice(&off.to_le_bytes());
+        }
+        t.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(t.len(), 80);
+        for (d, m, s) in [lat, lon] {
+            for v in [d, m, s] {
+                t.extend_from_slice(&v.to_le_bytes());
+                t.extend_from_slice(&1u32.to_le_bytes());
+            }
+        }
+        t
+    }
+
+    #[test]
+    fn parses_gps_coordinates() {
+        let exif = parse_tiff(&gps_tiff((47, 36, 0), true, (122, 18, 0), false)).unwrap();
+        let (lat, lon) = exif.gps.unwrap();
+        assert!((lat - 47.6).abs() < 1e-9);
+        assert!((lon + 122.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn scans_exif_and_frame_size_from_header() {
+        let tiff = gps_tiff((1, 0, 0), true, (2, 0, 0), true);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0xAA, 0xBB];
+        jpeg.extend_from_slice(&[0xFF, 0xE1]);
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x01, 0xE0, 0x02, 0x80]);
+        jpeg.extend_from_slice(&[0x03, 0x01, 0x22, 0x00]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA]);
+        let header = scan_header(&mut jpeg.as_slice()).unwrap();
+        assert_eq!(header.tiff.unwrap(), tiff);
+        assert_eq!(header.dimensions, Some((640, 480)));
+
+        // Cut short in a segment's length or body, a file has no EXIF
+        // rather than failing the scan.
+        let path = std::env::temp_dir().join(format!("image-rando-truncated-{}.jpg", std::process::id()));
+        for cut in [11, 20] {
+            std::fs::write(&path, &jpeg[..cut]).unwrap();
+            assert_eq!(read_exif(&path).unwrap(), Exif::default(), "cut at {cut}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parses_exif_datetimes() {
+        let dt = DateTime::parse("2023:06:01 18:30:05").unwrap();
+        assert_eq!((dt.year, dt.month, dt.day), (2023, 6, 1));
+        assert_eq!((dt.hour, dt.minute, dt.second), (18, 30, 5));
+        assert!(DateTime::parse("2023:06:01 18:30:06").unwrap() > dt);
+        assert_eq!(DateTime::parse("0000:00:00 00:00:00"), None);
+        assert_eq!(DateTime::parse("2023-06-01"), None);
+        assert_eq!(DateTime::from_unix(1_685_644_205), dt);
+        assert_eq!(DateTime::from_unix(951_782_400), DateTime::parse("2000:02:29 00:00:00").unwrap());
+        assert_eq!(dt.to_unix(), 1_685_644_205);
+        assert_eq!(DateTime::parse("1969:12:31 23:59:59").unwrap().to_unix(), -1);
+    }
+
+    #[test]
+    fn parses_utc_offsets() {
+        assert_eq!(parse_utc_offset("+09:00"), Some(540));
+        assert_eq!(parse_utc_offset("-05:30"), Some(-330));
+        assert_eq!(parse_utc_offset("+0545"), Some(345));
+        assert_eq!(parse_utc_offset("-8"), Some(-480));
+        assert_eq!(parse_utc_offset("UTC"), Some(0));
+        for bad in ["09:00", "+", "+25:00", "+01:75", "   :  ", "+1:x", "+aéb"] {
+            assert_eq!(parse_utc_offset(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_text_of_unparseable_dates() {
+        let tiff = |date: &[u8; 19]| {
+            let mut t = b"II*\0".to_vec();
+            t.extend_from_slice(&8u32.to_le_bytes());
+            t.extend_from_slice(&1u16.to_le_bytes());
+            t.extend_from_slice(&TAG_DATETIME.to_le_bytes());
+            t.extend_from_slice(&2u16.to_le_bytes());
+            t.extend_from_slice(&20u32.to_le_bytes());
+            t.extend_from_slice(&26u32.to_le_bytes());
+            t.extend_from_slice(&0u32.to_le_bytes());
+            t.extend_from_slice(date);
+            t.push(0);
+            parse_tiff(&t).unwrap()
+        };
+        let exif = tiff(b"0000:00:00 00:00:00");
+        assert_eq!(exif.captured, None);
+        assert_eq!(exif.captured_text.as_deref(), Some("0000:00:00 00:00:00"));
+        let exif = tiff(b"2020:02:29 12:00:00");
+        assert_eq!(exif.captured.map(|d| d.day), Some(29));
+    }
+
+    #[test]
+    fn display_dimensions_apply_orientation() {
+        let mut exif = Exif {
+            dimensions: Some((4000, 3000)),
+            ..Default::default()
+        };
+        assert_eq!(exif.display_dimensions(), Some((4000, 3000)));
+        exif.orientation = Some(6);
+        assert_eq!(exif.display_dimensions(), Some((3000, 4000)));
+    }
+
+    #[test]
+    fn camera_name_avoids_repeating_the_make() {
+        let s = |v: &str| Some(v.to_string());
+        assert_eq!(camera_name(s("Canon"), s("Canon EOS R5")), s("Canon EOS R5"));
+        assert_eq!(camera_name(s("Apple"), s("iPhone 12")), s("Apple iPhone 12"));
+        assert_eq!(camera_name(None, s("X100V")), s("X100V"));
+        assert_eq!(camera_name(None, None), None);
+    }
+
+    #[test]
+    fn haversine_matches_known_distance() {
+        // Seattle -> Portland is roughly 234 km.
+        let d = haversine_km((47.6062, -122.3321), (45.5152, -122.6784));
+        assert!((d - 234.0).abs() < 3.0, "{d}");
+    }
+
+    /// A JPEG whose EXIF has an orientation in IFD0 and a 100-byte thumbnail
+    /// after IFD1; with `model_after`, IFD0's camera model is stored after
+    /// the thumbnail.
+    fn jpeg_with_thumbnail(model_after: bool) -> Vec<u8> {
+        let n0 = if model_after { 2u16 } else { 1 };
+        let ifd1 = 8 + 2 + 12 * n0 as u32 + 4;
+        let thumb_at = ifd1 + 2 + 24 + 4;
+        let mut t: Vec<u8> = Vec::new();
+        t.extend_from_slice(b"II");
+        t.extend_from_slice(&42u16.to_le_bytes());
+        t.extend_from_slice(&8u32.to_le_bytes());
+        t.extend_from_slice(&n0.to_le_bytes());
+        let entry = |t: &mut Vec<u8>, tag: u16, typ: u16, count: u32, value: u32| {
+            t.extend_from_slice(&tag.to_le_bytes());
+            t.extend_from_slice(&typ.to_le_bytes());
+            t.extend_from_slice(&count.to_le_bytes());
+            t.extend_from_slice(&value.to_le_bytes());
+        };
+        entry(&mut t, TAG_ORIENTATION, 3, 1, 6);
+        if model_after {
+            entry(&mut t, TAG_MODEL, 2, 8, thumb_at + 100);
+        }
+        t.extend_from_slice(&ifd1.to_le_bytes());
+        t.extend_from_slice(&2u16.to_le_bytes());
+        entry(&mut t, TAG_THUMBNAIL_OFFSET, 4, 1, thumb_at);
+        entry(&mut t, TAG_THUMBNAIL_LENGTH, 4, 1, 100);
+        t.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(t.len(), thumb_at as usize);
+        t.extend_from_slice(&[0xAB; 100]);
+        if model_after {
+            t.extend_from_slice(b"Camera\0\0");
+        }
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((t.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&t);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn strips_the_thumbnail_and_keeps_the_rest() {
+        let jpeg = jpeg_with_thumbnail(false);
+        let stripped = strip_thumbnail(&jpeg).unwrap();
+        assert_eq!(stripped.len(), jpeg.len() - 30 - 100);
+        assert!(stripped.ends_with(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]));
+        let tiff = scan_header(&mut stripped.as_slice()).unwrap().tiff.unwrap();
+        assert_eq!(parse_tiff(&tiff).unwrap().orientation, Some(6));
+        assert_eq!(strip_thumbnail(&stripped), None);
+
+        // Cutting would lose the camera model stored after the thumbnail.
+        assert_eq!(strip_thumbnail(&jpeg_with_thumbnail(true)), None);
+        assert_eq!(strip_thumbnail(b"not a jpeg"), None);
+    }
+
+    #[test]
+    fn a_thumbnail_inside_ifd0_is_left_alone() {
+        // IFD0 holds an entry of an unknown type, which `entries` skips, and
+        // the thumbnail offset points into IFD0 just past the known one.
+        let mut t: Vec<u8> = Vec::new();
+        t.extend_from_slice(b"II");
+        t.extend_from_slice(&42u16.to_le_bytes());
+        t.extend_from_slice(&8u32.to_le_bytes());
+        t.extend_from_slice(&2u16.to_le_bytes());
+        let entry = |t: &mut Vec<u8>, tag: u16, typ: u16, count: u32, value: u32| {
+            t.extend_from_slice(&tag.to_le_bytes());
+            t.extend_from_slice(&typ.to_le_bytes());
+            t.extend_from_slice(&count.to_le_bytes());
+            t.extend_from_slice(&value.to_le_bytes());
+        };
+        entry(&mut t, TAG_ORIENTATION, 3, 1, 6);
+        entry(&mut t, 0x9999, 99, 1, 0);
+        let ifd1 = t.len() as u32 + 4;
+        t.extend_from_slice(&ifd1.to_le_bytes());
+        t.extend_from_slice(&2u16.to_le_bytes());
+        entry(&mut t, TAG_THUMBNAIL_OFFSET, 4, 1, 30);
+        entry(&mut t, TAG_THUMBNAIL_LENGTH, 4, 1, 4);
+        t.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(thumbnail_cut(&t), None);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((t.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&t);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        assert_eq!(strip_thumbnail(&jpeg), None);
+    }
+}
@@ -0,0 +1,330 @@
+// HDR gain maps: the second, smaller JPEG that Ultra HDR (Android) and Apple
+// HDR photos carry after the main image. Displays that understand it use it
+// to brighten highlights; everything else shows the main image, which is a
+// complete SDR picture on its own.
+//
+// The gain map is found through the MPF segment of the main image, and only
+// counts as one when its own XMP says so (`hdrgm:` or Apple's `HDRGainMap`):
+// cameras use MPF for large previews too. Copies keep files as they are;
+// paths that rewrite the main image put the gain map back with `attach`.
+
+use crate::exif::XMP_SIGNATURE;
+
+pub struct GainMap<'a> {
+    /// The main image, everything before the gain map.
+    pub primary: &'a [u8],
+    /// The gain map, a JPEG of its own.
+    pub image: &'a [u8],
+    /// The main image's MPF segment, marker included.
+    mpf: &'a [u8],
+    /// The main image's XMP segment describing the gain map, if any.
+    xmp: Option<&'a [u8]>,
+}
+
+struct Segment {
+    marker: u8,
+    /// From the 0xFF of the marker to the end of the segment.
+    start: usize,
+    end: usize,
+}
+
+impl Segment {
+    fn body<'a>(&self, jpeg: &'a [u8]) -> &'a [u8] {
+        &jpeg[self.start + 4..self.end]
+    }
+
+    fn is_mpf(&self, jpeg: &[u8]) -> bool {
+        self.marker == 0xE2 && self.body(jpeg).starts_with(b"MPF\0")
+    }
+
+    fn is_gain_map_xmp(&self, jpeg: &[u8]) -> bool {
+        let body = self.body(jpeg);
+        self.marker == 0xE1
+            && body.starts_with(XMP_SIGNATURE)
+            && [&b"hdrgm:"[..], b"HDRGainMap"].iter().any(|n| body.windows(n.len()).any(|w| w == *n))
+    }
+}
+
+/// The marker segments of `jpeg` before its scan data, or `None` when it
+/// isn't a well-formed JPEG.
+fn header_segments(jpeg: &[u8]) -> Option<Vec<Segment>> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if *jpeg.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *jpeg.get(pos + 1)?;
+        match marker {
+            0xDA | 0xD9 => return Some(segments),
+            0xFF => pos += 1,
+            0x01 | 0xD0..=0xD7 => pos += 2,
+            _ => {
+                let len = u16::from_be_bytes([*jpeg.get(pos + 2)?, *jpeg.get(pos + 3)?]) as usize;
+                if len < 2 || pos + 2 + len > jpeg.len() {
+                    return None;
+                }
+                segments.push(Segment { marker, start: pos, end: pos + 2 + len });
+                pos += 2 + len;
+            }
+        }
+    }
+}
+
+/// The gain map of `data`, if it is an HDR photo with one.
+pub fn find(data: &[u8]) -> Option<GainMap<'_>> {
+    let segments = header_segments(data)?;
+    let mpf = segments.iter().find(|s| s.is_mpf(data))?;
+    let tiff_at = mpf.start + 8;
+    let tiff = &data[tiff_at..mpf.end];
+    let entries = mp_entries(tiff)?;
+    let offset = entries.read(tiff, 16 + 8)? as usize;
+    let size = entries.read(tiff, 16 + 4)? as usize;
+    let at = tiff_at.checked_add(offset)?;
+    let image = data.get(at..at.checked_add(size)?)?;
+    if !header_segments(image)?.iter().any(|s| s.is_gain_map_xmp(image)) {
+        return None;
+    }
+    Some(GainMap {
+        primary: &data[..at],
+        image,
+        mpf: &data[mpf.start..mpf.end],
+        xmp: segments.iter().find(|s| s.is_gain_map_xmp(data)).map(|s| &data[s.start..s.end]),
+    })
+}
+
+/// Where the MP entries of a two-image MPF payload are, and in which byte
+/// order.
+struct MpEntries {
+    at: usize,
+    big_endian: bool,
+}
+
+impl MpEntries {
+    fn read(&self, tiff: &[u8], at: usize) -> Option<u32> {
+        let b: [u8; 4] = tiff.get(self.at + at..self.at + at + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn write(&self, tiff: &mut [u8], at: usize, value: usize) {
+        let v = value as u32;
+        let b = if self.big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        tiff[self.at + at..self.at + at + 4].copy_from_slice(&b);
+    }
+}
+
+/// Reads the MPF index IFD. Files listing more than the main image and one
+/// other (depth maps, several previews) are left alone.
+fn mp_entries(tiff: &[u8]) -> Option<MpEntries> {
+    let big_endian = match tiff.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let b: [u8; 2] = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+    let u32_at = |at: usize| MpEntries { at, big_endian }.read(tiff, 0);
+    let ifd = u32_at(4)? as usize;
+    let (mut images, mut entries) = (None, None);
+    for i in 0..u16_at(ifd)? as usize {
+        let e = ifd + 2 + 12 * i;
+        match u16_at(e)? {
+            0xB001 => images = u32_at(e + 8),
+            0xB002 => entries = u32_at(e + 8),
+            _ => {}
+        }
+    }
+    let at = entries? as usize;
+    (images? == 2 && at + 32 <= tiff.len()).then_some(MpEntries { at, big_endian })
+}
+
+/// `primary`, a rewrite of `gm.primary`, followed by `image` as its gain map
+/// (`gm.image`, or a resized copy from `map_with_metadata`). The MPF and
+/// gain-map XMP segments of the original are pointed at the new layout;
+/// those already in `primary` are replaced. If that can't be done, `primary`
+/// comes back alone.
+pub fn attach(primary: &[u8], gm: &GainMap, image: &[u8]) -> Vec<u8> {
+    let mut segments = match gm.xmp.map(|xmp| with_item_length(xmp, image.len())) {
+        Some(Some(xmp)) => xmp,
+        Some(None) => return primary.to_vec(),
+        None => Vec::new(),
+    };
+    let mpf_offset = segments.len();
+    segments.extend_from_slice(gm.mpf);
+    let Some((mut out, at)) = splice(primary, &segments) else {
+        return primary.to_vec();
+    };
+    let tiff_at = at + mpf_offset + 8;
+    let len = out.len();
+    if let Some(entries) = mp_entries(&out[tiff_at..]) {
+        let tiff = &mut out[tiff_at..];
+        entries.write(tiff, 4, len);
+        entries.write(tiff, 16 + 4, image.len());
+        entries.write(tiff, 16 + 8, len - tiff_at);
+    }
+    out.extend_from_slice(image);
+    out
+}
+
+/// `encoded`, a re-encoded copy of `gm.image`, with the XMP that tells
+/// displays how strongly to apply it.
+#[cfg(feature = "imaging")]
+pub fn map_with_metadata(encoded: &[u8], gm: &GainMap) -> Vec<u8> {
+    let image = gm.image;
+    let xmp: Vec<u8> = header_segments(image)
+        .unwrap_or_default()
+        .iter()
+        .filter(|s| s.is_gain_map_xmp(image))
+        .flat_map(|s| &image[s.start..s.end])
+        .copied()
+        .collect();
+    splice(encoded, &xmp).map_or_else(|| encoded.to_vec(), |(out, _)| out)
+}
+
+/// `jpeg` with `segments` put in after its JFIF and EXIF segments, and what
+/// MPF and gain-map XMP segments it had left out; also where they went.
+fn splice(jpeg: &[u8], segments: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut out = vec![0xFF, 0xD8];
+    let mut at = None;
+    let mut rest_at = 2;
+    for s in &header_segments(jpeg)? {
+        let leading = s.marker == 0xE0 || (s.marker == 0xE1 && s.body(jpeg).starts_with(b"Exif\0"));
+        if !leading && at.is_none() {
+            at = Some(out.len());
+            out.extend_from_slice(segments);
+        }
+        if !s.is_mpf(jpeg) && !s.is_gain_map_xmp(jpeg) {
+            out.extend_from_slice(&jpeg[s.start..s.end]);
+        }
+        rest_at = s.end;
+    }
+    let at = at.unwrap_or_else(|| {
+        out.extend_from_slice(segments);
+        out.len() - segments.len()
+    });
+    out.extend_from_slice(&jpeg[rest_at..]);
+    Some((out, at))
+}
+
+/// An Ultra HDR XMP segment with the length its container directory gives
+/// for the gain map set to `len`, or `None` if the longer number no longer
+/// fits in a segment.
+fn with_item_length(segment: &[u8], len: usize) -> Option<Vec<u8>> {
+    let body = &segment[4..];
+    let text = String::from_utf8_lossy(body);
+    let Some(item) = text.find("Item:Semantic=\"GainMap\"") else {
+        return Some(segment.to_vec());
+    };
+    let end = text[item..].find('>').map_or(text.len(), |e| item + e);
+    let Some(value) = text[item..end].find("Item:Length=\"").map(|v| item + v + "Item:Length=\"".len()) else {
+        return Some(segment.to_vec());
+    };
+    let Some(close) = text[value..].find('"') else {
+        return Some(segment.to_vec());
+    };
+    let body = format!("{}{len}{}", &text[..value], &text[value + close..]);
+    let mut out = vec![0xFF, 0xE1];
+    out.extend_from_slice(&u16::try_from(body.len() + 2).ok()?.to_be_bytes());
+    out.extend_from_slice(body.as_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xFF, marker];
+        out.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn xmp(packet: &str) -> Vec<u8> {
+        segment(0xE1, &[XMP_SIGNATURE, packet.as_bytes()].concat())
+    }
+
+    /// A little-endian MPF segment listing two images; offsets are filled in
+    /// by `attach`.
+    fn mpf() -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, kind, count, value) in [(0xB001u16, 4u16, 1u32, 2u32), (0xB002, 7, 32, 38)] {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&kind.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&[0; 32]);
+        segment(0xE2, &[&b"MPF\0"[..], &tiff].concat())
+    }
+
+    fn jpeg(segments: &[Vec<u8>], scan: &[u8]) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        out.extend(segments.concat());
+        out.extend_from_slice(&segment(0xDA, &[1, 1, 0, 0, 63, 0]));
+        out.extend_from_slice(scan);
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+
+    #[test]
+    fn finds_and_reattaches_ultra_hdr_gain_maps() {
+        let directory = concat!(
+            r#"<rdf:Description hdrgm:Version="1.0"><Container:Directory>"#,
+            r#"<Container:Item Item:Semantic="GainMap" Item:Mime="image/jpeg" Item:Length="0"/>"#,
+            "</Container:Directory></rdf:Description>",
+        );
+        let map = jpeg(&[xmp(r#"<rdf:Description hdrgm:Version="1.0"/>"#)], b"gain");
+        let primary = jpeg(&[segment(0xE0, b"JFIF\0"), mpf(), xmp(directory)], b"main image");
+        let gm = GainMap { primary: &primary, image: &map, mpf: &mpf(), xmp: Some(&xmp(directory)) };
+        let file = attach(&primary, &gm, &map);
+        assert!(file.windows(16).any(|w| w == format!("Item:Length=\"{}\"", map.len()).as_bytes()));
+
+        let found = find(&file).unwrap();
+        assert_eq!(found.image, &map[..]);
+        assert_eq!(found.primary.len() + map.len(), file.len());
+        // A longer main image and a smaller gain map, as after resizing.
+        let rewritten = jpeg(&[segment(0xE0, b"JFIF\0")], b"main image, encoded again");
+        let smaller = jpeg(&[xmp(r#"<rdf:Description hdrgm:Version="1.0"/>"#)], b"g");
+        let again = attach(&rewritten, &found, &smaller);
+        let found = find(&again).unwrap();
+        assert_eq!(found.image, &smaller[..]);
+        assert!(found.primary.ends_with(b"main image, encoded again\xFF\xD9"));
+        assert_eq!(found.primary.windows(4).filter(|w| w == b"MPF\0").count(), 1);
+
+        // MPF pointing at a plain preview isn't a gain map.
+        let preview = jpeg(&[], b"preview");
+        assert!(find(&attach(&primary, &gm, &preview)).is_none());
+        assert!(find(&primary).is_none());
+    }
+
+    #[test]
+    fn item_length_must_still_fit_its_segment() {
+        let item = r#"<Container:Item Item:Semantic="GainMap" Item:Length="0"/>"#;
+        let full = xmp(&format!("{item}{}", " ".repeat(65533 - XMP_SIGNATURE.len() - item.len())));
+        assert_eq!(full.len(), 2 + 65535);
+        assert_eq!(with_item_length(&full, 9).map(|s| s.len()), Some(full.len()));
+        assert_eq!(with_item_length(&full, 10), None);
+    }
+
+    #[cfg(feature = "imaging")]
+    #[test]
+    fn reencoded_gain_maps_get_their_metadata_back() {
+        let map = jpeg(&[xmp(r#"<rdf:Description hdrgm:Version="1.0" hdrgm:GainMapMax="2.3"/>"#)], b"gain");
+        let primary = jpeg(&[mpf()], b"main image");
+        let gm = GainMap { primary: &primary, image: &map, mpf: &mpf(), xmp: None };
+        let encoded = jpeg(&[segment(0xE0, b"JFIF\0")], b"smaller");
+        let restored = map_with_metadata(&encoded, &gm);
+        assert!(restored.starts_with(&[0xFF, 0xD8, 0xFF, 0xE0]));
+        assert!(restored.windows(16).any(|w| w == b"GainMapMax=\"2.3\""));
+        assert!(restored.ends_with(b"smaller\xFF\xD9"));
+    }
+}
@@ -0,0 +1,566 @@
+// Pixel-level helpers, only built with `--features imaging`.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use fast_image_resize as fr;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder};
+use jpeg_encoder::SamplingFactor;
+
+use crate::{gainmap, is_panorama, Chroma, FileInfo, Fit, JpegOptions, Panoramas, Resize, ResizeFilter};
+
+/// How many upcoming photos the colour pass may choose from. Keeping this small
+/// preserves most of the shuffle while still breaking up runs of similar shots.
+const DIVERSITY_WINDOW: usize = 8;
+
+/// Re-encode quality for resized photos; high enough not to show on a TV.
+const RESIZE_JPEG_QUALITY: u8 = 90;
+
+/// The frame `--panoramas crop-center|letterbox` aims at without `--resize`.
+const DEFAULT_FRAME: (u32, u32) = (3840, 2160);
+
+/// Average red, green, blue and brightness, each in 0..=255.
+pub type ColorSignature = [f32; 4];
+
+pub fn color_signature(path: &Path) -> Result<ColorSignature, String> {
+    let img = image::ImageReader::open(path)
+        .map_err(|e| format!("cannot open {}: {e}", path.display()))?
+        .with_guessed_format()
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?
+        .decode()
+        .map_err(|e| format!("cannot decode {}: {e}", path.display()))?;
+    let thumb = img.thumbnail(16, 16).to_rgb8();
+
+    let mut sum = [0f32; 3];
+    for px in thumb.pixels() {
+        for (s, v) in sum.iter_mut().zip(px.0) {
+            *s += v as f32;
+        }
+    }
+    let n = (thumb.width() * thumb.height()).max(1) as f32;
+    let [r, g, b] = sum.map(|s| s / n);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    Ok([r, g, b, luma])
+}
+
+/// 64-bit difference hash of the photo as displayed: one bit per pair of
+/// neighbouring pixels in a 9x8 greyscale thumbnail. Survives re-encoding and
+/// resizing, so near-identical photos end up a few bits apart.
+pub fn dhash(path: &Path) -> Result<u64, String> {
+    let mut decoder = image::ImageReader::open(path)
+        .map_err(|e| format!("cannot open {}: {e}", path.display()))?
+        .with_guessed_format()
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?
+        .into_decoder()
+        .map_err(|e| format!("cannot decode {}: {e}", path.display()))?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| format!("cannot decode {}: {e}", path.display()))?;
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|e| format!("cannot decode {}: {e}", path.display()))?;
+    img.apply_orientation(orientation);
+    let small = img.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x + 1, y)[0] > small.get_pixel(x, y)[0];
+            hash = hash << 1 | brighter as u64;
+        }
+    }
+    Ok(hash)
+}
+
+/// `src` scaled down (and, for `Fit::Crop`, cropped) to `resize`, encoded as
+/// JPEG with its EXIF orientation applied. `None` when the photo already
+/// fits, so it can be copied as is.
+pub fn resized_jpeg(src: &Path, resize: Resize, jpeg: JpegOptions) -> Result<Option<Vec<u8>>, String> {
+    let data = fs::read(src).map_err(|e| format!("cannot read {}: {e}", src.display()))?;
+    let (decoder, source, (w, h)) = open_oriented(src, &data)?;
+    let Some(plan) = plan_resize(w, h, resize) else {
+        return Ok(None);
+    };
+    let img = decode(decoder, &source)?;
+    let layout = Layout { plan, frame: None, filter: resize.filter };
+    reencode(&source, &img, &layout, jpeg).map(Some)
+}
+
+/// A panorama (see `is_panorama`) in `src` reframed for a display with the
+/// aspect ratio of `resize`, or of `DEFAULT_FRAME` without one: centre-cropped
+/// to it, or shrunk to fit inside it (inside `DEFAULT_FRAME` without
+/// `--resize`) and padded with black bars. `None` for other photos.
+pub fn reframed_panorama(
+    src: &Path,
+    panoramas: Panoramas,
+    resize: Option<Resize>,
+    jpeg: JpegOptions,
+) -> Result<Option<Vec<u8>>, String> {
+    let data = fs::read(src).map_err(|e| format!("cannot read {}: {e}", src.display()))?;
+    let (decoder, source, (w, h)) = open_oriented(src, &data)?;
+    if !is_panorama(w, h) || !matches!(panoramas, Panoramas::CropCenter | Panoramas::Letterbox) {
+        return Ok(None);
+    }
+    // A box that is only a pixel count has no shape.
+    let frame = resize.filter(|r| r.width != u32::MAX);
+    let (fw, fh) = frame.map_or(DEFAULT_FRAME, |r| (r.width, r.height));
+    let filter = resize.map_or(ResizeFilter::default(), |r| r.filter);
+    let max_pixels = resize.and_then(|r| r.max_pixels);
+    let layout = if panoramas == Panoramas::CropCenter {
+        // Without a box, the largest frame-shaped region, at full resolution.
+        let (bw, bh) = match frame {
+            Some(_) => (fw, fh),
+            None if w as u64 * fh as u64 > h as u64 * fw as u64 => ((h as u64 * fw as u64 / fh as u64) as u32, h),
+            None => (w, (w as u64 * fh as u64 / fw as u64) as u32),
+        };
+        let target = Resize { width: bw.max(1), height: bh.max(1), max_pixels, fit: Fit::Crop, filter };
+        let plan = plan_resize(w, h, target).unwrap_or(ResizePlan { crop: None, scale: None });
+        Layout { plan, frame: None, filter }
+    } else {
+        let target = Resize { width: fw, height: fh, max_pixels, fit: Fit::Inside, filter };
+        let plan = plan_resize(w, h, target).unwrap_or(ResizePlan { crop: None, scale: None });
+        Layout { plan, frame: Some((fw, fh)), filter }
+    };
+    let img = decode(decoder, &source)?;
+    reencode(&source, &img, &layout, jpeg).map(Some)
+}
+
+/// `src` as RGB pixels filling a `width`x`height` screen: shrunk to fit (never
+/// enlarged) and centred on black, for `display`.
+pub fn screen_frame(src: &Path, (width, height): (u32, u32)) -> Result<Vec<u8>, String> {
+    let data = fs::read(src).map_err(|e| format!("cannot read {}: {e}", src.display()))?;
+    let (decoder, source, (w, h)) = open_oriented(src, &data)?;
+    // Bilinear is plenty for a screen and much quicker on a Raspberry Pi.
+    let filter = ResizeFilter::Bilinear;
+    let target = Resize { width, height, max_pixels: None, fit: Fit::Inside, filter };
+    let plan = plan_resize(w, h, target).unwrap_or(ResizePlan { crop: None, scale: None });
+    let img = decode(decoder, &source)?;
+    let pixels = apply_layout(src, &img, (w, h), &Layout { plan, frame: None, filter })?;
+    Ok(center_on(&pixels, (width, height)).data)
+}
+
+/// How a photo becomes its re-encoded self: cropped and scaled as `plan`
+/// says, then, with a `frame`, letterboxed to its aspect ratio.
+struct Layout {
+    plan: ResizePlan,
+    frame: Option<(u32, u32)>,
+    filter: ResizeFilter,
+}
+
+/// A photo being re-encoded: its file and what its decoder said about it.
+struct Source<'a> {
+    path: &'a Path,
+    data: &'a [u8],
+    orientation: Orientation,
+    /// The embedded colour profile, if it describes RGB.
+    icc: Option<Vec<u8>>,
+}
+
+/// 8-bit RGB or greyscale pixels on their way to the encoder.
+struct Pixels {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+    grey: bool,
+}
+
+/// `img` (decoded from `source`) laid out and encoded, keeping its colour
+/// profile or converting to sRGB as `jpeg` says. An HDR gain map goes
+/// through the same steps, unless `jpeg` asks for SDR only.
+fn reencode(source: &Source, img: &DynamicImage, layout: &Layout, jpeg: JpegOptions) -> Result<Vec<u8>, String> {
+    let src = source.path;
+    let size = (img.width(), img.height());
+    let mut pixels = apply_layout(src, img, size, layout)?;
+    let mut icc = source.icc.as_deref();
+    if let Some(profile) = icc.filter(|_| jpeg.convert_srgb) {
+        // Converted after resizing, which touches far fewer pixels.
+        match to_srgb(&mut pixels, profile) {
+            Ok(()) => icc = None,
+            Err(e) => eprintln!("warning: keeping the colour profile of {}: {e}", src.display()),
+        }
+    }
+    let primary = encode(src, &pixels, icc, jpeg)?;
+    let Some(gm) = gainmap::find(source.data).filter(|_| !jpeg.tone_map_sdr) else {
+        return Ok(primary);
+    };
+    let map = image::load_from_memory(gm.image).map(|mut map| {
+        map.apply_orientation(source.orientation);
+        map
+    });
+    let map = match map {
+        Ok(map) if map.color().has_color() => DynamicImage::ImageRgb8(map.into_rgb8()),
+        Ok(map) => DynamicImage::ImageLuma8(map.into_luma8()),
+        Err(e) => {
+            eprintln!("warning: leaving out the HDR gain map of {}: {e}", src.display());
+            return Ok(primary);
+        }
+    };
+    let map_size = (map.width(), map.height());
+    let map_layout = Layout { plan: layout.plan.scaled(size, map_size), ..*layout };
+    // Gain maps are small, so they keep their full colour resolution.
+    let pixels = apply_layout(src, &map, map_size, &map_layout)?;
+    let encoded = encode(src, &pixels, None, JpegOptions { chroma: Chroma::Full, ..jpeg })?;
+    Ok(gainmap::attach(&primary, &gm, &gainmap::map_with_metadata(&encoded, &gm)))
+}
+
+/// Centres `pixels` on a black canvas with the aspect ratio of `frame`, just
+/// large enough to hold them.
+fn letterbox(pixels: &Pixels, (fw, fh): (u32, u32)) -> Pixels {
+    let (w, h) = (pixels.width, pixels.height);
+    let canvas = if w as u64 * fh as u64 > h as u64 * fw as u64 {
+        (w, (w as u64 * fh as u64).div_ceil(fw as u64) as u32)
+    } else {
+        ((h as u64 * fw as u64).div_ceil(fh as u64) as u32, h)
+    };
+    center_on(pixels, canvas)
+}
+
+/// `pixels` in the middle of a black `cw`x`ch` canvas at least as large.
+fn center_on(pixels: &Pixels, (cw, ch): (u32, u32)) -> Pixels {
+    let (w, h) = (pixels.width, pixels.height);
+    let channels = if pixels.grey { 1 } else { 3 };
+    let (x, y) = ((cw - w) / 2, (ch - h) / 2);
+    let mut canvas = vec![0u8; cw as usize * ch as usize * channels];
+    let row = w as usize * channels;
+    for r in 0..h as usize {
+        let at = ((y as usize + r) * cw as usize + x as usize) * channels;
+        canvas[at..at + row].copy_from_slice(&pixels.data[r * row..(r + 1) * row]);
+    }
+    Pixels { data: canvas, width: cw, height: ch, grey: pixels.grey }
+}
+
+/// A decoder for `data`, read from `src`, with what it says about the photo
+/// and its size as displayed.
+fn open_oriented<'a>(src: &'a Path, data: &'a [u8]) -> Result<(impl ImageDecoder + 'a, Source<'a>, (u32, u32)), String> {
+    let mut decoder = image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| format!("cannot read {}: {e}", src.display()))?
+        .into_decoder()
+        .map_err(|e| format!("cannot decode {}: {e}", src.display()))?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| format!("cannot decode {}: {e}", src.display()))?;
+    let icc = decoder
+        .icc_profile()
+        .map_err(|e| format!("cannot decode {}: {e}", src.display()))?
+        // The output is always RGB, so a CMYK or greyscale profile would be
+        // wrong for it.
+        .filter(|icc| icc.get(16..20) == Some(b"RGB "));
+    let (w, h) = decoder.dimensions();
+    let quarter_turn = matches!(
+        orientation,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    let size = if quarter_turn { (h, w) } else { (w, h) };
+    Ok((decoder, Source { path: src, data, orientation, icc }, size))
+}
+
+fn decode(decoder: impl ImageDecoder, source: &Source) -> Result<DynamicImage, String> {
+    let mut img = DynamicImage::from_decoder(decoder)
+        .map_err(|e| format!("cannot decode {}: {e}", source.path.display()))?;
+    img.apply_orientation(source.orientation);
+    // The JPEG encoder wants RGB anyway, and the SIMD kernels cover it.
+    Ok(DynamicImage::ImageRgb8(img.into_rgb8()))
+}
+
+/// `img` (`w`x`h`, RGB or greyscale) cropped, scaled and letterboxed as
+/// `layout` says.
+fn apply_layout(src: &Path, img: &DynamicImage, (w, h): (u32, u32), layout: &Layout) -> Result<Pixels, String> {
+    let (x, y, cw, ch) = layout.plan.crop.unwrap_or((0, 0, w, h));
+    let (sw, sh) = layout.plan.scale.unwrap_or((cw, ch));
+
+    let grey = matches!(img, DynamicImage::ImageLuma8(_));
+    let mut dst = fr::images::Image::new(sw, sh, if grey { fr::PixelType::U8 } else { fr::PixelType::U8x3 });
+    let alg = match layout.filter {
+        ResizeFilter::Lanczos3 => fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3),
+        ResizeFilter::Bilinear => fr::ResizeAlg::Convolution(fr::FilterType::Bilinear),
+    };
+    let options = fr::ResizeOptions::new()
+        .resize_alg(alg)
+        .crop(x as f64, y as f64, cw as f64, ch as f64);
+    fr::Resizer::new()
+        .resize(img, &mut dst, &options)
+        .map_err(|e| format!("cannot resize {}: {e}", src.display()))?;
+    let pixels = Pixels { data: dst.into_vec(), width: sw, height: sh, grey };
+    Ok(match layout.frame {
+        Some(frame) => letterbox(&pixels, frame),
+        None => pixels,
+    })
+}
+
+/// Converts RGB `pixels` from the colour space `icc` describes to sRGB.
+fn to_srgb(pixels: &mut Pixels, icc: &[u8]) -> Result<(), String> {
+    let from = moxcms::ColorProfile::new_from_slice(icc).map_err(|e| format!("unreadable colour profile: {e}"))?;
+    let transform = from
+        .create_transform_8bit(
+            moxcms::Layout::Rgb,
+            &moxcms::ColorProfile::new_srgb(),
+            moxcms::Layout::Rgb,
+            moxcms::TransformOptions::default(),
+        )
+        .map_err(|e| format!("cannot convert its colour profile: {e}"))?;
+    let mut converted = vec![0; pixels.data.len()];
+    transform
+        .transform(&pixels.data, &mut converted)
+        .map_err(|e| format!("cannot convert its colour profile: {e}"))?;
+    pixels.data = converted;
+    Ok(())
+}
+
+fn encode(src: &Path, pixels: &Pixels, icc: Option<&[u8]>, jpeg: JpegOptions) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut out, RESIZE_JPEG_QUALITY);
+    if let Some(icc) = icc {
+        encoder
+            .add_icc_profile(icc)
+            .map_err(|e| format!("cannot embed the colour profile of {}: {e}", src.display()))?;
+    }
+    encoder.set_progressive(jpeg.progressive);
+    encoder.set_optimized_huffman_tables(jpeg.optimize);
+    encoder.set_sampling_factor(match jpeg.chroma {
+        Chroma::Full => SamplingFactor::R_4_4_4,
+        Chroma::Half => SamplingFactor::R_4_2_2,
+        Chroma::Quarter => SamplingFactor::R_4_2_0,
+    });
+    let (w, h) = (pixels.width, pixels.height);
+    let (Ok(ew), Ok(eh)) = (u16::try_from(w), u16::try_from(h)) else {
+        return Err(format!("cannot encode {}: {w}x{h} is too large for JPEG", src.display()));
+    };
+    let color = if pixels.grey { jpeg_encoder::ColorType::Luma } else { jpeg_encoder::ColorType::Rgb };
+    encoder
+        .encode(&pixels.data, ew, eh, color)
+        .map_err(|e| format!("cannot encode {}: {e}", src.display()))?;
+    Ok(out)
+}
+
+#[derive(Debug, PartialEq)]
+struct ResizePlan {
+    /// x, y, width, height of the region to keep.
+    crop: Option<(u32, u32, u32, u32)>,
+    /// Final size.
+    scale: Option<(u32, u32)>,
+}
+
+impl ResizePlan {
+    /// The same plan for an image of `to` pixels covering the same picture
+    /// as one of `from`, such as a gain map.
+    fn scaled(&self, (w, h): (u32, u32), (tw, th): (u32, u32)) -> ResizePlan {
+        let x = |v: u32| (v as u64 * tw as u64 / w as u64) as u32;
+        let y = |v: u32| (v as u64 * th as u64 / h as u64) as u32;
+        ResizePlan {
+            crop: self.crop.map(|(cx, cy, cw, ch)| (x(cx), y(cy), x(cw).max(1), y(ch).max(1))),
+            scale: self.scale.map(|(sw, sh)| (x(sw).max(1), y(sh).max(1))),
+        }
+    }
+}
+
+/// What to do with a `w`x`h` (display orientation) photo; `None` if nothing.
+/// Photos are never scaled up.
+fn plan_resize(w: u32, h: u32, resize: Resize) -> Option<ResizePlan> {
+    let (tw, th) = (resize.width as u64, resize.height as u64);
+    let (mut cw, mut ch) = (w as u64, h as u64);
+    let mut crop = None;
+    if resize.fit == Fit::Crop {
+        if cw * th > ch * tw {
+            cw = (ch * tw / th).max(1);
+        } else {
+            ch = (cw * th / tw).max(1);
+        }
+        if (cw, ch) != (w as u64, h as u64) {
+            crop = Some((((w as u64 - cw) / 2) as u32, ((h as u64 - ch) / 2) as u32, cw as u32, ch as u32));
+        }
+    }
+    let (mut sw, mut sh) = if cw > tw || ch > th {
+        // Largest size with the same aspect ratio that fits the box; after a
+        // crop that is the box itself (whatever the rounding above).
+        if resize.fit == Fit::Crop {
+            (tw, th)
+        } else if cw * th > ch * tw {
+            (tw, (ch * tw / cw).max(1))
+        } else {
+            ((cw * th / ch).max(1), th)
+        }
+    } else {
+        (cw, ch)
+    };
+    if let Some(max) = resize.max_pixels.filter(|&max| sw * sh > max) {
+        let f = (max as f64 / (sw * sh) as f64).sqrt();
+        (sw, sh) = (((sw as f64 * f) as u64).max(1), ((sh as f64 * f) as u64).max(1));
+    }
+    let scale = ((sw, sh) != (cw, ch)).then_some((sw as u32, sh as u32));
+    (crop.is_some() || scale.is_some()).then_some(ResizePlan { crop, scale })
+}
+
+/// Reorders every group so consecutive photos differ in colour/brightness.
+pub fn diversify_groups(groups: &mut [Vec<FileInfo>]) -> Result<(), String> {
+    for group in groups.iter_mut() {
+        let sigs = group
+            .iter()
+            .map(|f| color_signature(&f.path))
+            .collect::<Result<Vec<_>, _>>()?;
+        let order = spread_order(&sigs, DIVERSITY_WINDOW);
+        let mut reordered = Vec::with_capacity(group.len());
+        for idx in order {
+            reordered.push(group[idx].clone());
+        }
+        *group = reordered;
+    }
+    Ok(())
+}
+
+/// Greedy pass over the current order: at each step take, from the next
+/// `window` remaining photos, the one least similar to the previous pick.
+fn spread_order(sigs: &[ColorSignature], window: usize) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..sigs.len()).collect();
+    let mut out = Vec::with_capacity(sigs.len());
+    while !remaining.is_empty() {
+        let pick = match out.last() {
+            None => 0,
+            Some(&prev) => {
+                let candidates = remaining.len().min(window.max(1));
+                (0..candidates)
+                    .max_by(|&a, &b| {
+                        distance(&sigs[prev], &sigs[remaining[a]])
+                            .total_cmp(&distance(&sigs[prev], &sigs[remaining[b]]))
+                            // Prefer the earlier candidate on ties.
+                            .then(b.cmp(&a))
+                    })
+                    .unwrap_or(0)
+            }
+        };
+        out.push(remaining.remove(pick));
+    }
+    out
+}
+
+fn distance(a: &ColorSignature, b: &ColorSignature) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_order_breaks_up_similar_runs() {
+        let beach = [200.0, 190.0, 150.0, 190.0];
+        let forest = [30.0, 90.0, 40.0, 70.0];
+        let sigs = vec![beach, beach, beach, forest, forest];
+        let order = spread_order(&sigs, 8);
+        assert_eq!(order, vec![0, 3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn reencoding_keeps_or_converts_the_colour_profile() {
+        let p3 = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+        let mut original = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut original, 95);
+        encoder.add_icc_profile(&p3).unwrap();
+        encoder.encode(&[0, 200, 0].repeat(64 * 32), 64, 32, jpeg_encoder::ColorType::Rgb).unwrap();
+        let src = std::env::temp_dir().join(format!("image-rando-icc-{}.jpg", std::process::id()));
+        fs::write(&src, &original).unwrap();
+
+        let resize = Resize { width: 32, height: 32, max_pixels: None, fit: Fit::Inside, filter: ResizeFilter::Bilinear };
+        let kept = resized_jpeg(&src, resize, JpegOptions::default()).unwrap().unwrap();
+        let converted = resized_jpeg(&src, resize, JpegOptions { convert_srgb: true, ..Default::default() }).unwrap().unwrap();
+        fs::remove_file(&src).unwrap();
+        let profile = |data: &[u8]| {
+            let mut decoder = image::codecs::jpeg::JpegDecoder::new(Cursor::new(data)).unwrap();
+            decoder.icc_profile().unwrap()
+        };
+        assert_eq!(profile(&kept), Some(p3));
+        assert_eq!(profile(&converted), None);
+        // P3 green is more saturated than sRGB's, which needs a larger value
+        // to get close.
+        let green = |data: &[u8]| image::load_from_memory(data).unwrap().to_rgb8().get_pixel(8, 8).0;
+        let (kept, converted) = (green(&kept), green(&converted));
+        assert!(converted[1] > kept[1] + 2, "{kept:?} -> {converted:?}");
+    }
+
+    #[test]
+    fn letterbox_centres_the_photo_on_black_bars() {
+        // A 4x1 white strip in a 16:9 frame: 4x3 (2.25 rounded up), one row in.
+        let strip = |width, height, grey| Pixels { data: vec![255; 12], width, height, grey };
+        let Pixels { data: canvas, width, height, .. } = letterbox(&strip(4, 1, false), (16, 9));
+        assert_eq!((width, height), (4, 3));
+        assert_eq!(canvas[..12], [0; 12]);
+        assert_eq!(canvas[12..24], [255; 12]);
+        assert_eq!(canvas[24..], [0; 12]);
+        // A tall greyscale strip (a gain map) gets bars left and right.
+        let Pixels { data: canvas, width, height, .. } = letterbox(&strip(1, 12, true), (16, 9));
+        assert_eq!((width, height), (22, 12));
+        assert_eq!(canvas.iter().filter(|&&b| b == 255).count(), 12);
+        assert_eq!(canvas[9..11], [0, 255]);
+    }
+
+    #[test]
+    fn screen_frames_fill_the_screen_without_enlarging() {
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, 95)
+            .encode(&[255; 3].repeat(64 * 16), 64, 16, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+        let src = std::env::temp_dir().join(format!("image-rando-screen-{}.jpg", std::process::id()));
+        fs::write(&src, &jpeg).unwrap();
+        let shrunk = screen_frame(&src, (32, 32)).unwrap();
+        let centred = screen_frame(&src, (100, 20)).unwrap();
+        fs::remove_file(&src).unwrap();
+        // 32x8 in the middle of 32x32: black above and below.
+        assert_eq!(shrunk.len(), 32 * 32 * 3);
+        let row = |frame: &[u8], width: usize, y: usize| frame[y * width * 3..(y + 1) * width * 3].to_vec();
+        assert!(row(&shrunk, 32, 11).iter().all(|&b| b == 0));
+        assert!(row(&shrunk, 32, 16).iter().all(|&b| b > 250));
+        // Kept at 64x16 in 100x20: 18 black columns either side.
+        let middle = row(&centred, 100, 10);
+        assert!(middle[..17 * 3].iter().all(|&b| b == 0) && middle[19 * 3..81 * 3].iter().all(|&b| b > 250));
+    }
+
+    #[test]
+    fn plan_resize_fits_or_crops_without_upscaling() {
+        let inside = Resize { width: 3840, height: 2160, max_pixels: None, fit: Fit::Inside, filter: ResizeFilter::Lanczos3 };
+        let crop = Resize { fit: Fit::Crop, ..inside };
+        assert_eq!(plan_resize(1920, 1080, inside), None);
+        assert_eq!(
+            plan_resize(6000, 4000, inside),
+            Some(ResizePlan { crop: None, scale: Some((3240, 2160)) })
+        );
+        // Portrait 3:4 into a 16:9 box: keep a centred 16:9 band, then shrink.
+        assert_eq!(
+            plan_resize(3000, 4000, crop),
+            Some(ResizePlan { crop: Some((0, 1156, 3000, 1687)), scale: None })
+        );
+        assert_eq!(
+            plan_resize(8000, 4500, crop),
+            Some(ResizePlan { crop: None, scale: Some((3840, 2160)) })
+        );
+        assert_eq!(
+            plan_resize(5000, 3000, crop),
+            Some(ResizePlan { crop: Some((0, 94, 5000, 2812)), scale: Some((3840, 2160)) })
+        );
+
+        let megapixels = |max_pixels, width, height| Resize { width, height, max_pixels: Some(max_pixels), ..inside };
+        assert_eq!(plan_resize(4000, 3000, megapixels(12_000_000, u32::MAX, u32::MAX)), None);
+        // Portrait or landscape, the result is just under 12 MP.
+        assert_eq!(
+            plan_resize(6000, 8000, megapixels(12_000_000, u32::MAX, u32::MAX)),
+            Some(ResizePlan { crop: None, scale: Some((3000, 4000)) })
+        );
+        assert_eq!(
+            plan_resize(8000, 6000, megapixels(12_000_000, u32::MAX, u32::MAX)),
+            Some(ResizePlan { crop: None, scale: Some((4000, 3000)) })
+        );
+        // With a box as well, whichever limit is tighter wins.
+        assert_eq!(
+            plan_resize(6000, 4000, megapixels(2_000_000, 3840, 2160)),
+            Some(ResizePlan { crop: None, scale: Some((1732, 1154)) })
+        );
+    }
+
+    #[test]
+    fn spread_order_respects_window() {
+        let a = [0.0; 4];
+        let b = [255.0; 4];
+        let sigs = vec![a, a, a, b];
+        // With a window of 1 the original order is kept.
+        assert_eq!(spread_order(&sigs, 1), vec![0, 1, 2, 3]);
+    }
+}
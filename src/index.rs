@@ -0,0 +1,209 @@
+// The library catalog written by `index` and read back with `--from-index`.
+//
+// It holds what a scan and the metadata passes find (paths, sizes, EXIF and
+// XMP fields) so runs over a huge library can plan without walking it. Paths
+// are stored relative to the source, `/`-separated and exactly as on disk.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache::{exif_from_json, push_exif};
+use crate::json::Value;
+use crate::{xmp, FileInfo, ImageFormat, MediaKind, ScanOptions};
+
+/// File name inside the source folder when `index --index` isn't given.
+pub const FILE_NAME: &str = ".image-rando-index.json";
+
+const VERSION: u64 = 1;
+
+/// Where `xmp::Xmp::place` is kept, narrowest first.
+const PLACE_KEYS: [&str; 3] = ["city", "state", "country"];
+
+pub fn save(path: &Path, src: &Path, created: &str, files: &[FileInfo]) -> Result<(), String> {
+    let files = files
+        .iter()
+        .map(|f| {
+            let mut fields = vec![
+                ("path".to_string(), Value::from(stored_path(src, &f.path))),
+                ("name".to_string(), Value::from(f.name.as_str())),
+                ("size".to_string(), Value::from(f.size)),
+                ("album".to_string(), Value::from(f.album.as_str())),
+            ];
+            if f.kind == MediaKind::Video {
+                fields.push(("video".to_string(), Value::from(true)));
+            }
+            if let Some(format) = f.format {
+                fields.push(("format".to_string(), Value::from(format.extension())));
+            }
+            if !f.sidecars.is_empty() {
+                let sidecars = f
+                    .sidecars
+                    .iter()
+                    .map(|(p, size)| {
+                        Value::Array(vec![Value::from(stored_path(src, p)), Value::from(*size)])
+                    })
+                    .collect();
+                fields.push(("sidecars".to_string(), Value::Array(sidecars)));
+            }
+            push_exif(&mut fields, &f.exif);
+            if !f.xmp.people.is_empty() {
+                let people = f.xmp.people.iter().map(|p| Value::from(p.as_str())).collect();
+                fields.push(("people".to_string(), Value::Array(people)));
+            }
+            if !f.xmp.keywords.is_empty() {
+                let keywords = f.xmp.keywords.iter().map(|k| Value::from(k.as_str())).collect();
+                fields.push(("keywords".to_string(), Value::Array(keywords)));
+            }
+            if let Some(stars) = f.xmp.rating {
+                fields.push(("stars".to_string(), Value::from(i64::from(stars))));
+            }
+            for (key, name) in PLACE_KEYS.iter().zip(&f.xmp.place) {
+                if let Some(name) = name {
+                    fields.push((key.to_string(), Value::from(name.as_str())));
+                }
+            }
+            Value::Object(fields)
+        })
+        .collect();
+    let catalog = Value::Object(vec![
+        ("version".to_string(), Value::from(VERSION)),
+        ("created".to_string(), Value::from(created)),
+        ("files".to_string(), Value::Array(files)),
+    ]);
+    // Written aside and renamed, so a run reading it never sees half a file.
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, catalog.to_pretty())
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("cannot write index {}: {e}", path.display()))
+}
+
+/// The catalog's files below `src`, keeping only what `opts` asks a scan
+/// for: videos with `--include-videos`, sidecars with `--with-sidecars`.
+pub fn load(path: &Path, src: &Path, opts: &ScanOptions) -> Result<Vec<FileInfo>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read index {}: {e}", path.display()))?;
+    let bad = |e: String| format!("bad index {}: {e}", path.display());
+    let v = Value::parse(&text).map_err(bad)?;
+    if v.get("version").and_then(Value::as_u64) != Some(VERSION) {
+        return Err(bad("unsupported version".to_string()));
+    }
+    let mut files = Vec::new();
+    for item in v.get("files").and_then(Value::as_array).ok_or_else(|| bad("files is not a list".to_string()))? {
+        let text = |key| item.get(key).and_then(Value::as_str);
+        let (Some(rel), Some(name), Some(size)) = (text("path"), text("name"), item.get("size").and_then(Value::as_u64))
+        else {
+            return Err(bad("entry without path, name or size".to_string()));
+        };
+        let kind = if item.get("video").is_some() { MediaKind::Video } else { MediaKind::Photo };
+        if kind == MediaKind::Video && !opts.include_videos {
+            continue;
+        }
+        // Catalogs from before formats were recorded hold only JPEGs.
+        let format = match kind {
+            MediaKind::Photo => Some(text("format").and_then(ImageFormat::parse).unwrap_or(ImageFormat::Jpeg)),
+            MediaKind::Video => None,
+        };
+        if format.is_some_and(|f| !opts.formats.contains(&f)) {
+            continue;
+        }
+        let mut sidecars = Vec::new();
+        if opts.with_sidecars {
+            for s in item.get("sidecars").and_then(Value::as_array).unwrap_or_default() {
+                let pair = s.as_array().unwrap_or_default();
+                if let (Some(p), Some(size)) = (pair.first().and_then(Value::as_str), pair.get(1).and_then(Value::as_u64)) {
+                    sidecars.push((src.join(p), size));
+                }
+            }
+        }
+        let strings = |key| -> Vec<String> {
+            let list = item.get(key).and_then(Value::as_array).unwrap_or_default();
+            list.iter().filter_map(Value::as_str).map(str::to_string).collect()
+        };
+        files.push(FileInfo {
+            path: src.join(rel),
+            name: name.to_string(),
+            size,
+            kind,
+            format,
+            album: text("album").unwrap_or_default().to_string(),
+            exif: exif_from_json(item),
+            xmp: xmp::Xmp {
+                people: strings("people"),
+                rating: item.get("stars").and_then(Value::as_i64).map(|r| r as i8),
+                keywords: strings("keywords"),
+                place: PLACE_KEYS.map(|key| text(key).map(str::to_string)),
+            },
+            sidecars,
+            flatten: false,
+        });
+    }
+    Ok(files)
+}
+
+fn stored_path(src: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(src).unwrap_or(path);
+    let parts: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    parts.join("/")
+}
+
+/// The default catalog location for `src`.
+pub fn default_path(src: &Path) -> PathBuf {
+    src.join(FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exif;
+    use std::env;
+
+    #[test]
+    fn round_trips_files_and_drops_what_the_run_does_not_scan_for() {
+        let root = env::temp_dir().join(format!("image-rando-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let photo = FileInfo {
+            path: root.join("2019/a.jpg"),
+            name: "a.jpg".to_string(),
+            size: 5,
+            kind: MediaKind::Photo,
+            format: Some(ImageFormat::Jpeg),
+            album: "2019".to_string(),
+            exif: exif::Exif { gps: Some((1.5, -2.25)), rating: Some(3), ..Default::default() },
+            xmp: xmp::Xmp {
+                people: vec!["Ann".to_string()],
+                rating: Some(-1),
+                keywords: vec!["beach".to_string()],
+                place: [None, Some("Oregon".to_string()), Some("USA".to_string())],
+            },
+            sidecars: vec![(root.join("2019/a.jpg.xmp"), 9)],
+            flatten: false,
+        };
+        let video = FileInfo {
+            path: root.join("clip.mp4"),
+            name: "clip.mp4".to_string(),
+            kind: MediaKind::Video,
+            format: None,
+            album: String::new(),
+            xmp: xmp::Xmp::default(),
+            exif: exif::Exif::default(),
+            sidecars: Vec::new(),
+            ..photo.clone()
+        };
+        let catalog = default_path(&root);
+        save(&catalog, &root, "2024-01-01T00:00:00Z", &[photo.clone(), video.clone()]).unwrap();
+
+        let mut opts = ScanOptions { include_videos: true, with_sidecars: true, ..Default::default() };
+        let loaded = load(&catalog, &root, &opts).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!((&loaded[0].path, &loaded[0].album, &loaded[0].sidecars), (&photo.path, &photo.album, &photo.sidecars));
+        assert_eq!((&loaded[0].exif, &loaded[0].xmp), (&photo.exif, &photo.xmp));
+        assert_eq!((loaded[0].format, loaded[1].format), (photo.format, None));
+        assert_eq!((loaded[1].kind, &loaded[1].path), (MediaKind::Video, &video.path));
+
+        opts = ScanOptions::default();
+        let loaded = load(&catalog, &root, &opts).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].sidecars.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,966 @@
+// Lossless JPEG optimisation (`--optimize-jpeg`), in the spirit of jpegtran.
+//
+// The entropy-coded data is decoded down to DCT coefficients and written
+// again with Huffman tables built for this very image, optionally as a
+// progressive file. Coefficients are never touched, so the decoded pixels are
+// identical. Metadata that displays don't use is dropped on the way.
+//
+// Only 8-bit Huffman-coded sequential files (SOF0/SOF1) are rewritten, which
+// is what cameras and phones produce; anything else is reported as
+// unsupported and should be copied unchanged.
+
+/// Coefficients of one 8x8 block, in zig-zag order.
+type Block = [i16; 64];
+
+const MAX_EOBRUN: u32 = 0x7FFF;
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    /// Blocks per row, padded to whole MCUs.
+    padded_w: usize,
+    /// Blocks per row/column that cover the image (non-interleaved scans).
+    w: usize,
+    hgt: usize,
+    blocks: Vec<Block>,
+}
+
+struct Frame {
+    components: Vec<Component>,
+    mcus_x: usize,
+    mcus_y: usize,
+}
+
+/// A Huffman table in decoding form (JPEG Annex F.2.2.3).
+#[derive(Clone)]
+struct DecodeTable {
+    maxcode: [i32; 18],
+    valptr: [i32; 17],
+    mincode: [i32; 17],
+    values: Vec<u8>,
+}
+
+/// Returns the optimised file, or why it cannot be optimised.
+pub fn optimize(data: &[u8], progressive: bool) -> Result<Vec<u8>, String> {
+    let Decoded { frame, mut head, sof_at } = decode(data)?;
+    if progressive {
+        head[sof_at + 1] = 0xC2;
+    }
+    let mut out = vec![0xFF, 0xD8];
+    out.extend_from_slice(&head);
+    if progressive {
+        write_progressive(&mut out, &frame)?;
+    } else {
+        write_sequential(&mut out, &frame)?;
+    }
+    out.extend_from_slice(&[0xFF, 0xD9]);
+    Ok(out)
+}
+
+/// Why `data` is not a complete, intact JPEG, if it isn't (`audit`).
+/// Sequential files are decoded down to their coefficients, which catches
+/// corrupt entropy data; other kinds only have their structure checked.
+pub fn check(data: &[u8]) -> Result<(), String> {
+    if check_structure(data)? {
+        decode(data)?;
+    }
+    Ok(())
+}
+
+/// Walks the segments up to the end-of-image marker, skipping entropy-coded
+/// data. Returns whether the frame is one `decode` understands.
+fn check_structure(data: &[u8]) -> Result<bool, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("not a JPEG".to_string());
+    }
+    let mut pos = 2;
+    let mut sequential = false;
+    loop {
+        if pos >= data.len() {
+            return Err("truncated: no end-of-image marker".to_string());
+        }
+        let marker = next_marker(data, &mut pos)?;
+        match marker {
+            0xD9 => return Ok(sequential),
+            0xD0..=0xD7 | 0x01 => continue,
+            _ => {}
+        }
+        let len = be16(data, pos).map_err(|_| "truncated: no end-of-image marker")? as usize;
+        if len < 2 || pos + len > data.len() {
+            return Err("truncated segment".to_string());
+        }
+        if matches!(marker, 0xC0 | 0xC1) {
+            // Too short to hold even the sample precision.
+            if len < 3 {
+                return Err("truncated segment".to_string());
+            }
+            sequential = data[pos + 2] == 8;
+        }
+        pos += len;
+        if marker == 0xDA {
+            // Entropy-coded data runs up to the next marker other than a
+            // stuffed 0xFF00 or a restart marker.
+            while pos + 1 < data.len() && (data[pos] != 0xFF || matches!(data[pos + 1], 0x00 | 0xD0..=0xD7 | 0xFF)) {
+                pos += 1;
+            }
+            if pos + 1 >= data.len() {
+                return Err("truncated: no end-of-image marker".to_string());
+            }
+        }
+    }
+}
+
+struct Decoded {
+    frame: Frame,
+    /// Kept segments written before the image data, SOF included.
+    head: Vec<u8>,
+    /// Offset of the SOF segment in `head`.
+    sof_at: usize,
+}
+
+fn decode(data: &[u8]) -> Result<Decoded, String> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("not a JPEG".to_string());
+    }
+    let mut pos = 2;
+    let mut dc_tables: [Option<DecodeTable>; 4] = Default::default();
+    let mut ac_tables: [Option<DecodeTable>; 4] = Default::default();
+    let mut restart_interval = 0usize;
+    let mut frame: Option<Frame> = None;
+    let mut head = Vec::new();
+    let mut sof_at = None;
+
+    loop {
+        let marker = next_marker(data, &mut pos)?;
+        match marker {
+            0xD9 => break,
+            0xD0..=0xD7 | 0x01 => continue,
+            _ => {}
+        }
+        let len = be16(data, pos)? as usize;
+        let seg = data
+            .get(pos + 2..pos + len)
+            .filter(|_| len >= 2)
+            .ok_or("truncated segment")?;
+        let whole = &data[pos - 2..pos + len];
+        pos += len;
+        match marker {
+            0xC0 | 0xC1 => {
+                if frame.is_some() {
+                    return Err("more than one frame".to_string());
+                }
+                frame = Some(parse_frame(seg)?);
+                sof_at = Some(head.len());
+                head.extend_from_slice(whole);
+            }
+            0xC2..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                return Err("not a baseline JPEG".to_string());
+            }
+            0xCC => return Err("arithmetic coding is not supported".to_string()),
+            0xC4 => parse_dht(seg, &mut dc_tables, &mut ac_tables)?,
+            0xDD => {
+                restart_interval = be16(seg, 0)? as usize;
+            }
+            0xDA => {
+                let frame = frame.as_mut().ok_or("scan before frame")?;
+                pos = decode_scan(data, pos, seg, frame, &dc_tables, &ac_tables, restart_interval)?;
+            }
+            0xDB => head.extend_from_slice(whole),
+            0xE0..=0xEF | 0xFE => {
+                if keep_segment(marker, seg) {
+                    head.extend_from_slice(whole);
+                }
+            }
+            _ => return Err(format!("unsupported marker 0x{marker:02X}")),
+        }
+    }
+
+    let frame = frame.ok_or("no image data")?;
+    let sof_at = sof_at.ok_or("no image data")?;
+    Ok(Decoded { frame, head, sof_at })
+}
+
+/// Keeps what affects how the image looks: JFIF, EXIF (orientation, capture
+/// date), ICC profiles and the Adobe colour transform flag. XMP, Photoshop
+/// blocks, comments, and MPF (which points at extra images appended after
+/// the main one) go.
+fn keep_segment(marker: u8, seg: &[u8]) -> bool {
+    match marker {
+        0xE0 => seg.starts_with(b"JFIF\0"),
+        0xE1 => seg.starts_with(b"Exif\0"),
+        0xE2 => seg.starts_with(b"ICC_PROFILE\0"),
+        0xEE => seg.starts_with(b"Adobe"),
+        _ => false,
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Result<u16, String> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "truncated segment".to_string())
+}
+
+/// Reads the marker at `pos` (after any fill bytes) and moves past it.
+fn next_marker(data: &[u8], pos: &mut usize) -> Result<u8, String> {
+    if data.get(*pos) != Some(&0xFF) {
+        return Err("expected a marker".to_string());
+    }
+    while data.get(*pos) == Some(&0xFF) {
+        *pos += 1;
+    }
+    let marker = *data.get(*pos).ok_or("truncated file")?;
+    *pos += 1;
+    Ok(marker)
+}
+
+fn parse_frame(seg: &[u8]) -> Result<Frame, String> {
+    if seg.len() < 6 || seg[0] != 8 {
+        return Err("only 8-bit JPEGs are supported".to_string());
+    }
+    let height = be16(seg, 1)? as usize;
+    let width = be16(seg, 3)? as usize;
+    let count = seg[5] as usize;
+    if height == 0 || width == 0 || count == 0 || count > 4 || seg.len() < 6 + 3 * count {
+        return Err("unsupported frame header".to_string());
+    }
+    let mut comps = Vec::with_capacity(count);
+    for k in 0..count {
+        let c = &seg[6 + 3 * k..9 + 3 * k];
+        let (h, v) = ((c[1] >> 4) as usize, (c[1] & 0x0F) as usize);
+        if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+            return Err("bad sampling factors".to_string());
+        }
+        comps.push((c[0], h, v));
+    }
+    let hmax = comps.iter().map(|c| c.1).max().unwrap_or(1);
+    let vmax = comps.iter().map(|c| c.2).max().unwrap_or(1);
+    let mcus_x = width.div_ceil(8 * hmax);
+    let mcus_y = height.div_ceil(8 * vmax);
+    let components = comps
+        .into_iter()
+        .map(|(id, h, v)| {
+            let (padded_w, padded_h) = (mcus_x * h, mcus_y * v);
+            Component {
+                id,
+                h,
+                v,
+                padded_w,
+                w: (width * h).div_ceil(hmax).div_ceil(8),
+                hgt: (height * v).div_ceil(vmax).div_ceil(8),
+                blocks: vec![[0; 64]; padded_w * padded_h],
+            }
+        })
+        .collect();
+    Ok(Frame { components, mcus_x, mcus_y })
+}
+
+fn parse_dht(
+    mut seg: &[u8],
+    dc: &mut [Option<DecodeTable>; 4],
+    ac: &mut [Option<DecodeTable>; 4],
+) -> Result<(), String> {
+    while !seg.is_empty() {
+        let class = seg[0] >> 4;
+        let id = (seg[0] & 0x0F) as usize;
+        let counts = seg.get(1..17).ok_or("truncated DHT")?;
+        let total: usize = counts.iter().map(|&n| n as usize).sum();
+        let values = seg.get(17..17 + total).ok_or("truncated DHT")?;
+        if id > 3 || class > 1 {
+            return Err("bad DHT".to_string());
+        }
+        let table = DecodeTable::new(counts, values);
+        if class == 0 {
+            dc[id] = Some(table);
+        } else {
+            ac[id] = Some(table);
+        }
+        seg = &seg[17 + total..];
+    }
+    Ok(())
+}
+
+impl DecodeTable {
+    fn new(counts: &[u8], values: &[u8]) -> DecodeTable {
+        let mut t = DecodeTable {
+            maxcode: [-1; 18],
+            valptr: [0; 17],
+            mincode: [0; 17],
+            values: values.to_vec(),
+        };
+        let mut code = 0i32;
+        let mut k = 0i32;
+        for len in 1..=16 {
+            let n = counts[len - 1] as i32;
+            if n > 0 {
+                t.valptr[len] = k;
+                t.mincode[len] = code;
+                code += n;
+                k += n;
+                t.maxcode[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        t.maxcode[17] = i32::MAX;
+        t
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u8, String> {
+        let mut code = bits.bit()? as i32;
+        for len in 1..=16 {
+            if code <= self.maxcode[len] {
+                let idx = self.valptr[len] + code - self.mincode[len];
+                return self.values.get(idx as usize).copied().ok_or_else(|| "bad Huffman code".to_string());
+            }
+            code = (code << 1) | bits.bit()? as i32;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, String> {
+        if self.count == 0 {
+            let byte = *self.data.get(self.pos).ok_or("truncated scan")?;
+            if byte == 0xFF {
+                match self.data.get(self.pos + 1) {
+                    Some(0x00) => self.pos += 1,
+                    _ => return Err("truncated scan".to_string()),
+                }
+            }
+            self.pos += 1;
+            self.acc = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Ok((self.acc >> self.count) & 1)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut v = 0;
+        for _ in 0..n {
+            v = (v << 1) | self.bit()?;
+        }
+        Ok(v)
+    }
+
+    /// Reads an `n`-bit magnitude category value (JPEG's EXTEND).
+    fn value(&mut self, n: u32) -> Result<i32, String> {
+        if n == 0 {
+            return Ok(0);
+        }
+        if n > 15 {
+            return Err("bad coefficient size".to_string());
+        }
+        let v = self.bits(n)? as i32;
+        Ok(if v < 1 << (n - 1) { v - (1 << n) + 1 } else { v })
+    }
+
+    /// Skips the padding and the RSTn marker that follows.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err("missing restart marker".to_string()),
+        }
+    }
+}
+
+/// Decodes one sequential scan into `frame`; returns the offset after it.
+fn decode_scan(
+    data: &[u8],
+    start: usize,
+    header: &[u8],
+    frame: &mut Frame,
+    dc_tables: &[Option<DecodeTable>; 4],
+    ac_tables: &[Option<DecodeTable>; 4],
+    restart_interval: usize,
+) -> Result<usize, String> {
+    let count = *header.first().ok_or("bad scan header")? as usize;
+    if count == 0 || header.len() < 1 + 2 * count + 3 {
+        return Err("bad scan header".to_string());
+    }
+    let mut members = Vec::with_capacity(count);
+    for k in 0..count {
+        let (id, tables) = (header[1 + 2 * k], header[2 + 2 * k]);
+        let idx = frame
+            .components
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or("scan names an unknown component")?;
+        let dc = dc_tables[(tables >> 4) as usize & 3].as_ref().ok_or("missing DC table")?;
+        let ac = ac_tables[(tables & 3) as usize].as_ref().ok_or("missing AC table")?;
+        members.push((idx, dc, ac));
+    }
+    let tail = &header[1 + 2 * count..];
+    if tail != [0, 63, 0] {
+        return Err("not a sequential scan".to_string());
+    }
+
+    let mut bits = BitReader { data, pos: start, acc: 0, count: 0 };
+    let mut pred = vec![0i32; count];
+    // Interleaved scans walk MCUs; a single-component scan walks the blocks
+    // that cover the image, one per "MCU".
+    let units: Vec<Vec<(usize, usize, usize)>> = if count == 1 {
+        let c = &frame.components[members[0].0];
+        (0..c.hgt)
+            .flat_map(|by| (0..c.w).map(move |bx| vec![(0, bx, by)]))
+            .collect()
+    } else {
+        let mut units = Vec::with_capacity(frame.mcus_x * frame.mcus_y);
+        for my in 0..frame.mcus_y {
+            for mx in 0..frame.mcus_x {
+                let mut unit = Vec::new();
+                for (m, &(idx, _, _)) in members.iter().enumerate() {
+                    let c = &frame.components[idx];
+                    for y in 0..c.v {
+                        for x in 0..c.h {
+                            unit.push((m, mx * c.h + x, my * c.v + y));
+                        }
+                    }
+                }
+                units.push(unit);
+            }
+        }
+        units
+    };
+
+    for (n, unit) in units.iter().enumerate() {
+        if restart_interval > 0 && n > 0 && n % restart_interval == 0 {
+            bits.restart()?;
+            pred.iter_mut().for_each(|p| *p = 0);
+        }
+        for &(m, bx, by) in unit {
+            let (idx, dc, ac) = members[m];
+            let mut block = [0i16; 64];
+            let size = dc.decode(&mut bits)? as u32;
+            pred[m] += bits.value(size)?;
+            block[0] = pred[m] as i16;
+            let mut k = 1;
+            while k < 64 {
+                let rs = ac.decode(&mut bits)?;
+                let (run, size) = ((rs >> 4) as usize, (rs & 0x0F) as u32);
+                if size == 0 {
+                    if run == 15 {
+                        k += 16;
+                        continue;
+                    }
+                    break;
+                }
+                k += run;
+                if k > 63 {
+                    return Err("bad AC run".to_string());
+                }
+                block[k] = bits.value(size)? as i16;
+                k += 1;
+            }
+            let c = &mut frame.components[idx];
+            c.blocks[by * c.padded_w + bx] = block;
+        }
+    }
+    Ok(bits.pos)
+}
+
+// --- Encoding --------------------------------------------------------------
+
+/// Receives the Huffman symbols of a scan; used once to count frequencies
+/// and once to write bits.
+trait Sink {
+    fn symbol(&mut self, table: usize, symbol: u8);
+    fn raw(&mut self, value: u32, bits: u32);
+}
+
+struct Counter {
+    freq: Vec<[u32; 256]>,
+}
+
+impl Sink for Counter {
+    fn symbol(&mut self, table: usize, symbol: u8) {
+        self.freq[table][symbol as usize] += 1;
+    }
+    fn raw(&mut self, _value: u32, _bits: u32) {}
+}
+
+struct Writer<'a> {
+    out: &'a mut Vec<u8>,
+    codes: Vec<[(u16, u8); 256]>,
+    acc: u32,
+    count: u32,
+}
+
+impl Sink for Writer<'_> {
+    fn symbol(&mut self, table: usize, symbol: u8) {
+        let (code, len) = self.codes[table][symbol as usize];
+        self.raw(code as u32, len as u32);
+    }
+
+    fn raw(&mut self, value: u32, bits: u32) {
+        for k in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> k) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.acc as u8);
+                if self.acc as u8 == 0xFF {
+                    self.out.push(0x00);
+                }
+                self.acc = 0;
+                self.count = 0;
+            }
+        }
+    }
+}
+
+impl Writer<'_> {
+    /// Pads the last byte with 1 bits.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let pad = 8 - self.count;
+            self.raw((1 << pad) - 1, pad);
+        }
+    }
+}
+
+/// Magnitude category and the bits that encode `v` within it.
+fn category(v: i32) -> (u32, u32) {
+    let size = 32 - v.unsigned_abs().leading_zeros();
+    let bits = if v < 0 { (v - 1) as u32 & ((1 << size) - 1) } else { v as u32 };
+    (size, bits)
+}
+
+/// What one scan covers.
+struct ScanPlan {
+    /// Component indices.
+    comps: Vec<usize>,
+    /// Spectral band.
+    ss: usize,
+    se: usize,
+}
+
+impl ScanPlan {
+    /// Huffman table slot of a component: luma gets its own, chroma share one.
+    fn table_of(&self, m: usize) -> usize {
+        usize::from(self.comps[m] != 0)
+    }
+}
+
+/// Feeds one scan's symbols to `sink`. DC tables are slots 0/1, AC tables
+/// slots 2/3.
+fn encode_scan(frame: &Frame, plan: &ScanPlan, sink: &mut impl Sink) {
+    let mut enc = ScanEncoder {
+        sink,
+        pred: vec![0; plan.comps.len()],
+        eobrun: 0,
+        eob_table: 0,
+    };
+    if plan.comps.len() == 1 {
+        let c = &frame.components[plan.comps[0]];
+        for by in 0..c.hgt {
+            for bx in 0..c.w {
+                enc.block(plan, 0, &c.blocks[by * c.padded_w + bx]);
+            }
+        }
+    } else {
+        for my in 0..frame.mcus_y {
+            for mx in 0..frame.mcus_x {
+                for (m, &idx) in plan.comps.iter().enumerate() {
+                    let c = &frame.components[idx];
+                    for y in 0..c.v {
+                        for x in 0..c.h {
+                            enc.block(plan, m, &c.blocks[(my * c.v + y) * c.padded_w + mx * c.h + x]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    enc.flush_eobrun();
+}
+
+struct ScanEncoder<'a, S: Sink> {
+    sink: &'a mut S,
+    pred: Vec<i32>,
+    /// Blocks whose band ended in zeros, not yet written (progressive AC).
+    eobrun: u32,
+    eob_table: usize,
+}
+
+impl<S: Sink> ScanEncoder<'_, S> {
+    fn block(&mut self, plan: &ScanPlan, m: usize, block: &Block) {
+        if plan.ss == 0 {
+            let dc = block[0] as i32;
+            let (size, bits) = category(dc - self.pred[m]);
+            self.pred[m] = dc;
+            self.sink.symbol(plan.table_of(m), size as u8);
+            self.sink.raw(bits, size);
+            if plan.se == 0 {
+                return;
+            }
+        }
+        let table = 2 + plan.table_of(m);
+        let mut run = 0;
+        for &c in &block[plan.ss.max(1)..=plan.se] {
+            if c == 0 {
+                run += 1;
+                continue;
+            }
+            self.flush_eobrun();
+            while run > 15 {
+                self.sink.symbol(table, 0xF0);
+                run -= 16;
+            }
+            let (size, bits) = category(c as i32);
+            self.sink.symbol(table, ((run << 4) as u8) | size as u8);
+            self.sink.raw(bits, size);
+            run = 0;
+        }
+        if run == 0 {
+            return;
+        }
+        if plan.ss == 0 {
+            // Sequential scans end each block with a plain EOB.
+            self.sink.symbol(table, 0x00);
+        } else {
+            self.eob_table = table;
+            self.eobrun += 1;
+            if self.eobrun == MAX_EOBRUN {
+                self.flush_eobrun();
+            }
+        }
+    }
+
+    /// Writes the pending run as EOBn: `n` is the run's bit length minus one.
+    fn flush_eobrun(&mut self) {
+        if self.eobrun == 0 {
+            return;
+        }
+        let n = 31 - self.eobrun.leading_zeros();
+        self.sink.symbol(self.eob_table, (n << 4) as u8);
+        self.sink.raw(self.eobrun - (1 << n), n);
+        self.eobrun = 0;
+    }
+}
+
+/// Writes DHT, SOS and entropy-coded data for one scan.
+fn write_scan(out: &mut Vec<u8>, frame: &Frame, plan: &ScanPlan) -> Result<(), String> {
+    let mut counter = Counter { freq: vec![[0; 256]; 4] };
+    encode_scan(frame, plan, &mut counter);
+
+    let mut codes = vec![[(0u16, 0u8); 256]; 4];
+    let mut dht = Vec::new();
+    for (slot, freq) in counter.freq.iter().enumerate() {
+        if freq.iter().all(|&f| f == 0) {
+            continue;
+        }
+        let (counts, values) = build_table(freq)?;
+        codes[slot] = code_map(&counts, &values);
+        let class = if slot < 2 { 0x00 } else { 0x10 };
+        dht.push(class | (slot & 1) as u8);
+        dht.extend_from_slice(&counts);
+        dht.extend_from_slice(&values);
+    }
+    out.extend_from_slice(&[0xFF, 0xC4]);
+    out.extend_from_slice(&((dht.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(&dht);
+
+    let n = plan.comps.len();
+    out.extend_from_slice(&[0xFF, 0xDA]);
+    out.extend_from_slice(&((6 + 2 * n) as u16).to_be_bytes());
+    out.push(n as u8);
+    for m in 0..n {
+        let t = plan.table_of(m) as u8;
+        out.push(frame.components[plan.comps[m]].id);
+        out.push((t << 4) | t);
+    }
+    out.extend_from_slice(&[plan.ss as u8, plan.se as u8, 0]);
+
+    let mut writer = Writer { out, codes, acc: 0, count: 0 };
+    encode_scan(frame, plan, &mut writer);
+    writer.flush();
+    Ok(())
+}
+
+fn write_sequential(out: &mut Vec<u8>, frame: &Frame) -> Result<(), String> {
+    let comps: Vec<usize> = (0..frame.components.len()).collect();
+    if comps.len() > 1 && frame.components.iter().map(|c| c.h * c.v).sum::<usize>() > 10 {
+        // Too many blocks per MCU to interleave: one scan per component.
+        for idx in comps {
+            write_scan(out, frame, &ScanPlan { comps: vec![idx], ss: 0, se: 63 })?;
+        }
+        return Ok(());
+    }
+    write_scan(out, frame, &ScanPlan { comps, ss: 0, se: 63 })
+}
+
+/// DC first, then a low and a high AC band per component (spectral
+/// selection only, so no precision is ever split).
+fn write_progressive(out: &mut Vec<u8>, frame: &Frame) -> Result<(), String> {
+    let comps: Vec<usize> = (0..frame.components.len()).collect();
+    if comps.len() > 1 && frame.components.iter().map(|c| c.h * c.v).sum::<usize>() > 10 {
+        for &idx in &comps {
+            write_scan(out, frame, &ScanPlan { comps: vec![idx], ss: 0, se: 0 })?;
+        }
+    } else {
+        write_scan(out, frame, &ScanPlan { comps: comps.clone(), ss: 0, se: 0 })?;
+    }
+    for (ss, se) in [(1, 5), (6, 63)] {
+        for &idx in &comps {
+            write_scan(out, frame, &ScanPlan { comps: vec![idx], ss, se })?;
+        }
+    }
+    Ok(())
+}
+
+/// Optimal code lengths limited to 16 bits (JPEG Annex K.2), as the DHT
+/// `counts` and `values` lists.
+fn build_table(freq: &[u32; 256]) -> Result<([u8; 16], Vec<u8>), String> {
+    let mut freq: Vec<u64> = freq.iter().map(|&f| f as u64).collect();
+    // A reserved symbol keeps any real code from being all 1 bits.
+    freq.push(1);
+    let mut codesize = [0usize; 257];
+    let mut others = [usize::MAX; 257];
+    loop {
+        let mut v1 = None;
+        let mut v2 = None;
+        for (i, &f) in freq.iter().enumerate() {
+            if f == 0 {
+                continue;
+            }
+            // Least frequency; the largest index on ties.
+            if v1.is_none_or(|v: usize| f <= freq[v]) {
+                v2 = v1;
+                v1 = Some(i);
+            } else if v2.is_none_or(|v: usize| f <= freq[v]) {
+                v2 = Some(i);
+            }
+        }
+        let (Some(mut v1), Some(mut v2)) = (v1, v2) else {
+            break;
+        };
+        freq[v1] += freq[v2];
+        freq[v2] = 0;
+        codesize[v1] += 1;
+        while others[v1] != usize::MAX {
+            v1 = others[v1];
+            codesize[v1] += 1;
+        }
+        others[v1] = v2;
+        codesize[v2] += 1;
+        while others[v2] != usize::MAX {
+            v2 = others[v2];
+            codesize[v2] += 1;
+        }
+    }
+
+    let mut bits = [0u32; 33];
+    for &size in &codesize {
+        if size > 32 {
+            return Err("Huffman code too long".to_string());
+        }
+        if size > 0 {
+            bits[size] += 1;
+        }
+    }
+    for i in (17..=32).rev() {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+    let mut i = 16;
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    let mut counts = [0u8; 16];
+    for len in 1..=16 {
+        counts[len - 1] = bits[len] as u8;
+    }
+    // Symbols by increasing (unclamped) code length; clamping keeps that
+    // order, and the reserved symbol is left out.
+    let mut values = Vec::new();
+    for len in 1..=32 {
+        for (sym, &size) in codesize.iter().enumerate().take(256) {
+            if size == len {
+                values.push(sym as u8);
+            }
+        }
+    }
+    Ok((counts, values))
+}
+
+/// Canonical codes for a DHT table (JPEG Annex C).
+fn code_map(counts: &[u8; 16], values: &[u8]) -> [(u16, u8); 256] {
+    let mut map = [(0u16, 0u8); 256];
+    let mut code = 0u16;
+    let mut k = 0;
+    for (len, &n) in counts.iter().enumerate() {
+        for _ in 0..n {
+            map[values[k] as usize] = (code, (len + 1) as u8);
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A baseline JPEG with made-up coefficients, 4:2:0 sampling and a size
+    /// that needs padding blocks, plus an XMP packet and a comment.
+    fn sample() -> Vec<u8> {
+        let (width, height) = (40u16, 24u16);
+        let mut sof = vec![8];
+        sof.extend_from_slice(&height.to_be_bytes());
+        sof.extend_from_slice(&width.to_be_bytes());
+        sof.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 0, 3, 0x11, 0]);
+        let mut frame = parse_frame(&sof).unwrap();
+        let mut seed = 0x1234_5678u32;
+        for c in frame.components.iter_mut() {
+            for block in c.blocks.iter_mut() {
+                for (k, coef) in block.iter_mut().enumerate() {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    // Mostly zeros at high frequencies, like real photos.
+                    if seed % 64 >= k as u32 {
+                        *coef = (seed >> 8) as i16 % (200 / (k as i16 + 1) + 1);
+                    }
+                }
+            }
+        }
+
+        let mut out = vec![0xFF, 0xD8];
+        let mut segment = |marker: u8, body: &[u8]| {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+            out.extend_from_slice(body);
+        };
+        segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        segment(0xE1, b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>");
+        segment(0xFE, b"made by a test");
+        let mut dqt = vec![0];
+        dqt.extend_from_slice(&[1; 64]);
+        segment(0xDB, &dqt);
+        segment(0xC0, &sof);
+        write_sequential(&mut out, &frame).unwrap();
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+
+    /// Every component's coefficients, as decoded from `data`.
+    fn coefficients(data: &[u8]) -> Vec<Vec<Block>> {
+        let mut pos = 2;
+        let mut dc: [Option<DecodeTable>; 4] = Default::default();
+        let mut ac: [Option<DecodeTable>; 4] = Default::default();
+        let mut frame = None;
+        loop {
+            let marker = next_marker(data, &mut pos).unwrap();
+            if marker == 0xD9 {
+                break;
+            }
+            let len = be16(data, pos).unwrap() as usize;
+            let seg = &data[pos + 2..pos + len];
+            pos += len;
+            match marker {
+                0xC0 => frame = Some(parse_frame(seg).unwrap()),
+                0xC4 => parse_dht(seg, &mut dc, &mut ac).unwrap(),
+                0xDA => pos = decode_scan(data, pos, seg, frame.as_mut().unwrap(), &dc, &ac, 0).unwrap(),
+                _ => {}
+            }
+        }
+        frame.unwrap().components.into_iter().map(|c| c.blocks).collect()
+    }
+
+    #[test]
+    fn sequential_rewrite_keeps_coefficients_and_drops_metadata() {
+        let original = sample();
+        let optimized = optimize(&original, false).unwrap();
+        assert!(optimized.len() < original.len());
+        assert_eq!(coefficients(&optimized), coefficients(&original));
+        assert!(!optimized.windows(4).any(|w| w == b"xmp"));
+        assert!(optimized.windows(4).any(|w| w == b"JFIF"));
+        assert_eq!(optimize(&optimized, false).unwrap(), optimized);
+    }
+
+    #[test]
+    fn progressive_output_is_marked_and_not_reprocessed() {
+        let progressive = optimize(&sample(), true).unwrap();
+        assert!(progressive.windows(2).any(|w| w == [0xFF, 0xC2]));
+        assert!(optimize(&progressive, false).is_err());
+        assert!(optimize(b"GIF89a", false).is_err());
+    }
+
+    #[test]
+    fn check_finds_truncated_files() {
+        let baseline = sample();
+        let progressive = optimize(&baseline, true).unwrap();
+        assert_eq!(check(&baseline), Ok(()));
+        assert_eq!(check(&progressive), Ok(()));
+        for data in [&baseline, &progressive] {
+            assert!(check(&data[..data.len() - 2]).is_err());
+            assert!(check(&data[..data.len() / 2]).is_err());
+        }
+        assert!(check(b"GIF89a").is_err());
+        // A frame header with no room for its fields, at the very end.
+        assert!(check(&[0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x02]).is_err());
+    }
+
+    #[test]
+    fn built_tables_fit_in_16_bits() {
+        let mut freq = [0u32; 256];
+        // Fibonacci frequencies would give codes far longer than 16 bits.
+        let (mut a, mut b) = (1u32, 1u32);
+        for f in freq.iter_mut().take(40) {
+            *f = a;
+            (a, b) = (b, a.saturating_add(b));
+        }
+        let (counts, values) = build_table(&freq).unwrap();
+        assert_eq!(values.len(), 40);
+        assert_eq!(counts.iter().map(|&n| n as usize).sum::<usize>(), 40);
+        // Kraft sum stays below 1: the all-ones code is never used.
+        let kraft: f64 = counts.iter().enumerate().map(|(l, &n)| n as f64 / (1u64 << (l + 1)) as f64).sum();
+        assert!(kraft < 1.0);
+        assert_eq!(category(-3), (2, 0b00));
+        assert_eq!(category(5), (3, 0b101));
+    }
+
+    /// Decodes both files with the `image` crate and compares pixels.
+    #[cfg(feature = "imaging")]
+    #[test]
+    fn optimized_files_decode_to_identical_pixels() {
+        let mut rgb = image::RgbImage::new(61, 37);
+        for (x, y, px) in rgb.enumerate_pixels_mut() {
+            *px = image::Rgb([(x * 4) as u8, (y * 6) as u8, ((x * y) % 256) as u8]);
+        }
+        let mut original = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut original, 85)
+            .encode_image(&rgb)
+            .unwrap();
+        let decode = |data: &[u8]| image::load_from_memory(data).unwrap().to_rgb8();
+        for progressive in [false, true] {
+            let optimized = optimize(&original, progressive).unwrap();
+            assert!(optimized.len() < original.len());
+            assert_eq!(decode(&optimized), decode(&original), "progressive: {progressive}");
+        }
+    }
+}
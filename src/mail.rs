@@ -0,0 +1,66 @@
+// `--email-report`: mails the run summary through the local `sendmail`
+// interface.
+//
+// Talking SMTP (with TLS and authentication) would need a network stack this
+// crate doesn't have; msmtp, Postfix, OpenSMTPD and the like all install a
+// `sendmail` that reads a message on stdin and deliver it with the relay,
+// account and password in their own configuration.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Program the message is handed to, looked up on `PATH`.
+const SENDMAIL: &str = "sendmail";
+
+/// Rejects what isn't a single plain address, so nothing can be smuggled
+/// into the headers.
+pub fn check_address(to: &str) -> Result<(), String> {
+    let plain = to.split_once('@').is_some_and(|(user, host)| !user.is_empty() && host.contains('.'))
+        && !to.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, ',' | '<' | '>'));
+    if plain {
+        Ok(())
+    } else {
+        Err(format!("--email-report needs one address like user@example.com, not {to}"))
+    }
+}
+
+/// A plain-text mail to `to`, with the bare `\n` line ends sendmail takes.
+pub fn message(to: &str, subject: &str, body: &str) -> String {
+    format!(
+        "To: {to}\nSubject: {subject}\nMIME-Version: 1.0\nContent-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: 8bit\n\n{}\n",
+        body.trim_end()
+    )
+}
+
+pub fn send(to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let failed = |e: std::io::Error| format!("cannot email the report with {SENDMAIL}: {e}");
+    // `-t` takes the recipient from the headers, `-i` keeps a lone `.` line.
+    let mut child = Command::new(SENDMAIL)
+        .args(["-t", "-i"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    let written = child.stdin.take().expect("piped stdin").write_all(message(to, subject, body).as_bytes());
+    let status = child.wait().map_err(failed)?;
+    written.map_err(failed)?;
+    if !status.success() {
+        return Err(format!("cannot email the report: {SENDMAIL} exited with {status}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_addresses_get_into_the_headers() {
+        assert!(check_address("mum@example.com").is_ok());
+        for bad in ["mum", "@example.com", "mum@localhost", "a@b.c\nBcc: x@y.z", "a@b.c, x@y.z"] {
+            assert!(check_address(bad).is_err(), "{bad:?}");
+        }
+        let m = message("mum@example.com", "image-rando: run finished", "Copied 3 photos\nTotal bytes copied: 9");
+        assert!(m.starts_with("To: mum@example.com\nSubject: image-rando: run finished\n"));
+        assert!(m.ends_with("\n\nCopied 3 photos\nTotal bytes copied: 9\n"));
+    }
+}
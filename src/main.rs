@@ -1,349 +1,7676 @@
+mod animated;
+mod bench;
+mod cache;
+#[cfg(unix)]
+mod daemon;
+#[cfg(feature = "imaging")]
+mod display;
+mod dupes;
+mod exif;
+mod gainmap;
+#[cfg(feature = "imaging")]
+mod imaging;
+mod index;
+mod jpegopt;
+mod json;
+mod mail;
+mod manifest;
+mod nfc;
+mod platform;
+mod regex;
+mod select;
+mod template;
+mod trash;
+mod xmp;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use json::Value;
 
-const DEFAULT_SRC: &str = "/home/jef/Pictures/theframe";
-const DEFAULT_DST: &str = "/home/jef/Pictures/display";
 const DEFAULT_MAX_FILES: usize = 1200;
 const DEFAULT_MAX_BYTES: u64 = 4 * 1024 * 1024 * 1024; // 4 GiB
+const DEFAULT_LOCATION_RADIUS_KM: f64 = 25.0;
+const DEFAULT_MAX_VIDEO_FILES: usize = 10;
+const DEFAULT_MAX_VIDEO_BYTES: u64 = 512 * 1024 * 1024; // 512 MiB
+/// Block list used when `--exclude-list` is not given; hidden so scans skip it.
+const DEFAULT_EXCLUDE_LIST: &str = ".image-rando-exclude.txt";
+const DEFAULT_FAVORITES_WEIGHT: f64 = 5.0;
+/// Files smaller than this are copied but warned about; real photos are
+/// rarely under 10 KiB, placeholders and thumbnails often are.
+const SUSPICIOUSLY_SMALL_BYTES: u64 = 10 * 1024;
+/// dHash bits two photos may differ in and still count as near-duplicates.
+const DEFAULT_DUPE_DISTANCE: u32 = 6;
 
 #[derive(Debug, Clone)]
 struct Args {
     src: PathBuf,
     dst: PathBuf,
+    /// Further destinations (`--mirror`, `--distribute`).
+    mirrors: Vec<PathBuf>,
+    /// Deal the planned folders out over the destinations instead of
+    /// writing all of them to each.
+    distribute: bool,
     max_files: usize,
     max_bytes: u64,
+    limit_mode: LimitMode,
+    /// Fewest photos a last folder may have before it is folded away (`--min-files-per-folder`).
+    min_files: Option<usize>,
+    /// Percentage over `max_bytes` a folder may go to take in the last one (`--bytes-tolerance`).
+    bytes_tolerance: Option<f64>,
     seed: u64,
+    scan: ScanOptions,
+    group_by: GroupBy,
+    /// What folder names describe besides their number (`--name-groups-by`).
+    name_groups_by: Vec<GroupLabel>,
+    order: Order,
+    diversify_colors: bool,
+    /// Keep perceptually similar photos in different folders (`--spread-similar`).
+    spread_similar: bool,
+    stratify_by: Option<Stratum>,
+    require_people: Vec<String>,
+    exclude_people: Vec<String>,
+    max_video_files: usize,
+    max_video_bytes: u64,
+    animated: AnimatedPolicy,
+    rename_template: Option<template::Template>,
+    pin_list: Option<PathBuf>,
+    include_list: Option<PathBuf>,
+    exclude_list: Option<PathBuf>,
+    favorites: Option<PathBuf>,
+    favorites_weight: f64,
+    processing: Processing,
+    safe_names: bool,
+    /// Recreate each file's source subfolders inside its numbered folder
+    /// (`--preserve-structure`).
+    preserve_structure: bool,
+    /// Worker threads for flattening/resizing (`--jobs`).
+    jobs: usize,
+    /// Update the previous run in `dst` instead of requiring it to be empty.
+    sync: bool,
+    /// Finish the run whose plan `dst` still holds (`--resume`, taken and
+    /// set by `copy_run`).
+    resume: bool,
+    /// Percentage of the previous run's photos to swap out (`--stable-reshuffle`).
+    stable_reshuffle: Option<u32>,
+    album_quotas: AlbumQuotas,
+    /// Folders (relative to the source) and the percentage of the run each
+    /// should make up (`--src-weight`).
+    src_weights: Vec<(String, u32)>,
+    /// Fill the run from one randomly chosen source folder (`--pick-one-album`).
+    pick_one_album: bool,
+    /// Albums picked by this many earlier runs aren't picked again (`--skip-recent-albums`).
+    skip_recent_albums: usize,
+    /// Stop copying once this much time has passed since the start (`--time-budget`).
+    time_budget: Option<Duration>,
+    /// Local times of day in which nothing is scanned or copied (`--quiet-hours`).
+    quiet_hours: Option<QuietHours>,
+    /// Most files to copy, for quick trial runs (`--limit`).
+    limit: Option<usize>,
+    /// Write to a temporary folder and delete it afterwards (`--sandbox`).
+    sandbox: bool,
+    /// Disjoint selections written to `set-1` .. `set-N` under `dst` (`--sets`).
+    sets: Option<usize>,
+    /// Landscape and portrait photos go to separate trees under `dst` (`--split-by-orientation`).
+    split_by_orientation: bool,
+    dst_layout: DstLayout,
+    /// Run folders to keep with `--dst-layout runs`, the new one included (`--keep-runs`).
+    keep_runs: Option<usize>,
+    /// Move what --sync and --keep-runs delete to the trash (`--use-trash`).
+    use_trash: bool,
+    /// Where EXIF fields are kept between runs; `None` with `--no-exif-cache`.
+    exif_cache: Option<PathBuf>,
+    /// Plan from this `index` catalog instead of scanning (`--from-index`).
+    from_index: Option<PathBuf>,
+    /// Take the selection from a list `--export-selection` wrote (`--import-selection`).
+    import_selection: Option<PathBuf>,
+    /// Metadata filter expression (`--select`); repeats are and-ed.
+    select: Option<select::Selector>,
+    /// Keep only photos taken inside one of these (`--within`).
+    within: Vec<Geofence>,
+    /// Drop photos taken inside any of these (`--outside`).
+    outside: Vec<Geofence>,
+    /// Minutes east of UTC for photos whose EXIF has no offset, and for
+    /// modification times standing in for capture dates (`--assume-timezone`).
+    assume_timezone: Option<i16>,
 }
 
-#[derive(Debug, Clone)]
-struct FileInfo {
-    path: PathBuf,
-    name: String,
-    size: u64,
+/// Limits on the photo bytes a source folder may contribute to a whole run
+/// (`--album-byte-quota`).
+#[derive(Debug, Clone, Default, PartialEq)]
+struct AlbumQuotas {
+    /// Folder (relative to the source) to its limit; covers its subfolders.
+    named: Vec<(String, u64)>,
+    /// Limit for every other album; top-level photos are never limited.
+    default: Option<u64>,
 }
 
-fn main() {
-    if let Err(e) = run() {
-        eprintln!("error: {e}");
-        std::process::exit(1);
+impl AlbumQuotas {
+    fn is_empty(&self) -> bool {
+        self.named.is_empty() && self.default.is_none()
+    }
+
+    /// The bucket an album's photos count against and its limit. The deepest
+    /// named folder wins; otherwise the album is its own bucket.
+    fn bucket<'a>(&'a self, album: &'a str) -> Option<(&'a str, u64)> {
+        let covers = |name: &str| album == name || album.strip_prefix(name).is_some_and(|rest| rest.starts_with('/'));
+        let named = self.named.iter().filter(|(name, _)| covers(name)).max_by_key(|(name, _)| name.len());
+        match named {
+            Some((name, limit)) => Some((name, *limit)),
+            None if album.is_empty() => None,
+            None => self.default.map(|limit| (album, limit)),
+        }
     }
 }
 
-fn run() -> Result<(), String> {
-    let args = parse_args(env::args().collect())?;
-    validate_dirs(&args)?;
+/// What happens to photo content on the way to the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Processing {
+    resize: Option<Resize>,
+    jpeg: JpegOptions,
+    /// Drop the thumbnail embedded in the EXIF block of copied JPEGs.
+    strip_thumbnails: bool,
+    panoramas: Panoramas,
+}
 
-    let mut files = collect_jpgs(&args.src)?;
-    if files.is_empty() {
-        return Err(format!(
-            "no .jpg files found in source folder: {}",
-            args.src.display()
-        ));
+/// Version of the list `Processing::fingerprint` hashes; changing it makes
+/// `--sync` write every photo again, so only bump it when the meaning of a
+/// value changes.
+const PROCESSING_FINGERPRINT_VERSION: u32 = 1;
+
+impl Processing {
+    /// FNV-1a of every setting that changes what a photo becomes (with
+    /// `flatten`, `--animated first-frame`), as 16 hex digits, so `--sync`
+    /// can tell outputs made with other settings from ones it may keep. The
+    /// fields are spelled out here so renaming one doesn't change the result.
+    fn fingerprint(&self, flatten: bool) -> String {
+        let resize = match self.resize {
+            None => "none".to_string(),
+            Some(r) => {
+                let fit = match r.fit {
+                    Fit::Inside => "inside",
+                    Fit::Crop => "crop",
+                };
+                let filter = match r.filter {
+                    ResizeFilter::Lanczos3 => "lanczos3",
+                    ResizeFilter::Bilinear => "bilinear",
+                };
+                format!("{}x{} max-pixels={:?} fit={fit} filter={filter}", r.width, r.height, r.max_pixels)
+            }
+        };
+        let chroma = match self.jpeg.chroma {
+            Chroma::Full => "full",
+            Chroma::Half => "half",
+            Chroma::Quarter => "quarter",
+        };
+        let panoramas = match self.panoramas {
+            Panoramas::Keep => "keep",
+            Panoramas::Skip => "skip",
+            Panoramas::CropCenter => "crop-center",
+            Panoramas::Letterbox => "letterbox",
+        };
+        let j = self.jpeg;
+        let fields = [
+            format!("version={PROCESSING_FINGERPRINT_VERSION}"),
+            format!("resize={resize}"),
+            format!("progressive={}", j.progressive),
+            format!("chroma={chroma}"),
+            format!("optimize={}", j.optimize),
+            format!("tone-map-sdr={}", j.tone_map_sdr),
+            format!("convert-srgb={}", j.convert_srgb),
+            format!("strip-thumbnails={}", self.strip_thumbnails),
+            format!("panoramas={panoramas}"),
+            format!("flatten={flatten}"),
+        ];
+        let hash =
+            fields.iter().fold(dupes::FNV_OFFSET, |hash, field| dupes::fnv1a(hash, format!("{field}\n").as_bytes()));
+        format!("{hash:016x}")
     }
+}
 
-    shuffle_in_place(&mut files, args.seed);
-    let groups = plan_groups(&files, args.max_files, args.max_bytes)?;
+/// What happens to photos more than `PANORAMA_ASPECT` times wider than tall,
+/// or taller than wide (`--panoramas`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Panoramas {
+    /// Copy them like any other photo.
+    #[default]
+    Keep,
+    /// Leave them out of the selection.
+    Skip,
+    /// Keep the middle, cut to the frame's aspect ratio.
+    CropCenter,
+    /// Show them whole, with black bars to fill the frame.
+    Letterbox,
+}
 
-    copy_groups(&groups, &args.dst)?;
-    print_summary(&groups, &args.dst);
-    Ok(())
+/// Aspect ratio beyond which a photo counts as a panorama.
+const PANORAMA_ASPECT: u64 = 3;
+
+fn is_panorama(width: u32, height: u32) -> bool {
+    let (long, short) = (width.max(height) as u64, width.min(height) as u64);
+    long > PANORAMA_ASPECT * short
 }
 
-fn parse_args(argv: Vec<String>) -> Result<Args, String> {
-    let mut src = PathBuf::from(DEFAULT_SRC);
-    let mut dst = PathBuf::from(DEFAULT_DST);
-    let mut max_files = DEFAULT_MAX_FILES;
-    let mut max_bytes = DEFAULT_MAX_BYTES;
-    let mut seed = default_seed();
+/// Encoder settings for re-encoded photos (`--jpeg`, `--chroma`), and for
+/// copies rewritten losslessly by `--optimize-jpeg`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct JpegOptions {
+    progressive: bool,
+    chroma: Chroma,
+    optimize: bool,
+    /// Write only the SDR picture of HDR photos, leaving out the gain map
+    /// (`--tone-map-sdr`).
+    tone_map_sdr: bool,
+    /// Convert re-encoded photos to sRGB instead of keeping their embedded
+    /// colour profile (`--convert-srgb`).
+    convert_srgb: bool,
+}
 
-    let mut i = 1;
-    while i < argv.len() {
-        match argv[i].as_str() {
-            "--help" | "-h" => {
-                print_help();
-                std::process::exit(0);
-            }
-            "--src" => {
-                i += 1;
-                src = PathBuf::from(required_arg(&argv, i, "--src")?);
-            }
-            "--dst" => {
-                i += 1;
-                dst = PathBuf::from(required_arg(&argv, i, "--dst")?);
-            }
-            "--max-files" => {
-                i += 1;
-                max_files = required_arg(&argv, i, "--max-files")?
-                    .parse::<usize>()
-                    .map_err(|_| "--max-files must be an integer".to_string())?;
-                if max_files == 0 {
-                    return Err("--max-files must be > 0".to_string());
-                }
-            }
-            "--max-bytes" => {
-                i += 1;
-                max_bytes = required_arg(&argv, i, "--max-bytes")?
-                    .parse::<u64>()
-                    .map_err(|_| "--max-bytes must be an integer".to_string())?;
-                if max_bytes == 0 {
-                    return Err("--max-bytes must be > 0".to_string());
-                }
-            }
-            "--seed" => {
-                i += 1;
-                seed = required_arg(&argv, i, "--seed")?
-                    .parse::<u64>()
-                    .map_err(|_| "--seed must be an integer".to_string())?;
-            }
-            other => {
-                return Err(format!(
-                    "unknown argument: {other}\n\nRun with --help for usage."
-                ));
-            }
-        }
-        i += 1;
-    }
+/// Chroma subsampling of re-encoded photos.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Chroma {
+    /// Full colour resolution.
+    Full,
+    /// Half horizontal colour resolution, as before these options existed.
+    #[default]
+    Half,
+    /// Half horizontal and vertical colour resolution; smallest files.
+    Quarter,
+}
 
-    Ok(Args {
-        src,
-        dst,
-        max_files,
-        max_bytes,
-        seed,
-    })
+/// Re-encode target for photos larger than the display (`--resize`,
+/// `--max-megapixels`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Resize {
+    /// Box to fit in; `u32::MAX` on both sides when only a pixel count is set.
+    width: u32,
+    height: u32,
+    /// Most pixels a photo may keep; larger ones shrink proportionally.
+    max_pixels: Option<u64>,
+    fit: Fit,
+    filter: ResizeFilter,
 }
 
-fn required_arg(argv: &[String], i: usize, flag: &str) -> Result<String, String> {
-    argv.get(i)
-        .cloned()
-        .ok_or_else(|| format!("missing value for {flag}"))
+/// Downscaling kernel (`--resize-filter`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum ResizeFilter {
+    /// Sharpest result; the default.
+    #[default]
+    Lanczos3,
+    /// Noticeably faster and slightly softer.
+    Bilinear,
 }
 
-fn print_help() {
-    println!(
-        "image-rando\n\n");
-    println!(
-        "Copies JPGs from a source folder into numbered destination folders (1..X),\n\
-obeying:\n\
-  - no more than 1200 photos per folder\n\
-  - no more than 4 GiB per folder\n\n\
-Default source: {DEFAULT_SRC}\n\
-Default dest:   {DEFAULT_DST}\n\n\
-USAGE:\n\
-  cargo run --release -- [--src PATH] [--dst PATH] [--max-files N] [--max-bytes BYTES] [--seed SEED]\n"
-    );
+/// How `--resize` deals with photos whose aspect ratio differs from the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fit {
+    /// Scale down to fit inside the box; the whole photo stays visible.
+    Inside,
+    /// Centre-crop to the box's aspect ratio, then scale down to fill it.
+    Crop,
 }
 
-fn default_seed() -> u64 {
-    let nanos = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_nanos() as u64;
-    nanos ^ (std::process::id() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+/// Settings known to suit a device (`--preset`); explicit flags win.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Preset {
+    max_files: usize,
+    max_bytes: u64,
+    resize: Resize,
+    /// The device only copes with plain ASCII file names.
+    safe_names: bool,
 }
 
-fn validate_dirs(args: &Args) -> Result<(), String> {
-    let src_meta = fs::metadata(&args.src)
-        .map_err(|e| format!("cannot read source folder {}: {e}", args.src.display()))?;
-    if !src_meta.is_dir() {
-        return Err(format!("source is not a directory: {}", args.src.display()));
-    }
+impl Preset {
+    const NAMES: &'static str = "samsung-frame|nixplay|pix-star|generic-4k";
 
-    fs::create_dir_all(&args.dst)
-        .map_err(|e| format!("cannot create destination folder {}: {e}", args.dst.display()))?;
-    let mut rd = fs::read_dir(&args.dst)
-        .map_err(|e| format!("cannot read destination folder {}: {e}", args.dst.display()))?;
-    if rd.next().is_some() {
-        return Err(format!(
-            "destination folder is not empty: {}\nRefusing to run to avoid mixing old/new output.",
-            args.dst.display()
-        ));
+    fn parse(s: &str) -> Option<Preset> {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let (max_files, max_bytes, width, height, fit, safe_names) = match s {
+            // Art Mode fills the 16:9 panel; large imports over USB stall.
+            "samsung-frame" => (1000, 4 * GIB, 3840, 2160, Fit::Crop, true),
+            // 10" Nixplay panels are 1280x800 at most.
+            "nixplay" => (1000, 2 * GIB, 1280, 800, Fit::Inside, true),
+            // Pix-Star frames are 1024x768 and index slowly past ~1000 files.
+            "pix-star" => (1000, 2 * GIB, 1024, 768, Fit::Inside, true),
+            "generic-4k" => (DEFAULT_MAX_FILES, DEFAULT_MAX_BYTES, 3840, 2160, Fit::Inside, false),
+            _ => return None,
+        };
+        Some(Preset {
+            max_files,
+            max_bytes,
+            resize: Resize { width, height, max_pixels: None, fit, filter: ResizeFilter::default() },
+            safe_names,
+        })
     }
-    Ok(())
 }
 
-fn collect_jpgs(src: &Path) -> Result<Vec<FileInfo>, String> {
-    let mut out = Vec::new();
-    let rd = fs::read_dir(src)
-        .map_err(|e| format!("cannot list source folder {}: {e}", src.display()))?;
+/// What happens to animated GIF/PNG/WebP files (`--animated`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AnimatedPolicy {
+    Skip,
+    FirstFrame,
+    Copy,
+}
 
-    for entry in rd {
-        let entry = entry.map_err(|e| format!("error reading directory entry: {e}"))?;
-        let path = entry.path();
-        let ft = entry
-            .file_type()
-            .map_err(|e| format!("cannot read file type for {}: {e}", path.display()))?;
-        if !ft.is_file() {
-            continue;
+/// What `collect_jpgs` picks up from the source.
+#[derive(Debug, Clone)]
+struct ScanOptions {
+    recursive: bool,
+    /// Photo formats to pick up (`--formats`).
+    formats: Vec<ImageFormat>,
+    include_videos: bool,
+    with_sidecars: bool,
+    detect_by: DetectBy,
+    follow_symlinks: bool,
+    include_hidden: bool,
+    ignore_nomedia: bool,
+    /// Files whose name or relative path matches any of these are skipped.
+    exclude_regex: Vec<regex::Regex>,
+    /// Folders listed at the same time (`--jobs`); 0 counts as 1.
+    threads: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            recursive: false,
+            formats: DEFAULT_FORMATS.to_vec(),
+            include_videos: false,
+            with_sidecars: false,
+            detect_by: DetectBy::default(),
+            follow_symlinks: false,
+            include_hidden: false,
+            ignore_nomedia: false,
+            exclude_regex: Vec::new(),
+            threads: 0,
         }
-        if !is_jpg(&path) {
-            continue;
+    }
+}
+
+/// How `collect_jpgs` decides what a file is (`--detect-by`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum DetectBy {
+    /// File extension only.
+    #[default]
+    Ext,
+    /// Leading magic bytes only; the extension is ignored (and fixed up).
+    Magic,
+    /// Extension and magic bytes must agree.
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MediaKind {
+    Photo,
+    Video,
+}
+
+/// How a photo is stored. Resizing, optimising and thumbnail stripping only
+/// handle JPEG; photos in the other formats are copied as they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ImageFormat {
+    Jpeg,
+    Png,
+    Heic,
+    Webp,
+}
+
+/// Photo extensions, lower case, and the format each stands for.
+const IMAGE_EXTENSIONS: [(&str, ImageFormat); 6] = [
+    ("jpg", ImageFormat::Jpeg),
+    ("jpeg", ImageFormat::Jpeg),
+    ("png", ImageFormat::Png),
+    ("heic", ImageFormat::Heic),
+    ("heif", ImageFormat::Heic),
+    ("webp", ImageFormat::Webp),
+];
+
+/// `ftyp` brands of HEIF stills (AVIF, which shares the container, has its own).
+const HEIF_BRANDS: [&[u8]; 4] = [b"heic", b"heix", b"mif1", b"msf1"];
+
+/// What a scan picks up without `--formats`: the one format every frame shows.
+const DEFAULT_FORMATS: [ImageFormat; 1] = [ImageFormat::Jpeg];
+
+impl ImageFormat {
+    /// The format `path`'s extension stands for.
+    fn of_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?;
+        IMAGE_EXTENSIONS.iter().find(|(e, _)| ext.eq_ignore_ascii_case(e)).map(|&(_, format)| format)
+    }
+
+    /// The format named `name` on the command line or in an index, by any of
+    /// its extensions.
+    fn parse(name: &str) -> Option<ImageFormat> {
+        IMAGE_EXTENSIONS.iter().find(|(e, _)| name.eq_ignore_ascii_case(e)).map(|&(_, format)| format)
+    }
+
+    /// The extension a file in this format is given when its own is wrong.
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Heic => "heic",
+            ImageFormat::Webp => "webp",
         }
-        let meta = fs::metadata(&path)
-            .map_err(|e| format!("cannot stat file {}: {e}", path.display()))?;
-        let size = meta.len();
-        let name = path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .ok_or_else(|| format!("non-utf8 filename not supported: {}", path.display()))?
-            .to_string();
+    }
 
-        out.push(FileInfo { path, name, size });
+    /// Recognises the format by the file's leading bytes.
+    fn sniff(head: &[u8]) -> Option<ImageFormat> {
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(ImageFormat::Jpeg)
+        } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+            Some(ImageFormat::Webp)
+        } else if head.get(4..8) == Some(b"ftyp") && head.get(8..12).is_some_and(|brand| HEIF_BRANDS.contains(&brand)) {
+            Some(ImageFormat::Heic)
+        } else {
+            None
+        }
     }
-    Ok(out)
 }
 
-fn is_jpg(path: &Path) -> bool {
-    match path.extension().and_then(|s| s.to_str()) {
-        Some(ext) => ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"),
-        None => false,
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Order {
+    /// Seeded shuffle.
+    Random,
+    /// Oldest EXIF capture time first; undated photos last.
+    Chronological,
+    /// Newest EXIF capture time first; undated photos last.
+    NewestFirst,
+    /// Source path, alphabetically.
+    Name,
 }
 
-// Simple, dependency-free RNG (xorshift64*) for shuffling.
-#[derive(Clone)]
-struct XorShift64 {
-    state: u64,
+/// Which of `--max-files` and `--max-bytes` start a new folder
+/// (`--limit-mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum LimitMode {
+    /// Whichever limit the next photo would break first.
+    #[default]
+    FirstHit,
+    /// Only once the next photo would break both, so a folder can go over
+    /// one of them.
+    Both,
+    FilesOnly,
+    BytesOnly,
 }
 
-impl XorShift64 {
-    fn new(seed: u64) -> Self {
-        // Avoid a zero state.
-        let seed = if seed == 0 { 0xA5A5_A5A5_5A5A_5A5A } else { seed };
-        Self { state: seed }
+impl LimitMode {
+    /// Whether a folder still has room with `files` photos of `bytes` in it.
+    /// The single-limit modes have the other limit lifted in `parse_args`.
+    fn has_room(self, files: usize, bytes: u64, max_files: usize, max_bytes: u64) -> bool {
+        let (files_ok, bytes_ok) = (files <= max_files, bytes <= max_bytes);
+        match self {
+            LimitMode::Both => files_ok || bytes_ok,
+            _ => files_ok && bytes_ok,
+        }
     }
+}
 
-    fn next_u64(&mut self) -> u64 {
-        let mut x = self.state;
-        x ^= x << 13;
-        x ^= x >> 7;
-        x ^= x << 17;
-        self.state = x;
-        x
-    }
+/// Where in `--dst` a run writes (`--dst-layout`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum DstLayout {
+    /// Straight into the destination, which must be empty.
+    #[default]
+    Flat,
+    /// Into a new timestamped folder, with `latest` pointing at it afterwards.
+    Runs,
+    /// Like `Runs`, but the run folders hold symlinks into one store of
+    /// content-named files that every run shares.
+    Cas,
 }
 
-fn shuffle_in_place(files: &mut [FileInfo], seed: u64) {
-    let mut rng = XorShift64::new(seed);
-    // Fisher-Yates
-    for i in (1..files.len()).rev() {
-        let j = (rng.next_u64() as usize) % (i + 1);
-        files.swap(i, j);
+impl DstLayout {
+    fn writes_runs(self) -> bool {
+        self != DstLayout::Flat
     }
 }
 
-fn plan_groups(files: &[FileInfo], max_files: usize, max_bytes: u64) -> Result<Vec<Vec<FileInfo>>, String> {
-    let mut groups: Vec<Vec<FileInfo>> = Vec::new();
-    let mut cur: Vec<FileInfo> = Vec::new();
-    let mut cur_bytes: u64 = 0;
+/// Dimension along which `--stratify-by` balances folder contents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stratum {
+    Year,
+    Album,
+    Orientation,
+}
 
-    for f in files {
-        if f.size > max_bytes {
-            return Err(format!(
-                "file is larger than max-bytes ({} > {}): {}",
-                f.size,
-                max_bytes,
-                f.path.display()
-            ));
-        }
+impl Order {
+    const NAMES: &'static str = "random|chronological|newest-first|name";
 
-        let would_exceed_files = !cur.is_empty() && (cur.len() + 1) > max_files;
-        let would_exceed_bytes = !cur.is_empty() && (cur_bytes + f.size) > max_bytes;
-        if would_exceed_files || would_exceed_bytes {
-            groups.push(cur);
-            cur = Vec::new();
-            cur_bytes = 0;
+    fn parse(s: &str) -> Option<Order> {
+        match s {
+            "random" => Some(Order::Random),
+            "chronological" => Some(Order::Chronological),
+            "newest-first" => Some(Order::NewestFirst),
+            "name" => Some(Order::Name),
+            _ => None,
         }
-
-        cur_bytes += f.size;
-        cur.push(f.clone());
     }
 
-    if !cur.is_empty() {
-        groups.push(cur);
+    fn needs_exif(self) -> bool {
+        matches!(self, Order::Chronological | Order::NewestFirst)
     }
-    Ok(groups)
 }
 
-fn copy_groups(groups: &[Vec<FileInfo>], dst_root: &Path) -> Result<(), String> {
-    for (idx, group) in groups.iter().enumerate() {
-        let folder_num = idx + 1;
-        let folder = dst_root.join(folder_num.to_string());
-        fs::create_dir_all(&folder)
-            .map_err(|e| format!("cannot create folder {}: {e}", folder.display()))?;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GroupBy {
+    /// One shuffled pool split purely by the limits.
+    Pool,
+    /// One (or more, when it exceeds the limits) folder per source subfolder.
+    Album,
+    /// One (or more) folder per cluster of EXIF GPS positions.
+    Location { radius_km: f64 },
+}
 
-        for f in group {
-            let dest = folder.join(&f.name);
-            if dest.exists() {
-                return Err(format!(
-                    "unexpected destination file already exists: {}",
-                    dest.display()
-                ));
-            }
-            fs::copy(&f.path, &dest)
-                .map_err(|e| format!("failed to copy {} -> {}: {e}", f.path.display(), dest.display()))?;
+/// One part of a `--name-groups-by` folder name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GroupLabel {
+    /// The XMP keyword most of the folder's photos share.
+    Keywords,
+    /// When the photos were taken: `june-2023`, `summer-2023`, `2019-2021`.
+    DateRange,
+    /// The XMP place name (city, else state, else country) most photos share.
+    Location,
+}
+
+/// A circle on the map (`--within`, `--outside`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Geofence {
+    /// Decimal degrees (latitude, longitude).
+    center: (f64, f64),
+    radius_km: f64,
+}
+
+impl Geofence {
+    /// `LAT,LON,RADIUS` with the radius in km (the default), m or mi, e.g.
+    /// `47.6,-122.3,25km`.
+    fn parse(s: &str) -> Option<Geofence> {
+        let mut parts = s.split(',').map(str::trim);
+        let lat = parts.next()?.parse::<f64>().ok().filter(|v| (-90.0..=90.0).contains(v))?;
+        let lon = parts.next()?.parse::<f64>().ok().filter(|v| (-180.0..=180.0).contains(v))?;
+        let radius = parts.next()?.to_ascii_lowercase();
+        if parts.next().is_some() {
+            return None;
         }
+        let (number, scale) = if let Some(n) = radius.strip_suffix("km") {
+            (n, 1.0)
+        } else if let Some(n) = radius.strip_suffix("mi") {
+            (n, 1.609_344)
+        } else if let Some(n) = radius.strip_suffix('m') {
+            (n, 0.001)
+        } else {
+            (radius.as_str(), 1.0)
+        };
+        let radius_km = number.trim().parse::<f64>().ok().filter(|r| r.is_finite() && *r > 0.0)? * scale;
+        Some(Geofence { center: (lat, lon), radius_km })
+    }
+
+    fn contains(&self, pos: (f64, f64)) -> bool {
+        exif::haversine_km(self.center, pos) <= self.radius_km
     }
-    Ok(())
 }
 
-fn print_summary(groups: &[Vec<FileInfo>], dst_root: &Path) {
-    let total_files: usize = groups.iter().map(|g| g.len()).sum();
-    let total_bytes: u64 = groups
-        .iter()
-        .flat_map(|g| g.iter())
-        .map(|f| f.size)
-        .sum();
+/// Photos must lie inside one of `within` (when given) and outside all of
+/// `outside`. Without a GPS position nothing is known: such photos fail
+/// `within` and pass `outside`.
+fn geofence_allows(f: &FileInfo, within: &[Geofence], outside: &[Geofence]) -> bool {
+    let Some(pos) = f.exif.gps else {
+        return within.is_empty();
+    };
+    (within.is_empty() || within.iter().any(|g| g.contains(pos))) && !outside.iter().any(|g| g.contains(pos))
+}
 
-    println!("Copied {total_files} photos into {} folders under {}", groups.len(), dst_root.display());
-    println!("Total bytes copied: {total_bytes}");
+#[derive(Debug, Clone)]
+struct FileInfo {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    kind: MediaKind,
+    /// What the photo is stored as; `None` for videos.
+    format: Option<ImageFormat>,
+    /// Parent folder relative to the source root ("" for top-level files).
+    album: String,
+    /// Only populated when a mode needs it (see `load_exif`).
+    exif: exif::Exif,
+    /// Only populated when a filter needs it (see `load_xmp`).
+    xmp: xmp::Xmp,
+    /// Metadata files copied next to the image (`--with-sidecars`), with sizes.
+    sidecars: Vec<(PathBuf, u64)>,
+    /// Write only the first frame of an animation (`--animated first-frame`).
+    flatten: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Args {
+    /// `--dst` followed by its mirrors.
+    fn destinations(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.dst.as_path()).chain(self.mirrors.iter().map(PathBuf::as_path))
+    }
+}
 
-    fn fi(name: &str, size: u64) -> FileInfo {
-        FileInfo {
-            path: PathBuf::from(name),
-            name: name.to_string(),
-            size,
-        }
+impl FileInfo {
+    /// Bytes this entry occupies in a destination folder, sidecars included.
+    fn footprint(&self) -> u64 {
+        self.size + self.sidecars.iter().map(|(_, size)| size).sum::<u64>()
     }
+}
 
-    #[test]
-    fn plan_groups_respects_max_files() {
-        let files = vec![fi("a.jpg", 1), fi("b.jpg", 1), fi("c.jpg", 1)];
-        let groups = plan_groups(&files, 2, 10).unwrap();
-        assert_eq!(groups.len(), 2);
-        assert_eq!(groups[0].len(), 2);
-        assert_eq!(groups[1].len(), 1);
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {e}");
+        std::process::exit(1);
     }
+}
 
-    #[test]
-    fn plan_groups_respects_max_bytes() {
-        let files = vec![fi("a.jpg", 6), fi("b.jpg", 6)];
-        let groups = plan_groups(&files, 1200, 10).unwrap();
-        assert_eq!(groups.len(), 2);
-        assert_eq!(groups[0][0].name, "a.jpg");
-        assert_eq!(groups[1][0].name, "b.jpg");
+fn run() -> Result<(), String> {
+    let argv: Vec<String> = env::args().collect();
+    if argv.get(1).map(String::as_str) == Some("block") {
+        return run_block(&argv[2..]);
+    }
+    if argv.get(1).map(String::as_str) == Some("report") {
+        return run_report(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("analyze") {
+        return run_analyze(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("audit") {
+        return run_audit(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("diff") {
+        return run_diff(&argv[2..]);
     }
+    if argv.get(1).map(String::as_str) == Some("bench") {
+        return run_bench(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("rotate") {
+        return run_rotate(argv[2..].to_vec());
+    }
+    if argv.get(1).map(String::as_str) == Some("index") {
+        return run_index(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("daemon") {
+        return run_daemon(argv);
+    }
+    if argv.get(1).map(String::as_str) == Some("display") {
+        return run_display(argv);
+    }
+    run_copy(argv)
+}
 
-    #[test]
-    fn plan_groups_combines_until_limit() {
-        let files = vec![fi("a.jpg", 6), fi("b.jpg", 4), fi("c.jpg", 1)];
-        let groups = plan_groups(&files, 1200, 10).unwrap();
-        assert_eq!(groups.len(), 2);
-        assert_eq!(groups[0].len(), 2); // 6 + 4 = 10
-        assert_eq!(groups[1].len(), 1);
+/// A normal run: select, plan and copy.
+fn run_copy(mut argv: Vec<String>) -> Result<(), String> {
+    let email = take_flag_value(&mut argv, "--email-report")?;
+    if let Some(to) = &email {
+        mail::check_address(to)?;
     }
+    let outcome = copy_run(argv);
+    if let Some(to) = &email {
+        let (subject, body) = match &outcome {
+            Ok(summary) => ("image-rando: run finished", summary.join("\n")),
+            Err(e) => ("image-rando: run failed", format!("error: {e}")),
+        };
+        if let Err(e) = mail::send(to, subject, &body) {
+            eprintln!("warning: {e}");
+        }
+    }
+    outcome.map(|_| ())
+}
 
-    #[test]
+/// The copy itself; returns the summary it printed.
+fn copy_run(mut argv: Vec<String>) -> Result<Vec<String>, String> {
+    let started = Instant::now();
+    // What a rescan starts over from.
+    let original = argv.clone();
+    // How the outcome is reported; not part of what --replay repeats.
+    let json = match take_flag_value(&mut argv, "--output")?.as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(other) => return Err(format!("unknown --output format: {other} (expected text or json)")),
+    };
+    let interactive = take_switch(&mut argv, "--interactive");
+    if interactive && json {
+        return Err("--interactive doesn't combine with --output json".to_string());
+    }
+    let dry_run = take_switch(&mut argv, "--dry-run");
+    let list_files = take_switch(&mut argv, "--list-files");
+    if list_files && !dry_run {
+        return Err("--list-files only applies to --dry-run".to_string());
+    }
+    if dry_run && (interactive || json) {
+        return Err("--dry-run only prints the plan; it doesn't combine with --interactive or --output json".to_string());
+    }
+    if let Some(list) = take_flag_value(&mut argv, "--export-selection")? {
+        if interactive || json || argv.iter().any(|a| a == "--replay") {
+            return Err("--export-selection only writes a list; it doesn't combine with --interactive, --output json or --replay".to_string());
+        }
+        return export_selection(argv, Path::new(&list));
+    }
+    let script = take_flag_value(&mut argv, "--emit-script")?.map(PathBuf::from);
+    if script.is_some() && (json || dry_run) {
+        return Err("--emit-script writes a script instead of copying; it doesn't combine with --output json or --dry-run".to_string());
+    }
+    let resume = take_switch(&mut argv, "--resume");
+    if resume && (interactive || dry_run || script.is_some()) {
+        return Err("--resume finishes copying; it doesn't combine with --interactive, --dry-run or --emit-script".to_string());
+    }
+    let (argv, replay) = if resume { resume_args(argv)? } else { replay_args(argv)? };
+    if interactive && replay.is_some() {
+        return Err("--interactive doesn't combine with --replay, which keeps the recorded seed".to_string());
+    }
+    let recorded = recorded_options(&argv);
+    let mut args = parse_args(argv)?;
+    if script.is_some() {
+        check_script_options(&args)?;
+    }
+    if resume {
+        if args.dst_layout.writes_runs() || args.sets.is_some() || args.split_by_orientation {
+            return Err("--resume only finishes runs written straight into --dst, not run folders, sets or orientation trees".to_string());
+        }
+        args.resume = true;
+    }
+    let quiet = args.quiet_hours.map(|hours| {
+        let offset = platform::utc_offset_minutes().unwrap_or_else(|| {
+            eprintln!("warning: cannot find the local time zone; taking --quiet-hours as UTC");
+            0
+        });
+        (hours, offset)
+    });
+    // The scan would wake the disks as much as the copy.
+    let window = CopyWindow { deadline: None, quiet };
+    window.wait_out_quiet_hours();
+    let window = CopyWindow { deadline: args.time_budget.map(|budget| Instant::now() + budget), ..window };
+    let sandbox = if args.sandbox { Some(Sandbox::redirect(&mut args, json)?) } else { None };
+    args.src = platform::long_path(&args.src)?;
+    args.dst = platform::long_path(&args.dst)?;
+    for m in args.mirrors.iter_mut() {
+        *m = platform::long_path(m)?;
+    }
+    let runs = match args.dst_layout {
+        DstLayout::Runs | DstLayout::Cas => {
+            let roots: Vec<PathBuf> = args.destinations().map(Path::to_path_buf).collect();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let name = run_dir_name(&roots, now);
+            args.dst = args.dst.join(&name);
+            for m in args.mirrors.iter_mut() {
+                *m = m.join(&name);
+            }
+            Some((roots, name))
+        }
+        DstLayout::Flat => None,
+    };
+    // A script is run elsewhere, so its destination isn't touched here; a
+    // dry run only looks at what's already there.
+    if script.is_some() {
+        validate_src(&args.src)?;
+    } else if dry_run {
+        validate_src(&args.src)?;
+        for dst in args.destinations().filter(|dst| dst.is_dir()) {
+            check_dst_empty(dst, args.sync)?;
+        }
+    } else {
+        validate_dirs(&args)?;
+    }
+    // With --sync, what each destination holds now.
+    let mut previous = Vec::new();
+    for dst in args.destinations() {
+        let path = dst.join(manifest::FILE_NAME);
+        previous.push(if args.sync && path.is_file() { Some(manifest::Manifest::load(&path)?) } else { None });
+    }
+
+    let Selection {
+        mut files,
+        pins,
+        required,
+        animated_skipped,
+        empty_skipped,
+        suspiciously_small,
+        links_skipped,
+        exif_loaded,
+        xmp_loaded,
+        snapshot,
+    } = select_files(&args)?;
+    let input = manifest::Input::of(files.iter().map(|f| (relative_name(&args.src, &f.path), f.size)).collect());
+    if let Some(Replay { path, manifest: old }) = &replay {
+        if old.input.as_ref() != Some(&input) {
+            return Err(format!(
+                "the source no longer matches {}: {} files selected now, {} then{}",
+                path.display(),
+                input.files,
+                old.input.as_ref().map_or(0, |i| i.files),
+                if old.input.as_ref().is_some_and(|i| i.files == input.files) { " (but not the same ones)" } else { "" }
+            ));
+        }
+    }
+
+    let needs_exif = matches!(args.group_by, GroupBy::Location { .. })
+        || args.order.needs_exif()
+        || matches!(args.stratify_by, Some(Stratum::Year | Stratum::Orientation))
+        || args.rename_template.as_ref().is_some_and(|t| t.uses_date())
+        || args.name_groups_by.contains(&GroupLabel::DateRange)
+        || args.split_by_orientation;
+    if needs_exif && !exif_loaded {
+        load_exif(&mut files, args.exif_cache.as_deref())?;
+    }
+    let needs_xmp = args.name_groups_by.iter().any(|l| matches!(l, GroupLabel::Keywords | GroupLabel::Location));
+    if needs_xmp && !xmp_loaded {
+        load_xmp(&mut files)?;
+    }
+    // Plan with the sizes the photos will have at the destination (resized
+    // ones carry no EXIF at all).
+    if args.processing.strip_thumbnails && args.processing.resize.is_none() {
+        for f in files.iter_mut().filter(|f| f.kind == MediaKind::Photo && !f.flatten) {
+            f.size -= exif::thumbnail_bytes(&f.path)?;
+        }
+    }
+    if let Some(template) = &args.rename_template {
+        apply_rename_template(&mut files, template, args.assume_timezone);
+    }
+    if args.safe_names {
+        for f in files.iter_mut() {
+            f.name = platform::ascii_file_name(&f.name);
+        }
+    }
+    // Destinations that aren't written now aren't tried out either.
+    let untried = script.is_some() || dry_run;
+    let mut caps = untried.then(platform::Capabilities::portable);
+    for dst in args.destinations().filter(|_| !untried) {
+        let found = platform::probe(dst)?;
+        caps = Some(match caps {
+            Some(caps) => caps.merge(found),
+            None => found,
+        });
+    }
+    let caps = caps.expect("at least one destination");
+    let adapted = adapt_names(&mut files, &caps);
+    if args.preserve_structure {
+        for f in files.iter_mut() {
+            if let Some(dirs) = structure_prefix(&args.src, &f.path, args.safe_names, &caps) {
+                f.name = format!("{dirs}/{}", f.name);
+            }
+        }
+    }
+    let copies_in_dst = match &previous[0] {
+        Some(previous) => skip_copies_in_dst(&mut files, previous, &args.src, &required),
+        None => 0,
+    };
+
+    let recent = if args.pick_one_album {
+        recent_albums(&args.dst, runs.as_ref(), args.skip_recent_albums)?
+    } else {
+        Vec::new()
+    };
+    let pick_album = |files: &mut Vec<FileInfo>, seed: u64| -> Result<Option<String>, String> {
+        if !args.pick_one_album {
+            return Ok(None);
+        }
+        // A --replay keeps the recorded album, whatever was picked since.
+        let album = match replay.as_ref().and_then(|r| r.manifest.album.clone()) {
+            Some(album) => album,
+            None => choose_album(files, seed, &recent, &required)
+                .ok_or_else(|| format!("--pick-one-album found no subfolder with photos in {}", args.src.display()))?,
+        };
+        keep_album(files, &album, &required);
+        Ok(Some(album))
+    };
+
+    // --interactive plans again from the same selection for each re-roll.
+    let rerolls = interactive.then(|| files.clone());
+    let mut album = pick_album(&mut files, args.seed)?;
+    let mut run = plan_run(files, &args, &pins, &required, previous[0].as_ref())?;
+    if let Some(files) = rerolls {
+        let stdin = io::stdin();
+        loop {
+            if let Some(album) = &album {
+                println!("Album: {album}");
+            }
+            print_plan(&run, &args, &input, PlanDetail::Preview);
+            match ask_choice(&mut stdin.lock(), &mut io::stdout())? {
+                Choice::Copy => break,
+                Choice::Reroll => {
+                    args.seed = default_seed();
+                    let mut files = files.clone();
+                    album = pick_album(&mut files, args.seed)?;
+                    run = plan_run(files, &args, &pins, &required, previous[0].as_ref())?;
+                }
+                Choice::Quit => {
+                    if runs.is_some() {
+                        for dst in args.destinations() {
+                            let _ = fs::remove_dir(dst);
+                        }
+                    }
+                    println!("Nothing copied");
+                    return Ok(vec!["Nothing copied".to_string()]);
+                }
+            }
+        }
+    }
+    if dry_run {
+        print_plan(&run, &args, &input, if list_files { PlanDetail::Files } else { PlanDetail::Folders });
+        println!("Nothing copied (dry run)");
+        return Ok(vec!["Nothing copied (dry run)".to_string()]);
+    }
+    let RunPlan {
+        destinations,
+        plans,
+        renamed,
+        videos_left_out,
+        over_quota,
+        over_limit,
+        folded,
+        stragglers_left_out,
+        tolerated,
+        similar_moved,
+        similar_left,
+        shape_unknown,
+    } = run;
+    if args.sets.is_some() || args.split_by_orientation {
+        for set in &destinations {
+            fs::create_dir_all(set).map_err(|e| format!("cannot create {}: {e}", set.display()))?;
+        }
+        previous.resize(destinations.len(), None);
+    }
+    let manifests: Vec<manifest::Manifest> =
+        plans.iter().map(|g| run_manifest(g, &args, recorded.clone(), input.clone(), album.clone())).collect();
+    if let Some(Replay { path, manifest: old }) = &replay {
+        // Sources touched since don't change where they go.
+        let placement = |files: &[manifest::Entry]| files.iter().map(manifest::Entry::placement).collect::<Vec<_>>();
+        if placement(&manifests[0].files) != placement(&old.files) {
+            return Err(format!(
+                "replaying {} gives a different plan; it was probably written by another version",
+                path.display()
+            ));
+        }
+    }
+    if let Some(script) = &script {
+        let name = script.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let text = plan_script(&name, &args, &destinations, &plans, &manifests)?;
+        fs::write(script, text).map_err(|e| format!("cannot write {}: {e}", script.display()))?;
+        let files: usize = plans.iter().flatten().map(Vec::len).sum();
+        let line = format!("Wrote a script copying {files} files to {}", script.display());
+        println!("{line}");
+        return Ok(vec![line]);
+    }
+    // With --dst-layout cas, the store next to the run folder.
+    let store_of = |dst: &Path| -> Option<PathBuf> {
+        let (roots, name) = runs.as_ref().filter(|_| args.dst_layout == DstLayout::Cas)?;
+        roots.iter().find(|root| dst.starts_with(root.join(name))).map(|root| root.join(STORE_DIR))
+    };
+    // Planning can take long enough for a sync client to land files.
+    if let Some(snapshot) = &snapshot {
+        let changes = snapshot.changes(&args.scan)?;
+        if changes != SourceChanges::default() {
+            let what = format!(
+                "the source changed since it was scanned: {} files added and {} removed in {} folders",
+                changes.added, changes.removed, changes.folders
+            );
+            if !interactive {
+                eprintln!("warning: {what}; copying the plan from the scan, run again to pick up the changes");
+            } else {
+                println!("Note: {what}");
+                match ask_rescan(&mut io::stdin().lock(), &mut io::stdout())? {
+                    SourceChoice::Proceed => {}
+                    SourceChoice::Rescan => {
+                        if runs.is_some() {
+                            for dst in args.destinations() {
+                                let _ = fs::remove_dir(dst);
+                            }
+                        }
+                        // The new run makes its own.
+                        drop(sandbox);
+                        return copy_run(original);
+                    }
+                    SourceChoice::Quit => {
+                        if runs.is_some() {
+                            for dst in args.destinations() {
+                                let _ = fs::remove_dir(dst);
+                            }
+                        }
+                        println!("Nothing copied");
+                        return Ok(vec!["Nothing copied".to_string()]);
+                    }
+                }
+            }
+        }
+    }
+    let synced = thread::scope(|scope| {
+        let handles: Vec<_> = destinations
+            .iter()
+            .zip(plans.iter().zip(&manifests))
+            .zip(&previous)
+            .map(|((dst, (groups, manifest)), previous)| {
+                let args = &args;
+                let store = store_of(dst);
+                scope.spawn(move || write_destination(dst, groups, manifest, previous.as_ref(), store.as_deref(), args, window))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("destination thread panicked"))
+            .collect::<Result<Vec<_>, String>>()
+    })?;
+    // With --dst-layout runs, `latest` only moves once every destination is complete.
+    let mut latest_moved = false;
+    let mut pruned = Vec::new();
+    let mut store_pruned = 0;
+    if let Some((roots, name)) = &runs {
+        if synced.iter().all(|done| done.left == 0) {
+            for root in roots {
+                platform::replace_symlink(&root.join(LATEST_LINK), Path::new(name))?;
+            }
+            latest_moved = true;
+            if let Some(keep) = args.keep_runs {
+                for root in roots {
+                    pruned.push((root, prune_runs(root, keep, name, args.use_trash)?));
+                    if args.dst_layout == DstLayout::Cas {
+                        store_pruned += prune_store(root, args.use_trash)?;
+                    }
+                }
+            }
+        }
+    }
+    if json {
+        let written = destinations
+            .iter()
+            .zip(plans.iter().zip(&manifests))
+            .zip(&synced)
+            .map(|((dst, (groups, manifest)), done)| destination_json(dst, groups, manifest, done))
+            .collect();
+        let counts = [
+            ("renamed", renamed),
+            ("animated_skipped", animated_skipped),
+            ("empty_skipped", empty_skipped),
+            ("suspiciously_small", suspiciously_small),
+            ("links_skipped", links_skipped),
+            ("copies_in_dst", copies_in_dst),
+            ("videos_left_out", videos_left_out),
+            ("names_adjusted", adapted),
+            ("over_quota", over_quota),
+            ("over_limit", over_limit),
+            ("folded", folded),
+            ("stragglers_left_out", stragglers_left_out),
+            ("tolerated", tolerated),
+            ("similar_moved", similar_moved),
+            ("similar_left", similar_left),
+            ("shape_unknown", shape_unknown),
+        ];
+        let mut fields = vec![
+            ("seed".to_string(), Value::from(args.seed)),
+            ("seconds".to_string(), Value::from((started.elapsed().as_secs_f64() * 1000.0).round() / 1000.0)),
+            ("destinations".to_string(), Value::Array(written)),
+        ];
+        fields.extend(counts.map(|(key, n)| (key.to_string(), Value::from(n))));
+        if let Some(album) = &album {
+            fields.push(("album".to_string(), Value::from(album.as_str())));
+        }
+        if let Some((_, name)) = &runs {
+            fields.push(("run".to_string(), Value::from(name.as_str())));
+            fields.push(("latest_moved".to_string(), Value::from(latest_moved)));
+            let deleted = pruned.iter().flat_map(|(root, names)| names.iter().map(|n| path_json(&root.join(n))));
+            fields.push(("runs_deleted".to_string(), Value::Array(deleted.collect())));
+            if args.dst_layout == DstLayout::Cas {
+                fields.push(("store_deleted".to_string(), Value::from(store_pruned)));
+            }
+        }
+        let doc = Value::Object(fields).to_pretty();
+        println!("{doc}");
+        return Ok(vec![doc]);
+    }
+    let mut summary = Vec::new();
+    if let Some(album) = &album {
+        summary.push(format!("Album: {album}"));
+    }
+    if args.distribute || args.sets.is_some() || args.split_by_orientation {
+        for ((dst, groups), manifest) in destinations.iter().zip(&plans).zip(&manifests) {
+            summary.extend(summary_lines(groups, dst));
+            summary.push(format!("Plan: {}", manifest.plan_hash()));
+        }
+    } else {
+        summary.extend(summary_lines(&plans[0], &args.dst));
+        summary.push(format!("Plan: {}", manifests[0].plan_hash()));
+        for m in &args.mirrors {
+            summary.push(format!("Mirrored to {}", m.display()));
+        }
+    }
+    for (dst, done) in destinations.iter().zip(&synced) {
+        if done.left > 0 {
+            summary.push(format!(
+                "Time budget used up: {} of these files were not copied to {}; run again with --sync and the same seed to finish",
+                done.left,
+                dst.display()
+            ));
+        }
+        if let Some(linked) = done.from_store.filter(|&n| n > 0) {
+            if destinations.len() > 1 {
+                summary.push(format!("Already in the store, linked without copying to {}: {linked}", dst.display()));
+            } else {
+                summary.push(format!("Already in the store, linked without copying: {linked}"));
+            }
+        }
+        if let Some(resumed) = done.resumed {
+            if destinations.len() > 1 {
+                summary.push(format!("Resumed {}: {resumed} already copied by the interrupted run", dst.display()));
+            } else {
+                summary.push(format!("Resumed: {resumed} already copied by the interrupted run"));
+            }
+        }
+        if let Some((in_place, removed)) = done.synced {
+            if destinations.len() > 1 {
+                summary.push(format!("Synced {}: {in_place} already in place, {removed} removed", dst.display()));
+            } else {
+                summary.push(format!("Synced: {in_place} already in place, {removed} removed"));
+            }
+        }
+    }
+    if let Some((_, name)) = &runs {
+        if latest_moved {
+            summary.push(format!("{LATEST_LINK} now points at {name}"));
+            for (root, names) in pruned.iter().filter(|(_, names)| !names.is_empty()) {
+                summary.push(format!("Old runs deleted from {}: {}", root.display(), names.join(", ")));
+            }
+            if store_pruned > 0 {
+                summary.push(format!("Stored files no run links to any more deleted: {store_pruned}"));
+            }
+        } else {
+            summary.push(format!("{LATEST_LINK} still points at the previous complete run"));
+        }
+    }
+    let counts = [
+        (renamed, "Renamed to avoid name collisions".to_string()),
+        (animated_skipped, "Animated images skipped".to_string()),
+        (empty_skipped, "Empty files skipped".to_string()),
+        (suspiciously_small, format!("Suspiciously small files copied (under {} KiB)", SUSPICIOUSLY_SMALL_BYTES / 1024)),
+        (links_skipped, "Further names of the same file skipped (hard links or symlinks)".to_string()),
+        (copies_in_dst, "Copies of photos already in the destination skipped".to_string()),
+        (videos_left_out, "Videos left out (over the per-folder video budget)".to_string()),
+        (adapted, format!("Names adjusted for the destination ({})", caps.describe())),
+        (over_quota, "Photos left out (over an album byte quota)".to_string()),
+        (over_limit, "Files left out (over --limit)".to_string()),
+        (folded, "Photos of a short last folder spread over the others".to_string()),
+        (stragglers_left_out, "Photos left out (too few for a last folder, no room in the others)".to_string()),
+        (tolerated, "Photos of a last folder taken in by the others (within --bytes-tolerance)".to_string()),
+        (similar_moved, "Similar photos moved to different folders".to_string()),
+        (similar_left, "Similar photos left in one folder (nothing to swap them with)".to_string()),
+        (shape_unknown, "Files left out (dimensions unknown, so neither landscape nor portrait)".to_string()),
+    ];
+    for (n, what) in counts {
+        if n > 0 {
+            summary.push(format!("{what}: {n}"));
+        }
+    }
+    for line in &summary {
+        println!("{line}");
+    }
+    Ok(summary)
+}
+
+/// `--src-weight`: interleaves the ordered files so that whatever the run
+/// takes from the front is made of the named folders in the given
+/// percentages, whatever their sizes. Files outside them share the
+/// percentage left over, or come last when there is none. A folder that runs
+/// out leaves its share to the others. The order within each share is kept.
+fn weigh_folders(files: Vec<FileInfo>, weights: &[(String, u32)]) -> Vec<FileInfo> {
+    let rest = 100 - weights.iter().map(|(_, p)| p).sum::<u32>();
+    let share = |f: &FileInfo| {
+        let covers =
+            |name: &str| f.album == name || f.album.strip_prefix(name).is_some_and(|rest| rest.starts_with('/'));
+        weights.iter().enumerate().filter(|(_, (name, _))| covers(name)).max_by_key(|(_, (name, _))| name.len())
+    };
+    let mut taken = vec![0usize; weights.len() + 1];
+    // Share k hands out a file every 1/percent; ties keep share order.
+    let mut keyed: Vec<(f64, usize, FileInfo)> = files
+        .into_iter()
+        .map(|f| {
+            let (k, percent) = match share(&f) {
+                Some((k, (_, percent))) => (k, *percent),
+                None => (weights.len(), rest),
+            };
+            let n = taken[k] as f64;
+            taken[k] += 1;
+            let at = if percent == 0 { f64::INFINITY } else { (n + 0.5) / f64::from(percent) };
+            (at, k, f)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    keyed.into_iter().map(|(_, _, f)| f).collect()
+}
+
+/// `--export-selection FILE`: writes what the run would choose from, one
+/// path per line like a `--pin-list`, and stops before anything is grouped
+/// or copied. Once edited, the list comes back with `--import-selection`.
+fn export_selection(argv: Vec<String>, list: &Path) -> Result<Vec<String>, String> {
+    let args = parse_read_only_args(argv)?;
+    if args.pick_one_album {
+        return Err("--pick-one-album picks when copying; give it together with --import-selection".to_string());
+    }
+    let Selection { files, .. } = select_files(&args)?;
+    let mut paths: Vec<String> = files.iter().map(|f| relative_name(&args.src, &f.path)).collect();
+    paths.sort();
+    let mut text = format!(
+        "# image-rando selection from {}: {} files.\n# Delete the lines of files that shouldn't be copied, then run with --import-selection.\n",
+        args.src.display(),
+        paths.len()
+    );
+    for path in &paths {
+        // A leading `#` would read back as a comment.
+        if path.starts_with('#') {
+            text.push_str("./");
+        }
+        text.push_str(path);
+        text.push('\n');
+    }
+    fs::write(list, text).map_err(|e| format!("cannot write {}: {e}", list.display()))?;
+    let line = format!("Wrote {} files to {}", paths.len(), list.display());
+    println!("{line}");
+    Ok(vec![line])
+}
+
+/// `--emit-script` only plans; a script can copy files into one plain
+/// destination but not process them.
+fn check_script_options(args: &Args) -> Result<(), String> {
+    if args.processing != Processing::default() {
+        return Err("--emit-script writes plain copies; it can't resize, re-encode or strip thumbnails".to_string());
+    }
+    if !args.mirrors.is_empty() {
+        return Err("--emit-script writes to a single --dst".to_string());
+    }
+    if args.sync || args.sandbox || args.dst_layout != DstLayout::Flat {
+        return Err("--emit-script fills an empty destination; it doesn't combine with --sync, --sandbox or --dst-layout".to_string());
+    }
+    Ok(())
+}
+
+/// `--emit-script`: a POSIX shell script that makes the planned folders and
+/// copies the files into them with `mkdir` and `cp`, then writes the
+/// manifest. `SRC` and `DST` can be set when running it, for a machine that
+/// mounts the folders elsewhere.
+fn plan_script(
+    name: &str,
+    args: &Args,
+    destinations: &[PathBuf],
+    plans: &[Vec<Vec<FileInfo>>],
+    manifests: &[manifest::Manifest],
+) -> Result<String, String> {
+    let files: usize = plans.iter().flatten().map(Vec::len).sum();
+    let folders: usize = plans.iter().map(Vec::len).sum();
+    let mut out = format!(
+        "#!/bin/sh\n# image-rando plan (seed {}): {files} files in {folders} folders.\n\
+         # Copies from SRC to DST; set either to run it against other paths:\n\
+         #   SRC=/mnt/photos DST=/media/frame sh {}\nset -e\nSRC=${{SRC:-{}}}\nDST=${{DST:-{}}}\n\
+         if [ -n \"$(ls -A \"$DST\" 2>/dev/null)\" ]; then\n  \
+         echo \"destination folder is not empty: $DST\" >&2\n  exit 1\nfi\n",
+        args.seed,
+        name,
+        sh_quote(&args.src.to_string_lossy()),
+        sh_quote(&args.dst.to_string_lossy()),
+    );
+    let source = |path: &Path| format!("\"$SRC/\"{}", sh_quote(&relative_name(&args.src, path)));
+    for ((dst, groups), manifest) in destinations.iter().zip(plans).zip(manifests) {
+        let below = relative_name(&args.dst, dst);
+        let below = if below.is_empty() { String::new() } else { format!("{below}/") };
+        let target = |rel: &str| format!("\"$DST/\"{}", sh_quote(&format!("{below}{rel}")));
+        let mut made = HashSet::new();
+        for (idx, group) in groups.iter().enumerate() {
+            let folder = manifest.folder_name(idx + 1);
+            for f in group {
+                if f.flatten {
+                    return Err(format!(
+                        "{} is animated and would be flattened to its first frame, which a script can't do; use --animated copy",
+                        f.path.display()
+                    ));
+                }
+                let name = format!("{folder}/{}", f.name);
+                let dir = name.rsplit_once('/').map_or(folder.clone(), |(dir, _)| dir.to_string());
+                if made.insert(dir.clone()) {
+                    out.push_str(&format!("mkdir -p {}\n", target(&dir)));
+                }
+                out.push_str(&format!("cp {} {}\n", source(&f.path), target(&name)));
+                for (sidecar, _) in &f.sidecars {
+                    let name = format!("{folder}/{}", sidecar_dest_name(f, sidecar));
+                    out.push_str(&format!("cp {} {}\n", source(sidecar), target(&name)));
+                }
+            }
+        }
+        // Written with the files, so a later --sync can update the destination.
+        out.push_str(&format!(
+            "mkdir -p {}\ncat > {} <<'IMAGE_RANDO_MANIFEST'\n{}\nIMAGE_RANDO_MANIFEST\n",
+            target(""),
+            target(manifest::FILE_NAME),
+            manifest.to_json().to_pretty().trim_end()
+        ));
+    }
+    out.push_str(&format!("echo \"Copied {files} files into $DST\"\n"));
+    Ok(out)
+}
+
+/// `s` as one single-quoted shell word.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// One shuffle of the selection, planned for every destination.
+struct RunPlan {
+    destinations: Vec<PathBuf>,
+    /// The folders each destination gets.
+    plans: Vec<Vec<Vec<FileInfo>>>,
+    renamed: usize,
+    videos_left_out: usize,
+    over_quota: usize,
+    over_limit: usize,
+    folded: usize,
+    stragglers_left_out: usize,
+    tolerated: usize,
+    similar_moved: usize,
+    similar_left: usize,
+    /// Left out by `--split-by-orientation` for want of dimensions.
+    shape_unknown: usize,
+}
+
+/// Orders the selected `files` with `args.seed` and plans the folders of
+/// every destination (or set); nothing is written yet.
+fn plan_run(
+    mut files: Vec<FileInfo>,
+    args: &Args,
+    pins: &[PathBuf],
+    required: &[PathBuf],
+    previous: Option<&manifest::Manifest>,
+) -> Result<RunPlan, String> {
+    match &args.favorites {
+        Some(list) => {
+            let favorites = load_favorites(list)?;
+            let weight = args.favorites_weight;
+            weighted_shuffle(&mut files, args.seed, |f| {
+                if is_favorite(f, &args.src, &favorites) { weight } else { 1.0 }
+            });
+        }
+        None => order_files(&mut files, args.order, args.seed, args.assume_timezone),
+    }
+    if !args.src_weights.is_empty() {
+        files = weigh_folders(files, &args.src_weights);
+    }
+    let mut shape_unknown = 0;
+    let (selections, over_sets) = match args.sets {
+        Some(n) => split_sets(files, n, args.limit, required),
+        None if args.split_by_orientation => {
+            let (trees, unknown) = split_orientation(files, required);
+            shape_unknown = unknown;
+            (Vec::from(trees), 0)
+        }
+        None => (vec![files], 0),
+    };
+    // Sets already hold at most --limit files each.
+    let limit = if args.sets.is_some() { None } else { args.limit };
+    let mut planned = selections
+        .into_iter()
+        .map(|files| plan_selection(files, args, pins, required, previous, limit))
+        .collect::<Result<Vec<Plan>, String>>()?;
+    let renamed = planned.iter().map(|p| p.renamed).sum();
+    let videos_left_out = planned.iter().map(|p| p.videos_left_out).sum();
+    let over_quota = planned.iter().map(|p| p.over_quota).sum();
+    let over_limit = over_sets + planned.iter().map(|p| p.over_limit).sum::<usize>();
+    let folded = planned.iter().map(|p| p.folded).sum();
+    let stragglers_left_out = planned.iter().map(|p| p.stragglers_left_out).sum();
+    let tolerated = planned.iter().map(|p| p.tolerated).sum();
+    let similar_moved = planned.iter().map(|p| p.similar_moved).sum();
+    let similar_left = planned.iter().map(|p| p.similar_left).sum();
+
+    let (destinations, plans): (Vec<PathBuf>, Vec<Vec<Vec<FileInfo>>>) = if let Some(n) = args.sets {
+        let sets: Vec<PathBuf> = (1..=n).map(|k| args.dst.join(format!("set-{k}"))).collect();
+        (sets, planned.into_iter().map(|p| p.groups).collect())
+    } else if args.split_by_orientation {
+        let trees = ORIENTATION_TREES.iter().map(|t| args.dst.join(t)).collect();
+        (trees, planned.into_iter().map(|p| p.groups).collect())
+    } else {
+        let destinations: Vec<PathBuf> = args.destinations().map(Path::to_path_buf).collect();
+        let groups = planned.pop().expect("one selection").groups;
+        let plans = if args.distribute {
+            distribute_groups(groups, destinations.len())
+        } else {
+            vec![groups; destinations.len()]
+        };
+        (destinations, plans)
+    };
+    Ok(RunPlan {
+        destinations,
+        plans,
+        renamed,
+        videos_left_out,
+        over_quota,
+        over_limit,
+        folded,
+        stragglers_left_out,
+        tolerated,
+        similar_moved,
+        similar_left,
+        shape_unknown,
+    })
+}
+
+/// Folders and names per folder `--interactive` shows of a plan.
+const PREVIEW_FOLDERS: usize = 20;
+const PREVIEW_NAMES: usize = 3;
+
+/// How much of a plan `print_plan` shows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlanDetail {
+    /// `--interactive`: the first folders, with a few names each.
+    Preview,
+    /// `--dry-run`: every folder.
+    Folders,
+    /// `--dry-run --list-files`: every folder and every name in it.
+    Files,
+}
+
+/// What each folder would get, so the user can judge the shuffle before
+/// anything is copied.
+fn print_plan(run: &RunPlan, args: &Args, input: &manifest::Input, detail: PlanDetail) {
+    let shown = if args.distribute || args.sets.is_some() || args.split_by_orientation { run.plans.len() } else { 1 };
+    for (dst, groups) in run.destinations.iter().zip(&run.plans).take(shown) {
+        let manifest = run_manifest(groups, args, Vec::new(), input.clone(), None);
+        let files: usize = groups.iter().map(Vec::len).sum();
+        let bytes: u64 = groups.iter().flatten().map(|f| f.footprint()).sum();
+        println!(
+            "Plan for {} (seed {}): {files} files in {} folders, {bytes} bytes",
+            dst.display(),
+            args.seed,
+            groups.len()
+        );
+        let shown = if detail == PlanDetail::Preview { PREVIEW_FOLDERS } else { groups.len() };
+        for (idx, group) in groups.iter().enumerate().take(shown) {
+            let bytes: u64 = group.iter().map(|f| f.footprint()).sum();
+            let folder = manifest.folder_name(idx + 1);
+            match detail {
+                PlanDetail::Preview => {
+                    let mut names: Vec<&str> = group.iter().take(PREVIEW_NAMES).map(|f| f.name.as_str()).collect();
+                    if group.len() > PREVIEW_NAMES {
+                        names.push("...");
+                    }
+                    println!("  {folder}: {} files, {bytes} bytes: {}", group.len(), names.join(", "));
+                }
+                PlanDetail::Folders => println!("  {folder}: {} files, {bytes} bytes", group.len()),
+                PlanDetail::Files => {
+                    println!("  {folder}: {} files, {bytes} bytes", group.len());
+                    for f in group {
+                        println!("    {} ({} bytes, from {})", f.name, f.footprint(), relative_name(&args.src, &f.path));
+                    }
+                }
+            }
+        }
+        if groups.len() > shown {
+            println!("  ... and {} more folders", groups.len() - PREVIEW_FOLDERS);
+        }
+    }
+}
+
+/// What to do with a plan shown by `--interactive`.
+#[derive(Debug, PartialEq)]
+enum Choice {
+    Copy,
+    Reroll,
+    Quit,
+}
+
+/// Asks until the answer is one of the choices; the end of input quits.
+fn ask_choice(input: &mut impl BufRead, out: &mut impl Write) -> Result<Choice, String> {
+    let failed = |e: io::Error| format!("cannot read the answer: {e}");
+    loop {
+        write!(out, "[c]opy / [r]e-roll / [q]uit? ").and_then(|()| out.flush()).map_err(failed)?;
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(failed)? == 0 {
+            return Ok(Choice::Quit);
+        }
+        match line.trim().to_ascii_lowercase().as_str() {
+            "c" | "copy" => return Ok(Choice::Copy),
+            "r" | "re-roll" | "reroll" => return Ok(Choice::Reroll),
+            "q" | "quit" => return Ok(Choice::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// What to do when `--interactive` finds the source changed under the plan.
+#[derive(Debug, PartialEq)]
+enum SourceChoice {
+    Rescan,
+    Proceed,
+    Quit,
+}
+
+/// `ask_choice` for a changed source.
+fn ask_rescan(input: &mut impl BufRead, out: &mut impl Write) -> Result<SourceChoice, String> {
+    let failed = |e: io::Error| format!("cannot read the answer: {e}");
+    loop {
+        write!(out, "[r]escan / [c]opy the plan anyway / [q]uit? ").and_then(|()| out.flush()).map_err(failed)?;
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(failed)? == 0 {
+            return Ok(SourceChoice::Quit);
+        }
+        match line.trim().to_ascii_lowercase().as_str() {
+            "r" | "rescan" => return Ok(SourceChoice::Rescan),
+            "c" | "copy" => return Ok(SourceChoice::Proceed),
+            "q" | "quit" => return Ok(SourceChoice::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// Symlink in the destination to the newest complete run (`--dst-layout runs`).
+const LATEST_LINK: &str = "latest";
+
+/// The folder a `--dst-layout runs` run writes to in each of `roots`: its
+/// start time (UTC) to the minute, with `-2`, `-3`... when that is taken.
+fn run_dir_name(roots: &[PathBuf], unix: u64) -> String {
+    let t = exif::DateTime::from_unix(unix);
+    let base = format!("{:04}-{:02}-{:02}T{:02}-{:02}", t.year, t.month, t.day, t.hour, t.minute);
+    let mut name = base.clone();
+    let mut k = 2;
+    while roots.iter().any(|root| root.join(&name).exists()) {
+        name = format!("{base}-{k}");
+        k += 1;
+    }
+    name
+}
+
+/// `--keep-runs`: deletes all but the `keep` newest run folders in `root`,
+/// newest by the time in their manifests. The `current` run always counts as
+/// the newest. Folders without a manifest aren't runs and are left alone, as
+/// is the `latest` link. Returns the names of the deleted folders.
+fn prune_runs(root: &Path, keep: usize, current: &str, use_trash: bool) -> Result<Vec<String>, String> {
+    let mut runs = Vec::new();
+    for (name, path) in past_runs(root, current)? {
+        match manifest::Manifest::load(&path) {
+            Ok(m) => runs.push((m.created, name)),
+            Err(e) => eprintln!("warning: keeping {}: {e}", root.join(&name).display()),
+        }
+    }
+    runs.sort();
+    let excess = runs.len().saturating_sub(keep - 1);
+    let mut pruned = Vec::new();
+    for (_, name) in runs.into_iter().take(excess) {
+        let dir = root.join(&name);
+        if use_trash {
+            trash::trash(&dir)?;
+        } else {
+            fs::remove_dir_all(&dir).map_err(|e| format!("cannot delete {}: {e}", dir.display()))?;
+        }
+        pruned.push(name);
+    }
+    Ok(pruned)
+}
+
+/// The run folders in `root` other than `current`, with their manifests.
+fn past_runs(root: &Path, current: &str) -> Result<Vec<(String, PathBuf)>, String> {
+    let rd = fs::read_dir(root).map_err(|e| format!("cannot list {}: {e}", root.display()))?;
+    let mut runs = Vec::new();
+    for entry in rd {
+        let entry = entry.map_err(|e| format!("error reading directory entry: {e}"))?;
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) || entry.file_name() == current {
+            continue;
+        }
+        if let Some(path) = run_manifest_path(&entry.path()) {
+            runs.push((entry.file_name().to_string_lossy().into_owned(), path));
+        }
+    }
+    Ok(runs)
+}
+
+/// The manifest of the run written to `dir`, if there is one. --sets and
+/// --split-by-orientation runs keep their manifests one level down.
+fn run_manifest_path(dir: &Path) -> Option<PathBuf> {
+    let below = ["set-1", ORIENTATION_TREES[0], ORIENTATION_TREES[1]].map(|sub| dir.join(sub));
+    std::iter::once(dir.to_path_buf())
+        .chain(below)
+        .map(|d| d.join(manifest::FILE_NAME))
+        .find(|p| p.is_file())
+}
+
+/// The albums `--pick-one-album` chose for the `n` newest earlier runs at
+/// the destination, newest first: the run still in a flat destination, or
+/// the other run folders with `--dst-layout runs`.
+fn recent_albums(dst: &Path, runs: Option<&(Vec<PathBuf>, String)>, n: usize) -> Result<Vec<String>, String> {
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    let found = match runs {
+        Some((roots, name)) => past_runs(&roots[0], name)?,
+        None => run_manifest_path(dst).map(|path| (String::new(), path)).into_iter().collect(),
+    };
+    // Runs started within the same second are told apart by their folder names.
+    let mut picks: Vec<(String, String, Option<String>)> = found
+        .into_iter()
+        .filter_map(|(name, path)| manifest::Manifest::load(&path).ok().map(|m| (m.created, name, m.album)))
+        .collect();
+    picks.sort_by(|a, b| b.cmp(a));
+    Ok(picks.into_iter().take(n).filter_map(|(_, _, album)| album).collect())
+}
+
+/// `--pick-one-album`: one source folder holding `files`, chosen with
+/// `seed` among those not in `recent` (newest first). When every folder was
+/// picked lately, the ones picked longest ago are allowed again. Top-level
+/// and pinned or must-include files belong to no album.
+fn choose_album(files: &[FileInfo], seed: u64, recent: &[String], required: &[PathBuf]) -> Option<String> {
+    let mut albums: Vec<&str> = files
+        .iter()
+        .filter(|f| !f.album.is_empty() && !required.contains(&f.path))
+        .map(|f| f.album.as_str())
+        .collect();
+    albums.sort_unstable();
+    albums.dedup();
+    let mut skip = recent.len();
+    let candidates = loop {
+        let left: Vec<&str> = albums.iter().copied().filter(|a| !recent[..skip].iter().any(|r| r == a)).collect();
+        if !left.is_empty() || skip == 0 {
+            break left;
+        }
+        skip -= 1;
+    };
+    if candidates.is_empty() {
+        return None;
+    }
+    let pick = XorShift64::new(seed).next_u64() % candidates.len() as u64;
+    Some(candidates[pick as usize].to_string())
+}
+
+/// Keeps the files of `album` and the pinned and must-include ones.
+fn keep_album(files: &mut Vec<FileInfo>, album: &str, required: &[PathBuf]) {
+    files.retain(|f| f.album == album || required.contains(&f.path));
+}
+
+/// One selection turned into numbered folders.
+struct Plan {
+    groups: Vec<Vec<FileInfo>>,
+    renamed: usize,
+    videos_left_out: usize,
+    over_quota: usize,
+    over_limit: usize,
+    /// `--min-files-per-folder`: photos of the last folder moved into
+    /// earlier ones, and those left out.
+    folded: usize,
+    stragglers_left_out: usize,
+    /// `--bytes-tolerance`: photos of the last folder taken in by earlier ones.
+    tolerated: usize,
+    /// `--spread-similar`: photos moved away from a look-alike, and those
+    /// that had to stay in its folder.
+    similar_moved: usize,
+    similar_left: usize,
+}
+
+/// `--bytes-tolerance`: when every photo of the last folder fits into the
+/// folders before it with up to `percent` more than `max_bytes` (and no more
+/// than `max_files` photos), they are moved there and the last folder goes
+/// away. Each photo goes to the folder with the most bytes still free. Returns
+/// how many photos were moved; nothing changes if any of them would not fit.
+fn absorb_last_folder(groups: &mut Vec<Vec<FileInfo>>, max_files: usize, max_bytes: u64, percent: f64) -> usize {
+    if groups.len() < 2 {
+        return 0;
+    }
+    let ceiling = max_bytes.saturating_add((max_bytes as f64 * percent / 100.0) as u64);
+    let last = groups.len() - 1;
+    let mut bytes: Vec<u64> = groups[..last].iter().map(|g| g.iter().map(|f| f.footprint()).sum()).collect();
+    let mut counts: Vec<usize> = groups[..last].iter().map(Vec::len).collect();
+    let mut targets = Vec::new();
+    for f in &groups[last] {
+        let room = (0..last)
+            .filter(|&k| counts[k] < max_files && bytes[k] + f.footprint() <= ceiling)
+            .min_by_key(|&k| bytes[k]);
+        let Some(k) = room else {
+            return 0;
+        };
+        bytes[k] += f.footprint();
+        counts[k] += 1;
+        targets.push(k);
+    }
+    let moved = targets.len();
+    let straggler = groups.pop().expect("two or more folders");
+    for (f, k) in straggler.into_iter().zip(targets) {
+        groups[k].push(f);
+    }
+    moved
+}
+
+/// `--min-files-per-folder`: a last folder of fewer than `min` photos is
+/// spread over the folders before it, each photo going to the one holding
+/// fewest that still has room under `max_bytes` (they may end up over
+/// `--max-files`). Photos that fit nowhere are left out, except pinned and
+/// must-include ones, which keep a last folder of their own. Returns how many
+/// photos were folded in and how many were left out.
+fn fold_straggler(groups: &mut Vec<Vec<FileInfo>>, min: usize, max_bytes: u64, required: &[PathBuf]) -> (usize, usize) {
+    if groups.len() < 2 || groups.last().is_some_and(|g| g.len() >= min) {
+        return (0, 0);
+    }
+    let straggler = groups.pop().expect("two or more folders");
+    let mut bytes: Vec<u64> = groups.iter().map(|g| g.iter().map(|f| f.footprint()).sum()).collect();
+    let (mut folded, mut left_out, mut kept) = (0, 0, Vec::new());
+    for f in straggler {
+        let room = (0..groups.len())
+            .filter(|&k| bytes[k] + f.footprint() <= max_bytes)
+            .min_by_key(|&k| groups[k].len());
+        match room {
+            Some(k) => {
+                bytes[k] += f.footprint();
+                groups[k].push(f);
+                folded += 1;
+            }
+            None if required.contains(&f.path) => kept.push(f),
+            None => left_out += 1,
+        }
+    }
+    if !kept.is_empty() {
+        groups.push(kept);
+    }
+    (folded, left_out)
+}
+
+/// Takes ordered candidates through the quotas, `limit`, grouping and video
+/// placement to the folders of one run (or one `--sets` set). `previous` is
+/// what `--sync` found at the destination.
+fn plan_selection(
+    mut files: Vec<FileInfo>,
+    args: &Args,
+    pins: &[PathBuf],
+    required: &[PathBuf],
+    previous: Option<&manifest::Manifest>,
+    limit: Option<usize>,
+) -> Result<Plan, String> {
+    let over_quota = apply_album_quotas(&mut files, &args.album_quotas, required);
+    let over_limit = match limit {
+        Some(limit) => apply_limit(&mut files, limit, required),
+        None => 0,
+    };
+    preflight(&files, args)?;
+    let (mut files, videos): (Vec<FileInfo>, Vec<FileInfo>) =
+        files.into_iter().partition(|f| f.kind == MediaKind::Photo);
+    if let Some(by) = args.stratify_by {
+        files = stratify(files, by);
+    }
+    let (pinned, mut files) = take_pinned(files, pins);
+    let mut groups = match args.group_by {
+        GroupBy::Pool => {
+            files.splice(0..0, pinned.iter().cloned());
+            match (args.stable_reshuffle, previous) {
+                (Some(percent), Some(previous)) => {
+                    plan_stable_groups(&files, previous, percent, &args.src, args.max_files, args.max_bytes, args.limit_mode)?
+                }
+                (Some(_), None) => {
+                    eprintln!("warning: no earlier run in {}; picking every photo afresh", args.dst.display());
+                    plan_groups(&files, args.max_files, args.max_bytes, args.limit_mode)?
+                }
+                (None, _) => plan_groups(&files, args.max_files, args.max_bytes, args.limit_mode)?,
+            }
+        }
+        GroupBy::Album => plan_album_groups(&files, args.max_files, args.max_bytes, args.limit_mode)?,
+        GroupBy::Location { radius_km } => {
+            plan_location_groups(&files, radius_km, args.max_files, args.max_bytes, args.limit_mode)?
+        }
+    };
+    let tolerated = match args.bytes_tolerance {
+        Some(percent) => absorb_last_folder(&mut groups, args.max_files, args.max_bytes, percent),
+        None => 0,
+    };
+    let (folded, stragglers_left_out) = match args.min_files {
+        Some(min) => fold_straggler(&mut groups, min, args.max_bytes, required),
+        None => (0, 0),
+    };
+    let (similar_moved, similar_left) =
+        if args.spread_similar { spread_similar(&mut groups, args.max_bytes, pins, args.jobs) } else { (0, 0) };
+    // Album and location folders stay pure; pinned photos get their own
+    // leading folder(s) instead.
+    if args.group_by != GroupBy::Pool && !pinned.is_empty() {
+        let mut lead = plan_groups(&pinned, args.max_files, args.max_bytes, args.limit_mode)?;
+        lead.append(&mut groups);
+        groups = lead;
+    }
+    if args.diversify_colors {
+        diversify_groups(&mut groups)?;
+        if let Some(first) = groups.first_mut() {
+            pins_first(first, pins);
+        }
+    }
+    let videos_left_out = sprinkle_videos(&mut groups, videos, args.max_video_files, args.max_video_bytes);
+    // With --sync, a photo already at its planned place keeps its name there.
+    let in_place: HashSet<(usize, &str, String)> = previous
+        .iter()
+        .flat_map(|m| &m.files)
+        .map(|e| (e.folder, e.name.as_str(), e.source.clone()))
+        .collect();
+    let renamed = resolve_name_collisions(&mut groups, |folder, f| {
+        in_place.contains(&(folder, f.name.as_str(), relative_name(&args.src, &f.path)))
+    });
+    Ok(Plan {
+        groups,
+        renamed,
+        videos_left_out,
+        over_quota,
+        over_limit,
+        folded,
+        stragglers_left_out,
+        tolerated,
+        similar_moved,
+        similar_left,
+    })
+}
+
+/// `--sets`: deals the ordered files out into `n` disjoint selections. With
+/// `--limit`, set k gets the k-th run of `limit` files; otherwise they are
+/// dealt round robin. Pinned and must-include files go into every set. Also
+/// returns how many files no set got.
+fn split_sets(files: Vec<FileInfo>, n: usize, limit: Option<usize>, required: &[PathBuf]) -> (Vec<Vec<FileInfo>>, usize) {
+    let mut sets = vec![Vec::new(); n];
+    let (mut dealt, mut left_over) = (0, 0);
+    for f in files {
+        if required.contains(&f.path) {
+            for set in sets.iter_mut() {
+                set.push(f.clone());
+            }
+            continue;
+        }
+        let set = match limit {
+            Some(limit) => dealt / limit.max(1),
+            None => dealt % n,
+        };
+        dealt += 1;
+        match sets.get_mut(set) {
+            Some(set) => set.push(f),
+            None => left_over += 1,
+        }
+    }
+    (sets, left_over)
+}
+
+/// The trees `--split-by-orientation` writes below the destination.
+const ORIENTATION_TREES: [&str; 2] = ["landscape", "portrait"];
+
+/// `--split-by-orientation`: the ordered files that are wider than tall and
+/// those that are taller than wide. Square photos fit either frame and go
+/// into both, as do pinned and must-include files whatever their shape.
+/// Also returns how many files were left out because their dimensions
+/// aren't known.
+fn split_orientation(files: Vec<FileInfo>, required: &[PathBuf]) -> ([Vec<FileInfo>; 2], usize) {
+    let (mut landscape, mut portrait, mut unknown) = (Vec::new(), Vec::new(), 0);
+    for f in files {
+        match f.exif.display_dimensions() {
+            Some((w, h)) if w > h => landscape.push(f),
+            Some((w, h)) if w < h => portrait.push(f),
+            None if !required.contains(&f.path) => unknown += 1,
+            _ => {
+                landscape.push(f.clone());
+                portrait.push(f);
+            }
+        }
+    }
+    ([landscape, portrait], unknown)
+}
+
+/// `--sync`: leaves out candidates with the same content as a file the
+/// destination already holds from another source path, so a copy added to
+/// the library later doesn't land in a second folder. Only entries whose
+/// source is still a candidate count, as the rest are about to be deleted,
+/// and only candidates of the same size are hashed. Returns how many were
+/// left out.
+fn skip_copies_in_dst(files: &mut Vec<FileInfo>, previous: &manifest::Manifest, src: &Path, required: &[PathBuf]) -> usize {
+    let candidates: HashSet<String> = files.iter().map(|f| relative_name(src, &f.path)).collect();
+    let mut held: HashMap<u64, Vec<&str>> = HashMap::new();
+    for e in previous.files.iter().filter(|e| candidates.contains(&e.source)) {
+        held.entry(e.size).or_default().push(&e.source);
+    }
+    let sources: HashSet<&str> = previous.files.iter().map(|e| e.source.as_str()).collect();
+    let mut hashes: HashMap<&str, Option<u64>> = HashMap::new();
+    let before = files.len();
+    files.retain(|f| {
+        let Some(same_size) = held.get(&f.size) else {
+            return true;
+        };
+        if required.contains(&f.path) || sources.contains(relative_name(src, &f.path).as_str()) {
+            return true;
+        }
+        let Ok(hash) = dupes::content_hash(&f.path) else {
+            return true;
+        };
+        !same_size.iter().any(|&source| {
+            *hashes.entry(source).or_insert_with(|| dupes::content_hash(&src.join(source)).ok()) == Some(hash)
+        })
+    });
+    before - files.len()
+}
+
+/// `--sandbox`: the run writes below a fresh temporary folder, which is
+/// deleted again when this is dropped, however the run ends.
+struct Sandbox {
+    root: PathBuf,
+    /// Say so on stderr, leaving stdout to `--output json`.
+    quiet: bool,
+}
+
+impl Sandbox {
+    fn redirect(args: &mut Args, quiet: bool) -> Result<Sandbox, String> {
+        let root = env::temp_dir().join(format!("image-rando-sandbox-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).map_err(|e| format!("cannot create sandbox {}: {e}", root.display()))?;
+        let note = format!("Sandbox: writing to {} instead of {}", root.display(), args.dst.display());
+        if quiet {
+            eprintln!("{note}");
+        } else {
+            println!("{note}");
+        }
+        args.dst = root.join("dst");
+        for (k, m) in args.mirrors.iter_mut().enumerate() {
+            *m = root.join(format!("dst-{}", k + 2));
+        }
+        Ok(Sandbox { root, quiet })
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        match fs::remove_dir_all(&self.root) {
+            Ok(()) if self.quiet => eprintln!("Sandbox deleted: {}", self.root.display()),
+            Ok(()) => println!("Sandbox deleted: {}", self.root.display()),
+            Err(e) => eprintln!("warning: cannot delete sandbox {}: {e}", self.root.display()),
+        }
+    }
+}
+
+/// Photos (and videos) that survive scanning and every filter, in scan order.
+struct Selection {
+    files: Vec<FileInfo>,
+    pins: Vec<PathBuf>,
+    /// Pinned and must-include photos; no filter drops these.
+    required: Vec<PathBuf>,
+    animated_skipped: usize,
+    /// Zero-byte files left out, e.g. placeholders from a failed sync.
+    empty_skipped: usize,
+    /// Files kept despite being under `SUSPICIOUSLY_SMALL_BYTES`.
+    suspiciously_small: usize,
+    /// Further names (hard links, symlinks) of files already selected.
+    links_skipped: usize,
+    /// Whether `files` already carry their EXIF fields.
+    exif_loaded: bool,
+    /// Whether `files` already carry their XMP fields.
+    xmp_loaded: bool,
+    /// The source folders as the scan saw them; none for a catalog or list.
+    snapshot: Option<SourceSnapshot>,
+}
+
+/// Drops zero-byte files, which no display can show, and warns about
+/// suspiciously small ones. Returns how many of each there were.
+fn skip_empty_files(files: &mut Vec<FileInfo>, src: &Path) -> (usize, usize) {
+    let (mut empty, mut small) = (0, 0);
+    files.retain(|f| {
+        if f.size == 0 {
+            eprintln!("warning: skipping empty file {}", relative_name(src, &f.path));
+            empty += 1;
+            return false;
+        }
+        if f.size < SUSPICIOUSLY_SMALL_BYTES {
+            eprintln!("warning: {} is only {} bytes; is it a placeholder?", relative_name(src, &f.path), f.size);
+            small += 1;
+        }
+        true
+    });
+    (empty, small)
+}
+
+/// Keeps one name of each file that is reachable under several (hard links,
+/// symlinks), so it is picked and counted against the budgets only once: a
+/// pinned or must-include name if there is one, otherwise the first by path.
+/// Returns how many names were dropped.
+fn skip_linked_copies(files: &mut Vec<FileInfo>, required: &[PathBuf]) -> usize {
+    let ids: Vec<Option<(u64, u64)>> = files.iter().map(|f| platform::file_id(&f.path)).collect();
+    let mut kept: HashMap<(u64, u64), usize> = HashMap::new();
+    for (k, id) in ids.iter().enumerate() {
+        let Some(id) = id else { continue };
+        let first = *kept.entry(*id).or_insert(k);
+        if required.contains(&files[k].path) && !required.contains(&files[first].path) {
+            kept.insert(*id, k);
+        }
+    }
+    let before = files.len();
+    let mut k = 0;
+    files.retain(|_| {
+        k += 1;
+        ids[k - 1].is_none_or(|id| kept[&id] == k - 1)
+    });
+    before - files.len()
+}
+
+/// The part of a run shared with `report`: scan the source, add pinned and
+/// must-include photos, then apply the block list, person filters and
+/// animated policy.
+fn select_files(args: &Args) -> Result<Selection, String> {
+    let mut snapshot = None;
+    let mut files = match (&args.import_selection, &args.from_index) {
+        // The list already went through the scan filters when it was exported.
+        (Some(list), _) => {
+            let mut files = Vec::new();
+            add_required_files(&mut files, &load_path_list(list, &args.src)?, &args.src, &args.scan)?;
+            files
+        }
+        (None, Some(catalog)) => {
+            let mut files = index::load(catalog, &args.src, &args.scan)?;
+            let listed = files.len();
+            files.retain(|f| f.path.is_file());
+            if files.len() < listed {
+                eprintln!(
+                    "warning: {} files in {} no longer exist; run index again to pick up changes",
+                    listed - files.len(),
+                    catalog.display()
+                );
+            }
+            files
+        }
+        (None, None) => {
+            let (files, folders) = scan_source(&args.src, &args.scan)?;
+            snapshot = Some(folders);
+            files
+        }
+    };
+    let pins = match &args.pin_list {
+        Some(list) => load_path_list(list, &args.src)?,
+        None => Vec::new(),
+    };
+    // Pinned and must-include photos survive every filter.
+    let mut required = pins.clone();
+    if let Some(list) = &args.include_list {
+        for path in load_path_list(list, &args.src)? {
+            if !required.contains(&path) {
+                required.push(path);
+            }
+        }
+    }
+    add_required_files(&mut files, &required, &args.src, &args.scan)?;
+    let exclude_list = args.exclude_list.clone().unwrap_or_else(|| args.src.join(DEFAULT_EXCLUDE_LIST));
+    let blocked = if args.exclude_list.is_some() || exclude_list.is_file() {
+        load_path_list(&exclude_list, &args.src)?
+    } else {
+        Vec::new()
+    };
+    files.retain(|f| !blocked.contains(&f.path));
+    let (empty_skipped, suspiciously_small) = skip_empty_files(&mut files, &args.src);
+    let links_skipped = skip_linked_copies(&mut files, &required);
+    if !files.iter().any(|f| f.kind == MediaKind::Photo) {
+        let formats: Vec<&str> = args.scan.formats.iter().map(|f| f.extension()).collect();
+        return Err(format!(
+            "no photos ({}) found in source folder: {}",
+            formats.join(", "),
+            args.src.display()
+        ));
+    }
+
+    let people_filter = !args.require_people.is_empty() || !args.exclude_people.is_empty();
+    if people_filter {
+        if args.from_index.is_none() {
+            load_xmp(&mut files)?;
+        }
+        files.retain(|f| {
+            required.contains(&f.path) || people_filter_allows(f, &args.require_people, &args.exclude_people)
+        });
+        if files.is_empty() {
+            return Err("no photos left after applying the person filters".to_string());
+        }
+    }
+    // A catalog already has it.
+    let mut exif_loaded = args.from_index.is_some();
+    let mut xmp_loaded = args.from_index.is_some() || people_filter;
+    if let Some(selector) = &args.select {
+        if selector.uses_exif() && !exif_loaded {
+            load_exif(&mut files, args.exif_cache.as_deref())?;
+            exif_loaded = true;
+        }
+        if selector.uses_xmp() && !xmp_loaded {
+            load_xmp(&mut files)?;
+            xmp_loaded = true;
+        }
+        files.retain(|f| required.contains(&f.path) || selector.matches(f));
+        if files.is_empty() {
+            return Err("no photos left after applying --select".to_string());
+        }
+    }
+    if !args.within.is_empty() || !args.outside.is_empty() {
+        if !exif_loaded {
+            load_exif(&mut files, args.exif_cache.as_deref())?;
+            exif_loaded = true;
+        }
+        files.retain(|f| required.contains(&f.path) || geofence_allows(f, &args.within, &args.outside));
+        if files.is_empty() {
+            return Err("no photos left after applying --within and --outside".to_string());
+        }
+    }
+    if args.processing.panoramas == Panoramas::Skip {
+        if !exif_loaded {
+            load_exif(&mut files, args.exif_cache.as_deref())?;
+            exif_loaded = true;
+        }
+        // Photos without recorded dimensions stay in.
+        files.retain(|f| {
+            required.contains(&f.path) || !f.exif.dimensions.is_some_and(|(w, h)| is_panorama(w, h))
+        });
+        if files.is_empty() {
+            return Err("no photos left after skipping panoramas".to_string());
+        }
+    }
+
+    let animated_skipped = apply_animated_policy(&mut files, args.animated, &required)?;
+    if !files.iter().any(|f| f.kind == MediaKind::Photo) {
+        return Err("no photos left after skipping animated images".to_string());
+    }
+
+    Ok(Selection {
+        files,
+        pins,
+        required,
+        animated_skipped,
+        empty_skipped,
+        suspiciously_small,
+        links_skipped,
+        exif_loaded,
+        xmp_loaded,
+        snapshot,
+    })
+}
+
+fn parse_args(argv: Vec<String>) -> Result<Args, String> {
+    let mut src = platform::default_src();
+    let mut dsts = Vec::new();
+    let mut mirror = false;
+    let mut distribute = false;
+    let mut max_files = None;
+    let mut min_files = None;
+    let mut bytes_tolerance = None;
+    let mut max_bytes = None;
+    let mut limit_mode = LimitMode::default();
+    let mut seed = default_seed();
+    let mut scan = ScanOptions::default();
+    let mut group_by = GroupBy::Pool;
+    let mut name_groups_by = Vec::new();
+    let mut order = Order::Random;
+    let mut diversify_colors = false;
+    let mut spread_similar = false;
+    let mut stratify_by = None;
+    let mut require_people = Vec::new();
+    let mut exclude_people = Vec::new();
+    let mut max_video_files = DEFAULT_MAX_VIDEO_FILES;
+    let mut max_video_bytes = DEFAULT_MAX_VIDEO_BYTES;
+    let mut animated = AnimatedPolicy::Skip;
+    let mut rename_template = None;
+    let mut pin_list = None;
+    let mut include_list = None;
+    let mut exclude_list = None;
+    let mut favorites = None;
+    let mut favorites_weight = DEFAULT_FAVORITES_WEIGHT;
+    let mut resize_box = None;
+    let mut max_pixels = None;
+    let mut fit = None;
+    let mut filter = ResizeFilter::default();
+    let mut jpeg = JpegOptions::default();
+    let mut strip_thumbnails = false;
+    let mut panoramas = Panoramas::Keep;
+    let mut safe_names = false;
+    let mut preserve_structure = false;
+    let mut preset = None;
+    let mut jobs = None;
+    let mut low_memory = false;
+    let mut sync = false;
+    let mut stable_reshuffle = None;
+    let mut album_quotas = AlbumQuotas::default();
+    let mut src_weights: Vec<(String, u32)> = Vec::new();
+    let mut pick_one_album = false;
+    let mut skip_recent_albums = None;
+    let mut time_budget = None;
+    let mut quiet_hours = None;
+    let mut limit = None;
+    let mut sandbox = false;
+    let mut sets = None;
+    let mut split_by_orientation = false;
+    let mut dst_layout = DstLayout::default();
+    let mut keep_runs = None;
+    let mut use_trash = false;
+    let mut exif_cache = platform::cache_dir().map(|d| d.join(cache::FILE_NAME));
+    let mut from_index = None;
+    let mut import_selection = None;
+    let mut selector: Option<select::Selector> = None;
+    let mut within = Vec::new();
+    let mut outside = Vec::new();
+    let mut assume_timezone = None;
+
+    let mut i = 1;
+    while i < argv.len() {
+        match argv[i].as_str() {
+            "--help" | "-h" => {
+                print_help();
+                std::process::exit(0);
+            }
+            "--src" => {
+                i += 1;
+                src = PathBuf::from(required_arg(&argv, i, "--src")?);
+            }
+            "--dst" => {
+                i += 1;
+                let dst = required_arg(&argv, i, "--dst")?;
+                // Would otherwise become a local `nextcloud:` folder.
+                if dst.contains("://") {
+                    return Err(format!(
+                        "--dst {dst} is a URL, but only folders can be written to; to fill a cloud folder (Nextcloud, Dropbox, ...), point --dst at the local folder its sync client uploads"
+                    ));
+                }
+                dsts.push(PathBuf::from(dst));
+            }
+            "--mirror" => {
+                mirror = true;
+            }
+            "--distribute" => {
+                distribute = true;
+            }
+            "--dst-layout" => {
+                i += 1;
+                dst_layout = match required_arg(&argv, i, "--dst-layout")?.as_str() {
+                    "flat" => DstLayout::Flat,
+                    "runs" => DstLayout::Runs,
+                    "cas" => DstLayout::Cas,
+                    other => return Err(format!("unknown --dst-layout: {other} (expected flat, runs or cas)")),
+                };
+            }
+            "--src-weight" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--src-weight")?;
+                let bad = || format!("bad --src-weight: {v} (expected FOLDER=PERCENT, e.g. best-of=70)");
+                let (name, percent) = v.rsplit_once('=').ok_or_else(bad)?;
+                let name = nfc::to_nfc(name.trim_matches('/'));
+                let percent = percent.trim_end_matches('%').parse::<u32>().map_err(|_| bad())?;
+                if name.is_empty() || !(1..=100).contains(&percent) {
+                    return Err(bad());
+                }
+                src_weights.retain(|(n, _)| *n != name);
+                src_weights.push((name, percent));
+            }
+            "--pick-one-album" => {
+                pick_one_album = true;
+            }
+            "--skip-recent-albums" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--skip-recent-albums")?
+                    .parse::<usize>()
+                    .map_err(|_| "--skip-recent-albums must be an integer".to_string())?;
+                skip_recent_albums = Some(n);
+            }
+            "--keep-runs" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--keep-runs")?
+                    .parse::<usize>()
+                    .map_err(|_| "--keep-runs must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--keep-runs must be > 0".to_string());
+                }
+                keep_runs = Some(n);
+            }
+            "--use-trash" => {
+                use_trash = true;
+            }
+            "--split-by-orientation" => {
+                split_by_orientation = true;
+            }
+            "--sets" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--sets")?
+                    .parse::<usize>()
+                    .map_err(|_| "--sets must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--sets must be > 0".to_string());
+                }
+                sets = Some(n);
+            }
+            "--max-files" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--max-files")?
+                    .parse::<usize>()
+                    .map_err(|_| "--max-files must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--max-files must be > 0".to_string());
+                }
+                max_files = Some(n);
+            }
+            "--min-files-per-folder" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--min-files-per-folder")?
+                    .parse::<usize>()
+                    .map_err(|_| "--min-files-per-folder must be an integer".to_string())?;
+                min_files = Some(n);
+            }
+            "--bytes-tolerance" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--bytes-tolerance")?;
+                let p = v
+                    .trim_end_matches('%')
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|p| *p > 0.0 && *p <= 100.0)
+                    .ok_or_else(|| format!("bad --bytes-tolerance: {v} (expected a percentage like 2%)"))?;
+                bytes_tolerance = Some(p);
+            }
+            "--max-bytes" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--max-bytes")?
+                    .parse::<u64>()
+                    .map_err(|_| "--max-bytes must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--max-bytes must be > 0".to_string());
+                }
+                max_bytes = Some(n);
+            }
+            "--limit-mode" => {
+                i += 1;
+                limit_mode = match required_arg(&argv, i, "--limit-mode")?.as_str() {
+                    "first-hit" => LimitMode::FirstHit,
+                    "both" => LimitMode::Both,
+                    "files-only" => LimitMode::FilesOnly,
+                    "bytes-only" => LimitMode::BytesOnly,
+                    other => {
+                        return Err(format!(
+                            "unknown --limit-mode: {other} (expected first-hit, both, files-only or bytes-only)"
+                        ))
+                    }
+                };
+            }
+            "--seed" => {
+                i += 1;
+                seed = required_arg(&argv, i, "--seed")?
+                    .parse::<u64>()
+                    .map_err(|_| "--seed must be an integer".to_string())?;
+            }
+            "--seed-phrase" => {
+                i += 1;
+                seed = seed_from_phrase(&required_arg(&argv, i, "--seed-phrase")?);
+            }
+            "--recursive" | "-r" => {
+                scan.recursive = true;
+            }
+            "--include-videos" => {
+                scan.include_videos = true;
+            }
+            "--formats" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--formats")?;
+                let mut formats = Vec::new();
+                for name in v.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    let format = ImageFormat::parse(name)
+                        .ok_or_else(|| format!("unknown format in --formats: {name} (expected jpg, png, heic or webp)"))?;
+                    if !formats.contains(&format) {
+                        formats.push(format);
+                    }
+                }
+                if formats.is_empty() {
+                    return Err("--formats needs at least one format, e.g. jpg,png".to_string());
+                }
+                scan.formats = formats;
+            }
+            "--with-sidecars" => {
+                scan.with_sidecars = true;
+            }
+            "--exclude-regex" => {
+                i += 1;
+                let pattern = required_arg(&argv, i, "--exclude-regex")?;
+                let re = regex::Regex::new(&pattern).map_err(|e| format!("invalid --exclude-regex: {e}"))?;
+                scan.exclude_regex.push(re);
+            }
+            "--ignore-nomedia" => {
+                scan.ignore_nomedia = true;
+            }
+            "--include-hidden" => {
+                scan.include_hidden = true;
+            }
+            "--follow-symlinks" => {
+                scan.follow_symlinks = true;
+            }
+            "--detect-by" => {
+                i += 1;
+                scan.detect_by = match required_arg(&argv, i, "--detect-by")?.as_str() {
+                    "ext" => DetectBy::Ext,
+                    "magic" => DetectBy::Magic,
+                    "both" => DetectBy::Both,
+                    other => {
+                        return Err(format!(
+                            "unknown --detect-by mode: {other} (expected ext, magic or both)"
+                        ))
+                    }
+                };
+            }
+            "--max-video-files" => {
+                i += 1;
+                max_video_files = required_arg(&argv, i, "--max-video-files")?
+                    .parse::<usize>()
+                    .map_err(|_| "--max-video-files must be an integer".to_string())?;
+            }
+            "--max-video-bytes" => {
+                i += 1;
+                max_video_bytes = required_arg(&argv, i, "--max-video-bytes")?
+                    .parse::<u64>()
+                    .map_err(|_| "--max-video-bytes must be an integer".to_string())?;
+            }
+            "--sandbox" => {
+                sandbox = true;
+            }
+            "--exif-cache" => {
+                i += 1;
+                exif_cache = Some(PathBuf::from(required_arg(&argv, i, "--exif-cache")?));
+            }
+            "--no-exif-cache" => {
+                exif_cache = None;
+            }
+            "--from-index" => {
+                i += 1;
+                from_index = Some(PathBuf::from(required_arg(&argv, i, "--from-index")?));
+            }
+            "--import-selection" => {
+                i += 1;
+                import_selection = Some(PathBuf::from(required_arg(&argv, i, "--import-selection")?));
+            }
+            "--within" | "--outside" => {
+                let flag = argv[i].clone();
+                i += 1;
+                let fence = Geofence::parse(&required_arg(&argv, i, &flag)?)
+                    .ok_or_else(|| format!("{flag} takes LAT,LON,RADIUS, e.g. 47.6,-122.3,25km"))?;
+                if flag == "--within" { within.push(fence) } else { outside.push(fence) }
+            }
+            "--assume-timezone" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--assume-timezone")?;
+                assume_timezone = Some(
+                    exif::parse_utc_offset(&v)
+                        .ok_or_else(|| format!("--assume-timezone takes a UTC offset like +02:00 or -05:30, not {v}"))?,
+                );
+            }
+            "--select" => {
+                i += 1;
+                let parsed = select::Selector::parse(&required_arg(&argv, i, "--select")?)
+                    .map_err(|e| format!("invalid --select: {e}"))?;
+                selector = Some(match selector.take() {
+                    Some(earlier) => earlier.and(parsed),
+                    None => parsed,
+                });
+            }
+            "--limit" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--limit")?
+                    .parse::<usize>()
+                    .map_err(|_| "--limit must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--limit must be > 0".to_string());
+                }
+                limit = Some(n);
+            }
+            "--time-budget" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--time-budget")?;
+                time_budget = Some(
+                    parse_duration(&v)
+                        .filter(|d| !d.is_zero())
+                        .ok_or_else(|| format!("bad --time-budget: {v} (expected e.g. 90s, 15m or 2h)"))?,
+                );
+            }
+            "--quiet-hours" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--quiet-hours")?;
+                quiet_hours = Some(
+                    QuietHours::parse(&v).ok_or_else(|| format!("bad --quiet-hours: {v} (expected e.g. 08:00-22:00)"))?,
+                );
+            }
+            "--album-byte-quota" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--album-byte-quota")?;
+                let bad = || format!("bad --album-byte-quota: {v} (expected FOLDER=SIZE or SIZE, e.g. trips/rome=200MiB)");
+                match v.rsplit_once('=') {
+                    Some((name, size)) => {
+                        let name = nfc::to_nfc(name.trim_matches('/'));
+                        let limit = parse_byte_size(size).ok_or_else(bad)?;
+                        album_quotas.named.retain(|(n, _)| *n != name);
+                        album_quotas.named.push((name, limit));
+                    }
+                    None => album_quotas.default = Some(parse_byte_size(&v).ok_or_else(bad)?),
+                }
+            }
+            "--group-by" => {
+                i += 1;
+                group_by = match required_arg(&argv, i, "--group-by")?.as_str() {
+                    "none" => GroupBy::Pool,
+                    "album" => GroupBy::Album,
+                    "location" => {
+                        // The radius is an optional trailing value.
+                        let mut radius_km = DEFAULT_LOCATION_RADIUS_KM;
+                        if let Some(r) = argv.get(i + 1).and_then(|v| v.parse::<f64>().ok()) {
+                            if r.is_nan() || r <= 0.0 {
+                                return Err("--group-by location radius must be > 0".to_string());
+                            }
+                            radius_km = r;
+                            i += 1;
+                        }
+                        GroupBy::Location { radius_km }
+                    }
+                    other => {
+                        return Err(format!(
+                            "unknown --group-by mode: {other} (expected none, album or location)"
+                        ))
+                    }
+                };
+            }
+            "--name-groups-by" => {
+                i += 1;
+                name_groups_by.clear();
+                for part in required_arg(&argv, i, "--name-groups-by")?.split(',') {
+                    let label = match part.trim() {
+                        "keywords" => GroupLabel::Keywords,
+                        "date-range" => GroupLabel::DateRange,
+                        "location" => GroupLabel::Location,
+                        other => {
+                            return Err(format!(
+                                "unknown --name-groups-by part: {other} (expected keywords, date-range or location)"
+                            ))
+                        }
+                    };
+                    if !name_groups_by.contains(&label) {
+                        name_groups_by.push(label);
+                    }
+                }
+            }
+            "--order" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--order")?;
+                order = Order::parse(&v).ok_or_else(|| {
+                    format!("unknown --order mode: {v} (expected {})", Order::NAMES)
+                })?;
+            }
+            "--diversify-colors" => {
+                if !cfg!(feature = "imaging") {
+                    return Err(
+                        "--diversify-colors needs a build with `--features imaging`".to_string()
+                    );
+                }
+                diversify_colors = true;
+            }
+            "--spread-similar" => {
+                if !cfg!(feature = "imaging") {
+                    return Err("--spread-similar needs a build with `--features imaging`".to_string());
+                }
+                spread_similar = true;
+            }
+            "--stratify-by" => {
+                i += 1;
+                stratify_by = Some(match required_arg(&argv, i, "--stratify-by")?.as_str() {
+                    "year" => Stratum::Year,
+                    "album" => Stratum::Album,
+                    "orientation" => Stratum::Orientation,
+                    other => {
+                        return Err(format!(
+                            "unknown --stratify-by dimension: {other} (expected year, album or orientation)"
+                        ))
+                    }
+                });
+            }
+            "--require-person" => {
+                i += 1;
+                require_people.push(required_arg(&argv, i, "--require-person")?);
+            }
+            "--exclude-person" => {
+                i += 1;
+                exclude_people.push(required_arg(&argv, i, "--exclude-person")?);
+            }
+            "--animated" => {
+                i += 1;
+                animated = match required_arg(&argv, i, "--animated")?.as_str() {
+                    "skip" => AnimatedPolicy::Skip,
+                    "first-frame" => AnimatedPolicy::FirstFrame,
+                    "copy" => AnimatedPolicy::Copy,
+                    other => {
+                        return Err(format!(
+                            "unknown --animated policy: {other} (expected skip, first-frame or copy)"
+                        ))
+                    }
+                };
+            }
+            "--rename-template" => {
+                i += 1;
+                let t = required_arg(&argv, i, "--rename-template")?;
+                rename_template = Some(
+                    template::Template::parse(&t).map_err(|e| format!("invalid --rename-template: {e}"))?,
+                );
+            }
+            "--pin-list" => {
+                i += 1;
+                pin_list = Some(PathBuf::from(required_arg(&argv, i, "--pin-list")?));
+            }
+            "--include-list" => {
+                i += 1;
+                include_list = Some(PathBuf::from(required_arg(&argv, i, "--include-list")?));
+            }
+            "--exclude-list" => {
+                i += 1;
+                exclude_list = Some(PathBuf::from(required_arg(&argv, i, "--exclude-list")?));
+            }
+            "--favorites" => {
+                i += 1;
+                favorites = Some(PathBuf::from(required_arg(&argv, i, "--favorites")?));
+            }
+            "--favorites-weight" => {
+                i += 1;
+                favorites_weight = required_arg(&argv, i, "--favorites-weight")?
+                    .parse::<f64>()
+                    .map_err(|_| "--favorites-weight must be a number".to_string())?;
+                // An infinite weight would leave favourites in scan order.
+                if !(favorites_weight.is_finite() && favorites_weight > 0.0) {
+                    return Err("--favorites-weight must be > 0".to_string());
+                }
+            }
+            "--resize" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--resize")?;
+                if !cfg!(feature = "imaging") {
+                    return Err("--resize needs a build with `--features imaging`".to_string());
+                }
+                resize_box = Some(parse_dimensions(&v).ok_or_else(|| {
+                    format!("invalid --resize: {v} (expected WIDTHxHEIGHT, e.g. 3840x2160)")
+                })?);
+            }
+            "--max-megapixels" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--max-megapixels")?;
+                if !cfg!(feature = "imaging") {
+                    return Err("--max-megapixels needs a build with `--features imaging`".to_string());
+                }
+                let mp = v
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid --max-megapixels: {v} (expected a number, e.g. 12)"))?;
+                if !(mp > 0.0 && mp.is_finite()) {
+                    return Err("--max-megapixels must be > 0".to_string());
+                }
+                max_pixels = Some(((mp * 1_000_000.0) as u64).max(1));
+            }
+            "--fit" => {
+                i += 1;
+                fit = Some(match required_arg(&argv, i, "--fit")?.as_str() {
+                    "inside" => Fit::Inside,
+                    "crop" => Fit::Crop,
+                    other => {
+                        return Err(format!("unknown --fit mode: {other} (expected inside or crop)"))
+                    }
+                });
+            }
+            "--resize-filter" => {
+                i += 1;
+                filter = match required_arg(&argv, i, "--resize-filter")?.as_str() {
+                    "lanczos3" => ResizeFilter::Lanczos3,
+                    "bilinear" => ResizeFilter::Bilinear,
+                    other => {
+                        return Err(format!(
+                            "unknown --resize-filter: {other} (expected lanczos3 or bilinear)"
+                        ))
+                    }
+                };
+            }
+            "--jpeg" => {
+                i += 1;
+                jpeg.progressive = match required_arg(&argv, i, "--jpeg")?.as_str() {
+                    "baseline" => false,
+                    "progressive" => true,
+                    other => {
+                        return Err(format!(
+                            "unknown --jpeg mode: {other} (expected baseline or progressive)"
+                        ))
+                    }
+                };
+            }
+            "--chroma" => {
+                i += 1;
+                jpeg.chroma = match required_arg(&argv, i, "--chroma")?.as_str() {
+                    "4:4:4" => Chroma::Full,
+                    "4:2:2" => Chroma::Half,
+                    "4:2:0" => Chroma::Quarter,
+                    other => {
+                        return Err(format!(
+                            "unknown --chroma subsampling: {other} (expected 4:4:4, 4:2:2 or 4:2:0)"
+                        ))
+                    }
+                };
+            }
+            "--optimize-jpeg" => {
+                jpeg.optimize = true;
+            }
+            "--tone-map-sdr" => {
+                jpeg.tone_map_sdr = true;
+            }
+            "--convert-srgb" => {
+                jpeg.convert_srgb = true;
+            }
+            "--strip-thumbnails" => {
+                strip_thumbnails = true;
+            }
+            "--panoramas" => {
+                i += 1;
+                panoramas = match required_arg(&argv, i, "--panoramas")?.as_str() {
+                    "keep" => Panoramas::Keep,
+                    "skip" => Panoramas::Skip,
+                    "crop-center" => Panoramas::CropCenter,
+                    "letterbox" => Panoramas::Letterbox,
+                    other => {
+                        return Err(format!(
+                            "unknown --panoramas policy: {other} (expected keep, skip, crop-center or letterbox)"
+                        ))
+                    }
+                };
+                if matches!(panoramas, Panoramas::CropCenter | Panoramas::Letterbox) && !cfg!(feature = "imaging") {
+                    return Err("--panoramas crop-center and letterbox need a build with `--features imaging`".to_string());
+                }
+            }
+            "--safe-names" => {
+                safe_names = true;
+            }
+            "--preserve-structure" => {
+                preserve_structure = true;
+            }
+            "--preset" => {
+                i += 1;
+                let v = required_arg(&argv, i, "--preset")?;
+                preset = Some(Preset::parse(&v).ok_or_else(|| {
+                    format!("unknown --preset: {v} (expected {})", Preset::NAMES)
+                })?);
+            }
+            "--jobs" | "-j" => {
+                i += 1;
+                let n = required_arg(&argv, i, "--jobs")?
+                    .parse::<usize>()
+                    .map_err(|_| "--jobs must be an integer".to_string())?;
+                if n == 0 {
+                    return Err("--jobs must be > 0".to_string());
+                }
+                jobs = Some(n);
+            }
+            "--low-memory" => {
+                low_memory = true;
+            }
+            "--sync" => {
+                sync = true;
+            }
+            "--stable-reshuffle" => {
+                i += 1;
+                let p = required_arg(&argv, i, "--stable-reshuffle")?
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|p| *p <= 100)
+                    .ok_or("--stable-reshuffle must be a percentage from 0 to 100")?;
+                stable_reshuffle = Some(p);
+            }
+            other => {
+                return Err(format!(
+                    "unknown argument: {other}\n\nRun with --help for usage."
+                ));
+            }
+        }
+        i += 1;
+    }
+
+    if (limit_mode == LimitMode::FilesOnly && max_bytes.is_some()) || (limit_mode == LimitMode::BytesOnly && max_files.is_some()) {
+        return Err("--limit-mode files-only ignores --max-bytes, and bytes-only ignores --max-files; give only the limit that counts".to_string());
+    }
+    let max_files = match limit_mode {
+        LimitMode::BytesOnly => usize::MAX,
+        _ => max_files.or(preset.map(|p| p.max_files)).unwrap_or(DEFAULT_MAX_FILES),
+    };
+    let max_bytes = match limit_mode {
+        LimitMode::FilesOnly => u64::MAX,
+        _ => max_bytes.or(preset.map(|p| p.max_bytes)).unwrap_or(DEFAULT_MAX_BYTES),
+    };
+    if min_files.is_some_and(|n| n > max_files) {
+        return Err(format!("--min-files-per-folder can't be more than the {max_files} files per folder"));
+    }
+    if min_files.is_some() && group_by != GroupBy::Pool {
+        return Err("--min-files-per-folder only works with --group-by none".to_string());
+    }
+    if diversify_colors && scan.formats != [ImageFormat::Jpeg] {
+        return Err("--diversify-colors can only read JPEG photos; leave the other formats out of --formats".to_string());
+    }
+    if bytes_tolerance.is_some() && limit_mode == LimitMode::FilesOnly {
+        return Err("--bytes-tolerance needs a byte limit; --limit-mode files-only has none".to_string());
+    }
+    if bytes_tolerance.is_some() && group_by != GroupBy::Pool {
+        return Err("--bytes-tolerance only works with --group-by none".to_string());
+    }
+    let safe_names = safe_names || preset.is_some_and(|p| p.safe_names);
+    let resize = match (resize_box, preset) {
+        (Some((width, height)), _) => Some(Resize { width, height, max_pixels, fit: fit.unwrap_or(Fit::Inside), filter }),
+        (None, Some(p)) if cfg!(feature = "imaging") => {
+            Some(Resize { max_pixels, fit: fit.unwrap_or(p.resize.fit), filter, ..p.resize })
+        }
+        (None, Some(p)) => {
+            eprintln!(
+                "warning: --preset would resize photos to {}x{}; that needs a build with `--features imaging`",
+                p.resize.width, p.resize.height
+            );
+            None
+        }
+        (None, None) => max_pixels.map(|max_pixels| Resize {
+            width: u32::MAX,
+            height: u32::MAX,
+            max_pixels: Some(max_pixels),
+            fit: fit.unwrap_or(Fit::Inside),
+            filter,
+        }),
+    };
+    if fit == Some(Fit::Crop) && resize.is_some_and(|r| r.width == u32::MAX) {
+        return Err("--fit crop needs a --resize box".to_string());
+    }
+    if fit.is_some() && resize.is_none() {
+        return Err("--fit needs --resize".to_string());
+    }
+    if jpeg.chroma != Chroma::default() && resize.is_none() {
+        return Err("--chroma only applies to re-encoded photos (--resize)".to_string());
+    }
+    if jpeg.progressive && resize.is_none() && !jpeg.optimize {
+        return Err("--jpeg only applies to re-encoded photos (--resize) or --optimize-jpeg".to_string());
+    }
+    let reframes = matches!(panoramas, Panoramas::CropCenter | Panoramas::Letterbox);
+    if jpeg.tone_map_sdr && resize.is_none() && !jpeg.optimize && !reframes {
+        return Err(
+            "--tone-map-sdr only applies to re-encoded photos (--resize, --panoramas crop-center|letterbox) \
+             or --optimize-jpeg"
+                .to_string(),
+        );
+    }
+    if jpeg.convert_srgb && resize.is_none() && !reframes {
+        return Err("--convert-srgb only applies to re-encoded photos (--resize, --panoramas crop-center|letterbox)".to_string());
+    }
+    if favorites.is_some() && order != Order::Random {
+        return Err("--favorites only applies to --order random".to_string());
+    }
+    // One photo decoded at a time is what keeps a run within a few hundred
+    // megabytes; a full-size decode alone can take a hundred.
+    let jobs = match jobs {
+        Some(n) if low_memory && n > 1 => {
+            return Err("--low-memory processes one photo at a time; leave out --jobs".to_string())
+        }
+        Some(n) => n,
+        None if low_memory => 1,
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    };
+    scan.threads = jobs;
+    if group_by == GroupBy::Album && !scan.recursive {
+        return Err("--group-by album requires --recursive".to_string());
+    }
+    let mut dsts = dsts.into_iter();
+    let dst = dsts.next().unwrap_or_else(platform::default_dst);
+    let mirrors: Vec<PathBuf> = dsts.collect();
+    if !mirrors.is_empty() && !mirror && !distribute {
+        return Err(
+            "--dst is given more than once; add --mirror to write the same plan to each, or --distribute to split it"
+                .to_string(),
+        );
+    }
+    if mirror && distribute {
+        return Err("--mirror and --distribute don't combine".to_string());
+    }
+    if let Some(twice) = mirrors.iter().enumerate().find(|&(k, m)| *m == dst || mirrors[..k].contains(m)) {
+        return Err(format!("--dst {} is given twice", twice.1.display()));
+    }
+    if !album_quotas.is_empty() && !scan.recursive {
+        return Err("--album-byte-quota requires --recursive".to_string());
+    }
+    if !src_weights.is_empty() && !scan.recursive {
+        return Err("--src-weight requires --recursive".to_string());
+    }
+    if src_weights.iter().map(|(_, p)| p).sum::<u32>() > 100 {
+        return Err("--src-weight percentages add up to more than 100".to_string());
+    }
+    if pick_one_album && !scan.recursive {
+        return Err("--pick-one-album requires --recursive".to_string());
+    }
+    if skip_recent_albums.is_some() && !pick_one_album {
+        return Err("--skip-recent-albums needs --pick-one-album".to_string());
+    }
+    if sandbox && sync {
+        return Err("--sandbox always starts from an empty folder, so it doesn't combine with --sync".to_string());
+    }
+    if stable_reshuffle.is_some() && !sync {
+        return Err("--stable-reshuffle needs --sync".to_string());
+    }
+    if stable_reshuffle.is_some() && distribute {
+        return Err("--stable-reshuffle doesn't combine with --distribute".to_string());
+    }
+    if stable_reshuffle.is_some() && group_by != GroupBy::Pool {
+        return Err("--stable-reshuffle only works with --group-by none".to_string());
+    }
+    if sets.is_some() && (mirror || distribute) {
+        return Err("--sets writes everything below a single --dst".to_string());
+    }
+    if sets.is_some() && sync {
+        return Err("--sets doesn't combine with --sync".to_string());
+    }
+    if split_by_orientation && (mirror || distribute) {
+        return Err("--split-by-orientation writes everything below a single --dst".to_string());
+    }
+    if split_by_orientation && (sync || sets.is_some()) {
+        return Err("--split-by-orientation doesn't combine with --sync or --sets".to_string());
+    }
+    if import_selection.is_some() && from_index.is_some() {
+        return Err("--import-selection and --from-index both say what to choose from; give one".to_string());
+    }
+    if spread_similar && group_by != GroupBy::Pool {
+        return Err("--spread-similar only works with --group-by none; album and location folders keep their photos together".to_string());
+    }
+    if keep_runs.is_some() && !dst_layout.writes_runs() {
+        return Err("--keep-runs needs --dst-layout runs or cas".to_string());
+    }
+    if dst_layout.writes_runs() && sync {
+        return Err("--dst-layout runs and cas start a new folder every time, so they don't combine with --sync".to_string());
+    }
+
+    Ok(Args {
+        src,
+        dst,
+        mirrors,
+        distribute,
+        max_files,
+        max_bytes,
+        limit_mode,
+        min_files,
+        bytes_tolerance,
+        seed,
+        scan,
+        group_by,
+        name_groups_by,
+        order,
+        diversify_colors,
+        spread_similar,
+        stratify_by,
+        require_people,
+        exclude_people,
+        max_video_files,
+        max_video_bytes,
+        animated,
+        rename_template,
+        pin_list,
+        include_list,
+        exclude_list,
+        favorites,
+        favorites_weight,
+        processing: Processing { resize, jpeg, strip_thumbnails, panoramas },
+        safe_names,
+        preserve_structure,
+        jobs,
+        sync,
+        resume: false,
+        stable_reshuffle,
+        album_quotas,
+        src_weights,
+        pick_one_album,
+        skip_recent_albums: skip_recent_albums.unwrap_or(0),
+        time_budget,
+        quiet_hours,
+        limit,
+        sandbox,
+        sets,
+        split_by_orientation,
+        dst_layout,
+        keep_runs,
+        use_trash,
+        exif_cache,
+        from_index,
+        import_selection,
+        select: selector,
+        within,
+        outside,
+        assume_timezone,
+    })
+}
+
+/// A byte count with an optional unit: `500000`, `800KB`, `200MiB`, `1.5GiB`.
+/// KB/MB/GB are powers of 1000, KiB/MiB/GiB powers of 1024.
+fn parse_byte_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return None,
+    };
+    let n: f64 = number.parse().ok()?;
+    (n.is_finite() && n >= 0.0).then(|| (n * scale as f64).round() as u64)
+}
+
+/// `--quiet-hours`: a daily window of local time, in minutes after midnight.
+/// It wraps past midnight when `end` comes before `start`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QuietHours {
+    start: u32,
+    end: u32,
+}
+
+impl QuietHours {
+    /// `08:00-22:00`, `22:30-6:00`.
+    fn parse(s: &str) -> Option<QuietHours> {
+        let minutes = |t: &str| {
+            let (h, m) = t.trim().split_once(':')?;
+            if m.len() != 2 {
+                return None;
+            }
+            let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+            (h < 24 && m < 60).then_some(h * 60 + m)
+        };
+        let (start, end) = s.split_once('-')?;
+        let (start, end) = (minutes(start)?, minutes(end)?);
+        (start != end).then_some(QuietHours { start, end })
+    }
+
+    /// Seconds until the window ends if `second` (of the local day) is in it.
+    fn left_at(&self, second: u32) -> Option<u32> {
+        let (start, end) = (self.start * 60, self.end * 60);
+        let inside = if start < end { (start..end).contains(&second) } else { second >= start || second < end };
+        inside.then(|| (end + 86_400 - second) % 86_400)
+    }
+}
+
+/// When copying may go on: until the `--time-budget` deadline, and not in
+/// the quiet hours (with the local UTC offset in minutes).
+#[derive(Debug, Clone, Copy, Default)]
+struct CopyWindow {
+    deadline: Option<Instant>,
+    quiet: Option<(QuietHours, i32)>,
+}
+
+impl CopyWindow {
+    fn expired(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Sleeps until the quiet hours are over, if they are on now.
+    fn wait_out_quiet_hours(&self) {
+        let Some((hours, offset)) = self.quiet else { return };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let second = (now + i64::from(offset) * 60).rem_euclid(86_400) as u32;
+        if let Some(left) = hours.left_at(second) {
+            let wait = Duration::from_secs(u64::from(left));
+            eprintln!("Quiet hours: waiting {}", format_duration(wait));
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// `90s`, `15m`, `1.5h`; a bare number is seconds.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, scale) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1.0),
+        (i, 'm') => (&s[..i], 60.0),
+        (i, 'h') => (&s[..i], 3600.0),
+        _ => (s, 1.0),
+    };
+    let n: f64 = number.trim().parse().ok()?;
+    // Too long for a `Duration` (1e20h) is as bad as negative.
+    Duration::try_from_secs_f64(n * scale).ok()
+}
+
+/// `WIDTHxHEIGHT`, both > 0.
+fn parse_dimensions(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    let (w, h) = (w.parse::<u32>().ok()?, h.parse::<u32>().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+/// `image-rando block [--src PATH] [--exclude-list FILE] PHOTO...`: appends
+/// photos to the block list so later runs never copy them.
+fn run_block(argv: &[String]) -> Result<(), String> {
+    let mut src = platform::default_src();
+    let mut list = None;
+    let mut photos = Vec::new();
+    let mut i = 0;
+    while i < argv.len() {
+        match argv[i].as_str() {
+            "--src" => {
+                i += 1;
+                src = PathBuf::from(required_arg(argv, i, "--src")?);
+            }
+            "--exclude-list" => {
+                i += 1;
+                list = Some(PathBuf::from(required_arg(argv, i, "--exclude-list")?));
+            }
+            other if other.starts_with('-') => {
+                return Err(format!("unknown argument: {other}\n\nRun with --help for usage."));
+            }
+            photo => photos.push(PathBuf::from(photo)),
+        }
+        i += 1;
+    }
+    if photos.is_empty() {
+        return Err("block needs at least one photo".to_string());
+    }
+    let list = list.unwrap_or_else(|| src.join(DEFAULT_EXCLUDE_LIST));
+    let added = append_to_block_list(&list, &src, &photos)?;
+    println!("Blocked {added} photo(s) in {}", list.display());
+    Ok(())
+}
+
+/// Photos under `src` are recorded relative to it, so the list survives the
+/// library moving; anything else is recorded as an absolute path. Returns how
+/// many new entries were written.
+fn append_to_block_list(list: &Path, src: &Path, photos: &[PathBuf]) -> Result<usize, String> {
+    let existing = if list.is_file() {
+        fs::read_to_string(list).map_err(|e| format!("cannot read list {}: {e}", list.display()))?
+    } else {
+        String::new()
+    };
+    let known = load_path_list_text(&existing, src)?;
+    let abs_src = std::path::absolute(src).map_err(|e| format!("cannot resolve path {}: {e}", src.display()))?;
+
+    let mut text = String::new();
+    let mut added = Vec::new();
+    for photo in photos {
+        if !photo.is_file() {
+            return Err(format!("not a file: {}", photo.display()));
+        }
+        let abs = std::path::absolute(photo)
+            .map_err(|e| format!("cannot resolve path {}: {e}", photo.display()))?;
+        let entry = match abs.strip_prefix(&abs_src) {
+            Ok(rel) => relative_name(Path::new(""), rel),
+            Err(_) => abs.to_string_lossy().into_owned(),
+        };
+        let resolved = platform::long_path(&src.join(&entry))?;
+        if known.contains(&resolved) || added.contains(&resolved) {
+            continue;
+        }
+        added.push(resolved);
+        text.push_str(&entry);
+        text.push('\n');
+    }
+    if text.is_empty() {
+        return Ok(0);
+    }
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        text.insert(0, '\n');
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(list)
+        .map_err(|e| format!("cannot open list {}: {e}", list.display()))?;
+    file.write_all(text.as_bytes())
+        .map_err(|e| format!("cannot write list {}: {e}", list.display()))?;
+    Ok(added.len())
+}
+
+/// `image-rando report [--format csv] [OPTIONS]`: runs the normal scan and
+/// filters, then prints one line per photo instead of copying anything.
+fn run_report(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    match take_flag_value(&mut argv, "--format")?.as_deref() {
+        None | Some("csv") => {}
+        Some(other) => return Err(format!("unknown report --format: {other} (expected csv)")),
+    }
+    let args = parse_read_only_args(argv)?;
+
+    let Selection { mut files, exif_loaded, .. } = select_files(&args)?;
+    files.retain(|f| f.kind == MediaKind::Photo);
+    if !exif_loaded {
+        load_exif(&mut files, args.exif_cache.as_deref())?;
+    }
+    if args.from_index.is_none() {
+        load_xmp(&mut files)?;
+    }
+    let mut out = io::stdout().lock();
+    write_csv_report(&mut out, &files, &args.src).map_err(|e| format!("cannot write report: {e}"))
+}
+
+/// `image-rando index [--index FILE] [OPTIONS]`: scans the source and reads
+/// every file's metadata into a catalog that later runs can plan from with
+/// `--from-index`.
+fn run_index(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let catalog = take_flag_value(&mut argv, "--index")?.map(PathBuf::from);
+    let args = parse_read_only_args(argv)?;
+    if args.from_index.is_some() {
+        return Err("index always scans the source, so --from-index doesn't apply".to_string());
+    }
+    let catalog = catalog.unwrap_or_else(|| index::default_path(&args.src));
+    let mut files = collect_jpgs(&args.src, &args.scan)?;
+    load_exif(&mut files, args.exif_cache.as_deref())?;
+    load_xmp(&mut files)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let t = exif::DateTime::from_unix(now);
+    let created = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    );
+    index::save(&catalog, &args.src, &created, &files)?;
+    let videos = files.iter().filter(|f| f.kind == MediaKind::Video).count();
+    println!("Indexed {} photos and {videos} videos into {}", files.len() - videos, catalog.display());
+    Ok(())
+}
+
+fn write_csv_report(out: &mut impl Write, files: &[FileInfo], src: &Path) -> io::Result<()> {
+    writeln!(out, "path,size,captured,camera,width,height,gps,rating")?;
+    for f in files {
+        let captured = f.exif.captured.map(|d| {
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                d.year, d.month, d.day, d.hour, d.minute, d.second
+            )
+        });
+        let (width, height) = match f.exif.display_dimensions() {
+            Some((w, h)) => (w.to_string(), h.to_string()),
+            None => (String::new(), String::new()),
+        };
+        let rating = f.xmp.rating.or(f.exif.rating.map(|r| r as i8));
+        writeln!(
+            out,
+            "{},{},{},{},{width},{height},{},{}",
+            csv_field(&relative_name(src, &f.path)),
+            f.size,
+            captured.unwrap_or_default(),
+            csv_field(f.exif.camera.as_deref().unwrap_or_default()),
+            if f.exif.gps.is_some() { "yes" } else { "no" },
+            rating.map(|r| r.to_string()).unwrap_or_default(),
+        )?;
+    }
+    out.flush()
+}
+
+/// Quotes a field when it contains a comma, quote or line break (RFC 4180).
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+/// `image-rando analyze --dupes [--max-distance N] [OPTIONS]`: lists groups of
+/// identical (and, with `--features imaging`, near-identical) photos among
+/// those the options select, without copying anything.
+fn run_analyze(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let dupes = take_switch(&mut argv, "--dupes");
+    let max_distance = match take_flag_value(&mut argv, "--max-distance")? {
+        Some(_) if !cfg!(feature = "imaging") => {
+            return Err("--max-distance needs a build with `--features imaging`".to_string())
+        }
+        Some(v) => Some(
+            v.parse::<u32>()
+                .ok()
+                .filter(|&d| d <= 64)
+                .ok_or_else(|| format!("invalid --max-distance: {v} (expected 0..=64)"))?,
+        ),
+        None => cfg!(feature = "imaging").then_some(DEFAULT_DUPE_DISTANCE),
+    };
+    if !dupes {
+        return Err("analyze needs --dupes (the only analysis so far)".to_string());
+    }
+    let args = parse_read_only_args(argv)?;
+
+    let Selection { mut files, .. } = select_files(&args)?;
+    files.retain(|f| f.kind == MediaKind::Photo);
+    let clusters = duplicate_clusters(&files, max_distance, args.jobs)?;
+    let mut reclaimable = 0;
+    for cluster in &clusters {
+        let kind = if cluster.identical { "identical" } else { "similar" };
+        println!("{kind}: {} photos", cluster.members.len());
+        for &idx in &cluster.members {
+            let f = &files[idx];
+            println!("  {} ({} bytes)", relative_name(&args.src, &f.path), f.size);
+        }
+        println!();
+        reclaimable += cluster.reclaimable(&files);
+    }
+    println!(
+        "{} duplicate groups; keeping only the largest photo of each would free {reclaimable} bytes",
+        clusters.len()
+    );
+    Ok(())
+}
+
+/// `image-rando audit [OPTIONS]`: reads every file the scan finds and reports
+/// the ones a frame would choke on or sort wrongly.
+fn run_audit(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let args = parse_read_only_args(argv)?;
+    let files = collect_jpgs(&args.src, &args.scan)?;
+    let findings = parallel_map(&files, args.jobs, audit_file);
+
+    let mut counts = BTreeMap::new();
+    for (f, finding) in files.iter().zip(&findings) {
+        let Some(finding) = finding else {
+            continue;
+        };
+        *counts.entry(finding.label()).or_insert(0) += 1;
+        let path = relative_name(&args.src, &f.path);
+        match finding {
+            AuditFinding::Unreadable(detail) | AuditFinding::Corrupt(detail) | AuditFinding::BadDate(detail) => {
+                println!("{}: {path} ({detail})", finding.label())
+            }
+            AuditFinding::Empty | AuditFinding::NoDate => println!("{}: {path}", finding.label()),
+        }
+    }
+    let summary: Vec<String> = counts.iter().map(|(label, n)| format!("{n} {label}")).collect();
+    if summary.is_empty() {
+        println!("Audited {} files: no problems found", files.len());
+    } else {
+        println!("Audited {} files: {}", files.len(), summary.join(", "));
+    }
+    Ok(())
+}
+
+/// The most serious problem with one file; a corrupt file isn't also
+/// reported for its date.
+#[derive(Debug, PartialEq)]
+enum AuditFinding {
+    Unreadable(String),
+    Empty,
+    /// Truncated or otherwise not decodable.
+    Corrupt(String),
+    /// No EXIF capture date at all.
+    NoDate,
+    /// An EXIF date that doesn't parse, e.g. `0000:00:00 00:00:00`.
+    BadDate(String),
+}
+
+impl AuditFinding {
+    fn label(&self) -> &'static str {
+        match self {
+            AuditFinding::Unreadable(_) => "unreadable",
+            AuditFinding::Empty => "empty",
+            AuditFinding::Corrupt(_) => "corrupt",
+            AuditFinding::NoDate => "no date",
+            AuditFinding::BadDate(_) => "bad date",
+        }
+    }
+}
+
+fn audit_file(f: &FileInfo) -> Option<AuditFinding> {
+    let data = match fs::read(&f.path) {
+        Ok(data) => data,
+        Err(e) => return Some(AuditFinding::Unreadable(e.to_string())),
+    };
+    if data.is_empty() {
+        return Some(AuditFinding::Empty);
+    }
+    if f.format != Some(ImageFormat::Jpeg) {
+        return None;
+    }
+    if let Err(e) = jpegopt::check(&data) {
+        return Some(AuditFinding::Corrupt(e));
+    }
+    let exif = match exif::read_exif(&f.path) {
+        Ok(exif) => exif,
+        Err(e) => return Some(AuditFinding::Unreadable(e)),
+    };
+    match (exif.captured, exif.captured_text) {
+        (Some(_), _) => None,
+        (None, Some(text)) if !text.is_empty() => Some(AuditFinding::BadDate(format!("{text:?}"))),
+        (None, _) => Some(AuditFinding::NoDate),
+    }
+}
+
+/// `image-rando diff OLD NEW`: compares the manifests of two runs.
+fn run_diff(argv: &[String]) -> Result<(), String> {
+    let [old, new] = argv else {
+        return Err("usage: diff OLD_MANIFEST NEW_MANIFEST".to_string());
+    };
+    let old = manifest::Manifest::load(Path::new(old))?;
+    let new = manifest::Manifest::load(Path::new(new))?;
+    let d = manifest::diff(&old, &new);
+    for e in &d.added {
+        println!("added:   {} -> {}", e.source, new.destination(e));
+    }
+    for e in &d.removed {
+        println!("removed: {} (was {})", e.source, old.destination(e));
+    }
+    for (o, e) in &d.moved {
+        println!("moved:   {}: {} -> {}", e.source, old.destination(o), new.destination(e));
+    }
+    println!(
+        "{} added, {} removed, {} moved, {} unchanged",
+        d.added.len(),
+        d.removed.len(),
+        d.moved.len(),
+        d.unchanged
+    );
+    Ok(())
+}
+
+/// Default amount written by the sequential part of `bench`.
+const DEFAULT_BENCH_BYTES: u64 = 256 * 1024 * 1024;
+
+/// `image-rando bench [--size SIZE] [OPTIONS]`: measures how fast the
+/// destination takes data, then estimates how long copying the photos the
+/// options select would take.
+fn run_bench(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let size = match take_flag_value(&mut argv, "--size")? {
+        Some(v) => parse_byte_size(&v)
+            .filter(|&n| n >= bench::SMALL_FILE_BYTES as u64)
+            .ok_or_else(|| format!("bad bench --size: {v} (expected e.g. 256MiB)"))?,
+        None => DEFAULT_BENCH_BYTES,
+    };
+    let mut args = parse_args(argv)?;
+    args.dst = platform::long_path(&args.dst)?;
+    fs::create_dir_all(&args.dst)
+        .map_err(|e| format!("cannot create destination folder {}: {e}", args.dst.display()))?;
+
+    println!("Benchmarking {} ...", args.dst.display());
+    let t = bench::measure(&args.dst, size)?;
+    let mb = |bytes_per_sec: f64| bytes_per_sec / 1e6;
+    println!("Sequential write: {:.1} MB/s ({} MiB, synced)", mb(t.sequential_write), size >> 20);
+    println!("Sequential read:  {:.1} MB/s (may come from the cache)", mb(t.sequential_read));
+    println!(
+        "Small files ({} x {} KiB): {:.0} written/s, {:.0} read/s",
+        bench::SMALL_FILES,
+        bench::SMALL_FILE_BYTES / 1024,
+        t.small_writes,
+        t.small_reads
+    );
+
+    args.src = platform::long_path(&args.src)?;
+    if !args.src.is_dir() {
+        println!("No source folder at {}; skipping the estimate", args.src.display());
+        return Ok(());
+    }
+    let Selection { files, .. } = select_files(&args)?;
+    let bytes: u64 = files.iter().map(|f| f.footprint()).sum();
+    let count = files.len() + files.iter().map(|f| f.sidecars.len()).sum::<usize>();
+    println!(
+        "Estimated time to copy {} files ({:.1} MB) from {}: {}",
+        count,
+        bytes as f64 / 1e6,
+        args.src.display(),
+        format_duration(t.estimate(count, bytes))
+    );
+    if args.processing.resize.is_some() || args.processing.jpeg.optimize {
+        println!("(before resizing or optimising, which usually makes the files smaller but costs CPU time)");
+    }
+    Ok(())
+}
+
+/// `1h 05m`, `4m 10s`, `12s`.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs_f64().round() as u64;
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Time between scheduled daemon runs without `--every`.
+#[cfg(unix)]
+const DEFAULT_DAEMON_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// `image-rando daemon --socket PATH [--every DURATION] [--options FILE]
+/// [OPTIONS]`: runs the copy with OPTIONS (and those in FILE) every DURATION
+/// and on command (see `daemon.rs`).
+#[cfg(unix)]
+fn run_daemon(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let socket = take_flag_value(&mut argv, "--socket")?.map(PathBuf::from).ok_or("daemon needs --socket PATH")?;
+    let every = match take_flag_value(&mut argv, "--every")? {
+        Some(v) => parse_duration(&v).ok_or_else(|| format!("bad --every: {v} (expected e.g. 6h or 30m)"))?,
+        None => DEFAULT_DAEMON_INTERVAL,
+    };
+    let options_file = take_flag_value(&mut argv, "--options")?.map(PathBuf::from);
+    let config = daemon::Config { socket, every, args: argv, options_file };
+    daemon::serve(config, check_daemon_options, |argv| run_copy(argv.to_vec()))
+}
+
+#[cfg(not(unix))]
+fn run_daemon(_argv: Vec<String>) -> Result<(), String> {
+    Err("daemon listens on a Unix socket, which this platform doesn't have".to_string())
+}
+
+/// Time each photo stays on screen with `display` unless `--interval` says.
+#[cfg(feature = "imaging")]
+const DEFAULT_DISPLAY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `image-rando display [--fb DEVICE] [--interval DURATION] [OPTIONS]`: shows
+/// the photos the options select full screen on a Linux framebuffer, in
+/// `--order` (shuffled again for every pass), instead of copying them.
+#[cfg(feature = "imaging")]
+fn run_display(mut argv: Vec<String>) -> Result<(), String> {
+    argv.remove(1);
+    let device = PathBuf::from(take_flag_value(&mut argv, "--fb")?.unwrap_or_else(|| "/dev/fb0".to_string()));
+    let interval = match take_flag_value(&mut argv, "--interval")? {
+        Some(v) => parse_duration(&v).ok_or_else(|| format!("bad --interval: {v} (expected e.g. 30s or 5m)"))?,
+        None => DEFAULT_DISPLAY_INTERVAL,
+    };
+    let args = parse_read_only_args(argv)?;
+    let Selection { mut files, exif_loaded, .. } = select_files(&args)?;
+    files.retain(|f| f.kind == MediaKind::Photo);
+    if args.order.needs_exif() && !exif_loaded {
+        load_exif(&mut files, args.exif_cache.as_deref())?;
+    }
+    let mut screen = display::Framebuffer::open(&device)?;
+    let size = (screen.width, screen.height);
+    for pass in 0u64.. {
+        let mut order = files.clone();
+        order_files(&mut order, args.order, args.seed.wrapping_add(pass), args.assume_timezone);
+        let mut any_shown = false;
+        for f in &order {
+            let shown = Instant::now();
+            match imaging::screen_frame(&f.path, size) {
+                Ok(pixels) => screen.show(&pixels)?,
+                Err(e) => {
+                    eprintln!("warning: skipping {e}");
+                    continue;
+                }
+            }
+            any_shown = true;
+            thread::sleep(interval.saturating_sub(shown.elapsed()));
+        }
+        // Going round again would only spin through the same failures.
+        if !any_shown {
+            return Err(format!("none of the {} photos in {} could be shown", order.len(), args.src.display()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "imaging"))]
+fn run_display(_argv: Vec<String>) -> Result<(), String> {
+    Err("display decodes photos, which needs a build with `--features imaging`".to_string())
+}
+
+/// Later runs write where the first one did, so they have to update it.
+#[cfg(unix)]
+fn check_daemon_options(argv: &[String]) -> Result<(), String> {
+    let mut argv = argv.to_vec();
+    take_flag_value(&mut argv, "--output")?;
+    if let Some(to) = take_flag_value(&mut argv, "--email-report")? {
+        mail::check_address(&to)?;
+    }
+    if take_switch(&mut argv, "--interactive") {
+        return Err("daemon runs have no one to ask; leave out --interactive".to_string());
+    }
+    if take_switch(&mut argv, "--resume") {
+        return Err("daemon runs don't resume; finish an interrupted run with a single --resume run".to_string());
+    }
+    if take_switch(&mut argv, "--dry-run") {
+        return Err("daemon runs copy; preview the plan with a single --dry-run instead".to_string());
+    }
+    if take_flag_value(&mut argv, "--export-selection")?.is_some() {
+        return Err("daemon runs copy; export the selection with a single run instead".to_string());
+    }
+    if take_flag_value(&mut argv, "--emit-script")?.is_some() {
+        return Err("daemon runs copy; write a script with a single run instead".to_string());
+    }
+    let (argv, _) = replay_args(argv)?;
+    let args = parse_args(argv)?;
+    if !args.sync && !args.dst_layout.writes_runs() {
+        return Err("daemon runs need --sync or --dst-layout runs or cas, so each run can write where the last one did".to_string());
+    }
+    Ok(())
+}
+
+/// `image-rando rotate [--dst PATH]`: renumbers the folders of an earlier run,
+/// 2 to 1, 3 to 2 and 1 to the last, so a frame that shows folder 1 moves on
+/// to the next batch without copying anything.
+fn run_rotate(mut argv: Vec<String>) -> Result<(), String> {
+    let dst = take_flag_value(&mut argv, "--dst")?.map(PathBuf::from).unwrap_or_else(platform::default_dst);
+    if let Some(other) = argv.first() {
+        return Err(format!("unknown argument: {other}\n\nusage: rotate [--dst PATH]"));
+    }
+    let dst = platform::long_path(&dst)?;
+    let path = dst.join(manifest::FILE_NAME);
+    if !path.is_file() {
+        return Err(format!("no manifest in {}; rotate only works on the output of an earlier run", dst.display()));
+    }
+    let mut m = manifest::Manifest::load(&path)?;
+    let n = m.folders();
+    if n < 2 {
+        println!("Nothing to rotate: {} has {n} folder(s)", dst.display());
+        return Ok(());
+    }
+
+    // Two passes so no folder is renamed onto one that hasn't moved yet.
+    let parked = |k: usize| dst.join(format!(".image-rando-rotate-{k}"));
+    for k in 1..=n {
+        let folder = dst.join(m.folder_name(k));
+        if folder.is_dir() {
+            fs::rename(&folder, parked(k))
+                .map_err(|e| format!("cannot rename {}: {e}", folder.display()))?;
+        }
+    }
+    m.rotate();
+    for k in 1..=n {
+        let target = dst.join(m.folder_name(if k == 1 { n } else { k - 1 }));
+        if parked(k).is_dir() {
+            fs::rename(parked(k), &target)
+                .map_err(|e| format!("cannot rename {}: {e}", parked(k).display()))?;
+        }
+    }
+    m.save(&path)?;
+    println!("Rotated {n} folders under {}: folder 1 is now what was folder 2", dst.display());
+    Ok(())
+}
+
+/// The earlier run being reproduced by `--replay`.
+struct Replay {
+    path: PathBuf,
+    manifest: manifest::Manifest,
+}
+
+/// `--replay MANIFEST [--dst PATH]`: the options and seed recorded in the
+/// manifest stand in for the command line.
+fn replay_args(mut argv: Vec<String>) -> Result<(Vec<String>, Option<Replay>), String> {
+    let Some(path) = take_flag_value(&mut argv, "--replay")? else {
+        return Ok((argv, None));
+    };
+    let dst = take_flag_value(&mut argv, "--dst")?;
+    if argv.len() > 1 {
+        return Err("--replay only combines with --dst; the other options come from the manifest".to_string());
+    }
+    let path = PathBuf::from(path);
+    let old = manifest::Manifest::load(&path)?;
+    if old.input.is_none() {
+        return Err(format!("{} predates --replay and cannot be replayed", path.display()));
+    }
+    let mut replayed = argv;
+    replayed.extend(old.args.iter().cloned());
+    replayed.extend(["--seed".to_string(), old.seed.to_string()]);
+    if let Some(dst) = dst {
+        replayed.extend(["--dst".to_string(), dst]);
+    }
+    Ok((replayed, Some(Replay { path, manifest: old })))
+}
+
+/// `--resume --dst PATH`: replays the plan an interrupted run left in the
+/// destination.
+fn resume_args(mut argv: Vec<String>) -> Result<(Vec<String>, Option<Replay>), String> {
+    let dst = take_flag_value(&mut argv, "--dst")?.ok_or("--resume needs the --dst of the interrupted run")?;
+    if argv.len() > 1 {
+        return Err("--resume only combines with --dst; the other options come from the interrupted run".to_string());
+    }
+    let plan = Path::new(&dst).join(manifest::PLAN_FILE_NAME);
+    if !plan.is_file() {
+        return Err(format!("nothing to resume in {dst}: no interrupted run left its plan ({}) there", manifest::PLAN_FILE_NAME));
+    }
+    argv.extend(["--replay".to_string(), plan.to_string_lossy().into_owned(), "--dst".to_string(), dst]);
+    replay_args(argv)
+}
+
+/// The options worth replaying: everything but the program name, `--dst`
+/// (the next run may write elsewhere) and the seed (recorded on its own).
+fn recorded_options(argv: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = argv.iter().skip(1);
+    while let Some(a) = rest.next() {
+        if matches!(a.as_str(), "--dst" | "--seed" | "--seed-phrase") {
+            rest.next();
+        } else {
+            out.push(a.clone());
+        }
+    }
+    out
+}
+
+/// What this run put where, for `diff`, `--replay` and later runs.
+fn run_manifest(
+    groups: &[Vec<FileInfo>],
+    args: &Args,
+    recorded: Vec<String>,
+    input: manifest::Input,
+    album: Option<String>,
+) -> manifest::Manifest {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let t = exif::DateTime::from_unix(now);
+    let files = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, group)| {
+            group.iter().map(move |f| manifest::Entry {
+                folder: idx + 1,
+                name: f.name.clone(),
+                source: relative_name(&args.src, &f.path),
+                size: f.size,
+                sidecars: f.sidecars.iter().map(|(p, _)| sidecar_dest_name(f, p)).collect(),
+                modified: fs::metadata(&f.path)
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_nanos() as u64),
+            })
+        })
+        .collect();
+    manifest::Manifest {
+        seed: args.seed,
+        created: format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            t.year, t.month, t.day, t.hour, t.minute, t.second
+        ),
+        args: recorded,
+        input: Some(input),
+        labels: if args.name_groups_by.is_empty() {
+            Vec::new()
+        } else {
+            groups.iter().map(|g| group_label(g, &args.name_groups_by)).collect()
+        },
+        album,
+        processing: Some(args.processing.fingerprint(args.animated == AnimatedPolicy::FirstFrame)),
+        files,
+    }
+}
+
+/// `parse_args` for subcommands that only read the source.
+fn parse_read_only_args(argv: Vec<String>) -> Result<Args, String> {
+    let mut args = parse_args(argv)?;
+    args.src = platform::long_path(&args.src)?;
+    if !args.src.is_dir() {
+        return Err(format!("source is not a directory: {}", args.src.display()));
+    }
+    Ok(args)
+}
+
+/// Removes `flag VALUE` from a subcommand's arguments and returns the value,
+/// leaving the rest for `parse_args`.
+fn take_flag_value(argv: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let Some(at) = argv.iter().position(|a| a == flag) else {
+        return Ok(None);
+    };
+    let value = required_arg(argv, at + 1, flag)?;
+    argv.drain(at..at + 2);
+    Ok(Some(value))
+}
+
+fn take_switch(argv: &mut Vec<String>, flag: &str) -> bool {
+    let found = argv.iter().position(|a| a == flag);
+    if let Some(at) = found {
+        argv.remove(at);
+    }
+    found.is_some()
+}
+
+struct DuplicateCluster {
+    /// Indices into the analysed files, in scan order.
+    members: Vec<usize>,
+    /// Every member has the same bytes.
+    identical: bool,
+}
+
+impl DuplicateCluster {
+    fn reclaimable(&self, files: &[FileInfo]) -> u64 {
+        let sizes = self.members.iter().map(|&idx| files[idx].size);
+        sizes.clone().sum::<u64>() - sizes.max().unwrap_or(0)
+    }
+}
+
+/// Byte-identical photos, merged with perceptually similar ones when
+/// `max_distance` is set. Identical copies are only decoded once.
+fn duplicate_clusters(files: &[FileInfo], max_distance: Option<u32>, jobs: usize) -> Result<Vec<DuplicateCluster>, String> {
+    // Only files that share their size with another one can be identical.
+    let mut size_count: HashMap<u64, usize> = HashMap::new();
+    for f in files {
+        *size_count.entry(f.size).or_default() += 1;
+    }
+    let needs_hash: Vec<usize> = (0..files.len()).filter(|&i| size_count[&files[i].size] > 1).collect();
+    let hashes = parallel_map(&needs_hash, jobs, |&i| dupes::content_hash(&files[i].path));
+    // Files nobody shares a size with get a key of their own.
+    let mut keys: Vec<(u64, u64)> = (0..files.len()).map(|i| (u64::MAX, i as u64)).collect();
+    for (&i, hash) in needs_hash.iter().zip(hashes) {
+        keys[i] = (files[i].size, hash?);
+    }
+    let identical = dupes::equal_clusters(&keys);
+
+    let Some(max_distance) = max_distance else {
+        return Ok(identical.into_iter().map(|members| DuplicateCluster { members, identical: true }).collect());
+    };
+    // One representative per distinct content.
+    let mut duplicate_of: Vec<usize> = (0..files.len()).collect();
+    for cluster in &identical {
+        for &i in &cluster[1..] {
+            duplicate_of[i] = cluster[0];
+        }
+    }
+    let reps: Vec<usize> = (0..files.len()).filter(|&i| duplicate_of[i] == i).collect();
+    let mut rep_hashes = Vec::with_capacity(reps.len());
+    let mut hashed_reps = Vec::with_capacity(reps.len());
+    for (&i, hash) in reps.iter().zip(parallel_map(&reps, jobs, |&i| perceptual_hash(&files[i].path))) {
+        match hash {
+            Ok(hash) => {
+                rep_hashes.push(hash);
+                hashed_reps.push(i);
+            }
+            Err(e) => eprintln!("warning: {e}; only checking it for identical copies"),
+        }
+    }
+    let mut similar_root = HashMap::new();
+    for similar in dupes::similar_clusters(&rep_hashes, max_distance) {
+        for &k in &similar {
+            similar_root.insert(hashed_reps[k], hashed_reps[similar[0]]);
+        }
+    }
+    let merged: Vec<usize> = duplicate_of.iter().map(|&rep| similar_root.get(&rep).copied().unwrap_or(rep)).collect();
+    Ok(dupes::equal_clusters(&merged)
+        .into_iter()
+        .map(|members| {
+            let identical = members.iter().all(|&i| keys[i] == keys[members[0]]);
+            DuplicateCluster { members, identical }
+        })
+        .collect())
+}
+
+fn required_arg(argv: &[String], i: usize, flag: &str) -> Result<String, String> {
+    argv.get(i)
+        .cloned()
+        .ok_or_else(|| format!("missing value for {flag}"))
+}
+
+fn print_help() {
+    let default_src = platform::default_src();
+    let default_src = default_src.display();
+    let default_dst = platform::default_dst();
+    let default_dst = default_dst.display();
+    let manifest_file = manifest::FILE_NAME;
+    let index_file = index::FILE_NAME;
+    println!(
+        "image-rando
+
+Copies photos from a source folder into numbered destination folders (1..X),
+obeying:
+  - no more than 1200 photos per folder
+  - no more than 4 GiB per folder
+
+Default source: {default_src}
+Default dest:   {default_dst}
+
+USAGE:
+  cargo run --release -- [OPTIONS]
+  cargo run --release -- block [--src PATH] [--exclude-list FILE] PHOTO...
+                          add photos to the block list (see --exclude-list)
+  cargo run --release -- report [--format csv] [OPTIONS]
+                          print date, camera, size, GPS and rating of every
+                          photo the options select, without copying
+  cargo run --release -- index [--index FILE] [OPTIONS]
+                          record the files the scan options find, with their
+                          EXIF and XMP fields, in FILE (default
+                          SRC/{index_file}) for --from-index
+  cargo run --release -- audit [OPTIONS]
+                          report unreadable, empty and corrupt files, and
+                          photos without a usable EXIF date
+  cargo run --release -- diff OLD.json NEW.json
+                          compare the manifests (DST/{manifest_file}) of
+                          two runs
+  cargo run --release -- bench [--size SIZE] [OPTIONS]
+                          measure the destination's write speed (default
+                          256MiB plus small files) and estimate how long
+                          copying the selected photos takes
+  cargo run --release -- rotate [--dst PATH]
+                          renumber the folders of an earlier run (2 becomes
+                          1, 1 becomes the last) without copying anything
+  cargo run --release -- daemon --socket PATH [--every DURATION]
+                          [--options FILE] [OPTIONS]
+                          run every DURATION (default 24h) and take the
+                          commands run-now, status, pause, resume, reload and
+                          events on a Unix socket
+  cargo run --release -- display [--fb DEVICE] [--interval DURATION] [OPTIONS]
+                          show the selected photos full screen on a Linux
+                          framebuffer (default /dev/fb0), one every DURATION
+                          (default 30s), instead of copying them; needs
+                          imaging
+  cargo run --release -- analyze --dupes [--max-distance N] [OPTIONS]
+                          list groups of identical photos (and similar ones,
+                          up to N of 64 hash bits apart, default
+                          {DEFAULT_DUPE_DISTANCE}, with `--features imaging`)
+
+OPTIONS:
+  --src PATH              source folder
+  --dst PATH              destination folder (must be empty)
+  --mirror                write the same plan to every --dst given (repeat
+                          --dst for each destination)
+  --distribute            split the plan over every --dst given: folder 1 to
+                          the first, folder 2 to the second and so on
+  --dst-layout flat|runs|cas
+                          flat (default) writes into the empty destination;
+                          runs writes each run into a new dst/YYYY-MM-DDTHH-MM
+                          folder (UTC) and points dst/latest at it; cas does
+                          the same with symlinks into dst/.store, which keeps
+                          one copy of each photo for all runs
+  --keep-runs N           with --dst-layout runs or cas, delete all but the N
+                          newest run folders after a complete run (and, with
+                          cas, the stored files no run links to)
+  --use-trash             move what --sync and --keep-runs delete to the
+                          trash (Recycle Bin on Windows) instead
+  --sets N                write N disjoint selections to set-1 .. set-N under
+                          the destination, each with its own folders (with
+                          --limit, up to that many files per set)
+  --split-by-orientation  write landscape and portrait photos to separate
+                          landscape/ and portrait/ trees under the destination
+                          (square ones to both)
+  --max-files N           photos per folder
+  --max-bytes BYTES       bytes per folder
+  --limit-mode first-hit|both|files-only|bytes-only
+                          start a new folder when either limit is reached
+                          (first-hit, default), only when both are, or only
+                          on the file count or the bytes
+  --bytes-tolerance PERCENT
+                          let folders go up to PERCENT (e.g. 2%) over
+                          --max-bytes if that takes in the whole last folder
+  --min-files-per-folder N
+                          spread a last folder of fewer than N photos over the
+                          other folders (bytes permitting) or leave them out
+  --seed SEED             shuffle seed (default: time-based)
+  --seed-phrase TEXT      shuffle seed made from a memorable phrase, e.g.
+                          \"christmas-2024\" (case doesn't matter)
+  -r, --recursive         also scan subfolders of the source
+  --group-by none|album   album: one folder (or more, if over the limits) per
+                          source subfolder, shuffled internally; needs -r
+  --group-by location [RADIUS_KM]
+                          one folder (or more) per cluster of GPS positions
+                          within RADIUS_KM (default 25); photos without GPS
+                          go to a final catch-all group
+  --name-groups-by keywords|date-range|location[,...]
+                          add what a folder's photos mostly share to its name,
+                          in the order given (03-summer-2023-oregon); places
+                          come from the City/State/Country the photo manager
+                          wrote into XMP
+  --album-byte-quota [FOLDER=]SIZE
+                          most photo bytes (e.g. 200MiB) one source folder
+                          may add to the run; FOLDER covers its subfolders,
+                          a bare SIZE applies to every album; repeatable
+  --src-weight FOLDER=PERCENT
+                          make photos from FOLDER (and its subfolders) about
+                          PERCENT of the run, e.g. best-of=70; the rest share
+                          what is left; repeatable
+  --pick-one-album        fill the run from one source folder chosen at
+                          random, e.g. a single trip
+  --skip-recent-albums N  with --pick-one-album, don't pick a folder the last
+                          N runs at the destination picked
+  --order random|chronological|newest-first|name
+                          chronological/newest-first sort by EXIF capture time
+                          instead of shuffling (undated photos go last); name
+                          sorts by source path
+  --assume-timezone OFFSET
+                          UTC offset (e.g. +02:00) for capture times without
+                          an EXIF OffsetTime, when ordering photos by the
+                          moment they were taken; also applied to file times
+                          standing in for missing capture dates
+  --diversify-colors      reorder each folder so consecutive photos differ in
+                          colour/brightness (needs `--features imaging`)
+  --spread-similar        move near-duplicates that both get picked into
+                          different folders (needs `--features imaging`)
+  --stratify-by year|album|orientation
+                          give every folder a similar mix along this dimension
+                          instead of whatever the shuffle produced
+  --require-person NAME   only photos tagged with NAME (repeatable: any of them)
+  --exclude-person NAME   drop photos tagged with NAME (repeatable)
+                          people come from XMP sidecars or embedded XMP
+  --formats LIST          photo formats to pick up, any of jpg, png, heic and
+                          webp, comma-separated (default: jpg)
+  --include-videos        also pick up .mp4/.mov/.m4v clips and sprinkle them into
+                          the photo folders, on top of the photo limits
+  --max-video-files N     videos per folder (default {DEFAULT_MAX_VIDEO_FILES})
+  --max-video-bytes BYTES video bytes per folder (default 512 MiB)
+  --with-sidecars         copy .xmp/.aae/.json sidecars along with each image
+                          (they count toward --max-bytes)
+  --animated skip|first-frame|copy
+                          what to do with animated GIF/PNG/WebP content
+                          (default: skip)
+  --detect-by ext|magic|both
+                          recognise files by extension (default), by content,
+                          or only when both agree
+  --follow-symlinks       treat symlinks to files/folders like the real thing
+                          (folder loops are detected and skipped)
+  --include-hidden        don't skip dotfiles/folders, AppleDouble `._*` files,
+                          .DS_Store and Thumbs.db
+  --ignore-nomedia        with -r, also scan subfolders holding a .nomedia file
+  --exclude-regex REGEX   skip files whose name or path relative to the source
+                          matches REGEX, e.g. '(?i)screenshot|whatsapp|meme'
+                          (repeatable)
+  --rename-template TEMPLATE
+                          name copies from TEMPLATE, e.g.
+                          '{{exif_date:%Y%m%d_%H%M%S}}_{{orig}}'; the extension
+                          is kept, undated files use their modification time
+  --pin-list FILE         photos listed in FILE (one path per line, relative to
+                          the source or absolute) always go first in folder 1
+  --include-list FILE     photos listed in FILE (same format as --pin-list) are
+                          always copied, even if a filter would drop them, but
+                          placed like any other photo
+  --exclude-list FILE     never copy the photos listed in FILE (same format as
+                          --pin-list; default: {DEFAULT_EXCLUDE_LIST} in the
+                          source folder, if present)
+  --favorites FILE        photos listed in FILE (file names or paths, one per
+                          line) come up earlier in the shuffle
+  --favorites-weight N    how many times likelier a favourite is to be picked
+                          at each position (default {DEFAULT_FAVORITES_WEIGHT})
+  --resize WxH            re-encode photos larger than WxH pixels to fit it
+                          (needs `--features imaging`)
+  --max-megapixels N      re-encode photos with more than N million pixels
+                          to just under that, keeping the aspect ratio; also
+                          applies on top of --resize (needs `--features
+                          imaging`)
+  --fit inside|crop       with --resize: keep the whole photo (default) or
+                          centre-crop to the WxH aspect ratio
+  --resize-filter lanczos3|bilinear
+                          downscaling kernel: sharper (default) or faster
+  --jpeg baseline|progressive
+                          encoding of re-encoded and optimised photos
+                          (default: baseline); progressive ones appear
+                          gradually on slow frames
+  --chroma 4:4:4|4:2:2|4:2:0
+                          colour subsampling of re-encoded photos (default
+                          4:2:2); 4:2:0 gives noticeably smaller files
+  --optimize-jpeg         losslessly shrink copied JPEGs: rebuild their Huffman
+                          tables and drop metadata displays don't use
+  --tone-map-sdr          leave the HDR gain map out of re-encoded photos, for
+                          frames that show HDR photos washed out or grey
+  --convert-srgb          convert re-encoded photos to sRGB rather than keep
+                          their embedded colour profile
+  --strip-thumbnails      drop the preview embedded in the EXIF block of copied
+                          JPEGs (often 50-100 KB); other EXIF data is kept
+  --panoramas keep|skip|crop-center|letterbox
+                          what to do with photos more than 3:1 wide or tall
+                          (default: keep); crop-center and letterbox reframe
+                          them to the --resize box, or 16:9, and need imaging
+  --safe-names            restrict destination names to ASCII letters, digits,
+                          `-`, `_` and `.`
+  --preserve-structure    recreate each file's source subfolders inside its
+                          numbered folder (1/2023/06/IMG_1.jpg)
+  --preset samsung-frame|nixplay|pix-star|generic-4k
+                          limits, resize target and name rules for a device;
+                          other options override it
+  --replay MANIFEST       run again with the options and seed in MANIFEST,
+                          after checking the source still has the same files;
+                          only --dst may be given as well
+  --sync                  update the previous run in the destination: copy
+                          only new or moved files and delete the ones no
+                          longer planned; new copies of photos already
+                          there are skipped
+  --resume                with --dst, finish a run that was cut short: replay
+                          the plan it left there, keep the files it wrote in
+                          full and copy the rest
+  --stable-reshuffle P    with --sync, keep (100-P)% of the photos already in
+                          the destination where they are and swap the other
+                          P% for new picks (pool mode only)
+  --sandbox               run everything, processing included, against a
+                          temporary folder that is deleted afterwards
+  --limit N               copy only the first N files after shuffling, for
+                          quick trial runs against a big library
+  --time-budget DURATION  stop copying (cleanly, between files) this long after
+                          the start, e.g. 15m or 1h; the manifest lists only
+                          what was copied
+  --quiet-hours HH:MM-HH:MM
+                          local times of day to wait out before scanning and
+                          to pause copying in, e.g. 08:00-22:00
+  --interactive           show the plan and ask whether to copy it, re-roll it
+                          with a new seed or quit, without scanning again
+  --dry-run               print every folder the run would write, with its file
+                          count and bytes, and stop before touching the
+                          destination
+  --list-files            with --dry-run, also list each folder's files
+  --email-report ADDRESS  mail the summary (or the error) to ADDRESS after the
+                          run, through the local sendmail (msmtp, Postfix...)
+                          and the SMTP account configured there
+  --output text|json      json: print the summary (folders, counts, bytes,
+                          skipped files, seed, time taken, manifest paths) as
+                          one JSON document instead; warnings stay on stderr
+  --exif-cache PATH       where capture dates, sizes and other EXIF fields are
+                          kept between runs (default: exif-cache.json in the
+                          user cache folder, e.g. ~/.cache/image-rando)
+  --no-exif-cache         read every photo's EXIF block afresh
+  --select EXPR           keep only photos matching EXPR, e.g. 'rating>=4 &&
+                          (keyword:beach || keyword:lake) && year>=2020';
+                          see the README for fields and operators
+  --within LAT,LON,RADIUS keep only photos whose EXIF GPS position is within
+                          RADIUS (km, or with m or mi) of the point, e.g.
+                          47.6,-122.3,25km; may be repeated
+  --outside LAT,LON,RADIUS
+                          leave out photos taken within RADIUS of the point;
+                          photos without a position are kept
+  --from-index FILE       plan from a catalog written by `index` instead of
+                          scanning the source; scan options are the index's
+  --emit-script FILE      write a shell script that makes the planned folders
+                          and copies the files with mkdir and cp, instead of
+                          copying; SRC=... DST=... override the paths in it
+  --export-selection FILE write the files the run would choose from to FILE,
+                          one per line, instead of copying
+  --import-selection FILE choose only from the files listed in FILE (as
+                          written by --export-selection, perhaps edited)
+  -j, --jobs N            photos copied (and resized or flattened) in
+                          parallel, and source folders listed in parallel
+                          (default: number of CPUs)
+  --low-memory            process one photo at a time and list folders one
+                          at a time, for boards with 512 MB of memory
+  -h, --help              print this help
+"
+    );
+}
+
+/// Case and surrounding spaces don't matter, so "Christmas 2024 " and
+/// "christmas 2024" shuffle the same way.
+fn seed_from_phrase(phrase: &str) -> u64 {
+    let phrase = nfc::to_nfc(phrase.trim()).to_lowercase();
+    dupes::fnv1a(dupes::FNV_OFFSET, phrase.as_bytes())
+}
+
+fn default_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    nanos ^ (std::process::id() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+fn validate_dirs(args: &Args) -> Result<(), String> {
+    validate_src(&args.src)?;
+    for dst in args.destinations() {
+        fs::create_dir_all(dst)
+            .map_err(|e| format!("cannot create destination folder {}: {e}", dst.display()))?;
+        if !args.resume {
+            check_dst_empty(dst, args.sync)?;
+        }
+    }
+    Ok(())
+}
+
+/// A destination must be empty, or with `--sync` hold an earlier run.
+fn check_dst_empty(dst: &Path, sync: bool) -> Result<(), String> {
+    let mut rd = fs::read_dir(dst)
+        .map_err(|e| format!("cannot read destination folder {}: {e}", dst.display()))?;
+    if rd.next().is_some() && !(sync && dst.join(manifest::FILE_NAME).is_file()) {
+        let hint = if dst.join(manifest::PLAN_FILE_NAME).is_file() {
+            "\nAn interrupted run left its plan there; --resume finishes it."
+        } else if sync {
+            "\n--sync only updates folders written by an earlier run (with a manifest)."
+        } else {
+            ""
+        };
+        return Err(format!(
+            "destination folder is not empty: {}\nRefusing to run to avoid mixing old/new output.{hint}",
+            dst.display()
+        ));
+    }
+    Ok(())
+}
+
+fn validate_src(src: &Path) -> Result<(), String> {
+    let src_meta = fs::metadata(src).map_err(|e| format!("cannot read source folder {}: {e}", src.display()))?;
+    if !src_meta.is_dir() {
+        return Err(format!("source is not a directory: {}", src.display()));
+    }
+    Ok(())
+}
+
+/// Every photo (and video, with `--include-videos`) under `src`, sorted by
+/// path. The order a filesystem lists folders in (and with several walkers,
+/// timing) varies, and the shuffle needs the same start every time.
+fn collect_jpgs(src: &Path, opts: &ScanOptions) -> Result<Vec<FileInfo>, String> {
+    scan_source(src, opts).map(|(files, _)| files)
+}
+
+/// `collect_jpgs`, along with the folders it listed.
+fn scan_source(src: &Path, opts: &ScanOptions) -> Result<(Vec<FileInfo>, SourceSnapshot), String> {
+    // Which of several routes to a folder gets walked must not depend on
+    // timing, so symlinked trees are walked one folder at a time.
+    let threads = if opts.follow_symlinks { 1 } else { opts.threads.max(1) };
+    let progress = ScanProgress::default();
+    let mut out = thread::scope(|scope| {
+        let (finished, wait) = mpsc::channel::<()>();
+        let progress = &progress;
+        scope.spawn(move || {
+            let mut shown = false;
+            while let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(SCAN_PROGRESS_EVERY) {
+                let at = progress.current.lock().expect("scan progress poisoned").display().to_string();
+                eprintln!("Scanning: {} so far (at {at})", progress.counts());
+                shown = true;
+            }
+            if shown {
+                eprintln!("Scanned: {}", progress.counts());
+            }
+        });
+        let out = if threads == 1 { walk(src, opts, progress) } else { walk_parallel(src, opts, threads, progress) };
+        drop(finished);
+        out
+    })?;
+    out.0.sort_by(|a, b| a.path.cmp(&b.path));
+    out.1.sort_by(|a, b| a.dir.cmp(&b.dir));
+    Ok((out.0, SourceSnapshot { src: src.to_path_buf(), folders: out.1 }))
+}
+
+/// A folder as a scan listed it.
+struct FolderStamp {
+    dir: PathBuf,
+    /// Changes whenever a file in it is added, removed or renamed.
+    modified: Option<SystemTime>,
+    /// Media files the scan took from it.
+    files: usize,
+}
+
+/// The source folders a scan listed, sorted by path, so the run can tell
+/// whether files came or went (a sync client landing photos, say) while it
+/// was planning.
+struct SourceSnapshot {
+    src: PathBuf,
+    folders: Vec<FolderStamp>,
+}
+
+/// Media files that came and went after the scan, and in how many folders.
+#[derive(Debug, Default, PartialEq)]
+struct SourceChanges {
+    folders: usize,
+    added: usize,
+    removed: usize,
+}
+
+impl SourceSnapshot {
+    /// Lists again the folders whose modification time moved. A folder with
+    /// the same number of media files as before (a rename, an edited file)
+    /// isn't counted as a change.
+    fn changes(&self, opts: &ScanOptions) -> Result<SourceChanges, String> {
+        let mut changes = SourceChanges::default();
+        for folder in &self.folders {
+            let modified = fs::metadata(&folder.dir).and_then(|m| m.modified()).ok();
+            if modified.is_some() && modified == folder.modified {
+                continue;
+            }
+            let (added, removed) = if folder.dir.is_dir() {
+                let (dirs, files) = scan_dir(&self.src, &folder.dir, opts)?;
+                // Folders made since are new in full; with symlinks followed
+                // they might lead back anywhere, so only their own files count.
+                let mut pending: Vec<PathBuf> = dirs
+                    .into_iter()
+                    .filter(|d| self.folders.binary_search_by(|f| f.dir.cmp(d)).is_err())
+                    .collect();
+                let mut found = files.len();
+                while let Some(dir) = pending.pop() {
+                    let (dirs, files) = scan_dir(&self.src, &dir, opts)?;
+                    found += files.len();
+                    if !opts.follow_symlinks {
+                        pending.extend(dirs);
+                    }
+                }
+                (found.saturating_sub(folder.files), folder.files.saturating_sub(found))
+            } else {
+                (0, folder.files)
+            };
+            if added + removed > 0 {
+                changes.folders += 1;
+                changes.added += added;
+                changes.removed += removed;
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// How long a scan runs before it shows progress, and how often after that.
+const SCAN_PROGRESS_EVERY: Duration = Duration::from_secs(5);
+
+/// What the walkers have got through so far, for the lines a slow scan (a
+/// network share, a sleeping disk) prints instead of staying silent.
+#[derive(Default)]
+struct ScanProgress {
+    folders: AtomicUsize,
+    files: AtomicUsize,
+    /// Folder listed last.
+    current: Mutex<PathBuf>,
+}
+
+impl ScanProgress {
+    fn listed(&self, dir: &Path, files: usize) {
+        self.folders.fetch_add(1, Ordering::Relaxed);
+        self.files.fetch_add(files, Ordering::Relaxed);
+        *self.current.lock().expect("scan progress poisoned") = dir.to_path_buf();
+    }
+
+    fn counts(&self) -> String {
+        format!("{} folders, {} files", self.folders.load(Ordering::Relaxed), self.files.load(Ordering::Relaxed))
+    }
+}
+
+fn walk(src: &Path, opts: &ScanOptions, progress: &ScanProgress) -> Result<(Vec<FileInfo>, Vec<FolderStamp>), String> {
+    let mut out = Vec::new();
+    let mut stamps = Vec::new();
+    let mut pending = vec![src.to_path_buf()];
+    // Canonical folders already walked, so symlink loops end the descent.
+    let mut visited = HashSet::new();
+
+    while let Some(dir) = pending.pop() {
+        if opts.follow_symlinks {
+            let real = fs::canonicalize(&dir)
+                .map_err(|e| format!("cannot resolve folder {}: {e}", dir.display()))?;
+            if !visited.insert(real) {
+                eprintln!("warning: skipping {}: folder already visited (symlink loop?)", dir.display());
+                continue;
+            }
+        }
+        let modified = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+        let (mut dirs, files) = scan_dir(src, &dir, opts)?;
+        progress.listed(&dir, files.len());
+        stamps.push(FolderStamp { dir, modified, files: files.len() });
+        // Depth first, in name order.
+        dirs.reverse();
+        pending.append(&mut dirs);
+        out.extend(files);
+    }
+    Ok((out, stamps))
+}
+
+/// `walk` on `threads` threads sharing one queue of folders to list, which
+/// keeps slow disks busy with several requests at once.
+fn walk_parallel(
+    src: &Path,
+    opts: &ScanOptions,
+    threads: usize,
+    progress: &ScanProgress,
+) -> Result<(Vec<FileInfo>, Vec<FolderStamp>), String> {
+    struct Queue {
+        pending: Vec<PathBuf>,
+        /// Folders being listed right now; their subfolders may still come.
+        busy: usize,
+        failed: Option<String>,
+    }
+    let queue = Mutex::new(Queue { pending: vec![src.to_path_buf()], busy: 0, failed: None });
+    let ready = Condvar::new();
+    let found = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut found = Vec::new();
+                    let mut stamps = Vec::new();
+                    loop {
+                        let dir = {
+                            let mut q = queue.lock().expect("walker queue poisoned");
+                            loop {
+                                if q.failed.is_some() {
+                                    return (found, stamps);
+                                }
+                                if let Some(dir) = q.pending.pop() {
+                                    q.busy += 1;
+                                    break dir;
+                                }
+                                if q.busy == 0 {
+                                    return (found, stamps);
+                                }
+                                q = ready.wait(q).expect("walker queue poisoned");
+                            }
+                        };
+                        let modified = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+                        let result = scan_dir(src, &dir, opts);
+                        if let Ok((_, files)) = &result {
+                            progress.listed(&dir, files.len());
+                            stamps.push(FolderStamp { dir, modified, files: files.len() });
+                        }
+                        let mut q = queue.lock().expect("walker queue poisoned");
+                        q.busy -= 1;
+                        match result {
+                            Ok((dirs, files)) => {
+                                q.pending.extend(dirs);
+                                found.extend(files);
+                            }
+                            Err(e) => {
+                                q.failed.get_or_insert(e);
+                            }
+                        }
+                        ready.notify_all();
+                    }
+                })
+            })
+            .collect();
+        let (mut found, mut stamps) = (Vec::new(), Vec::new());
+        for h in handles {
+            let (files, listed) = h.join().expect("walker thread panicked");
+            found.extend(files);
+            stamps.extend(listed);
+        }
+        (found, stamps)
+    });
+    match queue.into_inner().expect("walker queue poisoned").failed {
+        Some(e) => Err(e),
+        None => Ok(found),
+    }
+}
+
+/// Lists one folder: the subfolders to walk (in name order) and the media
+/// files in it.
+fn scan_dir(src: &Path, dir: &Path, opts: &ScanOptions) -> Result<(Vec<PathBuf>, Vec<FileInfo>), String> {
+    let rd = fs::read_dir(dir).map_err(|e| format!("cannot list source folder {}: {e}", dir.display()))?;
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in rd {
+        let entry = entry.map_err(|e| format!("error reading directory entry: {e}"))?;
+        if !opts.include_hidden && is_hidden_or_junk(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let path = entry.path();
+        let mut ft = entry
+            .file_type()
+            .map_err(|e| format!("cannot read file type for {}: {e}", path.display()))?;
+        if ft.is_symlink() && opts.follow_symlinks {
+            match fs::metadata(&path) {
+                Ok(target) => ft = target.file_type(),
+                Err(e) => {
+                    eprintln!("warning: skipping broken symlink {}: {e}", path.display());
+                    continue;
+                }
+            }
+        }
+        if ft.is_dir() && opts.recursive {
+            // Android convention: the folder's media is not for galleries.
+            if !opts.ignore_nomedia && path.join(".nomedia").exists() {
+                continue;
+            }
+            // Photo library bundles hold originals, edits and thumbnails in
+            // an internal layout; only scan them when given as --src.
+            if is_library_bundle(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            dirs.push(path);
+            continue;
+        }
+        if !ft.is_file() {
+            continue;
+        }
+        if !opts.exclude_regex.is_empty() {
+            let file_name = nfc::to_nfc(&entry.file_name().to_string_lossy());
+            let rel = relative_name(src, &path);
+            if opts.exclude_regex.iter().any(|re| re.is_match(&file_name) || re.is_match(&rel)) {
+                continue;
+            }
+        }
+        let Some(detected) = classify(&path, opts)? else {
+            continue;
+        };
+        files.push(file_info(src, path, detected, opts)?);
+    }
+    dirs.sort();
+    Ok((dirs, files))
+}
+
+fn file_info(src: &Path, path: PathBuf, (kind, format): Detected, opts: &ScanOptions) -> Result<FileInfo, String> {
+    let meta = fs::metadata(&path)
+        .map_err(|e| format!("cannot stat file {}: {e}", path.display()))?;
+    let size = meta.len();
+    let name = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("non-utf8 filename not supported: {}", path.display()))?
+        .to_string();
+    // Content-detected files (`IMG_1.JPG~`, `DSC0001`) get an extension
+    // the display device will recognise.
+    let name = match (kind, format) {
+        (MediaKind::Photo, Some(format)) if ImageFormat::of_path(&path) != Some(format) => {
+            with_extension(&name, format.extension())
+        }
+        (MediaKind::Video, _) if !is_video(&path) => with_extension(&name, "mp4"),
+        _ => name,
+    };
+    let name = dest_file_name(&nfc::to_nfc(&name));
+    let album = album_of(src, &path)?;
+    let sidecars = if opts.with_sidecars {
+        find_sidecars(&path)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(FileInfo {
+        path,
+        name,
+        size,
+        kind,
+        format,
+        album,
+        exif: exif::Exif::default(),
+        xmp: xmp::Xmp::default(),
+        sidecars,
+        flatten: false,
+    })
+}
+
+/// Adds listed files that the scan skipped (hidden, excluded by a pattern, in
+/// a `.nomedia` folder, outside the source...). Unusable entries are warned
+/// about, since a typo in a list should not abort the run.
+fn add_required_files(files: &mut Vec<FileInfo>, required: &[PathBuf], src: &Path, opts: &ScanOptions) -> Result<(), String> {
+    let scanned: HashSet<&Path> = files.iter().map(|f| f.path.as_path()).collect();
+    let missing: Vec<&PathBuf> = required.iter().filter(|p| !scanned.contains(p.as_path())).collect();
+    for path in missing {
+        if !path.is_file() {
+            eprintln!("warning: listed photo not found: {}", path.display());
+            continue;
+        }
+        match classify(path, opts)? {
+            Some(detected) => files.push(file_info(src, path.clone(), detected, opts)?),
+            None => eprintln!("warning: listed file is not a supported photo: {}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// Parent folder of `path` relative to `src`, using `/` as the separator.
+fn album_of(src: &Path, path: &Path) -> Result<String, String> {
+    let parent = path
+        .parent()
+        .and_then(|p| p.strip_prefix(src).ok())
+        .unwrap_or_else(|| Path::new(""));
+    let mut parts = Vec::new();
+    for c in parent.components() {
+        let part = c
+            .as_os_str()
+            .to_str()
+            .ok_or_else(|| format!("non-utf8 folder name not supported: {}", path.display()))?;
+        parts.push(part);
+    }
+    Ok(nfc::to_nfc(&parts.join("/")))
+}
+
+/// `path` relative to `src` with `/` separators, for matching and display.
+fn relative_name(src: &Path, path: &Path) -> String {
+    let rel = path.strip_prefix(src).unwrap_or(path);
+    let parts: Vec<String> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    nfc::to_nfc(&parts.join("/"))
+}
+
+/// Apple Photos, iPhoto and Aperture library packages.
+fn is_library_bundle(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    [".photoslibrary", ".photolibrary", ".aplibrary"]
+        .iter()
+        .any(|ext| lower.ends_with(ext))
+}
+
+/// Existing sidecars of `path`: XMP (see `xmp::sidecar_candidates`), Apple
+/// `.aae` edit files and Google Takeout `.json` metadata.
+fn find_sidecars(path: &Path) -> Result<Vec<(PathBuf, u64)>, String> {
+    let mut candidates = xmp::sidecar_candidates(path);
+    for ext in ["aae", "AAE", "json"] {
+        candidates.push(path.with_extension(ext));
+    }
+    for suffix in [".json", ".supplemental-metadata.json"] {
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(suffix);
+        candidates.push(PathBuf::from(appended));
+    }
+
+    let mut out: Vec<(PathBuf, u64)> = Vec::new();
+    for c in candidates {
+        // On case-insensitive volumes `IMG.xmp` and `IMG.XMP` are one file.
+        let seen = out.iter().any(|(p, _)| {
+            p.to_string_lossy().eq_ignore_ascii_case(&c.to_string_lossy())
+        });
+        if seen || !c.is_file() {
+            continue;
+        }
+        let size = fs::metadata(&c)
+            .map_err(|e| format!("cannot stat sidecar {}: {e}", c.display()))?
+            .len();
+        out.push((c, size));
+    }
+    Ok(out)
+}
+
+/// Drops or marks animated photos according to `policy`; returns how many
+/// were dropped. Detection looks at the content, not the extension.
+/// Files in `keep` are flattened rather than skipped.
+fn apply_animated_policy(files: &mut Vec<FileInfo>, policy: AnimatedPolicy, keep: &[PathBuf]) -> Result<usize, String> {
+    if policy == AnimatedPolicy::Copy {
+        return Ok(0);
+    }
+    let before = files.len();
+    let mut kept = Vec::with_capacity(files.len());
+    for mut f in files.drain(..) {
+        if f.kind == MediaKind::Photo && animated::is_animated(&f.path)? {
+            if policy == AnimatedPolicy::Skip && !keep.contains(&f.path) {
+                continue;
+            }
+            // Sizes stay those of the full animation: a safe upper bound.
+            f.flatten = true;
+        }
+        kept.push(f);
+    }
+    *files = kept;
+    Ok(before - files.len())
+}
+
+/// Reads `--pin-list`/`--include-list`: one path per line, relative to `src` unless absolute.
+/// Blank lines and `#` comments are ignored.
+fn load_path_list(list: &Path, src: &Path) -> Result<Vec<PathBuf>, String> {
+    let text = fs::read_to_string(list)
+        .map_err(|e| format!("cannot read list {}: {e}", list.display()))?;
+    load_path_list_text(&text, src)
+}
+
+fn load_path_list_text(text: &str, src: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut pins = Vec::new();
+    let mut seen = HashSet::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let path = Path::new(line);
+        let path = if path.is_absolute() { path.to_path_buf() } else { src.join(path) };
+        let path = platform::long_path(&path)?;
+        if seen.insert(path.clone()) {
+            pins.push(path);
+        }
+    }
+    Ok(pins)
+}
+
+/// Drops photos, in order, once their album's quota is used up; a photo that
+/// doesn't fit leaves room for smaller ones after it. Videos are left alone.
+/// Returns how many were dropped.
+fn apply_album_quotas(files: &mut Vec<FileInfo>, quotas: &AlbumQuotas, required: &[PathBuf]) -> usize {
+    if quotas.is_empty() {
+        return 0;
+    }
+    let before = files.len();
+    let mut used: HashMap<String, u64> = HashMap::new();
+    files.retain(|f| {
+        let Some((bucket, limit)) = quotas.bucket(&f.album).filter(|_| f.kind == MediaKind::Photo) else {
+            return true;
+        };
+        let used = used.entry(bucket.to_string()).or_default();
+        if required.contains(&f.path) || *used + f.footprint() <= limit {
+            *used += f.footprint();
+            true
+        } else {
+            false
+        }
+    });
+    before - files.len()
+}
+
+/// `--limit`: keeps the first `limit` files in order, plus any pinned or
+/// must-include ones after them. Returns how many were dropped.
+fn apply_limit(files: &mut Vec<FileInfo>, limit: usize, required: &[PathBuf]) -> usize {
+    let before = files.len();
+    let mut kept = 0;
+    files.retain(|f| {
+        kept += 1;
+        kept <= limit || required.contains(&f.path)
+    });
+    before - files.len()
+}
+
+/// Splits out the pinned photos, in pin-list order.
+fn take_pinned(files: Vec<FileInfo>, pins: &[PathBuf]) -> (Vec<FileInfo>, Vec<FileInfo>) {
+    let (mut pinned, rest): (Vec<FileInfo>, Vec<FileInfo>) =
+        files.into_iter().partition(|f| pins.contains(&f.path));
+    pinned.sort_by_key(|f| pins.iter().position(|p| p == &f.path));
+    (pinned, rest)
+}
+
+/// Moves pinned photos back to the front of `group`, after a pass that may
+/// have reordered it.
+fn pins_first(group: &mut [FileInfo], pins: &[PathBuf]) {
+    group.sort_by_key(|f| pins.iter().position(|p| p == &f.path).unwrap_or(usize::MAX));
+}
+
+/// Reads through the cache at `cache_file` when there is one; failing to
+/// save it only warrants a warning.
+fn load_exif(files: &mut [FileInfo], cache_file: Option<&Path>) -> Result<(), String> {
+    let Some(cache_file) = cache_file else {
+        for f in files.iter_mut() {
+            f.exif = exif::read_exif(&f.path)?;
+        }
+        return Ok(());
+    };
+    let mut cache = cache::Cache::open(cache_file);
+    for f in files.iter_mut() {
+        f.exif = cache.read_exif(&f.path)?;
+    }
+    if let Err(e) = cache.save() {
+        eprintln!("warning: {e}");
+    }
+    Ok(())
+}
+
+fn load_xmp(files: &mut [FileInfo]) -> Result<(), String> {
+    for f in files.iter_mut() {
+        f.xmp = xmp::read_xmp(&f.path)?;
+    }
+    Ok(())
+}
+
+/// Names match case-insensitively.
+fn people_filter_allows(f: &FileInfo, require: &[String], exclude: &[String]) -> bool {
+    let tagged = |name: &String| f.xmp.people.iter().any(|p| p.eq_ignore_ascii_case(name));
+    if !require.is_empty() && !require.iter().any(tagged) {
+        return false;
+    }
+    !exclude.iter().any(tagged)
+}
+
+/// Dotfiles and folders (which covers `._*` AppleDouble files and `.DS_Store`)
+/// plus Windows thumbnail caches.
+fn is_hidden_or_junk(name: &str) -> bool {
+    name.starts_with('.') || name.eq_ignore_ascii_case("Thumbs.db") || name.eq_ignore_ascii_case("desktop.ini")
+}
+
+/// What the file at `path` is, and for photos the format, if the scan wants it.
+type Detected = (MediaKind, Option<ImageFormat>);
+
+fn classify(path: &Path, opts: &ScanOptions) -> Result<Option<Detected>, String> {
+    let by_ext = match ImageFormat::of_path(path) {
+        Some(format) if opts.formats.contains(&format) => Some((MediaKind::Photo, Some(format))),
+        Some(_) => None,
+        None if opts.include_videos && is_video(path) => Some((MediaKind::Video, None)),
+        None => None,
+    };
+    match opts.detect_by {
+        DetectBy::Ext => Ok(by_ext),
+        DetectBy::Magic => sniff(path, opts),
+        DetectBy::Both => {
+            let Some(detected) = by_ext else {
+                return Ok(None);
+            };
+            if sniff(path, opts)? == Some(detected) {
+                Ok(Some(detected))
+            } else {
+                eprintln!(
+                    "warning: skipping {}: content does not match its extension",
+                    path.display()
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Recognises photos in the wanted formats (and, if wanted, MP4/QuickTime
+/// videos) by their leading bytes.
+fn sniff(path: &Path, opts: &ScanOptions) -> Result<Option<Detected>, String> {
+    let mut head = [0u8; 12];
+    let n = fs::File::open(path)
+        .and_then(|f| f.take(head.len() as u64).read(&mut head))
+        .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+    Ok(sniff_bytes(&head[..n], &opts.formats, opts.include_videos))
+}
+
+fn sniff_bytes(head: &[u8], formats: &[ImageFormat], include_videos: bool) -> Option<Detected> {
+    if let Some(format) = ImageFormat::sniff(head) {
+        return formats.contains(&format).then_some((MediaKind::Photo, Some(format)));
+    }
+    // ISO base media files carry `ftyp` at offset 4, but so do HEIF/AVIF
+    // stills and M4A audio, so only known video brands count.
+    if include_videos && head.len() >= 12 && &head[4..8] == b"ftyp" {
+        let brand = &head[8..12];
+        let video = [b"isom", b"iso2", b"mp41", b"mp42", b"avc1", b"qt  ", b"M4V "];
+        if video.iter().any(|b| brand == *b) || brand.starts_with(b"3gp") {
+            return Some((MediaKind::Video, None));
+        }
+    }
+    None
+}
+
+/// Name a source file gets in the destination; Windows can't write reserved
+/// device names or some characters that other systems allow.
+fn dest_file_name(name: &str) -> String {
+    if cfg!(windows) {
+        platform::sanitize_file_name(name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Room kept free below the name length limit for collision suffixes
+/// (`-12`) and sidecar extensions (`.xmp`).
+const NAME_HEADROOM: usize = 12;
+
+/// Makes names fit what `probe` found: characters the destination rejects
+/// are replaced, and over-long names are shortened and given a hash of the
+/// file's content so two names cut to the same prefix stay apart (and the
+/// same photo gets the same name every run). Whether names differ only in
+/// case never matters, since collisions are always resolved ignoring case.
+/// Returns how many names changed.
+fn adapt_names(files: &mut [FileInfo], caps: &platform::Capabilities) -> usize {
+    let max_bytes = caps.max_name_bytes.saturating_sub(NAME_HEADROOM).max(16);
+    let mut changed = 0;
+    for f in files.iter_mut() {
+        let mut name = f.name.clone();
+        if name.contains(caps.forbidden.as_slice()) {
+            name = platform::sanitize_file_name(&name);
+        }
+        if name.len() > max_bytes {
+            let hash = dupes::content_hash(&f.path)
+                .unwrap_or_else(|_| dupes::fnv1a(dupes::FNV_OFFSET, f.path.as_os_str().as_encoded_bytes()));
+            name = shorten_name(&name, max_bytes, &format!("-{:08x}", hash as u32));
+        }
+        if name != f.name {
+            f.name = name;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// `--preserve-structure`: the folders between `src` and the file at `path`,
+/// `/`-separated, each adjusted the way file names are. `None` for files
+/// directly in `src`.
+fn structure_prefix(src: &Path, path: &Path, safe_names: bool, caps: &platform::Capabilities) -> Option<String> {
+    let dirs = path.parent()?.strip_prefix(src).ok()?;
+    let parts: Vec<String> = dirs
+        .components()
+        .map(|c| {
+            let mut part = dest_file_name(&nfc::to_nfc(&c.as_os_str().to_string_lossy()));
+            if safe_names {
+                part = platform::ascii_file_name(&part);
+            }
+            if part.contains(caps.forbidden.as_slice()) {
+                part = platform::sanitize_file_name(&part);
+            }
+            part
+        })
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Splits a destination name into its subfolders (with the trailing `/`, or
+/// empty) and the file name; only `--preserve-structure` names have any.
+fn split_dest_name(name: &str) -> (&str, &str) {
+    match name.rfind('/') {
+        Some(slash) => name.split_at(slash + 1),
+        None => ("", name),
+    }
+}
+
+/// Cuts the stem of `name` so that with `suffix` after it the whole fits in
+/// `max_bytes` of UTF-8, keeping the extension and whole characters.
+fn shorten_name(name: &str, max_bytes: usize, suffix: &str) -> String {
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= 8 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut cut = max_bytes.saturating_sub(ext.len() + suffix.len()).min(stem.len());
+    while !stem.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    format!("{}{suffix}{ext}", &stem[..cut])
+}
+
+fn with_extension(name: &str, ext: &str) -> String {
+    Path::new(name).with_extension(ext).to_string_lossy().into_owned()
+}
+
+fn is_video(path: &Path) -> bool {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => ["mp4", "mov", "m4v"].iter().any(|v| ext.eq_ignore_ascii_case(v)),
+        None => false,
+    }
+}
+
+// Simple, dependency-free RNG (xorshift64*) for shuffling.
+#[derive(Clone)]
+struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        // Avoid a zero state.
+        let seed = if seed == 0 { 0xA5A5_A5A5_5A5A_5A5A } else { seed };
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+/// Chronological orders compare moments, not clock readings: a photo taken
+/// at 08:00 in Tokyo comes before one taken at 20:00 the evening before in
+/// Los Angeles. EXIF times without an offset count as `assumed` (or UTC).
+fn order_files(files: &mut [FileInfo], order: Order, seed: u64, assumed: Option<i16>) {
+    match order {
+        Order::Random => shuffle_in_place(files, seed),
+        Order::Chronological | Order::NewestFirst => {
+            let newest_first = order == Order::NewestFirst;
+            let instant = |f: &FileInfo| {
+                let offset = f.exif.offset.or(assumed).unwrap_or(0);
+                f.exif.captured.map(|t| t.to_unix() - i64::from(offset) * 60)
+            };
+            // `None` sorts before `Some`, so key on "is undated" first.
+            files.sort_by_cached_key(|f| {
+                let at = instant(f);
+                (at.is_none(), if newest_first { at.map(|t| -t) } else { at }, f.path.clone())
+            });
+        }
+        Order::Name => files.sort_by(|a, b| a.path.cmp(&b.path)),
+    }
+}
+
+/// Lower-cased, `/`-separated entries of a favourites export. Entries may be
+/// bare file names (as phone exports usually are) or paths.
+fn load_favorites(list: &Path) -> Result<HashSet<String>, String> {
+    let text = fs::read_to_string(list)
+        .map_err(|e| format!("cannot read favorites {}: {e}", list.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| nfc::to_nfc(&l.replace('\\', "/")).to_lowercase())
+        .collect())
+}
+
+/// Matches on the source file name, the path relative to `src` or the full path.
+fn is_favorite(f: &FileInfo, src: &Path, favorites: &HashSet<String>) -> bool {
+    let file_name = f.path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let rel = relative_name(src, &f.path);
+    let full = f.path.to_string_lossy().replace('\\', "/");
+    [nfc::to_nfc(&file_name), rel, nfc::to_nfc(&full)]
+        .iter()
+        .any(|key| favorites.contains(&key.to_lowercase()))
+}
+
+/// Seeded shuffle where, at every position, a file with weight `w` is `w`
+/// times as likely to come next as a file with weight 1 (Efraimidis-Spirakis:
+/// sort by `ln(u) / w` for uniform `u`).
+fn weighted_shuffle(files: &mut [FileInfo], seed: u64, weight: impl Fn(&FileInfo) -> f64) {
+    let mut rng = XorShift64::new(seed);
+    let mut keyed: Vec<(f64, FileInfo)> = files
+        .iter()
+        .map(|f| {
+            // Uniform in (0, 1], from the top 53 bits.
+            let u = ((rng.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+            (u.ln() / weight(f), f.clone())
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (slot, (_, f)) in files.iter_mut().zip(keyed) {
+        *slot = f;
+    }
+}
+
+fn stratum_key(f: &FileInfo, by: Stratum) -> String {
+    match by {
+        Stratum::Year => f
+            .exif
+            .captured
+            .map(|dt| dt.year.to_string())
+            .unwrap_or_default(),
+        Stratum::Album => f.album.clone(),
+        Stratum::Orientation => match f.exif.display_dimensions() {
+            Some((w, h)) if w > h => "landscape".to_string(),
+            Some((w, h)) if w < h => "portrait".to_string(),
+            Some(_) => "square".to_string(),
+            None => String::new(),
+        },
+    }
+}
+
+/// Interleaves the strata proportionally, so any run of consecutive files (and
+/// therefore every planned folder) gets about the same mix. The relative order
+/// inside each stratum is kept.
+fn stratify(files: Vec<FileInfo>, by: Stratum) -> Vec<FileInfo> {
+    let mut strata: BTreeMap<String, Vec<FileInfo>> = BTreeMap::new();
+    for f in files {
+        strata.entry(stratum_key(&f, by)).or_default().push(f);
+    }
+
+    // Spread stratum members evenly over [0, 1); ties keep stratum order.
+    let mut keyed: Vec<(f64, usize, FileInfo)> = Vec::new();
+    for (s, members) in strata.into_values().enumerate() {
+        let n = members.len() as f64;
+        for (k, f) in members.into_iter().enumerate() {
+            keyed.push(((k as f64 + 0.5) / n, s, f));
+        }
+    }
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    keyed.into_iter().map(|(_, _, f)| f).collect()
+}
+
+/// `--spread-similar`: a photo in the same folder as a perceptually similar
+/// one swaps places with a photo of another folder that holds none of its
+/// look-alikes, as long as both folders stay within `max_bytes`. Pinned
+/// photos and those with look-alikes of their own aren't swapped out.
+/// Returns how many photos were moved and how many had to stay.
+fn spread_similar(groups: &mut [Vec<FileInfo>], max_bytes: u64, pins: &[PathBuf], jobs: usize) -> (usize, usize) {
+    let positions: Vec<(usize, usize)> =
+        groups.iter().enumerate().flat_map(|(g, group)| (0..group.len()).map(move |i| (g, i))).collect();
+    let hashes = parallel_map(&positions, jobs, |&(g, i)| perceptual_hash(&groups[g][i].path));
+    let mut hashed = Vec::new();
+    let mut paths = Vec::new();
+    for (&(g, i), hash) in positions.iter().zip(hashes) {
+        match hash {
+            Ok(hash) => {
+                hashed.push(hash);
+                paths.push(groups[g][i].path.clone());
+            }
+            Err(e) => eprintln!("warning: {e}; not checking it for similar photos"),
+        }
+    }
+    let clusters: Vec<Vec<PathBuf>> = dupes::similar_clusters(&hashed, DEFAULT_DUPE_DISTANCE)
+        .into_iter()
+        .map(|members| members.into_iter().map(|k| paths[k].clone()).collect())
+        .collect();
+    let clustered: HashSet<&PathBuf> = clusters.iter().flatten().collect();
+    let mut at: HashMap<PathBuf, (usize, usize)> = HashMap::new();
+    for (g, group) in groups.iter().enumerate() {
+        for (i, f) in group.iter().enumerate() {
+            at.insert(f.path.clone(), (g, i));
+        }
+    }
+    let mut bytes: Vec<u64> = groups.iter().map(|g| g.iter().map(FileInfo::footprint).sum()).collect();
+    let (mut moved, mut left) = (0, 0);
+    for cluster in &clusters {
+        let mut used = HashSet::new();
+        for member in cluster {
+            let (g, i) = at[member];
+            if used.insert(g) {
+                continue;
+            }
+            let size = groups[g][i].footprint();
+            let holds_member = |t: usize| cluster.iter().any(|m| at[m].0 == t);
+            let swap = (!pins.contains(member))
+                .then(|| (g + 1..groups.len()).chain(0..g))
+                .into_iter()
+                .flatten()
+                .filter(|&t| !used.contains(&t) && !holds_member(t))
+                .find_map(|t| {
+                    let j = groups[t].iter().position(|p| {
+                        !clustered.contains(&p.path)
+                            && !pins.contains(&p.path)
+                            && bytes[t] - p.footprint() + size <= max_bytes
+                            && bytes[g] - size + p.footprint() <= max_bytes
+                    })?;
+                    Some((t, j))
+                });
+            let Some((t, j)) = swap else {
+                left += 1;
+                continue;
+            };
+            let other = groups[t][j].footprint();
+            let f = groups[g][i].clone();
+            groups[g][i] = std::mem::replace(&mut groups[t][j], f);
+            (bytes[g], bytes[t]) = (bytes[g] - size + other, bytes[t] - other + size);
+            at.insert(groups[g][i].path.clone(), (g, i));
+            at.insert(groups[t][j].path.clone(), (t, j));
+            used.insert(t);
+            moved += 1;
+        }
+    }
+    (moved, left)
+}
+
+#[cfg(feature = "imaging")]
+fn diversify_groups(groups: &mut [Vec<FileInfo>]) -> Result<(), String> {
+    imaging::diversify_groups(groups)
+}
+
+#[cfg(feature = "imaging")]
+fn resized_jpeg(src: &Path, resize: Resize, jpeg: JpegOptions) -> Result<Option<Vec<u8>>, String> {
+    imaging::resized_jpeg(src, resize, jpeg)
+}
+
+#[cfg(not(feature = "imaging"))]
+fn resized_jpeg(_src: &Path, _resize: Resize, _jpeg: JpegOptions) -> Result<Option<Vec<u8>>, String> {
+    Err("resizing needs a build with `--features imaging`".to_string())
+}
+
+#[cfg(feature = "imaging")]
+fn reframed_panorama(src: &Path, processing: Processing) -> Result<Option<Vec<u8>>, String> {
+    imaging::reframed_panorama(src, processing.panoramas, processing.resize, processing.jpeg)
+}
+
+#[cfg(not(feature = "imaging"))]
+fn reframed_panorama(_src: &Path, _processing: Processing) -> Result<Option<Vec<u8>>, String> {
+    Err("reframing panoramas needs a build with `--features imaging`".to_string())
+}
+
+#[cfg(feature = "imaging")]
+fn perceptual_hash(path: &Path) -> Result<u64, String> {
+    imaging::dhash(path)
+}
+
+#[cfg(not(feature = "imaging"))]
+fn perceptual_hash(_path: &Path) -> Result<u64, String> {
+    Err("perceptual hashing needs a build with `--features imaging`".to_string())
+}
+
+#[cfg(not(feature = "imaging"))]
+fn diversify_groups(_groups: &mut [Vec<FileInfo>]) -> Result<(), String> {
+    Err("--diversify-colors needs a build with `--features imaging`".to_string())
+}
+
+fn shuffle_in_place(files: &mut [FileInfo], seed: u64) {
+    let mut rng = XorShift64::new(seed);
+    // Fisher-Yates
+    for i in (1..files.len()).rev() {
+        let j = (rng.next_u64() as usize) % (i + 1);
+        files.swap(i, j);
+    }
+}
+
+fn plan_groups(files: &[FileInfo], max_files: usize, max_bytes: u64, mode: LimitMode) -> Result<Vec<Vec<FileInfo>>, String> {
+    let mut groups: Vec<Vec<FileInfo>> = Vec::new();
+    let mut cur: Vec<FileInfo> = Vec::new();
+    let mut cur_bytes: u64 = 0;
+
+    for f in files {
+        if f.footprint() > max_bytes && mode != LimitMode::Both {
+            return Err(format!(
+                "file is larger than max-bytes ({} > {}): {}",
+                f.footprint(),
+                max_bytes,
+                f.path.display()
+            ));
+        }
+
+        if !cur.is_empty() && !mode.has_room(cur.len() + 1, cur_bytes + f.footprint(), max_files, max_bytes) {
+            groups.push(cur);
+            cur = Vec::new();
+            cur_bytes = 0;
+        }
+
+        cur_bytes += f.footprint();
+        cur.push(f.clone());
+    }
+
+    if !cur.is_empty() {
+        groups.push(cur);
+    }
+    Ok(groups)
+}
+
+/// `--stable-reshuffle`: keeps `100 - percent` % of the photos the previous
+/// run placed in the same folder under the same name (which ones follows
+/// `files`, i.e. the shuffle), fills the room left in those folders with the
+/// remaining photos, and plans new folders for whatever doesn't fit.
+fn plan_stable_groups(
+    files: &[FileInfo],
+    previous: &manifest::Manifest,
+    percent: u32,
+    src: &Path,
+    max_files: usize,
+    max_bytes: u64,
+    mode: LimitMode,
+) -> Result<Vec<Vec<FileInfo>>, String> {
+    let placed: HashMap<&str, &manifest::Entry> =
+        previous.files.iter().map(|e| (e.source.as_str(), e)).collect();
+    let was_placed = |f: &FileInfo| {
+        placed
+            .get(relative_name(src, &f.path).as_str())
+            .copied()
+            .filter(|e| e.size == f.size)
+    };
+    let candidates = files.iter().filter(|f| was_placed(f).is_some()).count();
+    let keep = (candidates * (100 - percent as usize) + 50) / 100;
+
+    let folders = previous.folders();
+    let mut groups: Vec<Vec<FileInfo>> = vec![Vec::new(); folders];
+    let mut bytes = vec![0u64; folders];
+    let mut rest = Vec::new();
+    let mut kept = 0;
+    for f in files {
+        match was_placed(f) {
+            Some(e) if kept < keep => {
+                let g = e.folder - 1;
+                // Limits may have shrunk since the previous run.
+                if mode.has_room(groups[g].len() + 1, bytes[g] + f.footprint(), max_files, max_bytes) {
+                    bytes[g] += f.footprint();
+                    groups[g].push(FileInfo { name: e.name.clone(), ..f.clone() });
+                    kept += 1;
+                    continue;
+                }
+                rest.push(f.clone());
+            }
+            _ => rest.push(f.clone()),
+        }
+    }
+
+    let mut overflow = Vec::new();
+    for f in rest {
+        let room = (0..folders).find(|&g| mode.has_room(groups[g].len() + 1, bytes[g] + f.footprint(), max_files, max_bytes));
+        match room {
+            Some(g) => {
+                bytes[g] += f.footprint();
+                groups[g].push(f);
+            }
+            None => overflow.push(f),
+        }
+    }
+    while groups.last().is_some_and(|g| g.is_empty()) {
+        groups.pop();
+    }
+    groups.append(&mut plan_groups(&overflow, max_files, max_bytes, mode)?);
+    Ok(groups)
+}
+
+/// Plans each album separately so no destination folder mixes albums.
+/// Albums are numbered in name order; the order within an album follows `files`.
+fn plan_album_groups(files: &[FileInfo], max_files: usize, max_bytes: u64, mode: LimitMode) -> Result<Vec<Vec<FileInfo>>, String> {
+    let mut albums: BTreeMap<&str, Vec<FileInfo>> = BTreeMap::new();
+    for f in files {
+        albums.entry(f.album.as_str()).or_default().push(f.clone());
+    }
+
+    let mut groups = Vec::new();
+    for album_files in albums.values() {
+        groups.extend(plan_groups(album_files, max_files, max_bytes, mode)?);
+    }
+    Ok(groups)
+}
+
+/// Clusters photos by GPS position and plans each cluster separately; photos
+/// without GPS form a catch-all cluster placed last. Clustering itself is done
+/// in path order so the places don't depend on the seed, while the order
+/// within each cluster still follows `files`.
+fn plan_location_groups(
+    files: &[FileInfo],
+    radius_km: f64,
+    max_files: usize,
+    max_bytes: u64,
+    mode: LimitMode,
+) -> Result<Vec<Vec<FileInfo>>, String> {
+    let mut by_path: Vec<usize> = (0..files.len()).collect();
+    by_path.sort_by(|&a, &b| files[a].path.cmp(&files[b].path));
+
+    // Leader clustering against a running centroid.
+    let mut centroids: Vec<((f64, f64), usize)> = Vec::new();
+    let mut cluster_of = vec![None; files.len()];
+    for &idx in &by_path {
+        let Some(pos) = files[idx].exif.gps else {
+            continue;
+        };
+        let near = centroids
+            .iter()
+            .position(|&(c, _)| exif::haversine_km(c, pos) <= radius_km);
+        let k = match near {
+            Some(k) => {
+                let ((lat, lon), n) = centroids[k];
+                let n1 = (n + 1) as f64;
+                centroids[k] = (
+                    ((lat * n as f64 + pos.0) / n1, (lon * n as f64 + pos.1) / n1),
+                    n + 1,
+                );
+                k
+            }
+            None => {
+                centroids.push((pos, 1));
+                centroids.len() - 1
+            }
+        };
+        cluster_of[idx] = Some(k);
+    }
+
+    let mut clusters: Vec<Vec<FileInfo>> = vec![Vec::new(); centroids.len()];
+    let mut unplaced = Vec::new();
+    for (f, k) in files.iter().zip(&cluster_of) {
+        match k {
+            Some(k) => clusters[*k].push(f.clone()),
+            None => unplaced.push(f.clone()),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for cluster in clusters.iter().chain(std::iter::once(&unplaced)) {
+        groups.extend(plan_groups(cluster, max_files, max_bytes, mode)?);
+    }
+    Ok(groups)
+}
+
+/// Deals videos round-robin into the planned folders, each folder having its
+/// own video file/byte budget. Returns how many videos did not fit anywhere.
+fn sprinkle_videos(
+    groups: &mut [Vec<FileInfo>],
+    videos: Vec<FileInfo>,
+    max_files: usize,
+    max_bytes: u64,
+) -> usize {
+    let mut used: Vec<(usize, u64)> = vec![(0, 0); groups.len()];
+    let mut next = 0;
+    let mut left_out = 0;
+    for v in videos {
+        let fits = |&(n, bytes): &(usize, u64)| n < max_files && bytes + v.footprint() <= max_bytes;
+        let slot = (0..groups.len())
+            .map(|k| (next + k) % groups.len())
+            .find(|&g| fits(&used[g]));
+        match slot {
+            Some(g) => {
+                used[g].0 += 1;
+                used[g].1 += v.footprint();
+                groups[g].push(v);
+                next = g + 1;
+            }
+            None => left_out += 1,
+        }
+    }
+    left_out
+}
+
+/// Longest name (in bytes) one `--name-groups-by` part adds to a folder.
+const MAX_LABEL_PART: usize = 24;
+
+/// The `--name-groups-by` name of one folder, e.g. `summer-2023-oregon`.
+/// Parts the folder's photos don't mostly agree on are left out.
+fn group_label(group: &[FileInfo], parts: &[GroupLabel]) -> String {
+    let photos: Vec<&FileInfo> = group.iter().filter(|f| f.kind == MediaKind::Photo).collect();
+    let words: Vec<String> = parts
+        .iter()
+        .filter_map(|part| match part {
+            GroupLabel::Keywords => {
+                let (keyword, count) = most_common(photos.iter().flat_map(|f| &f.xmp.keywords))?;
+                // A keyword on a quarter of the photos still says what the folder is about.
+                (count * 4 >= photos.len()).then(|| label_slug(keyword))
+            }
+            GroupLabel::DateRange => date_range_label(photos.iter().filter_map(|f| f.exif.captured).collect()),
+            GroupLabel::Location => {
+                let placed = photos.iter().filter(|f| f.xmp.place.iter().any(Option::is_some)).count();
+                // The narrowest place most of them were taken in.
+                (0..3).find_map(|level| {
+                    let (name, count) = most_common(photos.iter().filter_map(|f| f.xmp.place[level].as_ref()))?;
+                    (count * 2 > placed).then(|| label_slug(name))
+                })
+            }
+        })
+        .filter(|w| !w.is_empty())
+        .collect();
+    words.join("-")
+}
+
+/// The value given most often, ignoring case, with how often; ties go to the
+/// one seen first.
+fn most_common<'a>(values: impl Iterator<Item = &'a String>) -> Option<(&'a str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for v in values {
+        match counts.iter_mut().find(|(seen, _)| seen.eq_ignore_ascii_case(v)) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((v, 1)),
+        }
+    }
+    // `max_by_key` keeps the last of equals.
+    counts.into_iter().rev().max_by_key(|&(_, n)| n)
+}
+
+/// The span most capture dates fall in, as narrow as it gets: `june-2023`,
+/// `summer-2023`, `winter-2022-23`, `2023` or `2019-2021`. The earliest and
+/// latest tenth are ignored, so a stray old scan doesn't widen it.
+fn date_range_label(mut dates: Vec<exif::DateTime>) -> Option<String> {
+    const MONTHS: [&str; 12] =
+        ["january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november", "december"];
+    if dates.is_empty() {
+        return None;
+    }
+    dates.sort();
+    let trim = dates.len() / 10;
+    let (from, to) = (dates[trim], dates[dates.len() - 1 - trim]);
+    // Seasons by month; December starts the next year's winter.
+    let season = |d: exif::DateTime| match d.month {
+        12 => (d.year + 1, 0),
+        1 | 2 => (d.year, 0),
+        m => (d.year, (m as usize - 3) / 3 + 1),
+    };
+    Some(if (from.year, from.month) == (to.year, to.month) {
+        format!("{}-{}", MONTHS[from.month as usize - 1], from.year)
+    } else if season(from) == season(to) {
+        match season(from) {
+            (year, 0) => format!("winter-{}-{:02}", year - 1, year % 100),
+            (year, s) => format!("{}-{year}", ["spring", "summer", "autumn"][s - 1]),
+        }
+    } else if from.year == to.year {
+        from.year.to_string()
+    } else {
+        format!("{}-{}", from.year, to.year)
+    })
+}
+
+/// `text` lowercased for a folder name: ASCII letters and digits, accents
+/// dropped, everything else a single `-`.
+fn label_slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().map(nfc::base_letter) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_LABEL_PART);
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Replaces each destination name's stem with the rendered template. Files
+/// without an EXIF capture time fall back to their modification time, in
+/// the `assumed` offset from UTC (UTC itself without one).
+fn apply_rename_template(files: &mut [FileInfo], template: &template::Template, assumed: Option<i16>) {
+    for f in files.iter_mut() {
+        let date = f.exif.captured.or_else(|| {
+            let modified = fs::metadata(&f.path).and_then(|m| m.modified()).ok()?;
+            let secs = modified.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+            let local = secs + i64::from(assumed.unwrap_or(0)) * 60;
+            Some(exif::DateTime::from_unix(local.max(0) as u64))
+        });
+        let orig = f.path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let stem = template.render(&template::Fields { orig: &orig, date });
+        let name = match Path::new(&f.name).extension() {
+            Some(ext) => format!("{stem}.{}", ext.to_string_lossy()),
+            None => stem,
+        };
+        f.name = dest_file_name(&nfc::to_nfc(&name));
+    }
+}
+
+/// Renames files so no two in the whole run share a destination name, even
+/// in different folders, so manifests, verification and later merges can
+/// tell them apart by name alone. Names are compared case-insensitively,
+/// since FAT/exFAT and default macOS volumes treat `IMG_001.jpg` and
+/// `img_001.JPG` as one file. Files for which `keep(folder number, file)`
+/// holds claim their names first; the rest keep theirs in plan order.
+/// Returns the rename count.
+fn resolve_name_collisions(groups: &mut [Vec<FileInfo>], keep: impl Fn(usize, &FileInfo) -> bool) -> usize {
+    let mut taken = HashSet::new();
+    let mut kept = HashSet::new();
+    for (idx, group) in groups.iter().enumerate() {
+        for (pos, f) in group.iter().enumerate() {
+            if keep(idx + 1, f) && taken.insert(f.name.to_lowercase()) {
+                kept.insert((idx, pos));
+            }
+        }
+    }
+    let mut renamed = 0;
+    for (idx, group) in groups.iter_mut().enumerate() {
+        for (pos, f) in group.iter_mut().enumerate() {
+            if kept.contains(&(idx, pos)) || taken.insert(f.name.to_lowercase()) {
+                continue;
+            }
+            let (dirs, file) = split_dest_name(&f.name);
+            let path = Path::new(file);
+            let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file).to_string();
+            let ext = path.extension().and_then(|s| s.to_str()).map(|e| format!(".{e}"));
+            let ext = ext.unwrap_or_default();
+            let mut n = 2;
+            let name = loop {
+                let candidate = format!("{dirs}{stem}-{n}{ext}");
+                if taken.insert(candidate.to_lowercase()) {
+                    break candidate;
+                }
+                n += 1;
+            };
+            f.name = name;
+            renamed += 1;
+        }
+    }
+    renamed
+}
+
+/// A sidecar follows its image's destination name: `IMG.jpg.xmp` next to a
+/// renamed `IMG-2.jpg` becomes `IMG-2.jpg.xmp`, `IMG.AAE` becomes `IMG-2.AAE`.
+fn sidecar_dest_name(f: &FileInfo, sidecar: &Path) -> String {
+    let sidecar_name = sidecar.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let src_name = f.path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let src_stem = f.path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let (dirs, dest_name) = split_dest_name(&f.name);
+    let dest_stem = Path::new(dest_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| dest_name.to_string());
+
+    let renamed = if let Some(rest) = sidecar_name.strip_prefix(src_name.as_ref()) {
+        format!("{dest_name}{rest}")
+    } else if let Some(rest) = sidecar_name.strip_prefix(src_stem.as_ref()) {
+        format!("{dest_stem}{rest}")
+    } else {
+        sidecar_name.into_owned()
+    };
+    format!("{dirs}{}", dest_file_name(&nfc::to_nfc(&renamed)))
+}
+
+/// What the writer does with one planned file.
+enum Output {
+    /// Copy the source bytes unchanged.
+    Copy,
+    /// Write these processed bytes instead.
+    Bytes(Vec<u8>),
+}
+
+/// The CPU-heavy part of copying one file: flattening, resizing or lossless
+/// optimisation, then thumbnail stripping. Runs on the worker threads.
+fn process(f: &FileInfo, processing: Processing) -> Result<Output, String> {
+    let output = process_content(f, processing)?;
+    if !processing.strip_thumbnails || f.format != Some(ImageFormat::Jpeg) || f.flatten {
+        return Ok(output);
+    }
+    let data = match output {
+        Output::Bytes(bytes) => bytes,
+        Output::Copy => fs::read(&f.path).map_err(|e| format!("cannot read {}: {e}", f.path.display()))?,
+    };
+    // MPF records the main image's size, so a gain map is attached again.
+    let stripped = match gainmap::find(&data) {
+        Some(gm) => exif::strip_thumbnail(gm.primary).map(|p| gainmap::attach(&p, &gm, gm.image)),
+        None => exif::strip_thumbnail(&data),
+    };
+    Ok(Output::Bytes(stripped.unwrap_or(data)))
+}
+
+fn process_content(f: &FileInfo, processing: Processing) -> Result<Output, String> {
+    if f.flatten {
+        return animated::read_first_frame(&f.path).map(Output::Bytes);
+    }
+    if f.format != Some(ImageFormat::Jpeg) {
+        return Ok(Output::Copy);
+    }
+    if matches!(processing.panoramas, Panoramas::CropCenter | Panoramas::Letterbox) {
+        // Other photos go on to the usual handling below.
+        match reframed_panorama(&f.path, processing) {
+            Ok(Some(bytes)) => return Ok(Output::Bytes(bytes)),
+            Ok(None) => {}
+            Err(e) => eprintln!("warning: not reframing panorama {}: {e}", f.path.display()),
+        }
+    }
+    match processing.resize {
+        Some(resize) => match resized_jpeg(&f.path, resize, processing.jpeg) {
+            Ok(Some(bytes)) => Ok(Output::Bytes(bytes)),
+            Ok(None) => Ok(Output::Copy),
+            Err(e) => {
+                eprintln!("warning: copying {} unchanged: {e}", f.path.display());
+                Ok(Output::Copy)
+            }
+        },
+        None if processing.jpeg.optimize => {
+            let data = fs::read(&f.path).map_err(|e| format!("cannot read {}: {e}", f.path.display()))?;
+            // The optimiser drops what follows the main image, so a gain map
+            // is put back afterwards.
+            let gain_map = gainmap::find(&data).filter(|_| !processing.jpeg.tone_map_sdr);
+            let primary = gain_map.as_ref().map_or(&data[..], |gm| gm.primary);
+            // Files the optimiser doesn't handle (progressive, arithmetic,
+            // not a JPEG at all) are copied as they are.
+            match jpegopt::optimize(primary, processing.jpeg.progressive) {
+                Ok(bytes) if bytes.len() < primary.len() => Ok(Output::Bytes(match &gain_map {
+                    Some(gm) => gainmap::attach(&bytes, gm, gm.image),
+                    None => bytes,
+                })),
+                _ => Ok(Output::Bytes(data)),
+            }
+        }
+        _ => Ok(Output::Copy),
+    }
+}
+
+/// Processes and writes files on `jobs` worker threads, so copies to a slow
+/// disk or share overlap. Each worker holds one processed file at a time,
+/// which bounds memory. After the first failure no new file is started; the
+/// copies already under way are finished and that first error is returned.
+/// Files listed in `in_place` as (folder number, name) are already there.
+/// Nothing new is started once `window` closes; returns what was written.
+fn copy_groups(
+    groups: &[Vec<FileInfo>],
+    dst_root: &Path,
+    folder_names: &[String],
+    processing: Processing,
+    jobs: usize,
+    in_place: &HashSet<(usize, String)>,
+    window: CopyWindow,
+) -> Result<HashSet<(usize, String)>, String> {
+    let mut work = Vec::new();
+    for (idx, group) in groups.iter().enumerate() {
+        let folder_num = idx + 1;
+        let folder = dst_root.join(&folder_names[idx]);
+        fs::create_dir_all(&folder)
+            .map_err(|e| format!("cannot create folder {}: {e}", folder.display()))?;
+        work.extend(
+            group
+                .iter()
+                .filter(|f| !in_place.contains(&(folder_num, f.name.clone())))
+                .map(|f| (f, folder_num, folder.clone())),
+        );
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    // One worker waits out the quiet hours; the others queue up behind it.
+    let quiet = Mutex::new(());
+    let written = thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (work, next, stop, quiet) = (&work, &next, &stop, &quiet);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) && !window.expired() {
+                    let Some(&(f, folder_num, ref folder)) = work.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    let output = process(f, processing);
+                    {
+                        let _turn = quiet.lock().expect("quiet hours lock poisoned");
+                        window.wait_out_quiet_hours();
+                    }
+                    if stop.load(Ordering::Relaxed) || window.expired() {
+                        break;
+                    }
+                    let done = output.and_then(|output| write_output(f, folder, output));
+                    if done.is_err() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    let _ = tx.send((f, folder_num, done));
+                }
+            });
+        }
+        drop(tx);
+
+        let mut written = HashSet::new();
+        let mut result = Ok(());
+        for (f, folder_num, done) in rx.iter() {
+            match done {
+                Ok(()) => {
+                    written.insert((folder_num, f.name.clone()));
+                }
+                // Later failures are mostly the same full disk again.
+                Err(e) if result.is_ok() => result = Err(e),
+                Err(_) => {}
+            }
+        }
+        result.map(|()| written)
+    })?;
+    if written.len() < work.len() {
+        // Don't leave folders the budget never reached.
+        for name in folder_names {
+            let _ = fs::remove_dir(dst_root.join(name));
+        }
+    }
+    Ok(written)
+}
+
+/// `--distribute`: the `d`th of `n` destinations gets folders `d`, `d + n`,
+/// `d + 2n`... of the plan (0-based), renumbered from 1.
+fn distribute_groups(groups: Vec<Vec<FileInfo>>, n: usize) -> Vec<Vec<Vec<FileInfo>>> {
+    let mut out = vec![Vec::new(); n];
+    for (idx, group) in groups.into_iter().enumerate() {
+        out[idx % n].push(group);
+    }
+    out
+}
+
+/// Checks the candidates before they are planned against the limits no plan
+/// gets around: photos over `--max-bytes` (unless `--limit-mode both` lets a
+/// folder go over it), and files or sidecars over what a destination's
+/// filesystem (FAT32, mostly) can hold. All of them are listed in one error,
+/// largest first, instead of the planner stopping at the first it meets.
+fn preflight(files: &[FileInfo], args: &Args) -> Result<(), String> {
+    // (bytes, path, what it breaks)
+    let mut problems: Vec<(u64, &Path, String)> = Vec::new();
+    if args.limit_mode != LimitMode::Both {
+        for f in files.iter().filter(|f| f.kind == MediaKind::Photo && f.footprint() > args.max_bytes) {
+            problems.push((f.footprint(), &f.path, format!("over --max-bytes ({})", args.max_bytes)));
+        }
+    }
+    let mut too_big_for_fs = false;
+    for dst in args.destinations() {
+        let Some(fs_type) = platform::filesystem_type(dst) else {
+            continue;
+        };
+        let Some(max) = platform::max_file_size(&fs_type) else {
+            continue;
+        };
+        // Videos over their folder budget are left out anyway.
+        let placeable = files.iter().filter(|f| f.kind == MediaKind::Photo || f.footprint() <= args.max_video_bytes);
+        for (path, size) in placeable
+            .flat_map(|f| std::iter::once((&f.path, f.size)).chain(f.sidecars.iter().map(|(p, size)| (p, *size))))
+            .filter(|&(_, size)| size > max)
+        {
+            problems.push((size, path, format!("over the {max} bytes a {fs_type} filesystem ({}) can hold", dst.display())));
+            too_big_for_fs = true;
+        }
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    problems.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)).then_with(|| a.2.cmp(&b.2)));
+    problems.dedup();
+    let lines: Vec<String> =
+        problems.iter().map(|(size, path, what)| format!("  {} ({size} bytes): {what}", path.display())).collect();
+    let mut fixes = Vec::new();
+    if problems.iter().any(|p| p.2.starts_with("over --max-bytes")) {
+        fixes.push("raise --max-bytes or use --limit-mode both");
+    }
+    if too_big_for_fs {
+        fixes.push("use a drive formatted as exFAT");
+    }
+    fixes.push("leave them out (e.g. --exclude-regex, --max-video-bytes)");
+    Err(format!(
+        "{} files can't be placed in any folder, largest first:\n{}\nTo copy anyway, {}.",
+        problems.iter().map(|p| p.1).collect::<HashSet<_>>().len(),
+        lines.join("\n"),
+        fixes.join(", or ")
+    ))
+}
+
+/// How writing one destination went.
+struct Written {
+    /// Files left in place and removed by `--sync`.
+    synced: Option<(usize, usize)>,
+    /// Planned files not copied because the time budget ran out.
+    left: usize,
+    /// `--dst-layout cas`: files linked to a copy already in the store.
+    from_store: Option<usize>,
+    /// `--resume`: files the interrupted run had already written in full.
+    resumed: Option<usize>,
+}
+
+/// Writes the plan to one destination, syncing against what it held before
+/// when `previous` is set. If the `window` closes, the manifest only lists
+/// what made it. The plan is kept next to it until the manifest is saved, so
+/// a run that dies on the way can be finished with `--resume`.
+fn write_destination(
+    dst: &Path,
+    groups: &[Vec<FileInfo>],
+    manifest: &manifest::Manifest,
+    previous: Option<&manifest::Manifest>,
+    store: Option<&Path>,
+    args: &Args,
+    window: CopyWindow,
+) -> Result<Written, String> {
+    let plan = dst.join(manifest::PLAN_FILE_NAME);
+    manifest.save(&plan)?;
+    let (mut in_place, removed) = match previous {
+        Some(previous) => sync_destination(dst, previous, manifest, args.use_trash)?,
+        None => (HashSet::new(), 0),
+    };
+    let folder_names: Vec<String> = (1..=groups.len()).map(|k| manifest.folder_name(k)).collect();
+    let resumed = if args.resume {
+        let done = resume_in_place(groups, dst, &folder_names, args.processing, &in_place)?;
+        let n = done.len();
+        in_place.extend(done);
+        Some(n)
+    } else {
+        None
+    };
+    let stored = match store {
+        Some(store) => {
+            let names = store_names(groups, args.processing, args.jobs)?;
+            let linked = link_stored(groups, dst, &folder_names, store, &names)?;
+            in_place.extend(linked.iter().cloned());
+            Some((store, names, linked.len()))
+        }
+        None => None,
+    };
+    let copied = copy_groups(groups, dst, &folder_names, args.processing, args.jobs, &in_place, window)?;
+    if let Some((store, names, _)) = &stored {
+        move_into_store(groups, dst, &folder_names, store, names, &copied)?;
+    }
+    let left = manifest.files.len() - in_place.len() - copied.len();
+    if left == 0 {
+        manifest.save(&dst.join(manifest::FILE_NAME))?;
+    } else {
+        let done = |e: &&manifest::Entry| {
+            let key = (e.folder, e.name.clone());
+            in_place.contains(&key) || copied.contains(&key)
+        };
+        let files = manifest.files.iter().filter(done).cloned().collect();
+        manifest::Manifest { files, ..manifest.clone() }.save(&dst.join(manifest::FILE_NAME))?;
+    }
+    if let Err(e) = fs::remove_file(&plan) {
+        eprintln!("warning: cannot remove {}: {e}", plan.display());
+    }
+    Ok(Written {
+        synced: previous.map(|_| (in_place.len() - resumed.unwrap_or(0), removed)),
+        left,
+        from_store: stored.map(|(_, _, linked)| linked),
+        resumed,
+    })
+}
+
+/// `--resume`: the planned files (not already `in_place`) that the
+/// interrupted run wrote in full, i.e. with their sidecars at the planned
+/// sizes. Processing that changes the size by an amount only known once it
+/// runs (resizing, optimising, reframing, flattening) leaves nothing to
+/// check against, so those files are written again. Whatever is written
+/// again is removed first.
+fn resume_in_place(
+    groups: &[Vec<FileInfo>],
+    dst_root: &Path,
+    folder_names: &[String],
+    processing: Processing,
+    in_place: &HashSet<(usize, String)>,
+) -> Result<HashSet<(usize, String)>, String> {
+    let reworked = processing.resize.is_some()
+        || processing.jpeg.optimize
+        || matches!(processing.panoramas, Panoramas::CropCenter | Panoramas::Letterbox);
+    let has_size = |path: &Path, size: u64| fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() == size);
+    let mut done = HashSet::new();
+    for (idx, group) in groups.iter().enumerate() {
+        let folder = dst_root.join(&folder_names[idx]);
+        for f in group.iter().filter(|f| !in_place.contains(&(idx + 1, f.name.clone()))) {
+            let written: Vec<(PathBuf, u64)> = std::iter::once((folder.join(&f.name), f.size))
+                .chain(f.sidecars.iter().map(|(p, size)| (folder.join(sidecar_dest_name(f, p)), *size)))
+                .collect();
+            let reworked = f.flatten || (reworked && f.format == Some(ImageFormat::Jpeg));
+            if !reworked && written.iter().all(|(path, size)| has_size(path, *size)) {
+                done.insert((idx + 1, f.name.clone()));
+                continue;
+            }
+            for (path, _) in &written {
+                match fs::remove_file(path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(format!("cannot remove {}: {e}", path.display())),
+                }
+            }
+        }
+    }
+    Ok(done)
+}
+
+/// Folder inside the destination root that `--dst-layout cas` keeps the
+/// files in; hidden, so a scan of the destination passes it by.
+const STORE_DIR: &str = ".store";
+
+/// `--dst-layout cas`: the name each planned file has in the store, made
+/// from a hash of its source content and of how it is processed, since a
+/// photo resized differently is a different file. Names are spread over
+/// 256 subfolders by their first two digits.
+fn store_names(groups: &[Vec<FileInfo>], processing: Processing, jobs: usize) -> Result<Vec<Vec<String>>, String> {
+    let files: Vec<&FileInfo> = groups.iter().flatten().collect();
+    let how = format!("{processing:?}");
+    let hashes = parallel_map(&files, jobs, |f| dupes::content_hash(&f.path));
+    let mut names = hashes.into_iter().zip(&files).map(|(hash, f)| {
+        let hash = dupes::fnv1a(hash?, format!("{how}{}", f.flatten).as_bytes());
+        let ext = Path::new(&f.name).extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        Ok(format!("{:02x}/{hash:016x}{ext}", hash >> 56))
+    });
+    groups.iter().map(|g| names.by_ref().take(g.len()).collect()).collect()
+}
+
+/// Links the planned files whose content the store already holds, copying
+/// their sidecars next to the links. Returns them as (folder number, name).
+fn link_stored(
+    groups: &[Vec<FileInfo>],
+    dst_root: &Path,
+    folder_names: &[String],
+    store: &Path,
+    names: &[Vec<String>],
+) -> Result<HashSet<(usize, String)>, String> {
+    let mut linked = HashSet::new();
+    for (idx, (group, names)) in groups.iter().zip(names).enumerate() {
+        let folder = dst_root.join(&folder_names[idx]);
+        for (f, stored) in group.iter().zip(names) {
+            if !store.join(stored).is_file() {
+                continue;
+            }
+            let link = folder.join(&f.name);
+            if let Some(dir) = link.parent() {
+                fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+            }
+            link_to_store(&link, store, stored)?;
+            for (sidecar, _) in &f.sidecars {
+                let dest = folder.join(sidecar_dest_name(f, sidecar));
+                fs::copy(sidecar, &dest).map_err(|e| {
+                    format!("failed to copy {} -> {}: {e}", sidecar.display(), dest.display())
+                })?;
+            }
+            linked.insert((idx + 1, f.name.clone()));
+        }
+    }
+    Ok(linked)
+}
+
+/// Moves the files this run copied into the store and links them back;
+/// a file whose content another one stored meanwhile is just deleted.
+fn move_into_store(
+    groups: &[Vec<FileInfo>],
+    dst_root: &Path,
+    folder_names: &[String],
+    store: &Path,
+    names: &[Vec<String>],
+    copied: &HashSet<(usize, String)>,
+) -> Result<(), String> {
+    for (idx, (group, names)) in groups.iter().zip(names).enumerate() {
+        for (f, stored) in group.iter().zip(names) {
+            if !copied.contains(&(idx + 1, f.name.clone())) {
+                continue;
+            }
+            let file = dst_root.join(&folder_names[idx]).join(&f.name);
+            let target = store.join(stored);
+            if target.is_file() {
+                fs::remove_file(&file).map_err(|e| format!("cannot delete {}: {e}", file.display()))?;
+            } else {
+                if let Some(dir) = target.parent() {
+                    fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+                }
+                fs::rename(&file, &target)
+                    .map_err(|e| format!("cannot move {} to {}: {e}", file.display(), target.display()))?;
+            }
+            link_to_store(&file, store, stored)?;
+        }
+    }
+    Ok(())
+}
+
+/// Points `link` at `stored` inside `store` with a relative symlink, so the
+/// destination still works mounted somewhere else.
+fn link_to_store(link: &Path, store: &Path, stored: &str) -> Result<(), String> {
+    let root = store.parent().unwrap_or(store);
+    let depth = link.parent().and_then(|dir| dir.strip_prefix(root).ok()).map_or(0, |rel| rel.components().count());
+    let mut target: PathBuf = std::iter::repeat_n("..", depth).collect();
+    target.push(STORE_DIR);
+    target.push(stored);
+    platform::symlink_file(&target, link)
+}
+
+/// `--keep-runs` with `--dst-layout cas`: deletes the stored files that no
+/// symlink below `root` points at any more. Returns how many were deleted.
+fn prune_store(root: &Path, use_trash: bool) -> Result<usize, String> {
+    let store = root.join(STORE_DIR);
+    if !store.is_dir() {
+        return Ok(0);
+    }
+    let mut linked = HashSet::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let rd = fs::read_dir(&dir).map_err(|e| format!("cannot list {}: {e}", dir.display()))?;
+        for entry in rd {
+            let entry = entry.map_err(|e| format!("error reading directory entry: {e}"))?;
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            if kind.is_dir() && entry.path() != store {
+                pending.push(entry.path());
+            } else if kind.is_symlink() {
+                if let Some(name) = fs::read_link(entry.path()).ok().and_then(|t| t.file_name().map(|n| n.to_owned())) {
+                    linked.insert(name);
+                }
+            }
+        }
+    }
+    let mut deleted = 0;
+    for sub in fs::read_dir(&store).map_err(|e| format!("cannot list {}: {e}", store.display()))? {
+        let sub = sub.map_err(|e| format!("error reading directory entry: {e}"))?.path();
+        let Ok(rd) = fs::read_dir(&sub) else { continue };
+        for file in rd {
+            let file = file.map_err(|e| format!("error reading directory entry: {e}"))?;
+            if linked.contains(&file.file_name()) {
+                continue;
+            }
+            if use_trash {
+                trash::trash(&file.path())?;
+            } else {
+                fs::remove_file(file.path()).map_err(|e| format!("cannot delete {}: {e}", file.path().display()))?;
+            }
+            deleted += 1;
+        }
+        let _ = fs::remove_dir(&sub);
+    }
+    Ok(deleted)
+}
+
+/// `--sync`: deletes whatever the previous run wrote that `new` doesn't keep
+/// at the same place, then returns the files left in place as (folder number,
+/// name) along with how many were deleted. A source modified since the last
+/// run is copied again even if its size didn't change. Nothing stays when
+/// the runs processed photos differently (`--resize`, `--jpeg`...), since the
+/// old outputs would no longer be what the new run makes. Folders the new
+/// plan no longer uses (or names differently) are removed once empty. With
+/// `use_trash`, deleted files go to the trash.
+fn sync_destination(
+    dst_root: &Path,
+    old: &manifest::Manifest,
+    new: &manifest::Manifest,
+    use_trash: bool,
+) -> Result<(HashSet<(usize, String)>, usize), String> {
+    // Manifests from before the fingerprint was recorded are taken as plain copies.
+    let plain = Processing::default().fingerprint(false);
+    let reprocessed = old.processing.as_ref().unwrap_or(&plain) != new.processing.as_ref().unwrap_or(&plain);
+    let planned: HashMap<(usize, &str), &manifest::Entry> = if reprocessed {
+        HashMap::new()
+    } else {
+        new.files.iter().map(|e| ((e.folder, e.name.as_str()), e)).collect()
+    };
+    // The same source, unchanged since; an entry from before modification
+    // times were recorded can only be matched on the rest.
+    let unchanged = |e: &manifest::Entry| {
+        planned.get(&(e.folder, e.name.as_str())).is_some_and(|n| match e.modified {
+            Some(_) => *n == e,
+            None => n.placement() == *e,
+        })
+    };
+    let mut in_place = HashSet::new();
+    let mut removed = 0;
+    for e in &old.files {
+        let folder = dst_root.join(old.folder_name(e.folder));
+        let same_folder = old.folder_name(e.folder) == new.folder_name(e.folder);
+        if unchanged(e) && same_folder && folder.join(&e.name).is_file() {
+            in_place.insert((e.folder, e.name.clone()));
+            continue;
+        }
+        for name in std::iter::once(&e.name).chain(&e.sidecars) {
+            if use_trash {
+                if fs::symlink_metadata(folder.join(name)).is_ok() {
+                    trash::trash(&folder.join(name))?;
+                }
+                continue;
+            }
+            match fs::remove_file(folder.join(name)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(format!("cannot remove {}: {err}", folder.join(name).display())),
+            }
+        }
+        // `--preserve-structure` subfolders go once empty.
+        let mut dirs = Path::new(&e.name).parent();
+        while let Some(dir) = dirs.filter(|d| !d.as_os_str().is_empty()) {
+            if fs::remove_dir(folder.join(dir)).is_err() {
+                break;
+            }
+            dirs = dir.parent();
+        }
+        removed += 1;
+    }
+    let kept: HashSet<String> = (1..=new.folders()).map(|k| new.folder_name(k)).collect();
+    for name in (1..=old.folders()).map(|k| old.folder_name(k)) {
+        // Left alone if the user put something else in there.
+        if !kept.contains(&name) {
+            let _ = fs::remove_dir(dst_root.join(name));
+        }
+    }
+    Ok((in_place, removed))
+}
+
+/// `f` applied to every item on `jobs` threads; results keep the input order.
+fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let chunk = items.len().div_ceil(jobs.max(1)).max(1);
+    thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = items
+            .chunks(chunk)
+            .map(|part| scope.spawn(move || part.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().expect("worker thread panicked")).collect()
+    })
+}
+
+fn write_output(f: &FileInfo, folder: &Path, output: Output) -> Result<(), String> {
+    let dest = folder.join(&f.name);
+    if let Some(dir) = dest.parent().filter(|_| f.name.contains('/')) {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {e}", dir.display()))?;
+    }
+    if dest.exists() {
+        return Err(format!(
+            "unexpected destination file already exists: {}",
+            dest.display()
+        ));
+    }
+    match output {
+        Output::Bytes(bytes) => {
+            fs::write(&dest, bytes).map_err(|e| format!("cannot write {}: {e}", dest.display()))?;
+        }
+        Output::Copy => {
+            fs::copy(&f.path, &dest)
+                .map_err(|e| format!("failed to copy {} -> {}: {e}", f.path.display(), dest.display()))?;
+        }
+    }
+
+    for (sidecar, _) in &f.sidecars {
+        let dest = folder.join(sidecar_dest_name(f, sidecar));
+        if dest.exists() {
+            return Err(format!(
+                "unexpected destination file already exists: {}",
+                dest.display()
+            ));
+        }
+        fs::copy(sidecar, &dest).map_err(|e| {
+            format!("failed to copy {} -> {}: {e}", sidecar.display(), dest.display())
+        })?;
+    }
+    Ok(())
+}
+
+/// One destination in the `--output json` summary: what each folder got and
+/// where the manifest is.
+fn destination_json(dst: &Path, groups: &[Vec<FileInfo>], manifest: &manifest::Manifest, done: &Written) -> Value {
+    let totals = |files: &mut dyn Iterator<Item = &FileInfo>| {
+        let (mut photos, mut videos, mut bytes) = (0usize, 0usize, 0u64);
+        for f in files {
+            match f.kind {
+                MediaKind::Photo => photos += 1,
+                MediaKind::Video => videos += 1,
+            }
+            bytes += f.footprint();
+        }
+        vec![
+            ("photos".to_string(), Value::from(photos)),
+            ("videos".to_string(), Value::from(videos)),
+            ("bytes".to_string(), Value::from(bytes)),
+        ]
+    };
+    let folders = groups
+        .iter()
+        .enumerate()
+        .map(|(idx, group)| {
+            let mut fields = vec![("name".to_string(), Value::from(manifest.folder_name(idx + 1)))];
+            fields.extend(totals(&mut group.iter()));
+            Value::Object(fields)
+        })
+        .collect();
+    let mut fields = vec![
+        ("path".to_string(), path_json(dst)),
+        ("manifest".to_string(), path_json(&dst.join(manifest::FILE_NAME))),
+        ("plan".to_string(), Value::from(manifest.plan_hash())),
+    ];
+    fields.extend(totals(&mut groups.iter().flatten()));
+    fields.push(("folders".to_string(), Value::Array(folders)));
+    fields.push(("not_copied".to_string(), Value::from(done.left)));
+    if let Some((in_place, removed)) = done.synced {
+        fields.push(("in_place".to_string(), Value::from(in_place)));
+        fields.push(("removed".to_string(), Value::from(removed)));
+    }
+    if let Some(linked) = done.from_store {
+        fields.push(("from_store".to_string(), Value::from(linked)));
+    }
+    if let Some(resumed) = done.resumed {
+        fields.push(("resumed".to_string(), Value::from(resumed)));
+    }
+    Value::Object(fields)
+}
+
+fn path_json(path: &Path) -> Value {
+    Value::from(path.to_string_lossy().into_owned())
+}
+
+fn summary_lines(groups: &[Vec<FileInfo>], dst_root: &Path) -> [String; 2] {
+    let all = || groups.iter().flat_map(|g| g.iter());
+    let total_videos = all().filter(|f| f.kind == MediaKind::Video).count();
+    let total_files = all().count() - total_videos;
+    let total_bytes: u64 = all().map(|f| f.footprint()).sum();
+
+    let copied = if total_videos > 0 {
+        format!(
+            "Copied {total_files} photos and {total_videos} videos into {} folders under {}",
+            groups.len(),
+            dst_root.display()
+        )
+    } else {
+        format!("Copied {total_files} photos into {} folders under {}", groups.len(), dst_root.display())
+    };
+    [copied, format!("Total bytes copied: {total_bytes}")]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fi(name: &str, size: u64) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from(name),
+            name: name.to_string(),
+            size,
+            kind: MediaKind::Photo,
+            format: Some(ImageFormat::Jpeg),
+            album: String::new(),
+            exif: exif::Exif::default(),
+            xmp: xmp::Xmp::default(),
+            sidecars: Vec::new(),
+            flatten: false,
+        }
+    }
+
+    fn fg(name: &str, gps: Option<(f64, f64)>) -> FileInfo {
+        FileInfo {
+            exif: exif::Exif {
+                gps,
+                ..Default::default()
+            },
+            ..fi(name, 1)
+        }
+    }
+
+    fn fa(name: &str, size: u64, album: &str) -> FileInfo {
+        FileInfo {
+            album: album.to_string(),
+            ..fi(name, size)
+        }
+    }
+
+    #[test]
+    fn plan_groups_respects_max_files() {
+        let files = vec![fi("a.jpg", 1), fi("b.jpg", 1), fi("c.jpg", 1)];
+        let groups = plan_groups(&files, 2, 10, LimitMode::FirstHit).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[1].len(), 1);
+    }
+
+    #[test]
+    fn plan_groups_respects_max_bytes() {
+        let files = vec![fi("a.jpg", 6), fi("b.jpg", 6)];
+        let groups = plan_groups(&files, 1200, 10, LimitMode::FirstHit).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0][0].name, "a.jpg");
+        assert_eq!(groups[1][0].name, "b.jpg");
+    }
+
+    #[test]
+    fn plan_groups_combines_until_limit() {
+        let files = vec![fi("a.jpg", 6), fi("b.jpg", 4), fi("c.jpg", 1)];
+        let groups = plan_groups(&files, 1200, 10, LimitMode::FirstHit).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].len(), 2); // 6 + 4 = 10
+        assert_eq!(groups[1].len(), 1);
+    }
+
+    #[test]
+    fn limit_mode_picks_the_limits_that_split_folders() {
+        let files = vec![fi("a.jpg", 6), fi("b.jpg", 6), fi("c.jpg", 6), fi("d.jpg", 1)];
+        let sizes = |mode| -> Vec<usize> { plan_groups(&files, 2, 10, mode).unwrap().iter().map(Vec::len).collect() };
+        assert_eq!(sizes(LimitMode::FirstHit), vec![1, 1, 2]);
+        // Over the bytes but not the count, then over both.
+        assert_eq!(sizes(LimitMode::Both), vec![2, 2]);
+
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--limit-mode", "files-only", "--max-files", "50"])).unwrap();
+        assert_eq!((args.max_files, args.max_bytes), (50, u64::MAX));
+        let args = parse_args(argv(&["x", "--limit-mode", "bytes-only", "--preset", "pix-star"])).unwrap();
+        assert_eq!(args.max_files, usize::MAX);
+        assert!(parse_args(argv(&["x", "--limit-mode", "files-only", "--max-bytes", "9"])).is_err());
+    }
+
+    #[test]
     fn plan_groups_errors_if_single_file_too_large() {
         let files = vec![fi("big.jpg", 11)];
-        let err = plan_groups(&files, 1200, 10).unwrap_err();
+        let err = plan_groups(&files, 1200, 10, LimitMode::FirstHit).unwrap_err();
         assert!(err.contains("larger than max-bytes"));
     }
+
+    #[test]
+    fn preflight_lists_every_photo_too_big_for_a_folder_largest_first() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--dst", "/nonexistent/frame", "--max-bytes", "10"])).unwrap();
+        let video = FileInfo { kind: MediaKind::Video, format: None, ..fi("clip.mp4", 50) };
+        let files = vec![fi("big.jpg", 11), fi("ok.jpg", 10), fi("huge.jpg", 30), video];
+        let err = preflight(&files, &args).unwrap_err();
+        assert!(err.starts_with("2 files can't be placed in any folder"), "{err}");
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines[1], "  huge.jpg (30 bytes): over --max-bytes (10)");
+        assert_eq!(lines[2], "  big.jpg (11 bytes): over --max-bytes (10)");
+
+        let args = parse_args(argv(&["x", "--dst", "/nonexistent/frame", "--max-bytes", "10", "--limit-mode", "both"])).unwrap();
+        assert!(preflight(&files, &args).is_ok());
+    }
+
+    #[test]
+    fn plan_album_groups_never_mixes_albums() {
+        let files = vec![
+            fa("a.jpg", 1, "trip"),
+            fa("b.jpg", 1, "home"),
+            fa("c.jpg", 1, "trip"),
+            fa("d.jpg", 1, "trip"),
+        ];
+        let groups = plan_album_groups(&files, 2, 10, LimitMode::FirstHit).unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0][0].name, "b.jpg"); // "home" sorts first
+        assert_eq!(groups[1].len(), 2);
+        assert_eq!(groups[1][0].name, "a.jpg");
+        assert_eq!(groups[2][0].name, "d.jpg");
+    }
+
+    #[test]
+    fn album_of_is_relative_to_source() {
+        let src = Path::new("/photos");
+        assert_eq!(album_of(src, Path::new("/photos/a.jpg")).unwrap(), "");
+        assert_eq!(album_of(src, Path::new("/photos/2023/june/a.jpg")).unwrap(), "2023/june");
+        // Decomposed (macOS) folder names come out precomposed.
+        assert_eq!(
+            album_of(src, Path::new("/photos/Cafe\u{301}/a.jpg")).unwrap(),
+            "Caf\u{e9}"
+        );
+    }
+
+    #[test]
+    fn library_bundles_are_recognised() {
+        assert!(is_library_bundle("Photos Library.photoslibrary"));
+        assert!(is_library_bundle("iPhoto Library.PhotoLibrary"));
+        assert!(!is_library_bundle("photoslibrary exports"));
+    }
+
+    #[test]
+    fn plan_location_groups_clusters_nearby_photos() {
+        let home = Some((47.60, -122.33));
+        let near_home = Some((47.61, -122.30));
+        let italy = Some((41.90, 12.50));
+        let files = vec![
+            fg("a.jpg", home),
+            fg("b.jpg", italy),
+            fg("c.jpg", None),
+            fg("d.jpg", near_home),
+        ];
+        let groups = plan_location_groups(&files, 25.0, 1200, 10, LimitMode::FirstHit).unwrap();
+        let names: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|g| g.iter().map(|f| f.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["a.jpg", "d.jpg"], vec!["b.jpg"], vec!["c.jpg"]]);
+    }
+
+    #[test]
+    fn parse_args_accepts_optional_location_radius() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--group-by", "location", "5"])).unwrap();
+        assert_eq!(args.group_by, GroupBy::Location { radius_km: 5.0 });
+        let args = parse_args(argv(&["x", "--group-by", "location", "--seed", "1"])).unwrap();
+        assert_eq!(
+            args.group_by,
+            GroupBy::Location {
+                radius_km: DEFAULT_LOCATION_RADIUS_KM
+            }
+        );
+        assert_eq!(args.seed, 1);
+    }
+
+    #[test]
+    fn order_chronological_puts_undated_last() {
+        let dated = |name: &str, ts: &str| FileInfo {
+            exif: exif::Exif {
+                captured: exif::DateTime::parse(ts),
+                ..Default::default()
+            },
+            ..fi(name, 1)
+        };
+        let mut files = vec![
+            fi("undated.jpg", 1),
+            dated("new.jpg", "2024:01:01 00:00:00"),
+            dated("old.jpg", "2019:07:04 12:00:00"),
+        ];
+        order_files(&mut files, Order::Chronological, 0, None);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["old.jpg", "new.jpg", "undated.jpg"]);
+
+        order_files(&mut files, Order::NewestFirst, 0, None);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["new.jpg", "old.jpg", "undated.jpg"]);
+
+        order_files(&mut files, Order::Name, 0, None);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["new.jpg", "old.jpg", "undated.jpg"]);
+
+        // 08:00 in Tokyo is 23:00 UTC the day before, so it comes before
+        // 20:00 that evening in Los Angeles (03:00 UTC); a photo without an
+        // offset taken at 01:00 falls between with --assume-timezone +01:00.
+        let zoned = |name: &str, ts: &str, offset| {
+            let mut f = dated(name, ts);
+            f.exif.offset = offset;
+            f
+        };
+        let mut files = vec![
+            zoned("la.jpg", "2024:05:01 20:00:00", Some(-420)),
+            zoned("tokyo.jpg", "2024:05:02 08:00:00", Some(540)),
+            zoned("lisbon.jpg", "2024:05:02 01:00:00", None),
+        ];
+        order_files(&mut files, Order::Chronological, 0, Some(60));
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["tokyo.jpg", "lisbon.jpg", "la.jpg"]);
+        // In Hawaii, 01:00 is 11:00 UTC.
+        order_files(&mut files, Order::Chronological, 0, Some(-600));
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["tokyo.jpg", "la.jpg", "lisbon.jpg"]);
+    }
+
+    #[test]
+    fn stratify_spreads_each_album_across_groups() {
+        let mut files = Vec::new();
+        for k in 0..4 {
+            files.push(fa(&format!("big{k}.jpg"), 1, "big"));
+        }
+        files.push(fa("s0.jpg", 1, "small"));
+        files.push(fa("s1.jpg", 1, "small"));
+
+        let files = stratify(files, Stratum::Album);
+        let groups = plan_groups(&files, 3, 100, LimitMode::FirstHit).unwrap();
+        for g in &groups {
+            assert_eq!(g.iter().filter(|f| f.album == "big").count(), 2);
+            assert_eq!(g.iter().filter(|f| f.album == "small").count(), 1);
+        }
+        // Order within a stratum is preserved.
+        let big: Vec<&str> = files
+            .iter()
+            .filter(|f| f.album == "big")
+            .map(|f| f.name.as_str())
+            .collect();
+        assert_eq!(big, vec!["big0.jpg", "big1.jpg", "big2.jpg", "big3.jpg"]);
+    }
+
+    #[test]
+    fn people_filter_requires_any_and_excludes_all() {
+        let tagged = |name: &str, people: &[&str]| FileInfo {
+            xmp: xmp::Xmp {
+                people: people.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            },
+            ..fi(name, 1)
+        };
+        let both = tagged("both.jpg", &["Alice", "Ex"]);
+        let alice = tagged("alice.jpg", &["alice"]);
+        let nobody = tagged("nobody.jpg", &[]);
+        let require = vec!["Alice".to_string()];
+        let exclude = vec!["Ex".to_string()];
+
+        assert!(people_filter_allows(&alice, &require, &exclude));
+        assert!(!people_filter_allows(&both, &require, &exclude));
+        assert!(!people_filter_allows(&nobody, &require, &exclude));
+        assert!(people_filter_allows(&nobody, &[], &exclude));
+    }
+
+    #[test]
+    fn sprinkle_videos_respects_per_folder_budgets() {
+        let video = |name: &str, size: u64| FileInfo {
+            kind: MediaKind::Video,
+            ..fi(name, size)
+        };
+        let mut groups = vec![vec![fi("a.jpg", 1)], vec![fi("b.jpg", 1)]];
+        let videos = vec![video("1.mp4", 5), video("2.mp4", 5), video("3.mp4", 5), video("big.mp4", 50)];
+        let left_out = sprinkle_videos(&mut groups, videos, 1, 10);
+        assert_eq!(left_out, 2);
+        assert_eq!(groups[0][1].name, "1.mp4");
+        assert_eq!(groups[1][1].name, "2.mp4");
+    }
+
+    #[test]
+    fn plan_groups_counts_sidecar_bytes() {
+        let with_sidecar = FileInfo {
+            sidecars: vec![(PathBuf::from("a.jpg.xmp"), 3)],
+            ..fi("a.jpg", 6)
+        };
+        let files = vec![with_sidecar, fi("b.jpg", 2)];
+        let groups = plan_groups(&files, 1200, 10, LimitMode::FirstHit).unwrap();
+        assert_eq!(groups.len(), 2); // 6 + 3 + 2 > 10
+    }
+
+    #[test]
+    fn sniff_bytes_recognises_jpeg_and_video_containers() {
+        let jpg = &DEFAULT_FORMATS[..];
+        let photo = |format| Some((MediaKind::Photo, Some(format)));
+        assert_eq!(sniff_bytes(&[0xFF, 0xD8, 0xFF, 0xE1], jpg, false), photo(ImageFormat::Jpeg));
+        assert_eq!(sniff_bytes(b"\x89PNG\r\n\x1a\n", jpg, false), None);
+        let mp4 = b"\0\0\0\x18ftypisom";
+        assert_eq!(sniff_bytes(mp4, jpg, true), Some((MediaKind::Video, None)));
+        assert_eq!(sniff_bytes(mp4, jpg, false), None);
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypheic", jpg, true), None);
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypqt  ", jpg, true), Some((MediaKind::Video, None)));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftyp3gp5", jpg, true), Some((MediaKind::Video, None)));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypM4A ", jpg, true), None);
+
+        let all = [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::Heic, ImageFormat::Webp];
+        assert_eq!(sniff_bytes(b"\x89PNG\r\n\x1a\n", &all, false), photo(ImageFormat::Png));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypheic", &all, true), photo(ImageFormat::Heic));
+        assert_eq!(sniff_bytes(b"RIFF\x10\0\0\0WEBPVP8 ", &all, false), photo(ImageFormat::Webp));
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypavif", &all, true), None);
+    }
+
+    #[test]
+    fn formats_pick_which_photos_a_scan_finds() {
+        let src = env::temp_dir().join(format!("image-rando-formats-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.jpg"), [0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        fs::write(src.join("b.PNG"), b"\x89PNG\r\n\x1a\n").unwrap();
+        fs::write(src.join("c.heif"), b"\0\0\0\x18ftypheic").unwrap();
+        fs::write(src.join("d.webp.bak"), b"RIFF\x10\0\0\0WEBPVP8 ").unwrap();
+
+        let found = |opts: &ScanOptions| -> Vec<(String, Option<ImageFormat>)> {
+            collect_jpgs(&src, opts).unwrap().into_iter().map(|f| (f.name, f.format)).collect()
+        };
+        let mut opts = ScanOptions::default();
+        assert_eq!(found(&opts), vec![("a.jpg".to_string(), Some(ImageFormat::Jpeg))]);
+
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        opts = parse_args(argv(&["x", "--formats", "png,heic,webp"])).unwrap().scan;
+        assert_eq!(
+            found(&opts),
+            vec![("b.PNG".to_string(), Some(ImageFormat::Png)), ("c.heif".to_string(), Some(ImageFormat::Heic))]
+        );
+        // By content, a misnamed file gets the extension of what it holds.
+        opts.detect_by = DetectBy::Magic;
+        assert_eq!(found(&opts).last(), Some(&("d.webp.webp".to_string(), Some(ImageFormat::Webp))));
+
+        assert!(parse_args(argv(&["x", "--formats", "jpg,gif"])).is_err());
+        assert!(parse_args(argv(&["x", "--formats", ","])).is_err());
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn with_extension_fixes_odd_names() {
+        assert_eq!(with_extension("IMG_1.JPG~", "jpg"), "IMG_1.jpg");
+        assert_eq!(with_extension("DSC0001", "jpg"), "DSC0001.jpg");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_follows_symlinks_without_looping() {
+        let root = env::temp_dir().join(format!("image-rando-symlinks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let real = root.join("real");
+        let src = root.join("src");
+        fs::create_dir_all(&real).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        fs::write(real.join("a.jpg"), b"a").unwrap();
+        std::os::unix::fs::symlink(real.join("a.jpg"), src.join("link.jpg")).unwrap();
+        std::os::unix::fs::symlink(&real, src.join("best-of")).unwrap();
+        std::os::unix::fs::symlink(&src, src.join("loop")).unwrap();
+
+        let mut opts = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert!(collect_jpgs(&src, &opts).unwrap().is_empty());
+
+        opts.follow_symlinks = true;
+        let mut found: Vec<String> = collect_jpgs(&src, &opts)
+            .unwrap()
+            .into_iter()
+            .map(|f| format!("{}/{}", f.album, f.name))
+            .collect();
+        found.sort();
+        assert_eq!(found, vec!["/link.jpg", "best-of/a.jpg"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn scan_progress_counts_what_the_walkers_listed() {
+        let src = env::temp_dir().join(format!("image-rando-scan-progress-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("a/b")).unwrap();
+        for name in ["1.jpg", "a/2.jpg", "a/b/3.jpg", "a/b/4.jpg"] {
+            fs::write(src.join(name), b"j").unwrap();
+        }
+        let opts = ScanOptions { recursive: true, ..Default::default() };
+        for threads in [1, 3] {
+            let progress = ScanProgress::default();
+            let found = if threads == 1 { walk(&src, &opts, &progress) } else { walk_parallel(&src, &opts, threads, &progress) };
+            assert_eq!(found.unwrap().0.len(), 4);
+            assert_eq!(progress.counts(), "3 folders, 4 files");
+        }
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn source_snapshot_sees_files_that_came_and_went() {
+        let src = env::temp_dir().join(format!("image-rando-snapshot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("a")).unwrap();
+        for name in ["1.jpg", "2.jpg", "a/3.jpg", "a/4.jpg"] {
+            fs::write(src.join(name), b"j").unwrap();
+        }
+        // Well in the past, so the changes below can't share a timestamp tick.
+        for dir in [src.join("a"), src.clone()] {
+            fs::File::open(dir).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+        }
+        let opts = ScanOptions { recursive: true, ..Default::default() };
+        let (files, snapshot) = scan_source(&src, &opts).unwrap();
+        assert_eq!((files.len(), snapshot.folders.len()), (4, 2));
+        assert_eq!(snapshot.changes(&opts).unwrap(), SourceChanges::default());
+
+        fs::remove_file(src.join("a/3.jpg")).unwrap();
+        fs::create_dir_all(src.join("new/deeper")).unwrap();
+        for name in ["5.jpg", "new/6.jpg", "new/deeper/7.jpg", "notes.txt"] {
+            fs::write(src.join(name), b"j").unwrap();
+        }
+        assert_eq!(snapshot.changes(&opts).unwrap(), SourceChanges { folders: 2, added: 3, removed: 1 });
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn parallel_walk_finds_the_same_files_in_the_same_order() {
+        let src = env::temp_dir().join(format!("image-rando-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        for d in ["a/x", "a/y/z", "b", "c/d/e/f"] {
+            fs::create_dir_all(src.join(d)).unwrap();
+            for k in 0..3 {
+                fs::write(src.join(d).join(format!("{k}.jpg")), b"j").unwrap();
+            }
+        }
+        fs::write(src.join("top.jpg"), b"t").unwrap();
+
+        let mut opts = ScanOptions { recursive: true, ..Default::default() };
+        let paths = |opts: &ScanOptions| -> Vec<PathBuf> {
+            collect_jpgs(&src, opts).unwrap().into_iter().map(|f| f.path).collect()
+        };
+        let one = paths(&opts);
+        assert_eq!(one.len(), 13);
+        assert!(one.windows(2).all(|w| w[0] < w[1]));
+        opts.threads = 4;
+        assert_eq!(paths(&opts), one);
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn collect_skips_nomedia_folders_unless_told_otherwise() {
+        let src = env::temp_dir().join(format!("image-rando-nomedia-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("WhatsApp")).unwrap();
+        fs::write(src.join("WhatsApp/.nomedia"), b"").unwrap();
+        fs::write(src.join("WhatsApp/meme.jpg"), b"m").unwrap();
+        fs::write(src.join("keep.jpg"), b"k").unwrap();
+
+        let mut opts = ScanOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(collect_jpgs(&src, &opts).unwrap().len(), 1);
+        opts.ignore_nomedia = true;
+        assert_eq!(collect_jpgs(&src, &opts).unwrap().len(), 2);
+
+        opts.exclude_regex = vec![regex::Regex::new("(?i)^whatsapp/").unwrap()];
+        let found = collect_jpgs(&src, &opts).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "keep.jpg");
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn hidden_and_junk_names_are_recognised() {
+        for junk in ["._IMG_0001.jpg", ".DS_Store", "Thumbs.db", "THUMBS.DB", ".thumbnails"] {
+            assert!(is_hidden_or_junk(junk), "{junk}");
+        }
+        assert!(!is_hidden_or_junk("IMG_0001.jpg"));
+    }
+
+    #[test]
+    fn pinned_photos_lead_the_first_group() {
+        let files: Vec<FileInfo> = (0..6).map(|k| fi(&format!("{k}.jpg"), 1)).collect();
+        let pins = vec![PathBuf::from("4.jpg"), PathBuf::from("1.jpg"), PathBuf::from("missing.jpg")];
+        let (pinned, rest) = take_pinned(files, &pins);
+        let names: Vec<&str> = pinned.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["4.jpg", "1.jpg"]);
+        assert_eq!(rest.len(), 4);
+
+        let mut group: Vec<FileInfo> = rest.into_iter().chain(pinned).collect();
+        pins_first(&mut group, &pins);
+        let names: Vec<&str> = group.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["4.jpg", "1.jpg", "0.jpg", "2.jpg", "3.jpg", "5.jpg"]);
+    }
+
+    #[test]
+    fn required_files_bypass_scan_filters() {
+        let src = env::temp_dir().join(format!("image-rando-required-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("keep.jpg"), b"k").unwrap();
+        fs::write(src.join("screenshot.jpg"), b"s").unwrap();
+        fs::write(src.join("notes.txt"), b"n").unwrap();
+
+        let opts = ScanOptions {
+            exclude_regex: vec![regex::Regex::new("screenshot").unwrap()],
+            ..Default::default()
+        };
+        let mut files = collect_jpgs(&src, &opts).unwrap();
+        assert_eq!(files.len(), 1);
+        let list = src.join("must.txt");
+        fs::write(&list, "# family\nscreenshot.jpg\nkeep.jpg\nnotes.txt\ngone.jpg\n").unwrap();
+        let required = load_path_list(&list, &src).unwrap();
+        assert_eq!(required.len(), 4);
+        add_required_files(&mut files, &required, &src, &opts).unwrap();
+        let mut names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["keep.jpg", "screenshot.jpg"]);
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn block_list_appends_relative_entries_once() {
+        let src = env::temp_dir().join(format!("image-rando-block-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("2019")).unwrap();
+        fs::write(src.join("2019/bad.jpg"), b"b").unwrap();
+        fs::write(src.join("ok.jpg"), b"o").unwrap();
+        let list = src.join(DEFAULT_EXCLUDE_LIST);
+        fs::write(&list, "# never again").unwrap();
+
+        let photos = vec![src.join("2019/bad.jpg"), src.join("2019/bad.jpg")];
+        assert_eq!(append_to_block_list(&list, &src, &photos).unwrap(), 1);
+        assert_eq!(append_to_block_list(&list, &src, &photos).unwrap(), 0);
+        assert_eq!(fs::read_to_string(&list).unwrap(), "# never again\n2019/bad.jpg\n");
+        assert_eq!(load_path_list(&list, &src).unwrap(), vec![src.join("2019/bad.jpg")]);
+        fs::remove_dir_all(&src).unwrap();
+    }
+
+    #[test]
+    fn weighted_shuffle_favours_heavier_files() {
+        let mut favourite_first = 0;
+        for seed in 1..=400 {
+            let mut files = vec![fi("a.jpg", 1), fi("b.jpg", 1), fi("fav.jpg", 1), fi("c.jpg", 1)];
+            weighted_shuffle(&mut files, seed, |f| if f.name == "fav.jpg" { 5.0 } else { 1.0 });
+            assert_eq!(files.len(), 4);
+            if files[0].name == "fav.jpg" {
+                favourite_first += 1;
+            }
+        }
+        // Expected share is 5 / (5 + 3) = 62.5%, versus 25% unweighted.
+        assert!((200..300).contains(&favourite_first), "{favourite_first}");
+    }
+
+    #[test]
+    fn favorites_match_names_and_relative_paths() {
+        let favorites: HashSet<String> = ["img_0001.jpg", "trip/beach.jpg"].iter().map(|s| s.to_string()).collect();
+        let src = Path::new("/pics");
+        let mut f = fi("x", 1);
+        f.path = PathBuf::from("/pics/2020/IMG_0001.JPG");
+        assert!(is_favorite(&f, src, &favorites));
+        f.path = PathBuf::from("/pics/trip/beach.jpg");
+        assert!(is_favorite(&f, src, &favorites));
+        f.path = PathBuf::from("/pics/other/beach.jpg");
+        assert!(!is_favorite(&f, src, &favorites));
+
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_args(argv(&["x", "--favorites-weight", "2.5"])).unwrap().favorites_weight, 2.5);
+        for bad in ["0", "-1", "NaN", "inf"] {
+            assert!(parse_args(argv(&["x", "--favorites-weight", bad])).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn presets_yield_to_explicit_flags() {
+        let argv = |extra: &[&str]| {
+            let mut v = vec!["image-rando".to_string()];
+            v.extend(extra.iter().map(|s| s.to_string()));
+            v
+        };
+        let args = parse_args(argv(&["--preset", "pix-star", "--max-files", "50"])).unwrap();
+        assert_eq!(args.max_files, 50);
+        assert_eq!(args.max_bytes, 2 * 1024 * 1024 * 1024);
+        assert!(args.safe_names);
+        assert_eq!(
+            args.processing.resize,
+            cfg!(feature = "imaging").then_some(Resize {
+                width: 1024,
+                height: 768,
+                max_pixels: None,
+                fit: Fit::Inside,
+                filter: ResizeFilter::Lanczos3
+            })
+        );
+        assert!(parse_args(argv(&["--preset", "toaster"])).is_err());
+        assert!(parse_args(argv(&["--chroma", "4:2:0"])).is_err());
+        assert!(parse_args(argv(&["--jpeg", "progressive"])).is_err());
+        assert!(parse_args(argv(&["--optimize-jpeg", "--jpeg", "progressive"])).is_ok());
+        if cfg!(feature = "imaging") {
+            let args = parse_args(argv(&["--preset", "nixplay", "--jpeg", "progressive", "--chroma", "4:2:0"])).unwrap();
+            assert_eq!(args.processing.jpeg, JpegOptions { progressive: true, chroma: Chroma::Quarter, ..Default::default() });
+        }
+        if cfg!(feature = "imaging") {
+            let args = parse_args(argv(&["--max-megapixels", "12", "--chroma", "4:2:0"])).unwrap();
+            let resize = args.processing.resize.unwrap();
+            assert_eq!(resize.max_pixels, Some(12_000_000));
+            assert_eq!(resize.width, u32::MAX);
+            assert!(parse_args(argv(&["--max-megapixels", "12", "--fit", "crop"])).is_err());
+            assert!(parse_args(argv(&["--max-megapixels", "0"])).is_err());
+        }
+        assert_eq!(parse_dimensions("3840x2160"), Some((3840, 2160)));
+        assert_eq!(parse_dimensions("0x10"), None);
+    }
+
+    #[test]
+    fn copy_groups_writes_everything_from_parallel_workers() {
+        let root = env::temp_dir().join(format!("image-rando-jobs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        let mut groups = vec![Vec::new(), Vec::new()];
+        for k in 0..20 {
+            let path = root.join("src").join(format!("{k}.jpg"));
+            fs::write(&path, k.to_string()).unwrap();
+            let mut f = fi(&format!("{k}.jpg"), 1);
+            f.path = path;
+            groups[k % 2].push(f);
+        }
+        let names = ["1".to_string(), "2".to_string()];
+        copy_groups(&groups, &root.join("dst"), &names, Processing::default(), 3, &HashSet::new(), CopyWindow::default()).unwrap();
+        for k in 0..20 {
+            let copied = root.join("dst").join((k % 2 + 1).to_string()).join(format!("{k}.jpg"));
+            assert_eq!(fs::read_to_string(copied).unwrap(), k.to_string());
+        }
+        // A second run trips over the existing files instead of overwriting.
+        let err = copy_groups(&groups, &root.join("dst"), &names, Processing::default(), 3, &HashSet::new(), CopyWindow::default())
+            .unwrap_err();
+        assert!(err.starts_with("unexpected destination file already exists: "), "{err}");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn csv_report_has_one_row_per_photo() {
+        let mut a = fi("/pics/2019/IMG_1.jpg", 2048);
+        a.exif = exif::Exif {
+            gps: Some((1.0, 2.0)),
+            captured: exif::DateTime::parse("2019:07:04 09:05:30"),
+            orientation: Some(6),
+            dimensions: Some((4000, 3000)),
+            camera: Some("Canon EOS R5".to_string()),
+            rating: Some(2),
+            ..Default::default()
+        };
+        a.xmp.rating = Some(4);
+        let mut b = fi("/pics/b, \"c\".jpg", 10);
+        b.exif.rating = Some(3);
+        let mut out = Vec::new();
+        write_csv_report(&mut out, &[a, b], Path::new("/pics")).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "path,size,captured,camera,width,height,gps,rating\n\
+             2019/IMG_1.jpg,2048,2019-07-04 09:05:30,Canon EOS R5,3000,4000,yes,4\n\
+             \"b, \"\"c\"\".jpg\",10,,,,,no,3\n"
+        );
+    }
+
+    #[test]
+    fn duplicate_clusters_find_identical_files() {
+        let root = env::temp_dir().join(format!("image-rando-dupes-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut files = Vec::new();
+        for (name, body) in [("a.jpg", "same"), ("b.jpg", "diff"), ("c.jpg", "same"), ("d.jpg", "longer")] {
+            let path = root.join(name);
+            fs::write(&path, body).unwrap();
+            files.push(fi(path.to_str().unwrap(), body.len() as u64));
+        }
+        let clusters = duplicate_clusters(&files, None, 2).unwrap();
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].members, vec![0, 2]);
+        assert!(clusters[0].identical);
+        assert_eq!(clusters[0].reclaimable(&files), 4);
+        fs::remove_dir_all(&root).unwrap();
+
+        let mut argv: Vec<String> = ["x", "--dupes", "-r", "--max-distance", "3"].iter().map(|s| s.to_string()).collect();
+        assert!(take_switch(&mut argv, "--dupes"));
+        assert_eq!(take_flag_value(&mut argv, "--max-distance").unwrap().as_deref(), Some("3"));
+        assert_eq!(argv, vec!["x", "-r"]);
+    }
+
+    #[cfg(feature = "imaging")]
+    #[test]
+    fn spread_similar_swaps_look_alikes_apart() {
+        let root = env::temp_dir().join(format!("image-rando-similar-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        // Random 8x8-pixel blocks; "a2" is "a" a little brighter.
+        let write = |name: &str, seed: u64, lift: u8| {
+            let mut rng = XorShift64::new(seed);
+            let blocks: Vec<u8> = (0..72).map(|_| (rng.next_u64() % 200) as u8).collect();
+            let img = image::RgbImage::from_fn(72, 64, |x, y| {
+                let v = blocks[(y / 8 * 9 + x / 8) as usize] + lift;
+                image::Rgb([v, v, v])
+            });
+            let path = root.join(name);
+            img.save(&path).unwrap();
+            FileInfo { path, ..fi(name, 1) }
+        };
+        let (a, a2, b, c) = (write("a.jpg", 1, 0), write("a2.jpg", 1, 8), write("b.jpg", 2, 0), write("c.jpg", 3, 0));
+        let names = |groups: &[Vec<FileInfo>]| -> Vec<Vec<String>> {
+            groups.iter().map(|g| g.iter().map(|f| f.name.clone()).collect()).collect()
+        };
+        let mut groups = vec![vec![a.clone(), a2.clone()], vec![b.clone(), c.clone()]];
+        assert_eq!(spread_similar(&mut groups, 10, &[], 2), (1, 0));
+        assert_eq!(names(&groups), vec![vec!["a.jpg", "b.jpg"], vec!["a2.jpg", "c.jpg"]]);
+        let mut groups = vec![vec![a, a2.clone()], vec![b, c]];
+        assert_eq!(spread_similar(&mut groups, 10, &[a2.path], 2), (0, 1));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn audit_reports_the_worst_problem_per_file() {
+        let root = env::temp_dir().join(format!("image-rando-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = root.join(name);
+            fs::write(&path, data).unwrap();
+            fi(path.to_str().unwrap(), data.len() as u64)
+        };
+        // Smallest complete JPEG structure: SOI, a progressive SOF, a scan, EOI.
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xC2, 0, 11, 8, 0, 1, 0, 1, 1, 1, 0x11, 0];
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 8, 1, 1, 0, 0, 0, 0, 0x12, 0x34]);
+        let truncated = jpeg.clone();
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+        assert_eq!(audit_file(&write("empty.jpg", b"")), Some(AuditFinding::Empty));
+        assert!(matches!(audit_file(&write("cut.jpg", &truncated)), Some(AuditFinding::Corrupt(_))));
+        assert_eq!(audit_file(&write("ok.jpg", &jpeg)), Some(AuditFinding::NoDate));
+        assert!(matches!(audit_file(&fi(root.join("gone.jpg").to_str().unwrap(), 1)), Some(AuditFinding::Unreadable(_))));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn recorded_options_leave_out_destination_and_seed() {
+        let argv: Vec<String> = ["image-rando", "-r", "--dst", "/d", "--seed", "7", "--max-files", "5"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(recorded_options(&argv), vec!["-r", "--max-files", "5"]);
+    }
+
+    #[test]
+    fn seed_phrases_ignore_case_and_padding() {
+        assert_eq!(seed_from_phrase("Christmas-2024 "), seed_from_phrase("christmas-2024"));
+        assert_ne!(seed_from_phrase("christmas-2024"), seed_from_phrase("christmas-2023"));
+    }
+
+    fn entry(folder: usize, name: &str, source: &str) -> manifest::Entry {
+        manifest::Entry { folder, name: name.to_string(), source: source.to_string(), size: 1, sidecars: Vec::new(), modified: None }
+    }
+
+    fn manifest_of(files: Vec<manifest::Entry>) -> manifest::Manifest {
+        manifest::Manifest { seed: 1, created: String::new(), args: Vec::new(), input: None, labels: Vec::new(), album: None, processing: None, files }
+    }
+
+    #[test]
+    fn stable_reshuffle_keeps_photos_in_place_and_fills_the_gaps() {
+        let previous = manifest_of(vec![
+            entry(1, "x.jpg", "a.jpg"),
+            entry(1, "b.jpg", "b.jpg"),
+            entry(2, "c.jpg", "c.jpg"),
+            entry(2, "d.jpg", "d.jpg"),
+        ]);
+        // Shuffled order; e is new. Half of the four known photos stay.
+        let files: Vec<FileInfo> = ["e.jpg", "c.jpg", "a.jpg", "d.jpg", "b.jpg"].iter().map(|n| fi(n, 1)).collect();
+        let groups = plan_stable_groups(&files, &previous, 50, Path::new(""), 2, u64::MAX, LimitMode::FirstHit).unwrap();
+        let names: Vec<Vec<&str>> = groups.iter().map(|g| g.iter().map(|f| f.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["x.jpg", "e.jpg"], vec!["c.jpg", "d.jpg"], vec!["b.jpg"]]);
+
+        // A photo that changed size since counts as new.
+        let files = vec![fi("a.jpg", 7), fi("b.jpg", 1)];
+        let groups = plan_stable_groups(&files, &previous, 0, Path::new(""), 2, u64::MAX, LimitMode::FirstHit).unwrap();
+        let names: Vec<Vec<&str>> = groups.iter().map(|g| g.iter().map(|f| f.name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["b.jpg", "a.jpg"]]);
+    }
+
+    #[test]
+    fn sync_removes_only_what_the_new_plan_drops() {
+        let root = env::temp_dir().join(format!("image-rando-sync-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for name in ["1/a.jpg", "1/b.jpg", "2/c.jpg", "2/c.jpg.xmp"] {
+            fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let mut c = entry(2, "c.jpg", "c.jpg");
+        c.sidecars.push("c.jpg.xmp".to_string());
+        let old = manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(1, "b.jpg", "b.jpg"), c]);
+        let new = manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(1, "b.jpg", "c.jpg")]);
+        let (in_place, removed) = sync_destination(&root, &old, &new, false).unwrap();
+        assert_eq!(in_place, HashSet::from([(1, "a.jpg".to_string())]));
+        assert_eq!(removed, 2);
+        assert!(root.join("1/a.jpg").exists());
+        assert!(!root.join("1/b.jpg").exists());
+        assert!(!root.join("2").exists());
+
+        // Outputs made with other processing settings are all made again.
+        let stripped = Processing { strip_thumbnails: true, ..Processing::default() };
+        let again = manifest::Manifest { processing: Some(stripped.fingerprint(false)), ..new.clone() };
+        assert_eq!(sync_destination(&root, &new, &new, false).unwrap().0.len(), 1);
+        // With the same settings, processed outputs stay and aren't made again.
+        assert_eq!(sync_destination(&root, &again, &again, false).unwrap().0.len(), 1);
+        let (in_place, removed) = sync_destination(&root, &new, &again, false).unwrap();
+        assert_eq!((in_place.len(), removed), (0, 2));
+        assert!(!root.join("1/a.jpg").exists());
+        // Flattening animations changes outputs too; the fingerprint itself
+        // is pinned so an edit to `Processing` can't silently change it.
+        assert_ne!(Processing::default().fingerprint(true), Processing::default().fingerprint(false));
+        assert_eq!(Processing::default().fingerprint(false), "acda3671e05df2dd");
+
+        // A source edited since, even to the same size, is copied again.
+        fs::write(root.join("1/a.jpg"), "x").unwrap();
+        let at = |modified| manifest_of(vec![manifest::Entry { modified, ..entry(1, "a.jpg", "a.jpg") }]);
+        assert_eq!(sync_destination(&root, &at(Some(1)), &at(Some(1)), false).unwrap().0.len(), 1);
+        assert_eq!(sync_destination(&root, &at(None), &at(Some(1)), false).unwrap().0.len(), 1);
+        let (in_place, removed) = sync_destination(&root, &at(Some(1)), &at(Some(2)), false).unwrap();
+        assert_eq!((in_place.len(), removed), (0, 1));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sync_skips_new_copies_of_photos_still_in_the_destination() {
+        let root = env::temp_dir().join(format!("image-rando-copies-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for (name, content) in [("a.jpg", "x"), ("copy.jpg", "x"), ("other.jpg", "y"), ("b.jpg", "z"), ("b2.jpg", "z")] {
+            fs::write(root.join(name), content).unwrap();
+        }
+        let file = |name: &str| FileInfo { path: root.join(name), ..fi(name, 1) };
+        // b.jpg is no longer a candidate, so its copy is what keeps the photo.
+        let previous = manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(2, "b.jpg", "b.jpg")]);
+        let mut files: Vec<FileInfo> = ["a.jpg", "copy.jpg", "other.jpg", "b2.jpg"].iter().map(|n| file(n)).collect();
+        assert_eq!(skip_copies_in_dst(&mut files, &previous, &root, &[]), 1);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a.jpg", "other.jpg", "b2.jpg"]);
+        let mut files = vec![file("a.jpg"), file("copy.jpg")];
+        assert_eq!(skip_copies_in_dst(&mut files, &previous, &root, &[root.join("copy.jpg")]), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cas_stores_each_content_once_and_prunes_what_no_run_links() {
+        let root = env::temp_dir().join(format!("image-rando-cas-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        for (name, content) in [("a.jpg", "x"), ("b.jpg", "x"), ("c.jpg", "y")] {
+            fs::write(root.join("src").join(name), content).unwrap();
+        }
+        let file = |name: &str| FileInfo { path: root.join("src").join(name), ..fi(name, 1) };
+        let groups = vec![vec![file("a.jpg"), file("b.jpg")], vec![file("c.jpg")]];
+        let names = store_names(&groups, Processing::default(), 2).unwrap();
+        assert_eq!(names[0][0], names[0][1]);
+        assert_ne!(names[0][0], names[1][0]);
+
+        let (store, run) = (root.join("dst").join(STORE_DIR), root.join("dst/run"));
+        let folders = ["1".to_string(), "2".to_string()];
+        let copied = copy_groups(&groups, &run, &folders, Processing::default(), 1, &HashSet::new(), CopyWindow::default()).unwrap();
+        move_into_store(&groups, &run, &folders, &store, &names, &copied).unwrap();
+        assert_eq!(fs::read_to_string(run.join("1/b.jpg")).unwrap(), "x");
+        assert!(fs::symlink_metadata(run.join("1/a.jpg")).unwrap().file_type().is_symlink());
+        assert_eq!(link_stored(&groups, &root.join("dst/next"), &folders, &store, &names).unwrap().len(), 3);
+
+        fs::remove_dir_all(&run).unwrap();
+        fs::remove_file(root.join("dst/next/2/c.jpg")).unwrap();
+        assert_eq!(prune_store(&root.join("dst"), false).unwrap(), 1);
+        assert_eq!(fs::read_to_string(root.join("dst/next/1/a.jpg")).unwrap(), "x");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn distribute_deals_folders_round_robin() {
+        let groups: Vec<Vec<FileInfo>> = ["a", "b", "c", "d", "e"].iter().map(|n| vec![fi(n, 1)]).collect();
+        let plans = distribute_groups(groups, 2);
+        let names: Vec<Vec<&str>> =
+            plans.iter().map(|plan| plan.iter().map(|g| g[0].name.as_str()).collect()).collect();
+        assert_eq!(names, vec![vec!["a", "c", "e"], vec!["b", "d"]]);
+    }
+
+    #[test]
+    fn repeated_destinations_need_mirror() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--dst", "a", "--mirror", "--dst", "b"])).unwrap();
+        assert_eq!(args.destinations().collect::<Vec<_>>(), vec![Path::new("a"), Path::new("b")]);
+        assert!(parse_args(argv(&["x", "--dst", "a", "--dst", "b"])).is_err());
+        assert!(parse_args(argv(&["x", "--dst", "a", "--mirror", "--dst", "a"])).is_err());
+        assert!(parse_args(argv(&["x", "--dst", "a", "--distribute", "--dst", "b"])).is_ok());
+        assert!(parse_args(argv(&["x", "--dst", "a", "--distribute", "--mirror", "--dst", "b"])).is_err());
+    }
+
+    #[test]
+    fn byte_sizes_take_decimal_and_binary_units() {
+        assert_eq!(parse_byte_size("1234"), Some(1234));
+        assert_eq!(parse_byte_size("200MiB"), Some(200 << 20));
+        assert_eq!(parse_byte_size("1.5 GB"), Some(1_500_000_000));
+        assert_eq!(parse_byte_size("2kib"), Some(2048));
+        assert_eq!(parse_byte_size("3 parsecs"), None);
+        assert_eq!(parse_byte_size("MiB"), None);
+    }
+
+    #[test]
+    fn album_quotas_cap_each_folder_and_its_subfolders() {
+        let quotas = AlbumQuotas { named: vec![("wedding".to_string(), 10)], default: Some(5) };
+        let mut files = vec![
+            fa("w1.jpg", 6, "wedding"),
+            fa("w2.jpg", 6, "wedding/party"),
+            fa("w3.jpg", 4, "wedding/party"),
+            fa("t1.jpg", 4, "trips"),
+            fa("t2.jpg", 4, "trips"),
+            fa("top.jpg", 100, ""),
+            fa("pin.jpg", 9, "trips"),
+        ];
+        let dropped = apply_album_quotas(&mut files, &quotas, &[PathBuf::from("pin.jpg")]);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["w1.jpg", "w3.jpg", "t1.jpg", "top.jpg", "pin.jpg"]);
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn durations_take_seconds_minutes_and_hours() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_duration("1.5h"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("2d"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("1e20h"), None);
+        assert_eq!(parse_duration("1e300s"), None);
+    }
+
+    #[test]
+    fn copy_groups_stops_at_the_deadline() {
+        let root = env::temp_dir().join(format!("image-rando-deadline-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        let path = root.join("src").join("a.jpg");
+        fs::write(&path, "a").unwrap();
+        let groups = vec![vec![FileInfo { path, ..fi("a.jpg", 1) }]];
+        let names = ["1".to_string()];
+        let written = copy_groups(
+            &groups,
+            &root.join("dst"),
+            &names,
+            Processing::default(),
+            2,
+            &HashSet::new(),
+            CopyWindow { deadline: Some(Instant::now()), quiet: None },
+        )
+        .unwrap();
+        assert!(written.is_empty());
+        assert!(!root.join("dst").join("1").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn limit_keeps_the_first_files_and_every_pin() {
+        let mut files: Vec<FileInfo> = ["a", "b", "c", "d"].iter().map(|n| fi(n, 1)).collect();
+        assert_eq!(apply_limit(&mut files, 2, &[PathBuf::from("d")]), 1);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "d"]);
+    }
+
+    #[test]
+    fn run_dir_names_are_start_minutes_made_unique() {
+        let root = env::temp_dir().join(format!("image-rando-runs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        // 2024-06-01T03:00:30Z
+        let start = 1_717_210_830;
+        assert_eq!(run_dir_name(std::slice::from_ref(&root), start), "2024-06-01T03-00");
+        fs::create_dir_all(root.join("2024-06-01T03-00")).unwrap();
+        assert_eq!(run_dir_name(std::slice::from_ref(&root), start), "2024-06-01T03-00-2");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn prune_runs_keeps_the_newest_runs_by_manifest() {
+        let root = env::temp_dir().join(format!("image-rando-prune-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        // Folder names sort the other way round from the run times.
+        for (name, created) in [("a", "2024-06-03T00:00:00Z"), ("b", "2024-06-02T00:00:00Z"), ("c", "2024-06-01T00:00:00Z")] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            let m = manifest::Manifest { created: created.to_string(), ..manifest_of(Vec::new()) };
+            m.save(&root.join(name).join(manifest::FILE_NAME)).unwrap();
+        }
+        fs::create_dir_all(root.join("notes")).unwrap();
+        assert_eq!(prune_runs(&root, 2, "b", false).unwrap(), vec!["c"]);
+        assert!(root.join("a").is_dir() && root.join("b").is_dir() && root.join("notes").is_dir());
+        assert!(!root.join("c").exists());
+        // The current run stays even when its manifest looks older.
+        assert_eq!(prune_runs(&root, 1, "b", false).unwrap(), vec!["a"]);
+        assert!(root.join("b").is_dir());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn geofences_parse_units_and_filter_by_distance() {
+        let home = Geofence::parse("47.6,-122.3,25km").unwrap();
+        assert_eq!(home, Geofence { center: (47.6, -122.3), radius_km: 25.0 });
+        assert_eq!(Geofence::parse("47.6, -122.3, 500m").unwrap().radius_km, 0.5);
+        assert!((Geofence::parse("0,0,10mi").unwrap().radius_km - 16.09344).abs() < 1e-9);
+        for bad in ["47.6,-122.3", "91,0,1km", "1,2,-3", "1,2,3,4", "a,b,5km"] {
+            assert_eq!(Geofence::parse(bad), None, "{bad}");
+        }
+
+        // Seattle, Tacoma (~45 km away) and the office (~1 km from home).
+        let office = Geofence::parse("47.61,-122.31,200m").unwrap();
+        let photo = |gps| FileInfo { exif: exif::Exif { gps, ..Default::default() }, ..fi("p.jpg", 1) };
+        let (seattle, tacoma, at_office) = (photo(Some((47.6, -122.3))), photo(Some((47.25, -122.44))), photo(Some((47.61, -122.31))));
+        let nowhere = photo(None);
+        assert!(geofence_allows(&seattle, &[home], &[office]));
+        assert!(!geofence_allows(&tacoma, &[home], &[]));
+        assert!(!geofence_allows(&at_office, &[home], &[office]));
+        assert!(!geofence_allows(&nowhere, &[home], &[]));
+        assert!(geofence_allows(&nowhere, &[], &[office]));
+    }
+
+    #[test]
+    fn panoramas_are_more_than_three_times_wider_or_taller() {
+        assert!(is_panorama(12000, 2000));
+        assert!(is_panorama(1000, 3001));
+        assert!(!is_panorama(9000, 3000));
+        assert!(!is_panorama(4032, 3024));
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--panoramas", "skip"])).unwrap();
+        assert_eq!(args.processing.panoramas, Panoramas::Skip);
+        assert!(parse_args(argv(&["x", "--panoramas", "stretch"])).is_err());
+        assert_eq!(parse_args(argv(&["x", "--panoramas", "letterbox"])).is_ok(), cfg!(feature = "imaging"));
+    }
+
+    #[test]
+    fn preserved_structure_keeps_subfolders_through_renames() {
+        let caps = platform::Capabilities {
+            case_sensitive: false,
+            max_name_bytes: 255,
+            forbidden: vec![':'],
+            timestamp_resolution: None,
+        };
+        let src = Path::new("/src");
+        assert_eq!(structure_prefix(src, Path::new("/src/2023/06/IMG_1.jpg"), false, &caps).as_deref(), Some("2023/06"));
+        assert_eq!(structure_prefix(src, Path::new("/src/IMG_1.jpg"), false, &caps), None);
+        let odd = Path::new("/src/Été 10:30/IMG_1.jpg");
+        assert_eq!(structure_prefix(src, odd, false, &caps).as_deref(), Some("Été 10_30"));
+        assert_eq!(structure_prefix(src, odd, true, &caps).as_deref(), Some("Ete_10_30"));
+
+        let mut groups = vec![vec![fi("2023/06/IMG_1.jpg", 1), fi("2023/06/img_1.jpg", 1), fi("IMG_1.jpg", 1)]];
+        assert_eq!(resolve_name_collisions(&mut groups, |_, _| false), 1);
+        let names: Vec<&str> = groups[0].iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["2023/06/IMG_1.jpg", "2023/06/img_1-2.jpg", "IMG_1.jpg"]);
+        let f = FileInfo { path: PathBuf::from("/src/2023/06/IMG_1.jpg"), ..groups[0][1].clone() };
+        assert_eq!(sidecar_dest_name(&f, Path::new("/src/2023/06/IMG_1.AAE")), "2023/06/img_1-2.AAE");
+    }
+
+    #[test]
+    fn group_labels_name_what_most_photos_share() {
+        let photo = |date: &str, keywords: &[&str], city: Option<&str>| FileInfo {
+            exif: exif::Exif { captured: exif::DateTime::parse(date), ..Default::default() },
+            xmp: xmp::Xmp {
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+                place: [city.map(str::to_string), Some("Oregon".to_string()), Some("USA".to_string())],
+                ..Default::default()
+            },
+            ..fi("a.jpg", 1)
+        };
+        let all = [GroupLabel::DateRange, GroupLabel::Location, GroupLabel::Keywords];
+        let group = vec![
+            photo("2023:06:20 10:00:00", &["Beach"], Some("Bend")),
+            photo("2023:07:04 10:00:00", &["beach", "Dog"], Some("Portland")),
+            photo("2023:08:30 10:00:00", &[], Some("Bend")),
+        ];
+        assert_eq!(group_label(&group, &all), "summer-2023-bend-beach");
+        let group = vec![
+            photo("2022:12:24 10:00:00", &[], Some("Bend")),
+            photo("2023:02:01 10:00:00", &[], Some("Portland")),
+        ];
+        assert_eq!(group_label(&group, &all), "winter-2022-23-oregon");
+        let group = vec![photo("2019:01:01 10:00:00", &["a"], None), photo("2021:05:01 10:00:00", &["b"], None)];
+        assert_eq!(group_label(&group, &[GroupLabel::DateRange]), "2019-2021");
+        assert_eq!(group_label(&group, &[GroupLabel::DateRange, GroupLabel::Keywords]), "2019-2021-a");
+        assert_eq!(group_label(&[fi("undated.jpg", 1)], &all), "");
+        assert_eq!(label_slug("  São Paulo / Zona Sul! "), "sao-paulo-zona-sul");
+    }
+
+    #[test]
+    fn json_summary_counts_each_folder() {
+        let video = FileInfo { kind: MediaKind::Video, ..fi("clip.mp4", 100) };
+        let groups = vec![vec![fi("a.jpg", 10), video], vec![fi("b.jpg", 5)]];
+        let manifest = manifest::Manifest { labels: vec!["beach".to_string(), String::new()], ..manifest_of(Vec::new()) };
+        let done = Written { synced: Some((1, 2)), left: 0, from_store: None, resumed: None };
+        let v = destination_json(Path::new("/frame"), &groups, &manifest, &done);
+        let v = Value::parse(&v.to_pretty()).unwrap();
+        assert_eq!(v.get("manifest").and_then(Value::as_str), Some("/frame/.image-rando-manifest.json"));
+        assert_eq!(["photos", "videos", "bytes", "removed"].map(|k| v.get(k).and_then(Value::as_u64)), [2, 1, 115, 2].map(Some));
+        let folders = v.get("folders").and_then(Value::as_array).unwrap();
+        assert_eq!(folders[0].get("name").and_then(Value::as_str), Some("01-beach"));
+        assert_eq!(folders[1].get("bytes").and_then(Value::as_u64), Some(5));
+    }
+
+    #[test]
+    fn ask_choice_repeats_until_it_understands() {
+        let mut out = Vec::new();
+        assert_eq!(ask_choice(&mut io::Cursor::new("maybe\n\nR\n"), &mut out).unwrap(), Choice::Reroll);
+        assert_eq!(String::from_utf8(out).unwrap().matches("[c]opy").count(), 3);
+        assert_eq!(ask_choice(&mut io::Cursor::new(" copy \n"), &mut Vec::new()).unwrap(), Choice::Copy);
+        assert_eq!(ask_choice(&mut io::Cursor::new(""), &mut Vec::new()).unwrap(), Choice::Quit);
+        assert_eq!(ask_rescan(&mut io::Cursor::new("copy?\nR\n"), &mut Vec::new()).unwrap(), SourceChoice::Rescan);
+        assert_eq!(ask_rescan(&mut io::Cursor::new("c\n"), &mut Vec::new()).unwrap(), SourceChoice::Proceed);
+    }
+
+    #[test]
+    fn dry_run_plans_without_touching_the_destination() {
+        let root = env::temp_dir().join(format!("image-rando-dry-run-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        for name in ["a.jpg", "b.jpg", "c.jpg"] {
+            fs::write(root.join("src").join(name), b"jpeg").unwrap();
+        }
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        let run = |extra: &[&str]| {
+            let mut v = argv(&["x", "--src", src.to_str().unwrap(), "--dst", dst.to_str().unwrap(), "--max-files", "2"]);
+            v.extend(argv(extra));
+            copy_run(v)
+        };
+        assert_eq!(run(&["--dry-run", "--list-files"]).unwrap(), vec!["Nothing copied (dry run)"]);
+        assert!(!dst.exists());
+        assert!(run(&["--list-files"]).is_err());
+        assert!(run(&["--dry-run", "--output", "json"]).is_err());
+
+        // A destination the real run would refuse is refused here too.
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(dst.join("old.jpg"), b"old").unwrap();
+        assert!(run(&["--dry-run"]).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resume_finishes_what_an_interrupted_run_left() {
+        let root = env::temp_dir().join(format!("image-rando-resume-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        for (name, body) in [("a.jpg", "aaaa"), ("b.jpg", "bbbbbb"), ("c.jpg", "cc")] {
+            fs::write(root.join("src").join(name), body).unwrap();
+        }
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (src, dst) = (root.join("src"), root.join("dst"));
+        let (src, dst) = (src.to_str().unwrap(), dst.to_str().unwrap());
+        copy_run(argv(&["x", "--src", src, "--dst", dst, "--max-files", "2", "--seed", "7"])).unwrap();
+        let dst = Path::new(dst);
+        let done = manifest::Manifest::load(&dst.join(manifest::FILE_NAME)).unwrap();
+        assert!(!dst.join(manifest::PLAN_FILE_NAME).exists());
+
+        // As if the copy died: the plan but no manifest, one file cut short
+        // and one never started.
+        fs::rename(dst.join(manifest::FILE_NAME), dst.join(manifest::PLAN_FILE_NAME)).unwrap();
+        let path = |e: &manifest::Entry| dst.join(done.folder_name(e.folder)).join(&e.name);
+        fs::write(path(&done.files[0]), "x").unwrap();
+        fs::remove_file(path(&done.files[1])).unwrap();
+        assert!(copy_run(argv(&["x", "--src", src, "--dst", dst.to_str().unwrap()])).is_err());
+
+        let summary = copy_run(argv(&["x", "--resume", "--dst", dst.to_str().unwrap()])).unwrap();
+        assert!(summary.contains(&"Resumed: 1 already copied by the interrupted run".to_string()), "{summary:?}");
+        for e in &done.files {
+            assert_eq!(fs::metadata(path(e)).unwrap().len(), e.size);
+        }
+        assert_eq!(manifest::Manifest::load(&dst.join(manifest::FILE_NAME)).unwrap().files, done.files);
+        assert!(!dst.join(manifest::PLAN_FILE_NAME).exists());
+        assert!(copy_run(argv(&["x", "--resume", "--dst", dst.to_str().unwrap()])).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn album_picks_skip_recent_ones_until_none_is_left() {
+        let mut files = vec![
+            fa("a1.jpg", 1, "Trips/Rome"),
+            fa("a2.jpg", 1, "Trips/Rome"),
+            fa("b.jpg", 1, "Trips/Oslo"),
+            fa("c.jpg", 1, "Birthdays"),
+            fi("top.jpg", 1),
+            fa("pin.jpg", 1, "Pins"),
+        ];
+        let required = vec![PathBuf::from("pin.jpg")];
+        let recent = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        for seed in 0..20 {
+            let album = choose_album(&files, seed, &recent(&["Trips/Rome", "Birthdays"]), &required);
+            assert_eq!(album.as_deref(), Some("Trips/Oslo"));
+            // All three were picked lately: the oldest pick is allowed again.
+            let album = choose_album(&files, seed, &recent(&["Trips/Oslo", "Trips/Rome", "Birthdays"]), &required);
+            assert_eq!(album.as_deref(), Some("Birthdays"));
+        }
+        let seeds = (1..20u64).map(|k| k.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let picked: HashSet<_> = seeds.filter_map(|seed| choose_album(&files, seed, &[], &required)).collect();
+        assert_eq!(picked.len(), 3);
+
+        keep_album(&mut files, "Trips/Rome", &required);
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a1.jpg", "a2.jpg", "pin.jpg"]);
+        assert_eq!(choose_album(&[fi("top.jpg", 1)], 1, &[], &[]), None);
+    }
+
+    #[test]
+    fn orientation_split_sends_squares_to_both_trees() {
+        let shaped = |name: &str, dimensions| FileInfo {
+            exif: exif::Exif { dimensions, ..Default::default() },
+            ..fi(name, 1)
+        };
+        let files = vec![
+            shaped("wide.jpg", Some((4000, 3000))),
+            shaped("tall.jpg", Some((3000, 4000))),
+            shaped("square.jpg", Some((2000, 2000))),
+            shaped("unknown.jpg", None),
+            shaped("pin.jpg", None),
+        ];
+        let ([landscape, portrait], unknown) = split_orientation(files, &[PathBuf::from("pin.jpg")]);
+        let names = |tree: &[FileInfo]| tree.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&landscape), vec!["wide.jpg", "square.jpg", "pin.jpg"]);
+        assert_eq!(names(&portrait), vec!["tall.jpg", "square.jpg", "pin.jpg"]);
+        assert_eq!(unknown, 1);
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(argv(&["x", "--split-by-orientation", "--sets", "2"])).is_err());
+    }
+
+    #[test]
+    fn short_last_folder_is_spread_over_the_others_bytes_permitting() {
+        let files: Vec<FileInfo> = (0..7).map(|k| fi(&format!("{k}.jpg"), 1)).collect();
+        let mut groups = plan_groups(&files, 3, 100, LimitMode::FirstHit).unwrap();
+        let (folded, left_out) = fold_straggler(&mut groups, 2, 100, &[]);
+        assert_eq!((folded, left_out), (1, 0));
+        assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 3]);
+
+        // d.jpg fits in neither folder, so it is left out unless it must be copied.
+        let groups = vec![vec![fi("a.jpg", 5), fi("b.jpg", 5)], vec![fi("c.jpg", 6)], vec![fi("d.jpg", 5)]];
+        assert_eq!(fold_straggler(&mut groups.clone(), 2, 10, &[]), (0, 1));
+        let mut kept = groups;
+        assert_eq!(fold_straggler(&mut kept, 2, 10, &[PathBuf::from("d.jpg")]), (0, 0));
+        assert_eq!(kept.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 1, 1]);
+        assert_eq!(fold_straggler(&mut vec![vec![fi("a.jpg", 1)]], 2, 10, &[]), (0, 0));
+    }
+
+    #[test]
+    fn bytes_tolerance_takes_in_the_last_folder_only_when_all_of_it_fits() {
+        let groups = vec![vec![fi("a.jpg", 60), fi("b.jpg", 38)], vec![fi("c.jpg", 99)], vec![fi("d.jpg", 4), fi("e.jpg", 3)]];
+        let mut tolerated = groups.clone();
+        assert_eq!(absorb_last_folder(&mut tolerated, 10, 100, 5.0), 2);
+        let names = |g: &[FileInfo]| g.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&tolerated[0]), vec!["a.jpg", "b.jpg", "d.jpg"]);
+        assert_eq!(names(&tolerated[1]), vec!["c.jpg", "e.jpg"]);
+        // Without the slack, or with no room for a third photo, e.jpg has nowhere to go.
+        let mut strict = groups.clone();
+        assert_eq!(absorb_last_folder(&mut strict, 10, 100, 1.0), 0);
+        assert_eq!(strict.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 1, 2]);
+        assert_eq!(absorb_last_folder(&mut groups.clone(), 2, 100, 50.0), 0);
+
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--bytes-tolerance", "2.5%"])).unwrap();
+        assert_eq!(args.bytes_tolerance, Some(2.5));
+        for bad in [&["x", "--bytes-tolerance", "0"][..], &["x", "--bytes-tolerance", "lots"], &["x", "--bytes-tolerance", "2%", "--group-by", "album"]] {
+            assert!(parse_args(argv(bad)).is_err(), "{bad:?}");
+        }
+        assert!(parse_args(argv(&["x", "--bytes-tolerance", "2%", "--limit-mode", "files-only"])).is_err());
+    }
+
+    #[test]
+    fn exported_selection_comes_back_as_edited() {
+        let root = env::temp_dir().join(format!("image-rando-selection-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("trip")).unwrap();
+        for name in ["#1.jpg", "trip/a.jpg", "trip/b.jpg"] {
+            fs::write(root.join(name), b"not really a jpeg").unwrap();
+        }
+        let list = root.join("sel.txt");
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let src = root.to_str().unwrap();
+        export_selection(argv(&["x", "--src", src, "-r"]), &list).unwrap();
+        let text = fs::read_to_string(&list).unwrap();
+        assert!(text.ends_with("\n./#1.jpg\ntrip/a.jpg\ntrip/b.jpg\n"), "{text}");
+
+        fs::write(&list, text.replace("trip/a.jpg\n", "")).unwrap();
+        let args = parse_args(argv(&["x", "--src", src, "--import-selection", list.to_str().unwrap()])).unwrap();
+        let mut names: Vec<String> = select_files(&args).unwrap().files.iter().map(|f| f.name.clone()).collect();
+        names.sort();
+        assert_eq!(names, vec!["#1.jpg", "b.jpg"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn folder_weights_set_the_mix_whatever_the_folder_sizes() {
+        let mut files: Vec<FileInfo> = (0..10).map(|k| fa(&format!("best{k}.jpg"), 1, "best-of/2023")).collect();
+        files.extend((0..100).map(|k| fa(&format!("raw{k}.jpg"), 1, "camera-roll")));
+        let weighed = weigh_folders(files.clone(), &[("best-of".to_string(), 70)]);
+        let best = |fs: &[FileInfo]| fs.iter().filter(|f| f.album.starts_with("best-of")).count();
+        assert_eq!(best(&weighed[..10]), 7);
+        assert_eq!(best(&weighed), 10);
+        // Order within a folder is kept.
+        let names: Vec<&str> = weighed.iter().filter(|f| f.album == "camera-roll").map(|f| f.name.as_str()).collect();
+        assert_eq!(names[..3], ["raw0.jpg", "raw1.jpg", "raw2.jpg"]);
+
+        // Nothing left for the other files: they come after the named folders.
+        let weighed = weigh_folders(files, &[("best-of".to_string(), 60), ("camera-roll".to_string(), 40)]);
+        assert_eq!(best(&weighed[..10]), 6);
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(parse_args(argv(&["x", "-r", "--src-weight", "a=70", "--src-weight", "b=40"])).is_err());
+        assert!(parse_args(argv(&["x", "-r", "--src-weight", "a"])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_names_of_one_file_are_selected_once() {
+        let root = env::temp_dir().join(format!("image-rando-links-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.jpg"), b"one").unwrap();
+        fs::write(root.join("c.jpg"), b"two").unwrap();
+        fs::hard_link(root.join("a.jpg"), root.join("b.jpg")).unwrap();
+        std::os::unix::fs::symlink("a.jpg", root.join("d.jpg")).unwrap();
+        // A symlink to a file with a single link is another name for it too.
+        fs::write(root.join("e.jpg"), b"three").unwrap();
+        std::os::unix::fs::symlink("e.jpg", root.join("f.jpg")).unwrap();
+        let files: Vec<FileInfo> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg", "e.jpg", "f.jpg"]
+            .iter()
+            .map(|n| FileInfo { path: root.join(n), ..fi(n, 3) })
+            .collect();
+        let names = |files: &[FileInfo]| files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+        let mut kept = files.clone();
+        assert_eq!(skip_linked_copies(&mut kept, &[]), 3);
+        assert_eq!(names(&kept), vec!["a.jpg", "c.jpg", "e.jpg"]);
+        let mut kept = files;
+        assert_eq!(skip_linked_copies(&mut kept, &[root.join("d.jpg")]), 3);
+        assert_eq!(names(&kept), vec!["c.jpg", "d.jpg", "e.jpg"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn quiet_hours_may_wrap_past_midnight() {
+        let day = QuietHours::parse("08:00-22:00").unwrap();
+        assert_eq!(day.left_at(7 * 3600 + 3599), None);
+        assert_eq!(day.left_at(8 * 3600), Some(14 * 3600));
+        assert_eq!(day.left_at(21 * 3600 + 1800), Some(1800));
+        assert_eq!(day.left_at(22 * 3600), None);
+        let night = QuietHours::parse("22:30-6:00").unwrap();
+        assert_eq!(night.left_at(23 * 3600), Some(7 * 3600));
+        assert_eq!(night.left_at(3600), Some(5 * 3600));
+        assert_eq!(night.left_at(12 * 3600), None);
+        for bad in ["08:00", "8-22", "08:00-24:00", "08:00-08:00", "08:5-10:00"] {
+            assert_eq!(QuietHours::parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn low_memory_works_one_photo_at_a_time() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--low-memory"])).unwrap();
+        assert_eq!((args.jobs, args.scan.threads), (1, 1));
+        assert!(parse_args(argv(&["x", "--low-memory", "-j", "1"])).is_ok());
+        assert!(parse_args(argv(&["x", "--low-memory", "-j", "4"])).is_err());
+    }
+
+    #[test]
+    fn plan_script_quotes_every_path_and_keeps_the_manifest() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let args = parse_args(argv(&["x", "--src", "/photos", "--dst", "/frame"])).unwrap();
+        let mut f = fi("it's.jpg", 1);
+        f.path = PathBuf::from("/photos/a b/it's.jpg");
+        let plans = vec![vec![vec![f]]];
+        let manifests: Vec<manifest::Manifest> =
+            plans.iter().map(|g| run_manifest(g, &args, Vec::new(), manifest::Input::of(Vec::new()), None)).collect();
+        let script = plan_script("plan.sh", &args, std::slice::from_ref(&args.dst), &plans, &manifests).unwrap();
+        assert!(script.contains("SRC=${SRC:-'/photos'}\nDST=${DST:-'/frame'}\n"));
+        assert!(script.contains("mkdir -p \"$DST/\"'1'\ncp \"$SRC/\"'a b/it'\\''s.jpg' \"$DST/\"'1/it'\\''s.jpg'\n"));
+        assert!(script.contains("> \"$DST/\"'.image-rando-manifest.json' <<'IMAGE_RANDO_MANIFEST'\n{"));
+        assert!(parse_args(argv(&["x", "--strip-thumbnails"])).is_ok_and(|a| check_script_options(&a).is_err()));
+    }
+
+    #[test]
+    fn sets_are_disjoint_and_share_only_pins() {
+        let files: Vec<FileInfo> = ["a", "b", "p", "c", "d", "e", "f"].iter().map(|n| fi(n, 1)).collect();
+        let names = |sets: &[Vec<FileInfo>]| -> Vec<Vec<String>> {
+            sets.iter().map(|s| s.iter().map(|f| f.name.clone()).collect()).collect()
+        };
+        let pins = [PathBuf::from("p")];
+        let (sets, left_over) = split_sets(files.clone(), 2, Some(2), &pins);
+        assert_eq!(names(&sets), vec![vec!["a", "b", "p"], vec!["p", "c", "d"]]);
+        assert_eq!(left_over, 2);
+        let (sets, left_over) = split_sets(files, 3, None, &[]);
+        assert_eq!(names(&sets), vec![vec!["a", "c", "f"], vec!["b", "d"], vec!["p", "e"]]);
+        assert_eq!(left_over, 0);
+
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_args(argv(&["x", "--sets", "4"])).unwrap().sets, Some(4));
+        assert!(parse_args(argv(&["x", "--sets", "0"])).is_err());
+        assert!(parse_args(argv(&["x", "--sets", "2", "--sync"])).is_err());
+        assert!(parse_args(argv(&["x", "--dst", "a", "--mirror", "--dst", "b", "--sets", "2"])).is_err());
+    }
+
+    #[test]
+    fn sandbox_redirects_every_destination_and_cleans_up() {
+        let argv = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut args = parse_args(argv(&["x", "--dst", "/a", "--mirror", "--dst", "/b", "--sandbox"])).unwrap();
+        let sandbox = Sandbox::redirect(&mut args, false).unwrap();
+        assert!(args.destinations().all(|d| d.starts_with(&sandbox.root)));
+        fs::create_dir_all(&args.dst).unwrap();
+        let root = sandbox.root.clone();
+        drop(sandbox);
+        assert!(!root.exists());
+        assert!(parse_args(argv(&["x", "--sandbox", "--sync"])).is_err());
+    }
+
+    #[test]
+    fn durations_are_formatted_for_people() {
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(250)), "4m 10s");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h 05m");
+    }
+
+    #[test]
+    fn names_are_adapted_to_the_destination() {
+        let caps = platform::Capabilities {
+            case_sensitive: false,
+            max_name_bytes: 40,
+            forbidden: vec![':'],
+            timestamp_resolution: None,
+        };
+        let long = format!("{}.jpg", "é".repeat(20));
+        let longer = format!("{}1.jpg", "é".repeat(20));
+        let mut files = vec![fi("12:30.jpg", 1), fi(&long, 1), fi("fine?.jpg", 1), fi(&longer, 1)];
+        assert_eq!(adapt_names(&mut files, &caps), 3);
+        assert_eq!(files[0].name, "12_30.jpg");
+        assert_eq!(files[2].name, "fine?.jpg");
+        // At most 28 bytes: 7 two-byte letters, a 9-byte hash suffix and the extension.
+        let prefix = format!("{}-", "é".repeat(7));
+        assert!(files[1].name.starts_with(&prefix) && files[1].name.ends_with(".jpg"), "{}", files[1].name);
+        assert_eq!(files[1].name.len(), 27);
+        assert_ne!(files[1].name, files[3].name);
+        assert_eq!(shorten_name("no-extension-at-all", 8, "-ab"), "no-ex-ab");
+    }
+
+    #[test]
+    fn empty_files_are_skipped_and_small_ones_counted() {
+        let mut files = vec![fi("empty.jpg", 0), fi("tiny.jpg", 500), fi("photo.jpg", 2_000_000)];
+        assert_eq!(skip_empty_files(&mut files, Path::new("")), (1, 1));
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["tiny.jpg", "photo.jpg"]);
+    }
+
+    #[test]
+    fn resolve_name_collisions_is_case_insensitive() {
+        let mut groups = vec![
+            vec![fi("IMG_001.jpg", 1), fi("img_001.JPG", 1), fi("IMG_001-2.jpg", 1)],
+            vec![fi("IMG_001.jpg", 1)],
+        ];
+        assert_eq!(resolve_name_collisions(&mut groups, |_, _| false), 3);
+        let names: Vec<&str> = groups[0].iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["IMG_001.jpg", "img_001-2.JPG", "IMG_001-2-2.jpg"]);
+        // Names are unique across folders too.
+        assert_eq!(groups[1][0].name, "IMG_001-3.jpg");
+    }
+
+    #[test]
+    fn names_already_in_place_are_claimed_first() {
+        let mut groups = vec![vec![fi("a.jpg", 1)], vec![FileInfo { path: PathBuf::from("x/a.jpg"), ..fi("a.jpg", 1) }]];
+        assert_eq!(resolve_name_collisions(&mut groups, |folder, _| folder == 2), 1);
+        assert_eq!(groups[0][0].name, "a-2.jpg");
+        assert_eq!(groups[1][0].name, "a.jpg");
+    }
+
+    #[test]
+    fn sidecars_follow_renamed_images() {
+        let f = FileInfo {
+            path: PathBuf::from("/src/IMG.jpg"),
+            ..fi("IMG-2.jpg", 1)
+        };
+        assert_eq!(sidecar_dest_name(&f, Path::new("/src/IMG.jpg.xmp")), "IMG-2.jpg.xmp");
+        assert_eq!(sidecar_dest_name(&f, Path::new("/src/IMG.AAE")), "IMG-2.AAE");
+    }
 }
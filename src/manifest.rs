@@ -0,0 +1,382 @@
+// The record of a run, written next to its output folders.
+//
+// It lists where every copied file came from so later commands can compare
+// runs without looking at the (possibly unplugged) destination itself.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::dupes;
+use crate::json::Value;
+
+/// File name inside the destination folder.
+pub const FILE_NAME: &str = ".image-rando-manifest.json";
+
+/// The whole plan, written before copying starts and removed once the
+/// manifest is saved; a destination still holding it was cut short.
+pub const PLAN_FILE_NAME: &str = ".image-rando-plan.json";
+
+const VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub seed: u64,
+    /// UTC time of the run, `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created: String,
+    /// Command-line options of the run except `--dst` and `--seed`, for
+    /// `--replay`.
+    pub args: Vec<String>,
+    /// What the run selected from; absent in manifests older than `--replay`.
+    pub input: Option<Input>,
+    /// `--name-groups-by` names of folders 1, 2..., empty where nothing
+    /// described a folder; no labels at all for plain numbered folders.
+    pub labels: Vec<String>,
+    /// The source folder `--pick-one-album` chose.
+    pub album: Option<String>,
+    /// Fingerprint of what was done to photo content (`--resize`, `--jpeg`,
+    /// `--optimize-jpeg`...); absent in manifests older than it.
+    pub processing: Option<String>,
+    pub files: Vec<Entry>,
+}
+
+/// A fingerprint of the selected source files (paths and sizes).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    pub files: usize,
+    /// FNV-1a of the sorted `path NUL size LF` lines, as 16 hex digits.
+    pub fingerprint: String,
+}
+
+impl Input {
+    pub fn of(mut files: Vec<(String, u64)>) -> Input {
+        files.sort();
+        let mut hash = dupes::FNV_OFFSET;
+        for (path, size) in &files {
+            hash = dupes::fnv1a(hash, format!("{path}\0{size}\n").as_bytes());
+        }
+        Input { files: files.len(), fingerprint: format!("{hash:016x}") }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Entry {
+    /// Destination folder number (1-based).
+    pub folder: usize,
+    /// Name inside that folder.
+    pub name: String,
+    /// Source path relative to the source root, `/`-separated.
+    pub source: String,
+    pub size: u64,
+    /// Names of the sidecars copied next to it.
+    pub sidecars: Vec<String>,
+    /// When the source was last modified, in nanoseconds since the Unix
+    /// epoch, so `--sync` notices an edit that kept the size; absent in
+    /// manifests older than it.
+    pub modified: Option<u64>,
+}
+
+impl Entry {
+    /// The entry without `modified`, for comparing only where files go.
+    pub fn placement(&self) -> Entry {
+        Entry { modified: None, ..self.clone() }
+    }
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> Value {
+        let files = self
+            .files
+            .iter()
+            .map(|e| {
+                let mut fields = vec![
+                    ("folder".to_string(), Value::from(e.folder)),
+                    ("name".to_string(), Value::from(e.name.as_str())),
+                    ("source".to_string(), Value::from(e.source.as_str())),
+                    ("size".to_string(), Value::from(e.size)),
+                ];
+                if !e.sidecars.is_empty() {
+                    let names = e.sidecars.iter().map(|n| Value::from(n.as_str())).collect();
+                    fields.push(("sidecars".to_string(), Value::Array(names)));
+                }
+                if let Some(modified) = e.modified {
+                    fields.push(("modified".to_string(), Value::from(modified)));
+                }
+                Value::Object(fields)
+            })
+            .collect();
+        let mut fields = vec![
+            ("version".to_string(), Value::from(VERSION)),
+            ("seed".to_string(), Value::from(self.seed)),
+            ("created".to_string(), Value::from(self.created.as_str())),
+            ("args".to_string(), Value::Array(self.args.iter().map(|a| Value::from(a.as_str())).collect())),
+        ];
+        if let Some(input) = &self.input {
+            fields.push((
+                "input".to_string(),
+                Value::Object(vec![
+                    ("files".to_string(), Value::from(input.files)),
+                    ("fingerprint".to_string(), Value::from(input.fingerprint.as_str())),
+                ]),
+            ));
+        }
+        if !self.labels.is_empty() {
+            let labels = self.labels.iter().map(|l| Value::from(l.as_str())).collect();
+            fields.push(("labels".to_string(), Value::Array(labels)));
+        }
+        if let Some(album) = &self.album {
+            fields.push(("album".to_string(), Value::from(album.as_str())));
+        }
+        if let Some(processing) = &self.processing {
+            fields.push(("processing".to_string(), Value::from(processing.as_str())));
+        }
+        // Derived from `files`, so only written for people and other tools.
+        fields.push(("plan".to_string(), Value::from(self.plan_hash())));
+        fields.push(("files".to_string(), Value::Array(files)));
+        Value::Object(fields)
+    }
+
+    pub fn from_json(v: &Value) -> Result<Manifest, String> {
+        let version = v.get("version").and_then(Value::as_u64).ok_or("missing version")?;
+        if version != VERSION {
+            return Err(format!("unsupported manifest version {version}"));
+        }
+        let mut files = Vec::new();
+        for item in field(v, "files")?.as_array().ok_or("files is not a list")? {
+            let number = |key| field(item, key)?.as_u64().ok_or(format!("{key} is not a number"));
+            let text = |key| {
+                field(item, key)?
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or(format!("{key} is not a string"))
+            };
+            // Folders are numbered from 1; a 0 would index before the first.
+            let folder = number("folder")? as usize;
+            if folder == 0 {
+                return Err("folder must be ≥ 1".to_string());
+            }
+            files.push(Entry {
+                folder,
+                name: text("name")?,
+                source: text("source")?,
+                size: number("size")?,
+                sidecars: strings(item, "sidecars")?,
+                modified: item.get("modified").and_then(Value::as_u64),
+            });
+        }
+        let args = strings(v, "args")?;
+        let input = match v.get("input") {
+            Some(input) => Some(Input {
+                files: field(input, "files")?.as_u64().ok_or("input files is not a number")? as usize,
+                fingerprint: field(input, "fingerprint")?
+                    .as_str()
+                    .ok_or("fingerprint is not a string")?
+                    .to_string(),
+            }),
+            None => None,
+        };
+        Ok(Manifest {
+            seed: field(v, "seed")?.as_u64().ok_or("seed is not a number")?,
+            created: field(v, "created")?.as_str().unwrap_or_default().to_string(),
+            args,
+            input,
+            labels: strings(v, "labels")?,
+            album: v.get("album").and_then(Value::as_str).map(str::to_string),
+            processing: v.get("processing").and_then(Value::as_str).map(str::to_string),
+            files,
+        })
+    }
+
+    /// FNV-1a of the sorted `folder NUL name NUL source LF` lines, as 16 hex
+    /// digits: equal for two runs that put the same files in the same places,
+    /// whatever machine or order they were planned in.
+    pub fn plan_hash(&self) -> String {
+        let mut lines: Vec<String> =
+            self.files.iter().map(|e| format!("{}\0{}\0{}\n", e.folder, e.name, e.source)).collect();
+        lines.sort();
+        let hash = lines.iter().fold(dupes::FNV_OFFSET, |hash, line| dupes::fnv1a(hash, line.as_bytes()));
+        format!("{hash:016x}")
+    }
+
+    /// Highest folder number used, 0 when nothing was copied.
+    pub fn folders(&self) -> usize {
+        self.files.iter().map(|e| e.folder).max().unwrap_or(0)
+    }
+
+    /// The name of folder `k` on disk: `3`, or `03-summer-2023-oregon` with
+    /// labels (numbers are padded so the folders sort in order).
+    pub fn folder_name(&self, k: usize) -> String {
+        if self.labels.is_empty() {
+            return k.to_string();
+        }
+        let width = self.labels.len().to_string().len().max(2);
+        match self.labels.get(k - 1).filter(|l| !l.is_empty()) {
+            Some(label) => format!("{k:0width$}-{label}"),
+            None => format!("{k:0width$}"),
+        }
+    }
+
+    /// Where the file ended up, e.g. `3/IMG_0001.jpg`.
+    pub fn destination(&self, e: &Entry) -> String {
+        format!("{}/{}", self.folder_name(e.folder), e.name)
+    }
+
+    /// Folder `k` becomes `k - 1` and folder 1 becomes the last (`rotate`).
+    pub fn rotate(&mut self) {
+        let n = self.folders();
+        for e in &mut self.files {
+            e.folder = if e.folder == 1 { n } else { e.folder - 1 };
+        }
+        if let Some(labels) = self.labels.get_mut(..n) {
+            labels.rotate_left(1);
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read manifest {}: {e}", path.display()))?;
+        Value::parse(&text)
+            .and_then(|v| Manifest::from_json(&v))
+            .map_err(|e| format!("bad manifest {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_json().to_pretty())
+            .map_err(|e| format!("cannot write manifest {}: {e}", path.display()))
+    }
+}
+
+/// An optional list of strings; absent means empty.
+fn strings(v: &Value, key: &str) -> Result<Vec<String>, String> {
+    match v.get(key) {
+        Some(list) => list
+            .as_array()
+            .and_then(|a| a.iter().map(|s| s.as_str().map(str::to_string)).collect())
+            .ok_or(format!("{key} is not a list of strings")),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn field<'a>(v: &'a Value, key: &str) -> Result<&'a Value, String> {
+    v.get(key).ok_or(format!("missing {key}"))
+}
+
+/// What changed between two runs, matched by source path.
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff<'a> {
+    /// Only in the newer run.
+    pub added: Vec<&'a Entry>,
+    /// Only in the older run.
+    pub removed: Vec<&'a Entry>,
+    /// In both, at a different destination: (old, new).
+    pub moved: Vec<(&'a Entry, &'a Entry)>,
+    pub unchanged: usize,
+}
+
+pub fn diff<'a>(old: &'a Manifest, new: &'a Manifest) -> Diff<'a> {
+    let by_source: HashMap<&str, &Entry> = old.files.iter().map(|e| (e.source.as_str(), e)).collect();
+    let mut d = Diff::default();
+    for e in &new.files {
+        match by_source.get(e.source.as_str()) {
+            None => d.added.push(e),
+            Some(o) if o.folder != e.folder || o.name != e.name => d.moved.push((o, e)),
+            Some(_) => d.unchanged += 1,
+        }
+    }
+    let in_new: HashSet<&str> = new.files.iter().map(|e| e.source.as_str()).collect();
+    d.removed = old.files.iter().filter(|e| !in_new.contains(e.source.as_str())).collect();
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(folder: usize, name: &str, source: &str) -> Entry {
+        Entry { folder, name: name.to_string(), source: source.to_string(), size: 1, sidecars: Vec::new(), modified: None }
+    }
+
+    fn manifest_of(files: Vec<Entry>) -> Manifest {
+        Manifest {
+            seed: 1,
+            created: String::new(),
+            args: Vec::new(),
+            input: None,
+            labels: Vec::new(),
+            album: None,
+            processing: None,
+            files,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut m = Manifest {
+            seed: u64::MAX - 1,
+            created: "2024-12-24T18:00:00Z".to_string(),
+            args: vec!["-r".to_string(), "--max-files".to_string(), "10".to_string()],
+            input: Some(Input::of(vec![("b.jpg".to_string(), 2), ("a.jpg".to_string(), 1)])),
+            labels: vec!["beach-2023".to_string(), String::new()],
+            album: Some("Trips/Rome 2019".to_string()),
+            processing: Some("0123456789abcdef".to_string()),
+            files: vec![entry(1, "a.jpg", "x/a.jpg"), entry(2, "b \"1\".jpg", "b \"1\".jpg")],
+        };
+        m.files[0].sidecars.push("a.jpg.xmp".to_string());
+        m.files[1].modified = Some(1_700_000_000_123_456_789);
+        let text = m.to_json().to_pretty();
+        assert_eq!(Manifest::from_json(&Value::parse(&text).unwrap()).unwrap(), m);
+        assert!(Manifest::from_json(&Value::parse("{\"version\": 9}").unwrap()).is_err());
+        let zero = text.replacen("\"folder\": 1", "\"folder\": 0", 1);
+        assert_ne!(zero, text);
+        assert_eq!(Manifest::from_json(&Value::parse(&zero).unwrap()).unwrap_err(), "folder must be ≥ 1");
+    }
+
+    #[test]
+    fn plan_hash_ignores_order_but_not_placement() {
+        let m = manifest_of;
+        let a = m(vec![entry(1, "a.jpg", "x/a.jpg"), entry(2, "b.jpg", "b.jpg")]);
+        let b = m(vec![entry(2, "b.jpg", "b.jpg"), entry(1, "a.jpg", "x/a.jpg")]);
+        let c = m(vec![entry(2, "a.jpg", "x/a.jpg"), entry(1, "b.jpg", "b.jpg")]);
+        assert_eq!(a.plan_hash(), b.plan_hash());
+        assert_ne!(a.plan_hash(), c.plan_hash());
+        assert_eq!(a.plan_hash().len(), 16);
+    }
+
+    #[test]
+    fn rotate_moves_the_first_folder_to_the_end() {
+        let mut m = manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(2, "b.jpg", "b.jpg"), entry(3, "c.jpg", "c.jpg")]);
+        m.rotate();
+        let folders: Vec<usize> = m.files.iter().map(|e| e.folder).collect();
+        assert_eq!(folders, vec![3, 1, 2]);
+        assert_eq!(m.folders(), 3);
+        assert_eq!(m.folder_name(3), "3");
+
+        m.labels = vec!["a".to_string(), "b".to_string(), String::new()];
+        m.rotate();
+        assert_eq!((1..=3).map(|k| m.folder_name(k)).collect::<Vec<_>>(), vec!["01-b", "02", "03-a"]);
+    }
+
+    #[test]
+    fn input_fingerprint_ignores_order_but_not_sizes() {
+        let a = Input::of(vec![("a.jpg".to_string(), 1), ("b.jpg".to_string(), 2)]);
+        let b = Input::of(vec![("b.jpg".to_string(), 2), ("a.jpg".to_string(), 1)]);
+        let c = Input::of(vec![("a.jpg".to_string(), 1), ("b.jpg".to_string(), 3)]);
+        assert_eq!(a, b);
+        assert_eq!(a.files, 2);
+        assert_ne!(a.fingerprint, c.fingerprint);
+    }
+
+    #[test]
+    fn diff_matches_by_source_path() {
+        let old = manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(1, "b.jpg", "b.jpg"), entry(2, "c.jpg", "c.jpg")]);
+        let new = Manifest {
+            seed: 2,
+            ..manifest_of(vec![entry(1, "a.jpg", "a.jpg"), entry(2, "b.jpg", "b.jpg"), entry(2, "d.jpg", "d.jpg")])
+        };
+        let d = diff(&old, &new);
+        assert_eq!(d.added, vec![&new.files[2]]);
+        assert_eq!(d.removed, vec![&old.files[2]]);
+        assert_eq!(d.moved, vec![(&old.files[1], &new.files[1])]);
+        assert_eq!(d.unchanged, 1);
+    }
+}
@@ -0,0 +1,512 @@
+// Platform differences: default folders, Windows path limits, file names and
+// destination filesystems.
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use std::process::Command;
+
+use crate::nfc;
+
+/// `~/Pictures/theframe` (`%USERPROFILE%\Pictures\theframe` on Windows).
+pub fn default_src() -> PathBuf {
+    pictures_dir().join("theframe")
+}
+
+/// `~/Pictures/display` (`%USERPROFILE%\Pictures\display` on Windows).
+pub fn default_dst() -> PathBuf {
+    pictures_dir().join("display")
+}
+
+fn pictures_dir() -> PathBuf {
+    let home_var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    match env::var_os(home_var) {
+        Some(home) => PathBuf::from(home).join("Pictures"),
+        None => PathBuf::from("Pictures"),
+    }
+}
+
+/// Where image-rando keeps data it can rebuild: `$XDG_CACHE_HOME/image-rando`
+/// (`~/.cache/image-rando` by default), `~/Library/Caches/image-rando` on
+/// macOS and `%LOCALAPPDATA%\image-rando` on Windows.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(env::var_os("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?).join("Library/Caches")
+    } else {
+        match env::var_os("XDG_CACHE_HOME").map(PathBuf::from).filter(|p| p.is_absolute()) {
+            Some(dir) => dir,
+            None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        }
+    };
+    Some(base.join("image-rando"))
+}
+
+/// Turns `path` into an absolute path that is not subject to the 260
+/// character `MAX_PATH` limit on Windows (`\\?\C:\...`, `\\?\UNC\server\...`).
+/// Other platforms get the path back unchanged.
+pub fn long_path(path: &Path) -> Result<PathBuf, String> {
+    if !cfg!(windows) {
+        return Ok(path.to_path_buf());
+    }
+    let abs = std::path::absolute(path)
+        .map_err(|e| format!("cannot resolve path {}: {e}", path.display()))?;
+    match abs.to_str() {
+        Some(s) => Ok(PathBuf::from(extended_length(s))),
+        // Leave non-UTF-16-clean paths alone rather than mangle them.
+        None => Ok(abs),
+    }
+}
+
+/// `abs` must already be absolute and normalised (no `/`, `.` or `..`).
+fn extended_length(abs: &str) -> String {
+    if abs.starts_with(r"\\?\") {
+        abs.to_string()
+    } else if let Some(unc) = abs.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{unc}")
+    } else {
+        format!(r"\\?\{abs}")
+    }
+}
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes `name` writable on Windows (and FAT/exFAT/NTFS volumes): replaces
+/// forbidden characters, drops trailing dots/spaces and escapes reserved
+/// device names such as `CON.jpg`.
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if (c as u32) < 0x20 => '_',
+            c => c,
+        })
+        .collect();
+    while out.ends_with('.') || out.ends_with(' ') {
+        out.pop();
+    }
+    let stem = out.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r)) {
+        out.insert(0, '_');
+    }
+    if out.is_empty() {
+        out.push('_');
+    }
+    out
+}
+
+/// A [`sanitize_file_name`] result that also only uses ASCII letters, digits,
+/// `-`, `_` and `.`, for devices with poor Unicode support. Accents are
+/// dropped (`Café` becomes `Cafe`); anything else becomes `_`.
+pub fn ascii_file_name(name: &str) -> String {
+    let ascii: String = sanitize_file_name(name)
+        .chars()
+        .map(nfc::base_letter)
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    sanitize_file_name(&ascii)
+}
+
+/// The device and inode of the file at `path` (or a symlink's target), the
+/// same for every name it has, hard links and symlinks alike. On other
+/// platforms this is never known.
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Minutes the local time zone is ahead of UTC now, as the system reports
+/// it; there is no time zone database here.
+#[cfg(unix)]
+pub fn utc_offset_minutes() -> Option<i32> {
+    let out = Command::new("date").arg("+%z").output().ok()?;
+    crate::exif::parse_utc_offset(&String::from_utf8_lossy(&out.stdout)).map(i32::from)
+}
+
+#[cfg(windows)]
+pub fn utc_offset_minutes() -> Option<i32> {
+    let out = Command::new("powershell")
+        .args(["-NoProfile", "-Command", "(Get-Date).ToString('zzz')"])
+        .output()
+        .ok()?;
+    crate::exif::parse_utc_offset(&String::from_utf8_lossy(&out.stdout)).map(i32::from)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn utc_offset_minutes() -> Option<i32> {
+    None
+}
+
+/// Points the symlink `link` at `target` (relative to the link's folder),
+/// replacing the symlink that was there. Anything else at `link` is left
+/// alone and reported.
+pub fn replace_symlink(link: &Path, target: &Path) -> Result<(), String> {
+    if let Ok(meta) = fs::symlink_metadata(link) {
+        if !meta.file_type().is_symlink() {
+            return Err(format!("{} exists and is not a symlink; not replacing it", link.display()));
+        }
+    }
+    os_replace_symlink(link, target).map_err(|e| format!("cannot point {} at {}: {e}", link.display(), target.display()))
+}
+
+/// A new link renamed over the old one, so readers never see it missing.
+#[cfg(unix)]
+fn os_replace_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    let tmp = link.with_file_name(format!(".image-rando-link-{}", std::process::id()));
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(target, &tmp)?;
+    fs::rename(&tmp, link)
+}
+
+/// Directory symlinks can't be renamed over each other here, and creating
+/// them needs Developer Mode or administrator rights.
+#[cfg(windows)]
+fn os_replace_symlink(link: &Path, target: &Path) -> std::io::Result<()> {
+    match fs::remove_dir(link) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_replace_symlink(_link: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no symlinks on this platform"))
+}
+
+/// A new symlink `link` to the file `target` (relative to the link's
+/// folder).
+pub fn symlink_file(target: &Path, link: &Path) -> Result<(), String> {
+    os_symlink_file(target, link).map_err(|e| format!("cannot link {} to {}: {e}", link.display(), target.display()))
+}
+
+#[cfg(unix)]
+fn os_symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn os_symlink_file(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_symlink_file(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no symlinks on this platform"))
+}
+
+/// The type of the filesystem `dir` is on as the OS names it (`vfat`,
+/// `exfat`, `ext4` on Linux, `msdos`, `apfs` on macOS, `FAT32`, `NTFS` on
+/// Windows), when it can be found out.
+pub fn filesystem_type(dir: &Path) -> Option<String> {
+    let dir = fs::canonicalize(dir).ok()?;
+    os_filesystem_type(&dir)
+}
+
+#[cfg(target_os = "linux")]
+fn os_filesystem_type(dir: &Path) -> Option<String> {
+    mountinfo_type(&fs::read_to_string("/proc/self/mountinfo").ok()?, dir)
+}
+
+#[cfg(target_os = "macos")]
+fn os_filesystem_type(dir: &Path) -> Option<String> {
+    let out = Command::new("mount").output().ok()?;
+    mount_output_type(&String::from_utf8_lossy(&out.stdout), dir)
+}
+
+#[cfg(windows)]
+fn os_filesystem_type(dir: &Path) -> Option<String> {
+    // `\\?\D:\...` or `D:\...`; network shares have no drive letter to ask about.
+    let s = dir.to_str()?;
+    let s = s.strip_prefix(r"\\?\").unwrap_or(s);
+    let drive = s.get(..2).filter(|d| d.ends_with(':'))?;
+    let out = Command::new("fsutil").args(["fsinfo", "volumeinfo", drive]).output().ok()?;
+    String::from_utf8_lossy(&out.stdout).lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "File System Name").then(|| value.trim().to_string())
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn os_filesystem_type(_dir: &Path) -> Option<String> {
+    None
+}
+
+/// Picks the deepest mount containing `dir` from `/proc/self/mountinfo`:
+/// `ID PARENT MAJ:MIN ROOT MOUNTPOINT OPTIONS [TAGS...] - TYPE SOURCE ...`.
+#[cfg(any(target_os = "linux", test))]
+fn mountinfo_type(text: &str, dir: &Path) -> Option<String> {
+    text.lines()
+        .filter_map(|line| {
+            let mount_point = unescape_octal(line.split(' ').nth(4)?);
+            let (_, after) = line.split_once(" - ")?;
+            let fs_type = after.split(' ').next()?;
+            dir.starts_with(&mount_point).then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Mount points in mountinfo escape spaces and the like as `\040`.
+#[cfg(any(target_os = "linux", test))]
+fn unescape_octal(s: &str) -> String {
+    let mut out = Vec::new();
+    let b = s.as_bytes();
+    let mut i = 0;
+    while i < b.len() {
+        let code = b.get(i + 1..i + 4).and_then(|d| u8::from_str_radix(std::str::from_utf8(d).ok()?, 8).ok());
+        match code {
+            Some(c) if b[i] == b'\\' => {
+                out.push(c);
+                i += 4;
+            }
+            _ => {
+                out.push(b[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Picks the deepest mount containing `dir` from macOS `mount` output:
+/// `/dev/disk4s1 on /Volumes/NO NAME (msdos, local, nodev)`.
+#[cfg(any(target_os = "macos", test))]
+fn mount_output_type(text: &str, dir: &Path) -> Option<String> {
+    text.lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once(" on ")?;
+            let (mount_point, options) = rest.rsplit_once(" (")?;
+            let fs_type = options.split([',', ')']).next()?.trim();
+            dir.starts_with(mount_point).then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Largest single file a filesystem type holds, where that is small enough
+/// to matter: FAT12/16/32 stop one byte short of 4 GiB.
+pub fn max_file_size(fs_type: &str) -> Option<u64> {
+    let fat = ["vfat", "msdos", "fat", "fat12", "fat16", "fat32"];
+    fat.iter().any(|f| fs_type.eq_ignore_ascii_case(f)).then_some((4 << 30) - 1)
+}
+
+/// What a destination's filesystem turned out to accept when tried out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// `a.jpg` and `A.JPG` are different files.
+    pub case_sensitive: bool,
+    /// Longest file name, in UTF-8 bytes, that could be created (of those tried).
+    pub max_name_bytes: usize,
+    /// Characters of `:*?"<>|\` that a file name can't contain.
+    pub forbidden: Vec<char>,
+    /// Granularity of modification times, if they could be set at all.
+    pub timestamp_resolution: Option<Duration>,
+}
+
+const PROBE_CHARS: [char; 8] = [':', '*', '?', '"', '<', '>', '|', '\\'];
+const PROBE_NAME_BYTES: [usize; 5] = [255, 240, 200, 143, 100];
+
+impl Capabilities {
+    /// What any common filesystem accepts, for names written without
+    /// trying the destination out (`--emit-script`): FAT's rules.
+    pub fn portable() -> Capabilities {
+        Capabilities {
+            case_sensitive: false,
+            max_name_bytes: PROBE_NAME_BYTES[0],
+            forbidden: PROBE_CHARS.to_vec(),
+            timestamp_resolution: Some(Duration::from_secs(2)),
+        }
+    }
+
+    /// What two destinations both accept, for writing the same names to each.
+    pub fn merge(self, other: Capabilities) -> Capabilities {
+        let mut forbidden = self.forbidden;
+        for c in other.forbidden {
+            if !forbidden.contains(&c) {
+                forbidden.push(c);
+            }
+        }
+        Capabilities {
+            case_sensitive: self.case_sensitive && other.case_sensitive,
+            max_name_bytes: self.max_name_bytes.min(other.max_name_bytes),
+            forbidden,
+            timestamp_resolution: match (self.timestamp_resolution, other.timestamp_resolution) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                _ => None,
+            },
+        }
+    }
+
+    /// `case-insensitive, names up to 255 bytes, no : * ?, 2s timestamps`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![
+            if self.case_sensitive { "case-sensitive" } else { "case-insensitive" }.to_string(),
+            format!("names up to {} bytes", self.max_name_bytes),
+        ];
+        if !self.forbidden.is_empty() {
+            let chars: Vec<String> = self.forbidden.iter().map(char::to_string).collect();
+            parts.push(format!("no {}", chars.join(" ")));
+        }
+        match self.timestamp_resolution {
+            Some(r) if r >= Duration::from_secs(1) => parts.push(format!("{}s timestamps", r.as_secs())),
+            Some(r) if r >= Duration::from_millis(1) => parts.push(format!("{}ms timestamps", r.as_millis())),
+            Some(r) => parts.push(format!("{}ns timestamps", r.as_nanos())),
+            None => parts.push("no settable timestamps".to_string()),
+        }
+        parts.join(", ")
+    }
+}
+
+/// Finds out what `dir`'s filesystem accepts by creating (and removing)
+/// files in a scratch folder inside it.
+pub fn probe(dir: &Path) -> Result<Capabilities, String> {
+    let scratch = dir.join(format!(".image-rando-probe-{}", std::process::id()));
+    fs::create_dir_all(&scratch).map_err(|e| format!("cannot create {}: {e}", scratch.display()))?;
+    let caps = probe_in(&scratch);
+    if let Err(e) = fs::remove_dir_all(&scratch) {
+        eprintln!("warning: cannot remove {}: {e}", scratch.display());
+    }
+    caps
+}
+
+fn probe_in(scratch: &Path) -> Result<Capabilities, String> {
+    let create = |name: &str| File::create(scratch.join(name)).is_ok();
+    if !create("probe-case") {
+        return Err(format!("cannot write to {}", scratch.display()));
+    }
+    let case_sensitive = fs::symlink_metadata(scratch.join("PROBE-CASE")).is_err();
+    let max_name_bytes = PROBE_NAME_BYTES
+        .iter()
+        .copied()
+        .find(|&n| create(&format!("{}.jpg", "n".repeat(n - 4))))
+        .unwrap_or(64);
+    let forbidden = PROBE_CHARS
+        .iter()
+        .copied()
+        .filter(|&c| {
+            let name = format!("probe{c}x");
+            // Some filesystems "accept" the name but store something else.
+            !(create(&name) && scratch.join(&name).exists())
+        })
+        .collect();
+    Ok(Capabilities {
+        case_sensitive,
+        max_name_bytes,
+        forbidden,
+        timestamp_resolution: timestamp_resolution(&scratch.join("probe-case")),
+    })
+}
+
+/// Sets an odd modification time and sees how much of it sticks.
+fn timestamp_resolution(path: &Path) -> Option<Duration> {
+    let set = UNIX_EPOCH + Duration::new(1_700_000_001, 123_456_789);
+    File::options().write(true).open(path).ok()?.set_modified(set).ok()?;
+    let read = fs::metadata(path).ok()?.modified().ok()?;
+    let nanos = read.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    let steps = [2_000_000_000, 1_000_000_000, 10_000_000, 1_000_000, 1_000, 100];
+    let step = steps.iter().copied().find(|&step| nanos % step == 0).unwrap_or(1);
+    Some(Duration::from_nanos(step as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extended_length_handles_drives_and_unc() {
+        assert_eq!(extended_length(r"C:\Pictures"), r"\\?\C:\Pictures");
+        assert_eq!(extended_length(r"\\nas\photos\a"), r"\\?\UNC\nas\photos\a");
+        assert_eq!(extended_length(r"\\?\D:\x"), r"\\?\D:\x");
+    }
+
+    #[test]
+    fn sanitize_file_name_escapes_reserved_and_forbidden() {
+        assert_eq!(sanitize_file_name("CON.jpg"), "_CON.jpg");
+        assert_eq!(sanitize_file_name("com1"), "_com1");
+        assert_eq!(sanitize_file_name("Console.jpg"), "Console.jpg");
+        assert_eq!(sanitize_file_name("12:30 \"x\"?.jpg"), "12_30 _x__.jpg");
+        assert_eq!(sanitize_file_name("trailing. "), "trailing");
+    }
+
+    #[test]
+    fn filesystem_type_uses_the_deepest_mount() {
+        let mountinfo = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+98 22 8:17 / /media/pi/NO\\040NAME rw,nosuid shared:50 - vfat /dev/sdb1 rw,uid=1000
+";
+        assert_eq!(mountinfo_type(mountinfo, Path::new("/media/pi/NO NAME/1")).as_deref(), Some("vfat"));
+        assert_eq!(mountinfo_type(mountinfo, Path::new("/home/pi")).as_deref(), Some("ext4"));
+        // A sibling whose name merely starts the same is not inside the mount.
+        assert_eq!(mountinfo_type(mountinfo, Path::new("/media/pi/NO NAMES")).as_deref(), Some("ext4"));
+
+        let mount = "\
+/dev/disk3s1s1 on / (apfs, sealed, local, read-only, journaled)
+/dev/disk4s1 on /Volumes/NO NAME (msdos, local, nodev, nosuid, noowners)
+";
+        assert_eq!(mount_output_type(mount, Path::new("/Volumes/NO NAME/x")).as_deref(), Some("msdos"));
+        assert_eq!(mount_output_type(mount, Path::new("/Users/me")).as_deref(), Some("apfs"));
+
+        assert_eq!(max_file_size("FAT32"), Some(4_294_967_295));
+        assert_eq!(max_file_size("exfat"), None);
+    }
+
+    #[test]
+    fn probe_reports_what_the_temp_folder_accepts() {
+        let dir = env::temp_dir().join(format!("image-rando-probe-test-{}", std::process::id()));
+        let caps = probe(&dir).unwrap();
+        assert!(caps.max_name_bytes >= 100);
+        assert!(caps.timestamp_resolution.is_some());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0, "scratch folder left behind");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let fat = Capabilities {
+            case_sensitive: false,
+            max_name_bytes: 255,
+            forbidden: vec![':', '?'],
+            timestamp_resolution: Some(Duration::from_secs(2)),
+        };
+        let ext4 = Capabilities { case_sensitive: true, forbidden: Vec::new(), timestamp_resolution: Some(Duration::from_nanos(1)), ..fat.clone() };
+        assert_eq!(ext4.merge(fat.clone()), fat);
+        assert_eq!(fat.describe(), "case-insensitive, names up to 255 bytes, no : ?, 2s timestamps");
+    }
+
+    #[test]
+    fn ascii_file_name_strips_accents_and_symbols() {
+        assert_eq!(ascii_file_name("Café Ångström.jpg"), "Cafe_Angstrom.jpg");
+        assert_eq!(ascii_file_name("海 1.jpg"), "__1.jpg");
+        assert_eq!(ascii_file_name("NUL.jpg"), "_NUL.jpg");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_symlink_switches_the_link_but_not_a_folder() {
+        let root = env::temp_dir().join(format!("image-rando-link-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(root.join("b")).unwrap();
+        let link = root.join("latest");
+        replace_symlink(&link, Path::new("a")).unwrap();
+        replace_symlink(&link, Path::new("b")).unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("b"));
+        assert!(replace_symlink(&root.join("a"), Path::new("b")).is_err());
+        assert!(root.join("a").is_dir());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+// A small regular expression engine for name filters.
+//
+// Supports the everyday subset: literals, `.`, classes (`[a-z]`, `[^0-9]`,
+// `\d \w \s` and their negations), anchors `^ $ \b`, groups `(...)`/`(?:...)`,
+// alternation, the quantifiers `* + ? {n} {n,} {n,m}` (lazy forms accepted)
+// and a leading `(?i)` for case-insensitive matching. Patterns are compiled to
+// an NFA and run as a Pike VM, so matching time is linear in the input and
+// pathological patterns can't hang a scan.
+
+/// Refuse to expand `{n,m}` repetitions beyond this many instructions.
+const MAX_PROGRAM: usize = 10_000;
+
+/// Largest count `{n,m}` may give, as in RE2.
+const MAX_REPEAT: u32 = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+    classes: Vec<Class>,
+    case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(usize),
+    Assert(Assertion),
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let (case_insensitive, body) = match pattern.strip_prefix("(?i)") {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: body.chars().collect(),
+            pos: 0,
+        };
+        let ast = parser.parse_alt()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched ')' in regex: {pattern}"));
+        }
+        let mut compiler = Compiler::default();
+        compiler.emit_node(&ast)?;
+        compiler.prog.push(Inst::Match);
+        Ok(Regex {
+            prog: compiler.prog,
+            classes: compiler.classes,
+            case_insensitive,
+        })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current: Vec<usize> = Vec::new();
+        let mut next: Vec<usize> = Vec::new();
+        let mut on_list = vec![usize::MAX; self.prog.len()];
+
+        for pos in 0..=chars.len() {
+            // Unanchored search: a new thread may start at every position.
+            self.add_thread(&mut current, &mut on_list, 0, pos, &chars);
+            if current.iter().any(|&pc| matches!(self.prog[pc], Inst::Match)) {
+                return true;
+            }
+            let Some(&c) = chars.get(pos) else {
+                break;
+            };
+            next.clear();
+            let generation = pos + 1;
+            for &pc in &current {
+                let advance = match self.prog[pc] {
+                    Inst::Char(want) => self.char_eq(want, c),
+                    Inst::Any => true,
+                    Inst::Class(idx) => self.class_matches(&self.classes[idx], c),
+                    _ => false,
+                };
+                if advance {
+                    self.add_thread(&mut next, &mut on_list, pc + 1, generation, &chars);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    /// Adds `pc` and everything reachable from it without consuming input.
+    /// `on_list[pc] == pos` marks instructions already queued for this step.
+    fn add_thread(&self, list: &mut Vec<usize>, on_list: &mut [usize], pc: usize, pos: usize, chars: &[char]) {
+        if on_list[pc] == pos {
+            return;
+        }
+        on_list[pc] = pos;
+        match self.prog[pc] {
+            Inst::Jmp(to) => self.add_thread(list, on_list, to, pos, chars),
+            Inst::Split(a, b) => {
+                self.add_thread(list, on_list, a, pos, chars);
+                self.add_thread(list, on_list, b, pos, chars);
+            }
+            Inst::Assert(a) => {
+                if assertion_holds(a, pos, chars) {
+                    self.add_thread(list, on_list, pc + 1, pos, chars);
+                }
+            }
+            _ => list.push(pc),
+        }
+    }
+
+    fn char_eq(&self, want: char, c: char) -> bool {
+        want == c || (self.case_insensitive && fold(want) == fold(c))
+    }
+
+    fn class_matches(&self, class: &Class, c: char) -> bool {
+        let hit = |c: char| class.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let mut found = hit(c);
+        if !found && self.case_insensitive {
+            found = c.to_lowercase().any(hit) || c.to_uppercase().any(hit);
+        }
+        found != class.negated
+    }
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn assertion_holds(a: Assertion, pos: usize, chars: &[char]) -> bool {
+    match a {
+        Assertion::Start => pos == 0,
+        Assertion::End => pos == chars.len(),
+        Assertion::WordBoundary | Assertion::NotWordBoundary => {
+            let before = pos > 0 && is_word(chars[pos - 1]);
+            let after = pos < chars.len() && is_word(chars[pos]);
+            (before != after) == (a == Assertion::WordBoundary)
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            items.push(self.parse_quantifiers(atom)?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn parse_quantifiers(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.parse_braces()? {
+                    Some(bounds) => bounds,
+                    None => return Ok(node),
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(node),
+            };
+            // Lazy quantifiers match the same strings for `is_match`.
+            self.eat('?');
+            if matches!(node, Node::Assert(_) | Node::Empty) {
+                return Err("nothing to repeat in regex".to_string());
+            }
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`; a `{` not starting a valid bound is
+    /// left alone so it can be read as a literal.
+    fn parse_braces(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let rest: String = self.chars[self.pos..].iter().collect();
+        let Some(end) = rest.find('}') else {
+            return Ok(None);
+        };
+        let inner = &rest[1..end];
+        let parse = |s: &str| s.parse::<u32>().ok();
+        let bounds = match inner.split_once(',') {
+            None => parse(inner).map(|n| (n, Some(n))),
+            Some((lo, "")) => parse(lo).map(|n| (n, None)),
+            Some((lo, hi)) => match (parse(lo), parse(hi)) {
+                (Some(lo), Some(hi)) if lo <= hi => Some((lo, Some(hi))),
+                (Some(_), Some(_)) => return Err(format!("invalid repetition {{{inner}}} in regex")),
+                _ => None,
+            },
+        };
+        if let Some((lo, hi)) = bounds {
+            if hi.unwrap_or(lo) > MAX_REPEAT {
+                return Err(format!("repetition {{{inner}}} is over {MAX_REPEAT} in regex"));
+            }
+            self.pos += inner.chars().count() + 2;
+        }
+        Ok(bounds)
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("unexpected end of regex")?;
+        self.pos += 1;
+        match c {
+            '(' => {
+                if self.eat('?') && !self.eat(':') {
+                    return Err("only (?:...) groups and a leading (?i) are supported in regex".to_string());
+                }
+                let inner = self.parse_alt()?;
+                if !self.eat(')') {
+                    return Err("missing ')' in regex".to_string());
+                }
+                Ok(inner)
+            }
+            '[' => self.parse_class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Assert(Assertion::Start)),
+            '$' => Ok(Node::Assert(Assertion::End)),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' => Err("nothing to repeat in regex".to_string()),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("trailing backslash in regex")?;
+        self.pos += 1;
+        Ok(match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            'd' | 'w' | 's' | 'D' | 'W' | 'S' => Node::Class(Class {
+                ranges: perl_class(c.to_ascii_lowercase()),
+                negated: c.is_ascii_uppercase(),
+            }),
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            c if c.is_ascii_alphanumeric() => {
+                return Err(format!("unsupported escape \\{c} in regex"));
+            }
+            c => Node::Char(c),
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("missing ']' in regex")?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or("trailing backslash in regex")?;
+                self.pos += 1;
+                if matches!(e, 'd' | 'w' | 's') {
+                    ranges.extend(perl_class(e));
+                    continue;
+                }
+                match e {
+                    'n' => '\n',
+                    't' => '\t',
+                    e => e,
+                }
+            } else {
+                c
+            };
+            // A '-' right before ']' is literal.
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let mut hi = self.peek().ok_or("missing ']' in regex")?;
+                self.pos += 1;
+                if hi == '\\' {
+                    hi = self.peek().ok_or("trailing backslash in regex")?;
+                    self.pos += 1;
+                }
+                if hi < lo {
+                    return Err(format!("invalid class range {lo}-{hi} in regex"));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class(Class { ranges, negated }))
+    }
+}
+
+fn perl_class(c: char) -> Vec<(char, char)> {
+    match c {
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        _ => vec![(' ', ' '), ('\t', '\r')],
+    }
+}
+
+#[derive(Default)]
+struct Compiler {
+    prog: Vec<Inst>,
+    classes: Vec<Class>,
+}
+
+impl Compiler {
+    fn emit_node(&mut self, node: &Node) -> Result<(), String> {
+        if self.prog.len() > MAX_PROGRAM {
+            return Err("regex is too large".to_string());
+        }
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => self.prog.push(Inst::Char(*c)),
+            Node::Any => self.prog.push(Inst::Any),
+            Node::Class(class) => {
+                self.classes.push(class.clone());
+                self.prog.push(Inst::Class(self.classes.len() - 1));
+            }
+            Node::Assert(a) => self.prog.push(Inst::Assert(*a)),
+            Node::Concat(items) => {
+                for item in items {
+                    self.emit_node(item)?;
+                }
+            }
+            Node::Alt(branches) => {
+                // split L1, next; L1: branch; jmp end; next: ...
+                let mut jumps = Vec::new();
+                for (k, branch) in branches.iter().enumerate() {
+                    if k + 1 < branches.len() {
+                        let split = self.prog.len();
+                        self.prog.push(Inst::Split(split + 1, 0));
+                        self.emit_node(branch)?;
+                        jumps.push(self.prog.len());
+                        self.prog.push(Inst::Jmp(0));
+                        let next = self.prog.len();
+                        self.prog[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.emit_node(branch)?;
+                    }
+                }
+                let end = self.prog.len();
+                for j in jumps {
+                    self.prog[j] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    let before = self.prog.len();
+                    self.emit_node(node)?;
+                    // More copies of a body that emits nothing (`()`) add
+                    // nothing either, however many `min` asks for.
+                    if self.prog.len() == before {
+                        break;
+                    }
+                }
+                match max {
+                    None => {
+                        // loop: split body, end; body; jmp loop
+                        let split = self.prog.len();
+                        self.prog.push(Inst::Split(split + 1, 0));
+                        self.emit_node(node)?;
+                        self.prog.push(Inst::Jmp(split));
+                        let end = self.prog.len();
+                        self.prog[split] = Inst::Split(split + 1, end);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.prog.len());
+                            self.prog.push(Inst::Split(0, 0));
+                            self.emit_node(node)?;
+                        }
+                        let end = self.prog.len();
+                        for s in splits {
+                            self.prog[s] = Inst::Split(s + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn matches_the_camera_roll_junk_example() {
+        let re = Regex::new("(?i)screenshot|whatsapp|meme").unwrap();
+        assert!(re.is_match("Screenshot_20240101.jpg"));
+        assert!(re.is_match("IMG-20240101-WA0001 whatsapp.jpg"));
+        assert!(!re.is_match("IMG_0001.jpg"));
+    }
+
+    #[test]
+    fn supports_classes_anchors_and_repetition() {
+        assert!(m(r"^IMG_\d{4}\.jpe?g$", "IMG_0042.jpeg"));
+        assert!(!m(r"^IMG_\d{4}\.jpe?g$", "IMG_042.jpg"));
+        assert!(m(r"[^a-z]+x", "ABCx"));
+        assert!(m(r"\bcat\b", "a cat.jpg"));
+        assert!(!m(r"\bcat\b", "concatenate"));
+        assert!(m(r"(?:ab)+c", "xxababc"));
+        assert!(m(r"a{2,3}?", "aa"));
+        assert!(m(r"x{", "x{"));
+    }
+
+    #[test]
+    fn is_linear_on_pathological_patterns() {
+        let text = "a".repeat(5000);
+        assert!(!m("(a*)*b", &text));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for bad in ["(abc", "abc)", "[a-", "*a", r"\q", "(?<n>x)", "a{1001}", "((()()){4294967295}){4294967295}"] {
+            assert!(Regex::new(bad).is_err(), "{bad}");
+        }
+        // Allowed counts of an empty body compile at once.
+        assert!(m("x(((()()){1000}){1000}){1000}y", "xy"));
+    }
+}
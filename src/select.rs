@@ -0,0 +1,396 @@
+// `--select` expressions: one filter over a photo's metadata, for selections
+// that would otherwise take a stack of separate flags.
+//
+//   rating>=4 && (keyword:"beach" || keyword:lake) && year>=2020
+//
+// Comparisons are `FIELD OP NUMBER` with OP one of `= != < <= > >=`, on
+// `rating` (XMP, else EXIF), `year`, `month`, `day`, `width`, `height` (as
+// displayed), `megapixels` and `size` (which takes units, as in `size>2MB`).
+// Matches are `FIELD:TEXT`, quoted when it has spaces or operators in it, and
+// ignore case: `keyword`, `person`, `album` (the folder or one below it)
+// compare whole values, `camera` and `name` look for TEXT anywhere, and
+// `orientation` is `landscape`, `portrait` or `square`. `!` negates, `&&`
+// binds tighter than `||`, and a comparison on something the photo doesn't
+// have (no date, no rating) is false.
+
+use crate::{parse_byte_size, FileInfo};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector(Expr);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Number, Op, f64),
+    Match(Text, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Rating,
+    Year,
+    Month,
+    Day,
+    Width,
+    Height,
+    Megapixels,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Text {
+    Keyword,
+    Person,
+    Album,
+    Camera,
+    Name,
+    Orientation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Selector {
+    pub fn parse(s: &str) -> Result<Selector, String> {
+        let mut p = Parser { s: s.as_bytes(), pos: 0 };
+        let expr = p.or()?;
+        p.skip_ws();
+        if p.pos < p.s.len() {
+            return Err(p.error("expected && or ||"));
+        }
+        Ok(Selector(expr))
+    }
+
+    /// Both this and `other` (repeated `--select` flags).
+    pub fn and(self, other: Selector) -> Selector {
+        Selector(Expr::And(Box::new(self.0), Box::new(other.0)))
+    }
+
+    pub fn matches(&self, f: &FileInfo) -> bool {
+        eval(&self.0, f)
+    }
+
+    /// Whether the photos' EXIF fields must be loaded first.
+    pub fn uses_exif(&self) -> bool {
+        uses(&self.0, &|e| match e {
+            Expr::Compare(n, ..) => !matches!(n, Number::Size),
+            Expr::Match(t, _) => matches!(t, Text::Camera | Text::Orientation),
+            _ => false,
+        })
+    }
+
+    /// Whether the photos' XMP fields must be loaded first.
+    pub fn uses_xmp(&self) -> bool {
+        uses(&self.0, &|e| match e {
+            Expr::Compare(n, ..) => matches!(n, Number::Rating),
+            Expr::Match(t, _) => matches!(t, Text::Keyword | Text::Person),
+            _ => false,
+        })
+    }
+}
+
+fn uses(e: &Expr, leaf: &impl Fn(&Expr) -> bool) -> bool {
+    match e {
+        Expr::And(a, b) | Expr::Or(a, b) => uses(a, leaf) || uses(b, leaf),
+        Expr::Not(a) => uses(a, leaf),
+        _ => leaf(e),
+    }
+}
+
+fn eval(e: &Expr, f: &FileInfo) -> bool {
+    match e {
+        Expr::And(a, b) => eval(a, f) && eval(b, f),
+        Expr::Or(a, b) => eval(a, f) || eval(b, f),
+        Expr::Not(a) => !eval(a, f),
+        Expr::Compare(n, op, want) => {
+            let Some(have) = number(*n, f) else {
+                return false;
+            };
+            match op {
+                Op::Eq => have == *want,
+                Op::Ne => have != *want,
+                Op::Lt => have < *want,
+                Op::Le => have <= *want,
+                Op::Gt => have > *want,
+                Op::Ge => have >= *want,
+            }
+        }
+        Expr::Match(t, want) => {
+            let same = |have: &String| have.eq_ignore_ascii_case(want);
+            let contains = |have: &str| have.to_lowercase().contains(&want.to_lowercase());
+            match t {
+                Text::Keyword => f.xmp.keywords.iter().any(same),
+                Text::Person => f.xmp.people.iter().any(same),
+                Text::Album => {
+                    let album = f.album.to_lowercase();
+                    let want = want.trim_matches('/').to_lowercase();
+                    album == want || album.starts_with(&format!("{want}/"))
+                }
+                Text::Camera => f.exif.camera.as_deref().is_some_and(contains),
+                Text::Name => contains(&f.name),
+                Text::Orientation => f.exif.display_dimensions().is_some_and(|(w, h)| {
+                    let shape = match w.cmp(&h) {
+                        std::cmp::Ordering::Greater => "landscape",
+                        std::cmp::Ordering::Less => "portrait",
+                        std::cmp::Ordering::Equal => "square",
+                    };
+                    want.eq_ignore_ascii_case(shape)
+                }),
+            }
+        }
+    }
+}
+
+fn number(n: Number, f: &FileInfo) -> Option<f64> {
+    let date = f.exif.captured;
+    let dims = f.exif.display_dimensions();
+    Some(match n {
+        Number::Rating => f.xmp.rating.map(f64::from).or(f.exif.rating.map(f64::from))?,
+        Number::Year => f64::from(date?.year),
+        Number::Month => f64::from(date?.month),
+        Number::Day => f64::from(date?.day),
+        Number::Width => f64::from(dims?.0),
+        Number::Height => f64::from(dims?.1),
+        Number::Megapixels => dims.map(|(w, h)| f64::from(w) * f64::from(h) / 1e6)?,
+        Number::Size => f.size as f64,
+    })
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String {
+        format!("{msg} at column {} of: {}", self.pos + 1, String::from_utf8_lossy(self.s))
+    }
+
+    fn skip_ws(&mut self) {
+        while self.s.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        if self.s[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.eat("||") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.eat("&&") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        // `!=` only ever follows a field name, so a `!` here negates.
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat("(") {
+            let inner = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected )"));
+            }
+            return Ok(inner);
+        }
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self.s.get(self.pos).is_some_and(|b| b.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let field = String::from_utf8_lossy(&self.s[start..self.pos]).to_ascii_lowercase();
+        if field.is_empty() {
+            return Err(self.error("expected a field name"));
+        }
+        if self.s.get(self.pos) == Some(&b':') {
+            self.pos += 1;
+            let text = match field.as_str() {
+                "keyword" => Text::Keyword,
+                "person" => Text::Person,
+                "album" => Text::Album,
+                "camera" => Text::Camera,
+                "name" => Text::Name,
+                "orientation" => Text::Orientation,
+                _ => {
+                    self.pos = start;
+                    return Err(self.error(&format!("unknown text field {field:?}")));
+                }
+            };
+            let value = self.value()?;
+            let shape = value.to_ascii_lowercase();
+            if text == Text::Orientation && !["landscape", "portrait", "square"].contains(&shape.as_str()) {
+                return Err(self.error("orientation is landscape, portrait or square"));
+            }
+            return Ok(Expr::Match(text, value));
+        }
+        let number = match field.as_str() {
+            "rating" => Number::Rating,
+            "year" => Number::Year,
+            "month" => Number::Month,
+            "day" => Number::Day,
+            "width" => Number::Width,
+            "height" => Number::Height,
+            "megapixels" => Number::Megapixels,
+            "size" => Number::Size,
+            _ => {
+                self.pos = start;
+                return Err(self.error(&format!("unknown field {field:?}")));
+            }
+        };
+        let op = if self.eat("<=") {
+            Op::Le
+        } else if self.eat(">=") {
+            Op::Ge
+        } else if self.eat("!=") {
+            Op::Ne
+        } else if self.eat("==") || self.eat("=") {
+            Op::Eq
+        } else if self.eat("<") {
+            Op::Lt
+        } else if self.eat(">") {
+            Op::Gt
+        } else {
+            return Err(self.error(&format!("expected a comparison after {field}")));
+        };
+        let at = self.pos;
+        let value = self.value()?;
+        let parsed = if number == Number::Size {
+            parse_byte_size(&value).map(|n| n as f64)
+        } else {
+            value.parse::<f64>().ok().filter(|v| v.is_finite())
+        };
+        parsed.ok_or_else(|| {
+            self.pos = at;
+            self.error(&format!("{field} needs a number, not {value:?}"))
+        })
+        .map(|v| Expr::Compare(number, op, v))
+    }
+
+    /// A `"quoted"` string (with `\"` and `\\`) or a bare word.
+    fn value(&mut self) -> Result<String, String> {
+        self.skip_ws();
+        let mut out = Vec::new();
+        if self.s.get(self.pos) == Some(&b'"') {
+            self.pos += 1;
+            loop {
+                match self.s.get(self.pos) {
+                    None => return Err(self.error("unclosed quote")),
+                    Some(b'"') => break,
+                    Some(b'\\') if matches!(self.s.get(self.pos + 1), Some(b'"' | b'\\')) => {
+                        out.push(self.s[self.pos + 1]);
+                        self.pos += 2;
+                        continue;
+                    }
+                    Some(&b) => out.push(b),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+        } else {
+            while let Some(&b) = self.s.get(self.pos) {
+                if b.is_ascii_whitespace() || matches!(b, b'(' | b')' | b'&' | b'|' | b'!') {
+                    break;
+                }
+                out.push(b);
+                self.pos += 1;
+            }
+            if out.is_empty() {
+                return Err(self.error("expected a value"));
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{exif, xmp, ImageFormat, MediaKind};
+    use std::path::PathBuf;
+
+    fn photo(rating: Option<i8>, keywords: &[&str], captured: &str) -> FileInfo {
+        FileInfo {
+            path: PathBuf::from("Trips/Lake/a.jpg"),
+            name: "a.jpg".to_string(),
+            size: 3_000_000,
+            kind: MediaKind::Photo,
+            format: Some(ImageFormat::Jpeg),
+            album: "Trips/Lake".to_string(),
+            exif: exif::Exif {
+                captured: exif::DateTime::parse(captured),
+                dimensions: Some((4000, 3000)),
+                orientation: Some(6),
+                ..Default::default()
+            },
+            xmp: xmp::Xmp {
+                rating,
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+                ..Default::default()
+            },
+            sidecars: Vec::new(),
+            flatten: false,
+        }
+    }
+
+    #[test]
+    fn evaluates_the_example_with_precedence_and_grouping() {
+        let s = Selector::parse(r#"rating>=4 && (keyword:"beach" || keyword:lake) && year>=2020"#).unwrap();
+        assert!(s.matches(&photo(Some(5), &["Lake"], "2021:06:01 10:00:00")));
+        assert!(!s.matches(&photo(Some(3), &["beach"], "2021:06:01 10:00:00")));
+        assert!(!s.matches(&photo(Some(4), &["forest"], "2021:06:01 10:00:00")));
+        assert!(!s.matches(&photo(Some(4), &["beach"], "2019:06:01 10:00:00")));
+        // No date: year comparisons fail, and their negation holds.
+        assert!(!s.matches(&photo(Some(4), &["beach"], "")));
+        assert!(Selector::parse("!year>=2020").unwrap().matches(&photo(None, &[], "")));
+        assert!(s.uses_exif() && s.uses_xmp());
+    }
+
+    #[test]
+    fn text_fields_sizes_and_orientation() {
+        let p = photo(None, &[], "2021:06:01 10:00:00");
+        let yes = |e: &str| Selector::parse(e).unwrap().matches(&p);
+        assert!(yes("album:trips && album:Trips/Lake/ && !album:Trip"));
+        assert!(yes("orientation:portrait && height=4000 && megapixels>=12"));
+        assert!(yes("size>2MB && size<=3000000 && name:A.JPG"));
+        assert!(!yes("rating>=0 || camera:iphone"));
+        assert!(!Selector::parse("size<1GB").unwrap().uses_exif());
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        let bad = ["rating>=", "colour:red", "year>=twenty", "(rating>1", "keyword:\"beach", "orientation:up", "rating>1 rating<3"];
+        for bad in bad {
+            assert!(Selector::parse(bad).unwrap_err().contains("at column"), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// `--rename-template` parsing and rendering.
+//
+// A template is literal text with `{placeholder}` fields; the file extension
+// is never part of it and is kept from the source name.
+
+use crate::exif::DateTime;
+
+/// Format used by a bare `{exif_date}`.
+const DEFAULT_DATE_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// Source file name without its extension.
+    Orig,
+    /// Capture date, rendered with a strftime-style format.
+    Date(Vec<DatePart>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DatePart {
+    Char(char),
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+/// What a template can refer to for one file.
+pub struct Fields<'a> {
+    pub orig: &'a str,
+    pub date: Option<DateTime>,
+}
+
+impl Template {
+    /// Placeholders: `{orig}`, `{exif_date}` and `{exif_date:FORMAT}` where
+    /// FORMAT understands `%Y %y %m %d %H %M %S %%`. `{{` and `}}` are
+    /// literal braces.
+    pub fn parse(s: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            // `{exif_date:%Y/%m}` would put files in subfolders too.
+                            Some('/' | '\\') => return Err("template must not contain path separators".to_string()),
+                            Some(c) => field.push(c),
+                            None => return Err(format!("unclosed {{ in template: {s}")),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_field(&field)?);
+                }
+                '}' => return Err(format!("unmatched }} in template: {s}")),
+                '/' | '\\' => return Err("template must not contain path separators".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if parts.is_empty() {
+            return Err("template is empty".to_string());
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn uses_date(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p, Part::Date(_)))
+    }
+
+    /// The new file stem. Undated files render date fields as `undated`.
+    pub fn render(&self, fields: &Fields) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Orig => out.push_str(fields.orig),
+                Part::Date(format) => match fields.date {
+                    Some(dt) => render_date(&mut out, format, dt),
+                    None => out.push_str("undated"),
+                },
+            }
+        }
+        out
+    }
+}
+
+fn parse_field(field: &str) -> Result<Part, String> {
+    let (name, arg) = match field.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (field, None),
+    };
+    match (name, arg) {
+        ("orig", None) => Ok(Part::Orig),
+        ("exif_date", format) => Ok(Part::Date(parse_date_format(
+            format.unwrap_or(DEFAULT_DATE_FORMAT),
+        )?)),
+        _ => Err(format!(
+            "unknown template field: {{{field}}} (expected {{orig}} or {{exif_date[:FORMAT]}})"
+        )),
+    }
+}
+
+fn parse_date_format(format: &str) -> Result<Vec<DatePart>, String> {
+    let mut out = Vec::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(DatePart::Char(c));
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('Y') => DatePart::Year,
+            Some('y') => DatePart::ShortYear,
+            Some('m') => DatePart::Month,
+            Some('d') => DatePart::Day,
+            Some('H') => DatePart::Hour,
+            Some('M') => DatePart::Minute,
+            Some('S') => DatePart::Second,
+            Some('%') => DatePart::Char('%'),
+            Some(other) => return Err(format!("unsupported date format directive: %{other}")),
+            None => return Err("date format ends with a lone %".to_string()),
+        });
+    }
+    Ok(out)
+}
+
+fn render_date(out: &mut String, format: &[DatePart], dt: DateTime) {
+    for part in format {
+        match part {
+            DatePart::Char(c) => out.push(*c),
+            DatePart::Year => out.push_str(&format!("{:04}", dt.year)),
+            DatePart::ShortYear => out.push_str(&format!("{:02}", dt.year % 100)),
+            DatePart::Month => out.push_str(&format!("{:02}", dt.month)),
+            DatePart::Day => out.push_str(&format!("{:02}", dt.day)),
+            DatePart::Hour => out.push_str(&format!("{:02}", dt.hour)),
+            DatePart::Minute => out.push_str(&format!("{:02}", dt.minute)),
+            DatePart::Second => out.push_str(&format!("{:02}", dt.second)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: DateTime = DateTime {
+        year: 2019,
+        month: 7,
+        day: 4,
+        hour: 9,
+        minute: 5,
+        second: 30,
+    };
+
+    #[test]
+    fn renders_date_and_original_name() {
+        let t = Template::parse("{exif_date:%Y%m%d_%H%M%S}_{orig}").unwrap();
+        let fields = Fields { orig: "IMG_0001", date: Some(DT) };
+        assert_eq!(t.render(&fields), "20190704_090530_IMG_0001");
+        let undated = Fields { orig: "IMG_0001", date: None };
+        assert_eq!(t.render(&undated), "undated_IMG_0001");
+
+        let t = Template::parse("{{{exif_date}}} %y").unwrap();
+        assert_eq!(t.render(&fields), "{20190704_090530} %y");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for bad in ["", "{nope}", "{orig", "a}b", "{exif_date:%Q}", "{orig}/x", "{exif_date:%}", "{exif_date:%Y/%m}", "{exif_date:%Y\\%m}"] {
+            assert!(Template::parse(bad).is_err(), "{bad}");
+        }
+    }
+}
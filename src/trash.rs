@@ -0,0 +1,204 @@
+// Moving deleted destination content to the desktop trash (`--use-trash`).
+//
+// Linux and other Unix desktops follow the freedesktop.org trash spec: the
+// home trash for files on the home filesystem, `$topdir/.Trash-$uid` for
+// files on other drives (a frame's USB stick, typically), so nothing is
+// copied across devices. macOS uses `~/.Trash` (`.Trashes/$uid` on other
+// volumes) and Windows the Recycle Bin, through PowerShell.
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::fs;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::process::Command;
+#[cfg(unix)]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use crate::exif;
+
+/// Moves the file or folder at `path` to the trash.
+pub fn trash(path: &Path) -> Result<(), String> {
+    let abs = std::path::absolute(path).map_err(|e| format!("cannot resolve {}: {e}", path.display()))?;
+    os_trash(&abs).map_err(|e| format!("cannot move {} to the trash: {e}", path.display()))
+}
+
+#[cfg(unix)]
+fn os_trash(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let home = env::var_os("HOME").map(PathBuf::from).ok_or("HOME is not set")?;
+    let dev = fs::symlink_metadata(path).map_err(|e| e.to_string())?.dev();
+    let uid = fs::metadata(&home).map_err(|e| e.to_string())?.uid();
+    let home_trash = home_trash(&home);
+    let on_home_device = nearest_existing(&home_trash).is_some_and(|p| fs::metadata(p).is_ok_and(|m| m.dev() == dev));
+    if on_home_device {
+        return move_into_trash(&home_trash, path, &path.to_string_lossy());
+    }
+    let top = topdir(path, dev);
+    let trash = if cfg!(target_os = "macos") {
+        top.join(".Trashes").join(uid.to_string())
+    } else {
+        top.join(format!(".Trash-{uid}"))
+    };
+    // The spec records paths below the drive's top folder relative to it.
+    let recorded = path.strip_prefix(&top).unwrap_or(path).to_string_lossy().into_owned();
+    move_into_trash(&trash, path, &recorded)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn home_trash(home: &Path) -> PathBuf {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home.join(".local/share"));
+    data_home.join("Trash")
+}
+
+#[cfg(target_os = "macos")]
+fn home_trash(home: &Path) -> PathBuf {
+    home.join(".Trash")
+}
+
+/// `path` or its closest ancestor that exists.
+#[cfg(unix)]
+fn nearest_existing(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.exists())
+}
+
+/// The highest folder above `path` still on device `dev`: the mount point.
+#[cfg(unix)]
+fn topdir(path: &Path, dev: u64) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut top = path.parent().unwrap_or(path);
+    while let Some(parent) = top.parent() {
+        if !fs::metadata(parent).is_ok_and(|m| m.dev() == dev) {
+            break;
+        }
+        top = parent;
+    }
+    top.to_path_buf()
+}
+
+/// Moves `path` into `trash` under a name not used there yet. Outside macOS
+/// it goes to `files/`, with a `.trashinfo` file in `info/` that records
+/// where it came from (`recorded`) and when, so file managers can restore it.
+#[cfg(unix)]
+fn move_into_trash(trash: &Path, path: &Path, recorded: &str) -> Result<(), String> {
+    let name = path.file_name().ok_or("nothing to trash")?.to_string_lossy().into_owned();
+    let macos = cfg!(target_os = "macos");
+    let files = if macos { trash.to_path_buf() } else { trash.join("files") };
+    let info = trash.join("info");
+    fs::create_dir_all(&files).map_err(|e| format!("cannot create {}: {e}", files.display()))?;
+    if !macos {
+        fs::create_dir_all(&info).map_err(|e| format!("cannot create {}: {e}", info.display()))?;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    for k in 1.. {
+        let candidate = if k == 1 { name.clone() } else { format!("{name}.{k}") };
+        let target = files.join(&candidate);
+        if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        }
+        let info_file = info.join(format!("{candidate}.trashinfo"));
+        if !macos {
+            // Creating the info file first claims the name, as the spec asks.
+            match fs::OpenOptions::new().write(true).create_new(true).open(&info_file) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format!("cannot create {}: {e}", info_file.display())),
+            }
+            fs::write(&info_file, trash_info(recorded, now))
+                .map_err(|e| format!("cannot write {}: {e}", info_file.display()))?;
+        }
+        return fs::rename(path, &target).map_err(|e| {
+            let _ = fs::remove_file(&info_file);
+            e.to_string()
+        });
+    }
+    unreachable!()
+}
+
+/// Contents of a `.trashinfo` file. The spec asks for local time; UTC is
+/// what we have without a time zone database.
+#[cfg(unix)]
+fn trash_info(recorded: &str, now: u64) -> String {
+    let t = exif::DateTime::from_unix(now);
+    format!(
+        "[Trash Info]\nPath={}\nDeletionDate={:04}-{:02}-{:02}T{:02}:{:02}:{:02}\n",
+        percent_encode(recorded),
+        t.year,
+        t.month,
+        t.day,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+/// Escapes everything but unreserved URI characters and `/`.
+#[cfg(unix)]
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+#[cfg(windows)]
+fn os_trash(path: &Path) -> Result<(), String> {
+    // PowerShell doesn't understand `\\?\` paths.
+    let s = path.to_string_lossy();
+    let s = s.strip_prefix(r"\\?\").unwrap_or(&s).replace('\'', "''");
+    let method = if path.is_dir() { "DeleteDirectory" } else { "DeleteFile" };
+    let script = format!(
+        "Add-Type -AssemblyName Microsoft.VisualBasic; \
+         [Microsoft.VisualBasic.FileIO.FileSystem]::{method}('{s}', 'OnlyErrorDialogs', 'SendToRecycleBin')"
+    );
+    let out = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .map_err(|e| format!("cannot run powershell: {e}"))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn os_trash(_path: &Path) -> Result<(), String> {
+    Err("there is no trash on this platform".to_string())
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_into_the_trash_with_restore_info() {
+        let root = env::temp_dir().join(format!("image-rando-trash-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dst")).unwrap();
+        let trash = root.join("Trash");
+        for _ in 0..2 {
+            fs::write(root.join("dst/a b.jpg"), b"x").unwrap();
+            move_into_trash(&trash, &root.join("dst/a b.jpg"), "dst/a b.jpg").unwrap();
+        }
+        assert!(!root.join("dst/a b.jpg").exists());
+        assert!(trash.join("files/a b.jpg").is_file());
+        assert!(trash.join("files/a b.jpg.2").is_file());
+        let info = fs::read_to_string(trash.join("info/a b.jpg.trashinfo")).unwrap();
+        assert!(info.starts_with("[Trash Info]\nPath=dst/a%20b.jpg\nDeletionDate="), "{info}");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+// Just enough XMP reading for tag-based filters.
+//
+// XMP is RDF/XML, but the tags we care about are always either simple
+// attributes (`ns:Name="..."`), simple elements (`<ns:Name>...</ns:Name>`) or
+// `rdf:li` items inside a named container, so plain string scanning is enough
+// and avoids an XML dependency.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::exif;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Xmp {
+    /// People tagged in the photo (face regions, PersonInImage, digiKam tags).
+    pub people: Vec<String>,
+    /// `xmp:Rating`: 1..=5 stars, 0 for none, -1 for rejected.
+    pub rating: Option<i8>,
+    /// `dc:subject` keywords and the last part of hierarchical tags.
+    pub keywords: Vec<String>,
+    /// `photoshop:City`, `photoshop:State` and `photoshop:Country`, narrowest
+    /// first, as photo managers fill them in from GPS or by hand.
+    pub place: [Option<String>; 3],
+}
+
+/// Sidecar locations used by common tools: `IMG.jpg.xmp` (digiKam,
+/// darktable) and `IMG.xmp` (Lightroom).
+pub fn sidecar_candidates(path: &Path) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    for ext in ["xmp", "XMP"] {
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(".");
+        appended.push(ext);
+        out.push(PathBuf::from(appended));
+    }
+    for ext in ["xmp", "XMP"] {
+        out.push(path.with_extension(ext));
+    }
+    out
+}
+
+/// Merges the XMP found in sidecar files and embedded in the image.
+pub fn read_xmp(path: &Path) -> Result<Xmp, String> {
+    let mut packets = Vec::new();
+    for sidecar in sidecar_candidates(path) {
+        if sidecar.is_file() {
+            let raw = fs::read(&sidecar)
+                .map_err(|e| format!("cannot read sidecar {}: {e}", sidecar.display()))?;
+            packets.push(String::from_utf8_lossy(&raw).into_owned());
+            break;
+        }
+    }
+    if let Some(embedded) = exif::read_xmp_packet(path)? {
+        packets.push(embedded);
+    }
+
+    let mut xmp = Xmp::default();
+    for packet in &packets {
+        // The sidecar comes first and wins.
+        if xmp.rating.is_none() {
+            xmp.rating = rating(packet);
+        }
+        for person in people(packet) {
+            if !xmp.people.iter().any(|p| p.eq_ignore_ascii_case(&person)) {
+                xmp.people.push(person);
+            }
+        }
+        for keyword in keywords(packet) {
+            if !xmp.keywords.iter().any(|k| k.eq_ignore_ascii_case(&keyword)) {
+                xmp.keywords.push(keyword);
+            }
+        }
+        for (known, found) in xmp.place.iter_mut().zip(place(packet)) {
+            if known.is_none() {
+                *known = found;
+            }
+        }
+    }
+    Ok(xmp)
+}
+
+fn rating(packet: &str) -> Option<i8> {
+    simple_values(packet, "xmp:Rating")
+        .iter()
+        .find_map(|v| v.parse::<f64>().ok())
+        .map(|r| r.clamp(-1.0, 5.0) as i8)
+}
+
+fn place(packet: &str) -> [Option<String>; 3] {
+    ["photoshop:City", "photoshop:State", "photoshop:Country"]
+        .map(|tag| simple_values(packet, tag).into_iter().find(|v| !v.is_empty()))
+}
+
+fn people(packet: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    // MWG face regions (digiKam, Lightroom, Picasa exports).
+    out.extend(simple_values(packet, "mwg-rs:Name"));
+    // Windows Live Photo Gallery regions.
+    out.extend(simple_values(packet, "MPReg:PersonDisplayName"));
+    out.extend(list_items(packet, "Iptc4xmpExt:PersonInImage"));
+    // digiKam keeps face tags in its hierarchical tag tree.
+    for tag in list_items(packet, "digiKam:TagsList") {
+        for root in ["People/", "Persons/"] {
+            if let Some(rest) = tag.strip_prefix(root) {
+                if let Some(name) = rest.rsplit('/').next() {
+                    out.push(name.to_string());
+                }
+            }
+        }
+    }
+    out.retain(|p| !p.is_empty());
+    out
+}
+
+fn keywords(packet: &str) -> Vec<String> {
+    let mut out = list_items(packet, "dc:subject");
+    // `Places/Lake` (digiKam) and `Places|Lake` (Lightroom) tag `Lake`;
+    // people's tags are `people`'s business.
+    for tag in list_items(packet, "digiKam:TagsList").into_iter().chain(list_items(packet, "lr:hierarchicalSubject")) {
+        if ["People/", "Persons/", "People|", "Persons|"].iter().any(|root| tag.starts_with(root)) {
+            continue;
+        }
+        if let Some(leaf) = tag.rsplit(['/', '|']).next() {
+            out.push(leaf.to_string());
+        }
+    }
+    out.retain(|k| !k.is_empty());
+    out
+}
+
+/// Values of `name="..."` attributes and `<name>...</name>` elements.
+fn simple_values(packet: &str, name: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let attr = format!("{name}=\"");
+    let mut rest = packet;
+    while let Some(at) = rest.find(&attr) {
+        rest = &rest[at + attr.len()..];
+        if let Some(end) = rest.find('"') {
+            out.push(unescape(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    let open = format!("<{name}>");
+    let close = format!("</{name}>");
+    let mut rest = packet;
+    while let Some(at) = rest.find(&open) {
+        rest = &rest[at + open.len()..];
+        if let Some(end) = rest.find(&close) {
+            let value = rest[..end].trim();
+            // Containers (Bag/Seq) are handled by `list_items`.
+            if !value.starts_with('<') {
+                out.push(unescape(value));
+            }
+            rest = &rest[end..];
+        }
+    }
+    out
+}
+
+/// `rdf:li` texts inside `<name>...</name>`.
+fn list_items(packet: &str, name: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut rest = packet;
+    while let Some(at) = rest.find(&open) {
+        rest = &rest[at + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        let mut body = &rest[..end];
+        while let Some(li) = body.find("<rdf:li") {
+            body = &body[li..];
+            let Some(gt) = body.find('>') else {
+                break;
+            };
+            body = &body[gt + 1..];
+            let Some(li_end) = body.find("</rdf:li>") else {
+                break;
+            };
+            out.push(unescape(body[..li_end].trim()));
+            body = &body[li_end..];
+        }
+        rest = &rest[end..];
+    }
+    out
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGIKAM: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF>
+  <rdf:Description
+    xmlns:digiKam="http://www.digikam.org/ns/1.0/"
+    xmlns:mwg-rs="http://www.metadataworkinggroup.com/schemas/regions/">
+   <digiKam:TagsList>
+    <rdf:Seq>
+     <rdf:li>People/Family/Alice</rdf:li>
+     <rdf:li>Places/Lake</rdf:li>
+    </rdf:Seq>
+   </digiKam:TagsList>
+   <mwg-rs:Regions rdf:parseType="Resource">
+    <mwg-rs:RegionList>
+     <rdf:Bag>
+      <rdf:li>
+       <rdf:Description mwg-rs:Name="Bob &amp; Co" mwg-rs:Type="Face"/>
+      </rdf:li>
+     </rdf:Bag>
+    </mwg-rs:RegionList>
+   </mwg-rs:Regions>
+   <dc:subject>
+    <rdf:Bag><rdf:li>beach</rdf:li><rdf:li>Lake</rdf:li></rdf:Bag>
+   </dc:subject>
+   <Iptc4xmpExt:PersonInImage>
+    <rdf:Bag><rdf:li>Carol</rdf:li></rdf:Bag>
+   </Iptc4xmpExt:PersonInImage>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn extracts_people_from_common_schemas() {
+        let mut found = people(DIGIKAM);
+        found.sort();
+        assert_eq!(found, vec!["Alice", "Bob & Co", "Carol"]);
+    }
+
+    #[test]
+    fn keywords_merge_plain_and_hierarchical_tags() {
+        assert_eq!(keywords(DIGIKAM), vec!["beach", "Lake", "Lake"]);
+    }
+
+    #[test]
+    fn reads_star_ratings() {
+        assert_eq!(rating(r#"<rdf:Description xmp:Rating="4"/>"#), Some(4));
+        assert_eq!(rating("<xmp:Rating>-1</xmp:Rating>"), Some(-1));
+        assert_eq!(rating(DIGIKAM), None);
+    }
+
+    #[test]
+    fn reads_place_names() {
+        let packet = r#"<rdf:Description photoshop:City="Bend" photoshop:Country=""><photoshop:State>Oregon</photoshop:State></rdf:Description>"#;
+        assert_eq!(place(packet), [Some("Bend".to_string()), Some("Oregon".to_string()), None]);
+        assert_eq!(place(DIGIKAM), [None, None, None]);
+    }
+
+    #[test]
+    fn sidecar_candidates_cover_both_conventions() {
+        let c = sidecar_candidates(Path::new("/p/IMG_1.jpg"));
+        assert_eq!(c[0], PathBuf::from("/p/IMG_1.jpg.xmp"));
+        assert!(c.contains(&PathBuf::from("/p/IMG_1.xmp")));
+    }
+}